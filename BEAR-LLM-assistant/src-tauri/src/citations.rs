@@ -0,0 +1,192 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Legal citation extraction. Runs independently of PII redaction; the redactor only uses
+// `citation_spans` to keep its generic ID patterns from eating citations.
+
+lazy_static! {
+    // 123 F.3d 456, 410 U.S. 113, 550 F. Supp. 2d 1, 12 So. 3d 45 (optional pinpoint: ", 460")
+    static ref CASE_REPORTER_REGEX: Regex = Regex::new(
+        r"\b(?P<volume>\d{1,4})\s+(?P<reporter>U\.\s?S\.|S\.\s?Ct\.|L\.\s?Ed\.(?:\s?2d)?|F\.\s?Supp\.(?:\s?(?:2d|3d))?|F\.\s?App'x|F\.(?:\s?(?:2d|3d|4th))?|A\.(?:\s?(?:2d|3d))?|P\.(?:\s?(?:2d|3d))?|N\.E\.(?:\s?(?:2d|3d))?|N\.W\.(?:\s?2d)?|S\.E\.(?:\s?2d)?|S\.W\.(?:\s?(?:2d|3d))?|So\.(?:\s?(?:2d|3d))?|Cal\.\s?Rptr\.(?:\s?(?:2d|3d))?)\s+(?P<page>\d{1,5})\b(?:,\s*(?P<pinpoint>\d{1,5})\b)?"
+    ).unwrap();
+    // 42 U.S.C. § 1983, 15 U.S.C. §§ 78j(b)
+    static ref US_CODE_REGEX: Regex = Regex::new(
+        r"\b(?P<title>\d{1,2})\s+U\.S\.C\.?\s*(?:§{1,2}\s*)?(?P<section>\d+[a-z]?(?:-\d+)?)(?P<subsections>(?:\([A-Za-z0-9]+\))*)"
+    ).unwrap();
+    // 17 C.F.R. § 240.10b-5
+    static ref CFR_REGEX: Regex = Regex::new(
+        r"\b(?P<title>\d{1,2})\s+C\.F\.R\.?\s*(?:§{1,2}\s*)?(?P<section>\d+(?:\.\d+[a-z]?(?:-\d+)?)?)"
+    ).unwrap();
+    // Directive 95/46/EC, Regulation (EU) 2016/679, Council Decision 2010/405/EU
+    static ref EU_LEGISLATION_REGEX: Regex = Regex::new(
+        r"\b(?P<kind>Directive|Regulation|Decision)\s+(?:\((?P<prefix>EU|EC|EEC|Euratom)\)\s+)?(?P<numbered>No\.?\s+)?(?P<first>\d{1,4})/(?P<second>\d{1,4})(?:/(?P<suffix>EU|EC|EEC|Euratom))?\b"
+    ).unwrap();
+    // ECLI:EU:C:2014:317, ECLI:NL:HR:2019:1234
+    static ref ECLI_REGEX: Regex = Regex::new(
+        r"\bECLI:[A-Z]{2}:[A-Z0-9.]{1,7}:\d{4}:[A-Z0-9.]{1,25}\b"
+    ).unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CitationKind {
+    Case,
+    UsCode,
+    Cfr,
+    EuLegislation,
+    Ecli,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    pub kind: CitationKind,
+    // As written in the document
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    // Canonical form for matching the same citation across documents, e.g. "123 F.3d 456"
+    pub identifier: String,
+    pub volume: Option<u32>,
+    pub reporter: Option<String>,
+    pub page: Option<u32>,
+    pub pinpoint: Option<u32>,
+    // Title for US Code/CFR, year for EU acts
+    pub title: Option<String>,
+    pub section: Option<String>,
+    pub url: Option<String>,
+}
+
+impl Citation {
+    fn new(kind: CitationKind, m: regex::Match, identifier: String) -> Self {
+        Self {
+            kind,
+            text: m.as_str().to_string(),
+            start: m.start(),
+            end: m.end(),
+            identifier,
+            volume: None,
+            reporter: None,
+            page: None,
+            pinpoint: None,
+            title: None,
+            section: None,
+            url: None,
+        }
+    }
+}
+
+// Whitespace-insensitive reporter name: "F. Supp. 2d" and "F.Supp.2d" compare equal
+fn compact(text: &str) -> String {
+    text.split_whitespace().collect()
+}
+
+// Canonical identifier for a user-typed citation, so "123 F. 3d 456" finds "123 F.3d 456"
+pub fn citation_identifier(query: &str) -> String {
+    match extract_citations(query, false).into_iter().next() {
+        Some(citation) => citation.identifier,
+        None => query.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+// Finds citations in document order. With `link`, fills `url` for sources that have a stable public URL.
+pub fn extract_citations(text: &str, link: bool) -> Vec<Citation> {
+    let mut citations = Vec::new();
+
+    for caps in CASE_REPORTER_REGEX.captures_iter(text) {
+        let reporter = compact(&caps["reporter"]);
+        let volume: u32 = caps["volume"].parse().unwrap_or(0);
+        let page: u32 = caps["page"].parse().unwrap_or(0);
+        let mut citation = Citation::new(
+            CitationKind::Case,
+            caps.get(0).unwrap(),
+            format!("{} {} {}", volume, reporter, page),
+        );
+        citation.pinpoint = caps.name("pinpoint").and_then(|p| p.as_str().parse().ok());
+        if link {
+            citation.url = Some(format!("https://www.courtlistener.com/c/{}/{}/{}/", reporter, volume, page));
+        }
+        citation.volume = Some(volume);
+        citation.reporter = Some(reporter);
+        citation.page = Some(page);
+        citations.push(citation);
+    }
+
+    for caps in US_CODE_REGEX.captures_iter(text) {
+        let title = caps["title"].to_string();
+        let section = format!("{}{}", &caps["section"], &caps["subsections"]);
+        let mut citation = Citation::new(
+            CitationKind::UsCode,
+            caps.get(0).unwrap(),
+            format!("{} U.S.C. § {}", title, section),
+        );
+        if link {
+            citation.url = Some(format!("https://www.law.cornell.edu/uscode/text/{}/{}", title, &caps["section"]));
+        }
+        citation.title = Some(title);
+        citation.section = Some(section);
+        citations.push(citation);
+    }
+
+    for caps in CFR_REGEX.captures_iter(text) {
+        let title = caps["title"].to_string();
+        let section = caps["section"].to_string();
+        let mut citation = Citation::new(
+            CitationKind::Cfr,
+            caps.get(0).unwrap(),
+            format!("{} C.F.R. § {}", title, section),
+        );
+        if link {
+            citation.url = Some(format!("https://www.ecfr.gov/current/title-{}/section-{}", title, section));
+        }
+        citation.title = Some(title);
+        citation.section = Some(section);
+        citations.push(citation);
+    }
+
+    for caps in EU_LEGISLATION_REGEX.captures_iter(text) {
+        // "No 1049/2001" (pre-2015 regulations) puts the number first; everything else is year/number
+        let (first, second) = (&caps["first"], &caps["second"]);
+        let (year, number) = if caps.name("numbered").is_some() { (second, first) } else { (first, second) };
+        let year = match year.len() {
+            2 => format!("{}{}", if year.as_bytes()[0] >= b'5' { "19" } else { "20" }, year),
+            _ => year.to_string(),
+        };
+        let kind = &caps["kind"];
+        let mut citation = Citation::new(
+            CitationKind::EuLegislation,
+            caps.get(0).unwrap(),
+            format!("{} {}/{}", kind, year, number),
+        );
+        if link {
+            let eli_type = match kind {
+                "Directive" => "dir",
+                "Regulation" => "reg",
+                _ => "dec",
+            };
+            citation.url = Some(format!("https://eur-lex.europa.eu/eli/{}/{}/{}/oj", eli_type, year, number));
+        }
+        citation.title = Some(year);
+        citation.section = Some(number.to_string());
+        citations.push(citation);
+    }
+
+    for m in ECLI_REGEX.find_iter(text) {
+        let mut citation = Citation::new(CitationKind::Ecli, m, m.as_str().to_string());
+        if link {
+            citation.url = Some(format!("https://e-justice.europa.eu/ecli/{}", m.as_str()));
+        }
+        citations.push(citation);
+    }
+
+    citations.sort_by_key(|c| c.start);
+    citations
+}
+
+// Byte spans the PII redactor must leave alone
+pub fn citation_spans(text: &str) -> Vec<(usize, usize)> {
+    extract_citations(text, false)
+        .into_iter()
+        .map(|c| (c.start, c.end))
+        .collect()
+}
@@ -1,82 +1,217 @@
-use crate::system_monitor::{SystemMonitor, ModelParams, Quantization, ModelCompatibility};
+use crate::system_monitor::{SystemMonitor, ModelParams, Quantization, ModelCompatibility, GpuDevice, MemoryUsage};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
-use tauri::State;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::path::Path;
+use std::time::Instant;
+use tauri::{AppHandle, Emitter, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::{hf_download, hf_search};
 
 pub struct AppState {
-    pub system_monitor: Mutex<SystemMonitor>,
+    pub system_monitor: Arc<Mutex<SystemMonitor>>,
+    // Running downloads by model id
+    pub downloads: Arc<Mutex<HashMap<String, DownloadJob>>>,
+}
+
+#[derive(Clone)]
+pub struct DownloadJob {
+    cancel: CancellationToken,
+    // Set by cancel_download; read once the download has stopped
+    keep_partial: Arc<AtomicBool>,
+}
+
+impl AppState {
+    pub fn new(system_monitor: SystemMonitor) -> Self {
+        Self {
+            system_monitor: Arc::new(Mutex::new(system_monitor)),
+            downloads: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn downloads(&self) -> MutexGuard<'_, HashMap<String, DownloadJob>> {
+        self.downloads.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Monitor calls refresh sysinfo and shell out to wmic/nvidia-smi, so they run on the blocking
+    // pool instead of an async worker. A panic fails only this call: the monitor holds cached
+    // readings, not invariants, so a poisoned lock is simply taken over by the next caller.
+    pub async fn with_monitor<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SystemMonitor) -> T + Send + 'static,
+    {
+        let monitor = self.system_monitor.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut guard = monitor.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut guard)
+        })
+        .await
+        .map_err(|e| {
+            if e.is_panic() {
+                "System monitor call failed unexpectedly; try again".to_string()
+            } else {
+                e.to_string()
+            }
+        })
+    }
+
+    // Non-blocking access for status snapshots; None while another call holds the monitor
+    pub fn try_monitor(&self) -> Option<MutexGuard<'_, SystemMonitor>> {
+        match self.system_monitor.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
 }
 
 #[tauri::command]
 pub async fn get_system_specs(state: State<'_, AppState>) -> Result<String, String> {
-    let mut monitor = state.system_monitor.lock().map_err(|e| e.to_string())?;
-    let specs = monitor.get_system_specs();
+    let specs = state.with_monitor(|monitor| monitor.get_system_specs()).await?;
     serde_json::to_string(&specs).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub async fn list_gpus(state: State<'_, AppState>) -> Result<Vec<GpuDevice>, String> {
+    state.with_monitor(|monitor| monitor.list_gpus()).await
+}
+
+const DEFAULT_CONTEXT_LENGTH: u32 = 4096;
+
 #[tauri::command]
 pub async fn check_model_compatibility(
     state: State<'_, AppState>,
     model_name: String,
     param_count: u64,
     quantization: String,
+    context_length: Option<u32>,
+    layer_count: Option<u32>,
+    kv_embedding_dim: Option<u32>,
 ) -> Result<ModelCompatibility, String> {
-    let mut monitor = state.system_monitor.lock().map_err(|e| e.to_string())?;
-
     let quant = match quantization.as_str() {
         "f32" => Quantization::F32,
         "f16" => Quantization::F16,
         "q8_0" => Quantization::Q8_0,
-        "q5_k_m" => Quantization::Q5_K_M,
-        "q4_k_m" => Quantization::Q4_K_M,
+        "q5_k_m" => Quantization::Q5KM,
+        "q4_k_m" => Quantization::Q4KM,
         "q4_0" => Quantization::Q4_0,
-        _ => Quantization::Q4_K_M, // Default to 4-bit
+        _ => Quantization::Q4KM, // Default to 4-bit
     };
 
     let model_params = ModelParams {
         name: model_name,
         param_count,
         quantization: quant,
-        context_length: 4096, // Default context
+        context_length: context_length.unwrap_or(DEFAULT_CONTEXT_LENGTH),
+        layer_count,
+        kv_embedding_dim,
     };
 
-    Ok(monitor.check_model_compatibility(&model_params))
+    state.with_monitor(move |monitor| monitor.check_model_compatibility(&model_params)).await
 }
 
 #[tauri::command]
 pub async fn get_resource_usage(state: State<'_, AppState>) -> Result<String, String> {
-    let mut monitor = state.system_monitor.lock().map_err(|e| e.to_string())?;
-    let snapshot = monitor.monitor_resources_realtime();
+    let snapshot = state.with_monitor(|monitor| monitor.monitor_resources_realtime()).await?;
     serde_json::to_string(&snapshot).map_err(|e| e.to_string())
 }
 
+pub const MODEL_DOWNLOAD_EVENT: &str = "model-download-progress";
+
+const DOWNLOAD_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+// Downloads into `save_path`, emitting `model-download-progress` events while it runs. Without
+// `filename` the preferred GGUF quantization (plus tokenizer.json) is chosen. Running it again
+// after an interruption resumes the partial file. Gated, private or missing repos end with
+// status Failed rather than an error, so the UI gets the same event either way. Only one
+// download per model id runs at a time; cancel_download stops it.
 #[tauri::command]
 pub async fn download_model_from_huggingface(
+    app: AppHandle,
+    state: State<'_, AppState>,
     model_id: String,
     save_path: String,
+    filename: Option<String>,
 ) -> Result<DownloadProgress, String> {
-    use hf_hub::api::tokio::Api;
-    use hf_hub::Repo;
-
-    // Initialize HuggingFace API
-    let api = Api::new().map_err(|e| e.to_string())?;
-    let repo = api.model(model_id.clone());
-
-    // This would download the model files
-    // In production, you'd stream progress updates
-
-    Ok(DownloadProgress {
-        model_id,
-        status: DownloadStatus::InProgress,
-        progress_percent: 0.0,
-        downloaded_mb: 0,
-        total_mb: 0,
-        speed_mbps: 0.0,
-        eta_seconds: 0,
-    })
+    let job = DownloadJob {
+        cancel: CancellationToken::new(),
+        keep_partial: Arc::new(AtomicBool::new(true)),
+    };
+    {
+        let mut downloads = state.downloads();
+        if downloads.contains_key(&model_id) {
+            return Err(format!("{} is already being downloaded", model_id));
+        }
+        downloads.insert(model_id.clone(), job.clone());
+    }
+
+    let emit = |progress: &DownloadProgress| {
+        if let Err(e) = app.emit(MODEL_DOWNLOAD_EVENT, progress) {
+            eprintln!("Failed to emit download progress: {}", e);
+        }
+    };
+
+    emit(&DownloadProgress::new(&model_id, DownloadStatus::Queued));
+
+    let save_dir = Path::new(&save_path);
+    let mut files = Vec::new();
+    let result = async {
+        files = tokio::select! {
+            files = hf_download::select_files(&model_id, filename.as_deref()) => files?,
+            _ = job.cancel.cancelled() => return Err(hf_download::DownloadError::Cancelled.into()),
+        };
+        hf_download::download_files(&model_id, &files, save_dir, DOWNLOAD_PROGRESS_INTERVAL, &job.cancel, |progress| {
+            emit(&DownloadProgress::from_progress(&model_id, DownloadStatus::InProgress, progress));
+        })
+        .await
+    }
+    .await;
+    state.downloads().remove(&model_id);
+
+    let finished = match result {
+        Ok(_) => {
+            let mut done = DownloadProgress::new(&model_id, DownloadStatus::Completed);
+            done.progress_percent = 100.0;
+            done
+        }
+        Err(e) if matches!(e.downcast_ref::<hf_download::DownloadError>(), Some(hf_download::DownloadError::Cancelled)) => {
+            if !job.keep_partial.load(Ordering::SeqCst) {
+                if let Err(e) = hf_download::remove_partial_files(&files, save_dir).await {
+                    eprintln!("Failed to remove partial download: {}", e);
+                }
+            }
+            DownloadProgress::new(&model_id, DownloadStatus::Cancelled)
+        }
+        Err(e) => DownloadProgress::new(&model_id, DownloadStatus::Failed(e.to_string())),
+    };
+    emit(&finished);
+    Ok(finished)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Stops a running download; it emits a final Cancelled event. The partial file is kept so the
+// next download of the same model resumes, unless `keep_partial` is false. Returns false if
+// nothing is downloading under this id.
+#[tauri::command]
+pub async fn cancel_download(
+    state: State<'_, AppState>,
+    model_id: String,
+    keep_partial: Option<bool>,
+) -> Result<bool, String> {
+    match state.downloads().get(&model_id) {
+        Some(job) => {
+            job.keep_partial.store(keep_partial.unwrap_or(true), Ordering::SeqCst);
+            job.cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadProgress {
     pub model_id: String,
     pub status: DownloadStatus,
@@ -87,52 +222,70 @@ pub struct DownloadProgress {
     pub eta_seconds: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl DownloadProgress {
+    fn new(model_id: &str, status: DownloadStatus) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            status,
+            progress_percent: 0.0,
+            downloaded_mb: 0,
+            total_mb: 0,
+            speed_mbps: 0.0,
+            eta_seconds: 0,
+        }
+    }
+
+    fn from_progress(model_id: &str, status: DownloadStatus, progress: hf_download::Progress) -> Self {
+        let remaining = progress.total_bytes.saturating_sub(progress.downloaded_bytes);
+        Self {
+            model_id: model_id.to_string(),
+            status,
+            progress_percent: if progress.total_bytes > 0 {
+                (progress.downloaded_bytes as f64 / progress.total_bytes as f64 * 100.0) as f32
+            } else {
+                0.0
+            },
+            downloaded_mb: progress.downloaded_bytes / BYTES_PER_MB,
+            total_mb: progress.total_bytes / BYTES_PER_MB,
+            speed_mbps: (progress.bytes_per_second / BYTES_PER_MB as f64) as f32,
+            eta_seconds: if progress.bytes_per_second > 0.0 {
+                (remaining as f64 / progress.bytes_per_second).ceil() as u64
+            } else {
+                0
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DownloadStatus {
     Queued,
     InProgress,
     Paused,
     Completed,
+    Cancelled,
     Failed(String),
 }
 
+// Pass the previous page's `next_cursor` as `cursor` to fetch the next page; `limit` applies
+// to the first page and the cursor carries it forward
 #[tauri::command]
 pub async fn search_huggingface_models(
     query: String,
     filter_size: Option<String>,
     filter_type: Option<String>,
-) -> Result<Vec<HuggingFaceModel>, String> {
-    // This would actually search HuggingFace
-    // For now, return real model data structure
-
-    Ok(vec![
-        HuggingFaceModel {
-            model_id: "meta-llama/Llama-2-7b-chat-hf".to_string(),
-            author: "meta-llama".to_string(),
-            model_name: "Llama-2-7b-chat-hf".to_string(),
-            likes: 45234,
-            downloads: 2_145_678,
-            tags: vec!["text-generation".to_string(), "llama".to_string(), "chat".to_string()],
-            size_bytes: 13_476_839_424, // ~13GB
-            last_modified: "2024-01-15".to_string(),
-            description: "Llama 2 7B Chat model fine-tuned for dialogue use cases".to_string(),
-            license: "llama2".to_string(),
-            pipeline_tag: "text-generation".to_string(),
-        },
-        HuggingFaceModel {
-            model_id: "mistralai/Mistral-7B-Instruct-v0.2".to_string(),
-            author: "mistralai".to_string(),
-            model_name: "Mistral-7B-Instruct-v0.2".to_string(),
-            likes: 28567,
-            downloads: 1_567_234,
-            tags: vec!["text-generation".to_string(), "mistral".to_string(), "instruct".to_string()],
-            size_bytes: 14_483_456_000, // ~14GB
-            last_modified: "2024-02-01".to_string(),
-            description: "Mistral 7B fine-tuned for instruction following".to_string(),
-            license: "apache-2.0".to_string(),
-            pipeline_tag: "text-generation".to_string(),
-        },
-    ])
+    limit: Option<usize>,
+    cursor: Option<String>,
+) -> Result<HuggingFaceSearchPage, String> {
+    hf_search::search_models(&query, filter_size.as_deref(), filter_type.as_deref(), limit, cursor.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HuggingFaceSearchPage {
+    pub models: Vec<HuggingFaceModel>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -148,88 +301,305 @@ pub struct HuggingFaceModel {
     pub description: String,
     pub license: String,
     pub pipeline_tag: String,
+    pub param_count: Option<u64>,
 }
 
 #[tauri::command]
 pub async fn load_model(
     state: State<'_, AppState>,
+    app_state: State<'_, crate::AppState>,
     model_path: String,
 ) -> Result<ModelLoadResult, String> {
-    // Check resources before loading
-    let mut monitor = state.system_monitor.lock().map_err(|e| e.to_string())?;
-    let specs = monitor.get_system_specs();
-
-    // Check if we have enough free memory
-    if specs.gpu.available && specs.gpu.vram_free_mb < 4096 {
-        return Err("Insufficient GPU memory. Please close other applications.".to_string());
+    let specs = state.with_monitor(|monitor| monitor.get_system_specs()).await?;
+
+    let mut llm = app_state.llm_manager.write().await;
+    let required_mb = llm.model_file_bytes(&model_path).map_err(|e| e.to_string())? / BYTES_PER_MB;
+
+    let mut replacing = None;
+    if let Some(replacing_mb) = memory_freed_before_load(llm.loaded_model(), &model_path, llm.resident_bytes()) {
+        replacing = llm.loaded_model().map(str::to_string);
+
+        if llm.loads_to_gpu() && specs.gpu.available {
+            let free_mb = specs.gpu.vram_free_mb + replacing_mb;
+            if free_mb < required_mb + LOAD_HEADROOM_MB {
+                return Err(format!(
+                    "Insufficient GPU memory: the model needs about {} MB but only {} MB of VRAM is free. Please close other applications.",
+                    required_mb + LOAD_HEADROOM_MB,
+                    free_mb
+                ));
+            }
+        } else {
+            let free_mb = specs.memory.available_mb + replacing_mb;
+            if free_mb < required_mb + LOAD_HEADROOM_MB {
+                return Err(format!(
+                    "Insufficient system memory: the model needs about {} MB but only {} MB is available. Please close other applications.",
+                    required_mb + LOAD_HEADROOM_MB,
+                    free_mb
+                ));
+            }
+        }
     }
 
-    if specs.memory.available_mb < 8192 {
-        return Err("Insufficient system memory. Please close other applications.".to_string());
-    }
+    let started = Instant::now();
+    let ((), memory_delta_mb) = measured(
+        || state.with_monitor(|monitor| monitor.memory_usage()),
+        async { llm.load_model(&model_path).await.map_err(|e| e.to_string()) },
+    ).await?;
+    let load_time_ms = started.elapsed().as_millis() as u64;
 
-    // Here you would actually load the model using candle or llama.cpp
+    let mut warnings = vec![];
+    if let Some(replaced) = replacing {
+        warnings.push(format!("Unloaded {} to make room", replaced));
+    }
 
     Ok(ModelLoadResult {
         success: true,
         model_name: model_path,
-        load_time_ms: 1234,
-        memory_used_mb: 4096,
-        warnings: vec![],
+        load_time_ms,
+        memory_used_mb: llm.resident_bytes() / BYTES_PER_MB,
+        memory_delta_mb,
+        warnings,
     })
 }
 
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+// MB the current model hands back before `model_path` is loaded, which counts as free for the
+// memory check. None when `model_path` is already resident: loading it again is a no-op.
+fn memory_freed_before_load(loaded: Option<&str>, model_path: &str, resident_bytes: u64) -> Option<u64> {
+    match loaded {
+        Some(name) if name == model_path => None,
+        Some(_) => Some(resident_bytes / BYTES_PER_MB),
+        None => Some(0),
+    }
+}
+
+// Room left for the KV cache and activations on top of the weights
+const LOAD_HEADROOM_MB: u64 = 1024;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelLoadResult {
     pub success: bool,
     pub model_name: String,
     pub load_time_ms: u64,
     pub memory_used_mb: u64,
+    // Measured change in RAM + VRAM use across the load
+    pub memory_delta_mb: i64,
     pub warnings: Vec<String>,
 }
 
 #[tauri::command]
-pub async fn unload_model(model_name: String) -> Result<bool, String> {
-    // Unload model from memory
-    // This would actually free up GPU/CPU memory
-    Ok(true)
+pub async fn unload_model(
+    state: State<'_, AppState>,
+    app_state: State<'_, crate::AppState>,
+    model_name: String,
+) -> Result<ModelUnloadResult, String> {
+    let started = Instant::now();
+    let mut llm = app_state.llm_manager.write().await;
+    let (freed_bytes, memory_delta_mb) = measured(
+        || state.with_monitor(|monitor| monitor.memory_usage()),
+        async { llm.unload_model().await.map_err(|e| e.to_string()) },
+    ).await?;
+    let unload_time_ms = started.elapsed().as_millis() as u64;
+
+    Ok(ModelUnloadResult {
+        success: true,
+        model_name,
+        unload_time_ms,
+        memory_freed_mb: freed_bytes / BYTES_PER_MB,
+        memory_delta_mb,
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelUnloadResult {
+    pub success: bool,
+    pub model_name: String,
+    pub unload_time_ms: u64,
+    pub memory_freed_mb: u64,
+    // Measured change in RAM + VRAM use across the unload; negative when memory was released
+    pub memory_delta_mb: i64,
+}
+
+// Runs `operation` between two memory snapshots and returns its output with the change in total
+// RAM + VRAM use. Other processes allocate and free meanwhile, so this is an estimate.
+async fn measured<T, S, SnapshotFut, Op>(snapshot: S, operation: Op) -> Result<(T, i64), String>
+where
+    S: Fn() -> SnapshotFut,
+    SnapshotFut: Future<Output = Result<MemoryUsage, String>>,
+    Op: Future<Output = Result<T, String>>,
+{
+    let before = snapshot().await?;
+    let output = operation.await?;
+    let after = snapshot().await?;
+    Ok((output, after.total_mb() as i64 - before.total_mb() as i64))
 }
 
+// How long emergency_stop waits for cancelled generations to return before unloading anyway
+const GENERATION_STOP_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Panic button for an overloaded machine: cancels every running chat generation, unloads the
+// model once the generations have stopped, then applies the hardware monitor's emergency
+// throttle. The throttle's cool-down makes this take a few seconds.
 #[tauri::command]
-pub async fn emergency_stop() -> Result<bool, String> {
-    // Emergency stop - unload all models and free memory
-    // This is the panic button if system is overloading
+pub async fn emergency_stop(app_state: State<'_, crate::AppState>) -> Result<EmergencyStopResult, String> {
+    stop_everything(&app_state).await
+}
 
+async fn stop_everything(app_state: &crate::AppState) -> Result<EmergencyStopResult, String> {
     println!("EMERGENCY STOP: Unloading all models and freeing resources");
 
-    // Force garbage collection, unload models, clear caches
-    // In production, this would actually stop inference and free memory
+    let cancelled: Vec<String> = {
+        let jobs = app_state.chat_jobs.read().await;
+        for cancel in jobs.values() {
+            cancel.cancel();
+        }
+        jobs.keys().cloned().collect()
+    };
+
+    // A generation holds its own handle on the weights, not the manager lock, so unloading
+    // only frees them once every cancelled one has returned and left chat_jobs
+    let deadline = Instant::now() + GENERATION_STOP_WAIT;
+    let still_running = loop {
+        let running = {
+            let jobs = app_state.chat_jobs.read().await;
+            cancelled.iter().filter(|id| jobs.contains_key(*id)).count()
+        };
+        if running == 0 || Instant::now() >= deadline {
+            break running;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    };
+
+    let (unloaded_model, freed_bytes) = {
+        let mut llm = app_state.llm_manager.write().await;
+        let unloaded_model = llm.loaded_model().map(str::to_string);
+        let freed_bytes = llm.unload_model().await.map_err(|e| e.to_string())?;
+        (unloaded_model, freed_bytes)
+    };
 
-    Ok(true)
+    // The poll loop and safety checks keep running through the cool-down
+    let throttle_error = crate::hardware_monitor::HardwareMonitor::emergency_throttle()
+        .await
+        .err()
+        .map(|e| e.to_string());
+
+    Ok(EmergencyStopResult {
+        generations_cancelled: cancelled.len(),
+        unloaded_model,
+        memory_freed_mb: freed_bytes / BYTES_PER_MB,
+        memory_release_pending: still_running > 0,
+        throttled: throttle_error.is_none(),
+        throttle_error,
+    })
 }
 
-#[tauri::command]
-pub async fn set_resource_limits(
-    max_gpu_usage: f32,
-    max_cpu_usage: f32,
-    max_ram_usage: f32,
-) -> Result<bool, String> {
-    // Set resource usage limits
-    // The system will throttle or pause if these limits are exceeded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmergencyStopResult {
+    pub generations_cancelled: usize,
+    pub unloaded_model: Option<String>,
+    pub memory_freed_mb: u64,
+    // A generation had not returned in time, so memory_freed_mb is released once it does
+    pub memory_release_pending: bool,
+    pub throttled: bool,
+    pub throttle_error: Option<String>,
+}
 
-    if max_gpu_usage < 0.0 || max_gpu_usage > 100.0 {
-        return Err("GPU usage limit must be between 0 and 100".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_emergency_stop_leaves_the_monitor_available() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = crate::test_state(dir.path()).await;
+        let models_dir = dir.path().join("models");
+        std::fs::create_dir_all(&models_dir).unwrap();
+        let model_file = crate::inference::write_tiny_model(&models_dir);
+        let session = {
+            let mut llm = state.llm_manager.write().await;
+            llm.apply_model_files(vec![model_file]);
+            llm.session("tiny-llama").await.unwrap()
+        };
+        assert_eq!(state.llm_manager.read().await.get_active_model().as_deref(), Some("tiny-llama"));
+
+        // Stands in for send_message: it holds the session, not the manager lock, until cancelled
+        let cancel = CancellationToken::new();
+        state.chat_jobs.write().await.insert("chat-1".to_string(), cancel.clone());
+        let generation = tokio::spawn({
+            let (state, cancel) = (state.clone(), cancel.clone());
+            async move {
+                cancel.cancelled().await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                drop(session);
+                state.chat_jobs.write().await.remove("chat-1");
+            }
+        });
+
+        let stopping = tokio::spawn({
+            let state = state.clone();
+            async move { stop_everything(&state).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Mid cool-down, the poll loop could still take the monitor
+        let monitor = tokio::time::timeout(Duration::from_millis(500), state.hardware_monitor.write()).await;
+        assert!(monitor.is_ok());
+        drop(monitor);
+
+        let result = stopping.await.unwrap().unwrap();
+        assert_eq!(result.generations_cancelled, 1);
+        assert!(cancel.is_cancelled());
+        assert!(generation.is_finished());
+        assert!(!result.memory_release_pending);
+        assert_eq!(result.unloaded_model.as_deref(), Some("tiny-llama"));
+        assert!(state.llm_manager.read().await.get_active_model().is_none());
     }
 
-    if max_cpu_usage < 0.0 || max_cpu_usage > 100.0 {
-        return Err("CPU usage limit must be between 0 and 100".to_string());
-    }
+    #[tokio::test]
+    async fn test_a_panicking_monitor_call_does_not_break_later_ones() {
+        let state = AppState::new(SystemMonitor::new());
+
+        let panicked = state.with_monitor(|_| -> u32 { panic!("sensor read failed") }).await;
+        assert_eq!(panicked.unwrap_err(), "System monitor call failed unexpectedly; try again");
+        assert!(state.system_monitor.is_poisoned());
 
-    if max_ram_usage < 0.0 || max_ram_usage > 100.0 {
-        return Err("RAM usage limit must be between 0 and 100".to_string());
+        let gpu_index = state.with_monitor(|monitor| monitor.gpu_index()).await;
+        assert!(gpu_index.is_ok());
+        assert!(state.try_monitor().is_some());
     }
 
-    // Store these limits and enforce them during inference
+    #[tokio::test]
+    async fn test_measured_reports_the_memory_delta() {
+        use std::sync::atomic::AtomicU64;
+
+        let used_mb = AtomicU64::new(2048);
+        let snapshot = || async {
+            Ok(MemoryUsage { ram_used_mb: used_mb.load(Ordering::SeqCst), vram_used_mb: 512 })
+        };
+
+        let (name, delta) = measured(snapshot, async {
+            used_mb.fetch_add(4096, Ordering::SeqCst);
+            Ok("llama-7b")
+        }).await.unwrap();
+        assert_eq!((name, delta), ("llama-7b", 4096));
+
+        let ((), delta) = measured(snapshot, async {
+            used_mb.fetch_sub(4000, Ordering::SeqCst);
+            Ok(())
+        }).await.unwrap();
+        assert_eq!(delta, -4000);
+
+        let failed = measured(snapshot, async { Err::<(), _>("load failed".to_string()) }).await;
+        assert_eq!(failed.unwrap_err(), "load failed");
+    }
 
-    Ok(true)
-}
\ No newline at end of file
+    #[test]
+    fn test_reloading_the_resident_model_skips_the_memory_check() {
+        let resident = 6 * 1024 * BYTES_PER_MB;
+        assert_eq!(memory_freed_before_load(Some("llama-7b"), "llama-7b", resident), None);
+        assert_eq!(memory_freed_before_load(Some("llama-7b"), "phi-2", resident), Some(6 * 1024));
+        assert_eq!(memory_freed_before_load(None, "phi-2", 0), Some(0));
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Result, anyhow};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+const APP_DIR_NAME: &str = "legal-ai-assistant";
+const DATA_DIR_ENV: &str = "BEAR_DATA_DIR";
+const PORTABLE_ENV: &str = "BEAR_PORTABLE";
+const PORTABLE_MARKER: &str = "portable";
+
+// Resolution order: --data-dir <path>, BEAR_DATA_DIR, portable mode, then the OS data dir.
+// Portable mode is enabled by --portable, BEAR_PORTABLE=1, or a `portable` marker file
+// next to the executable, and keeps everything under the executable's directory.
+pub fn resolve_data_dir() -> Result<PathBuf> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if let Some(dir) = arg_value(&args, "--data-dir") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    if let Ok(dir) = std::env::var(DATA_DIR_ENV) {
+        if !dir.trim().is_empty() {
+            return Ok(PathBuf::from(dir));
+        }
+    }
+
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+
+    if is_portable(&args, exe_dir.as_deref()) {
+        let exe_dir = exe_dir.ok_or_else(|| anyhow!("Portable mode enabled but executable directory is unknown"))?;
+        return Ok(exe_dir.join("data"));
+    }
+
+    Ok(dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("./"))
+        .join(APP_DIR_NAME))
+}
+
+// Creates the directory if needed and proves we can write to it
+pub fn ensure_writable(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| anyhow!("Cannot create data directory {}: {}", dir.display(), e))?;
+
+    let probe = dir.join(".write_test");
+    std::fs::write(&probe, b"ok")
+        .map_err(|e| anyhow!("Data directory {} is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(probe);
+
+    Ok(())
+}
+
+fn is_portable(args: &[String], exe_dir: Option<&Path>) -> bool {
+    if args.iter().any(|a| a == "--portable") {
+        return true;
+    }
+
+    if matches!(std::env::var(PORTABLE_ENV).as_deref(), Ok("1") | Ok("true")) {
+        return true;
+    }
+
+    exe_dir.map(|dir| dir.join(PORTABLE_MARKER).exists()).unwrap_or(false)
+}
+
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    let prefix = format!("{}=", flag);
+    for (i, arg) in args.iter().enumerate() {
+        if arg == flag {
+            return args.get(i + 1).cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+// Settings files under the data dir: a missing file yields defaults
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use anyhow::{Result, anyhow};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use std::path::{Path, PathBuf};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+// Where `default_embedder` looks for a sentence-transformers model (config.json, tokenizer.json,
+// model.safetensors), relative to the data directory
+pub const DEFAULT_EMBEDDING_MODEL_DIR: &str = "models/embeddings/all-MiniLM-L6-v2";
+
+// Backend that turns text into vectors for the RAG index. Implementations must return
+// exactly one vector of `dimension()` floats per input text, in input order.
+pub trait EmbeddingModel: Send + Sync {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    fn dimension(&self) -> usize;
+
+    // Upper bound on texts per `embed` call, to keep forward-pass memory in check
+    fn max_batch_size(&self) -> usize {
+        32
+    }
+
+    // Stored with the index so vectors from different models are never mixed
+    fn model_id(&self) -> &str;
+
+    // Byte offsets of each token as the model's tokenizer sees them; None falls back to word chunking
+    fn token_offsets(&self, _text: &str) -> Option<Vec<(usize, usize)>> {
+        None
+    }
+
+    // Longest input (in tokens, including special tokens) embedded without truncation
+    fn max_sequence_length(&self) -> Option<usize> {
+        None
+    }
+}
+
+// Local fallback that needs no model files: character codes hashed into a fixed-size vector
+pub struct CharHashEmbedder {
+    dimension: usize,
+}
+
+impl CharHashEmbedder {
+    pub const MODEL_ID: &'static str = "builtin-char-hash";
+
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension }
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut embeddings = vec![0.0; self.dimension];
+        for (i, char) in text.chars().enumerate().take(self.dimension) {
+            embeddings[i % self.dimension] += (char as u32) as f32 / 1000.0;
+        }
+
+        let norm: f32 = embeddings.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            for val in &mut embeddings {
+                *val /= norm;
+            }
+        }
+
+        embeddings
+    }
+}
+
+impl Default for CharHashEmbedder {
+    fn default() -> Self {
+        Self::new(384)
+    }
+}
+
+impl EmbeddingModel for CharHashEmbedder {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> &str {
+        Self::MODEL_ID
+    }
+}
+
+// BERT-family sentence-transformers model run locally with candle: mean pooling over the
+// attention mask, then L2 normalization, as all-MiniLM-L6-v2 is trained for
+pub struct SentenceEmbedder {
+    model: BertModel,
+    // Unpadded and untruncated, so token offsets cover the whole text for chunking
+    tokenizer: Tokenizer,
+    batch_tokenizer: Tokenizer,
+    device: Device,
+    dimension: usize,
+    max_sequence_length: usize,
+    model_id: String,
+}
+
+impl SentenceEmbedder {
+    pub fn load(model_dir: &Path) -> Result<Self> {
+        let file = |name: &str| -> Result<PathBuf> {
+            let path = model_dir.join(name);
+            if path.exists() {
+                Ok(path)
+            } else {
+                Err(anyhow!("Embedding model file {} not found", path.display()))
+            }
+        };
+
+        let config: BertConfig = serde_json::from_str(&std::fs::read_to_string(file("config.json")?)?)?;
+        let tokenizer = Tokenizer::from_file(file("tokenizer.json")?).map_err(|e| anyhow!(e))?;
+
+        // sentence-transformers models are often trained on shorter inputs than BERT's position table
+        let max_sequence_length = std::fs::read_to_string(model_dir.join("sentence_bert_config.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+            .and_then(|config| config["max_seq_length"].as_u64())
+            .map(|n| n as usize)
+            .unwrap_or(config.max_position_embeddings);
+
+        let mut batch_tokenizer = tokenizer.clone();
+        batch_tokenizer.with_padding(Some(PaddingParams::default()));
+        batch_tokenizer
+            .with_truncation(Some(TruncationParams { max_length: max_sequence_length, ..Default::default() }))
+            .map_err(|e| anyhow!(e))?;
+
+        let device = Device::Cpu;
+        let weights = file("model.safetensors")?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DType::F32, &device)? };
+        let model = BertModel::load(vb, &config)?;
+
+        let name = model_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("sentence-embedder");
+
+        Ok(Self {
+            model,
+            tokenizer,
+            batch_tokenizer,
+            device,
+            dimension: config.hidden_size,
+            max_sequence_length,
+            model_id: format!("sentence-transformers/{}", name),
+        })
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self.batch_tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!(e))?;
+
+        let rows = |field: fn(&tokenizers::Encoding) -> &[u32]| -> Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(field(encoding), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let input_ids = rows(|e| e.get_ids())?;
+        let type_ids = rows(|e| e.get_type_ids())?;
+        let attention_mask = rows(|e| e.get_attention_mask())?;
+
+        let hidden = self.model.forward(&input_ids, &type_ids, Some(&attention_mask))?;
+
+        // Padding positions are excluded from the mean
+        let mask = attention_mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let pooled = summed.broadcast_div(&mask.sum(1)?)?;
+        let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        Ok(pooled.broadcast_div(&norm)?.to_vec2::<f32>()?)
+    }
+}
+
+impl EmbeddingModel for SentenceEmbedder {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.embed_batch(texts)
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<(usize, usize)>> {
+        let encoding = self.tokenizer.encode(text, false).ok()?;
+        Some(encoding.get_offsets().to_vec())
+    }
+
+    fn max_sequence_length(&self) -> Option<usize> {
+        Some(self.max_sequence_length)
+    }
+}
+
+// Every other sentence model installed next to the default one, for collections assigned a
+// model of their own (see `RAGEngine::assign_collection_model`). Each stays resident alongside
+// the default, roughly the size of its model.safetensors in RAM. Directories that don't load are
+// skipped with a warning.
+pub fn collection_embedders(data_dir: &Path) -> Vec<Box<dyn EmbeddingModel>> {
+    let default_dir = data_dir.join(DEFAULT_EMBEDDING_MODEL_DIR);
+    let models_dir = match default_dir.parent() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let mut dirs: Vec<PathBuf> = std::fs::read_dir(models_dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    dirs.sort();
+
+    dirs.into_iter()
+        .filter(|dir| dir.is_dir() && *dir != default_dir)
+        .filter_map(|dir| match SentenceEmbedder::load(&dir) {
+            Ok(embedder) => Some(Box::new(embedder) as Box<dyn EmbeddingModel>),
+            Err(e) => {
+                eprintln!("Skipping embedding model {}: {}", dir.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+// The sentence model when its files are installed; otherwise the character-hash fallback,
+// which keeps the app usable but ranks by spelling rather than meaning
+pub fn default_embedder(data_dir: &Path) -> Box<dyn EmbeddingModel> {
+    match SentenceEmbedder::load(&data_dir.join(DEFAULT_EMBEDDING_MODEL_DIR)) {
+        Ok(embedder) => Box::new(embedder),
+        Err(e) => {
+            eprintln!("Sentence embedding model unavailable, using {}: {}", CharHashEmbedder::MODEL_ID, e);
+            Box::new(CharHashEmbedder::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_embedders_skip_the_default_and_incomplete_models() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(collection_embedders(dir.path()).is_empty());
+
+        let models_dir = dir.path().join(DEFAULT_EMBEDDING_MODEL_DIR);
+        std::fs::create_dir_all(&models_dir).unwrap();
+        let dutch = models_dir.parent().unwrap().join("robbert-dutch");
+        std::fs::create_dir_all(&dutch).unwrap();
+        std::fs::write(dutch.join("config.json"), "{}").unwrap();
+        assert!(collection_embedders(dir.path()).is_empty());
+    }
+
+    // cargo test -- --ignored, with BEAR_TEST_EMBEDDING_MODEL pointing at a sentence-transformers
+    // directory such as all-MiniLM-L6-v2 (config.json, tokenizer.json, model.safetensors)
+    #[tokio::test]
+    #[ignore]
+    async fn test_related_sentences_outrank_unrelated_ones_with_a_real_model() {
+        let model_dir = std::env::var("BEAR_TEST_EMBEDDING_MODEL").expect("BEAR_TEST_EMBEDDING_MODEL is not set");
+        let embedder = SentenceEmbedder::load(Path::new(&model_dir)).unwrap();
+
+        let texts: Vec<String> = [
+            "The tenant must pay rent on the first day of each month.",
+            "Monthly lease payments are due at the start of the month.",
+            "The court dismissed the appeal for lack of jurisdiction.",
+        ]
+        .iter()
+        .map(|text| text.to_string())
+        .collect();
+        let vectors = embedder.embed(&texts).unwrap();
+        assert!(vectors.iter().all(|vector| vector.len() == embedder.dimension()));
+        let similarity = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        assert!(similarity(&vectors[0], &vectors[1]) > similarity(&vectors[0], &vectors[2]));
+
+        // Same through the index, where the character-hash fallback ranks by spelling instead
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = crate::rag_engine::RAGEngine::with_embedder(dir.path(), Box::new(embedder));
+        for text in &texts[1..] {
+            rag.add_document(text, serde_json::json!({}), None, None).await.unwrap();
+        }
+        let results = rag.search(&texts[0], 2).await.unwrap();
+        assert_eq!(results[0]["content"], texts[1]);
+    }
+}
@@ -0,0 +1,384 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Minimal EXIF reader/stripper for JPEG (APP1), PNG (eXIf), WebP (EXIF chunk) and bare TIFF files
+
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const GPS_LATITUDE_REF: u16 = 0x0001;
+const GPS_LATITUDE: u16 = 0x0002;
+const GPS_LONGITUDE_REF: u16 = 0x0003;
+const GPS_LONGITUDE: u16 = 0x0004;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_ASCII: u16 = 2;
+const TYPE_RATIONAL: u16 = 5;
+
+// Text tags worth reporting, from IFD0 and the Exif sub-IFD
+const TEXT_TAGS: &[(u16, &str)] = &[
+    (0x010E, "ImageDescription"),
+    (0x010F, "Make"),
+    (0x0110, "Model"),
+    (0x0131, "Software"),
+    (0x0132, "DateTime"),
+    (0x013B, "Artist"),
+    (0x8298, "Copyright"),
+    (0x9003, "DateTimeOriginal"),
+    (0x9004, "DateTimeDigitized"),
+    (0xA420, "ImageUniqueID"),
+    (0xA430, "CameraOwnerName"),
+    (0xA431, "BodySerialNumber"),
+    (0xA435, "LensSerialNumber"),
+];
+
+// Tags that identify a person or a device outright, whatever their content
+pub const IDENTIFYING_TAGS: &[&str] = &[
+    "GPSPosition", "Artist", "CameraOwnerName", "BodySerialNumber", "LensSerialNumber", "ImageUniqueID",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExifMetadata {
+    // Tag name -> value, text tags only
+    pub fields: BTreeMap<String, String>,
+    pub gps: Option<GpsPosition>,
+}
+
+impl ExifMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.gps.is_none()
+    }
+}
+
+// Returns the TIFF block holding the EXIF data, or None when the image has none
+pub fn find_tiff_block(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        return Some(bytes);
+    }
+    if bytes.starts_with(PNG_SIGNATURE) {
+        return png_chunks(bytes)
+            .find(|(kind, _, _)| kind == b"eXIf")
+            .map(|(_, data, _)| data);
+    }
+    if is_webp(bytes) {
+        return webp_chunks(bytes)
+            .find(|(kind, _, _)| kind == b"EXIF")
+            .map(|(_, data, _)| data.strip_prefix(b"Exif\0\0").unwrap_or(data));
+    }
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    jpeg_segments(bytes)
+        .find(|(marker, data, _)| *marker == 0xE1 && data.starts_with(b"Exif\0\0"))
+        .map(|(_, data, _)| &data[6..])
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    // Offset of the value (inline values point into the entry itself)
+    value_offset: usize,
+}
+
+pub struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let little_endian = match data.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(anyhow!("Invalid TIFF byte order marker")),
+        };
+        Ok(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16> {
+        let bytes: [u8; 2] = self.data
+            .get(offset..offset + 2)
+            .ok_or_else(|| anyhow!("EXIF data truncated"))?
+            .try_into()?;
+        Ok(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let bytes: [u8; 4] = self.data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("EXIF data truncated"))?
+            .try_into()?;
+        Ok(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn first_ifd_offset(&self) -> Result<usize> {
+        Ok(self.u32_at(4)? as usize)
+    }
+
+    fn entries(&self, ifd_offset: usize) -> Result<Vec<IfdEntry>> {
+        let count = self.u16_at(ifd_offset)? as usize;
+        let mut entries = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let entry = ifd_offset + 2 + i * 12;
+            let field_type = self.u16_at(entry + 2)?;
+            let count = self.u32_at(entry + 4)?;
+            let size = type_size(field_type) * count as usize;
+            let value_offset = if size <= 4 { entry + 8 } else { self.u32_at(entry + 8)? as usize };
+
+            entries.push(IfdEntry {
+                tag: self.u16_at(entry)?,
+                field_type,
+                count,
+                value_offset,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    // Offset stored in a pointer tag (Exif or GPS sub-IFD)
+    fn pointer(&self, entry: &IfdEntry) -> Option<usize> {
+        match entry.field_type {
+            TYPE_LONG => self.u32_at(entry.value_offset).ok().map(|o| o as usize),
+            TYPE_SHORT => self.u16_at(entry.value_offset).ok().map(|o| o as usize),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, entry: &IfdEntry) -> Option<String> {
+        if entry.field_type != TYPE_ASCII {
+            return None;
+        }
+        let bytes = self.data.get(entry.value_offset..entry.value_offset + entry.count as usize)?;
+        Some(String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string())
+    }
+
+    fn rationals(&self, entry: &IfdEntry) -> Option<Vec<f64>> {
+        if entry.field_type != TYPE_RATIONAL {
+            return None;
+        }
+        (0..entry.count as usize)
+            .map(|i| {
+                let offset = entry.value_offset + i * 8;
+                let numerator = self.u32_at(offset).ok()? as f64;
+                let denominator = self.u32_at(offset + 4).ok()? as f64;
+                Some(if denominator == 0.0 { 0.0 } else { numerator / denominator })
+            })
+            .collect()
+    }
+
+    pub fn metadata(&self) -> Result<ExifMetadata> {
+        let ifd0 = self.entries(self.first_ifd_offset()?)?;
+        let mut entries: Vec<IfdEntry> = Vec::new();
+        let mut gps = None;
+
+        for entry in ifd0 {
+            match entry.tag {
+                TAG_EXIF_IFD => {
+                    if let Some(offset) = self.pointer(&entry) {
+                        entries.extend(self.entries(offset)?);
+                    }
+                }
+                TAG_GPS_IFD => {
+                    if let Some(offset) = self.pointer(&entry) {
+                        gps = self.gps_position(offset)?;
+                    }
+                }
+                _ => entries.push(entry),
+            }
+        }
+
+        let mut fields = BTreeMap::new();
+        for entry in &entries {
+            let name = match TEXT_TAGS.iter().find(|(tag, _)| *tag == entry.tag) {
+                Some((_, name)) => *name,
+                None => continue,
+            };
+            if let Some(value) = self.ascii(entry).filter(|v| !v.is_empty()) {
+                fields.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(ExifMetadata { fields, gps })
+    }
+
+    fn gps_position(&self, gps_offset: usize) -> Result<Option<GpsPosition>> {
+        let gps = self.entries(gps_offset)?;
+        let find = |tag: u16| gps.iter().find(|e| e.tag == tag);
+
+        let coordinate = |value_tag: u16, ref_tag: u16, negative_ref: &str| -> Option<f64> {
+            let dms = self.rationals(find(value_tag)?)?;
+            let degrees = dms.first().copied().unwrap_or(0.0)
+                + dms.get(1).copied().unwrap_or(0.0) / 60.0
+                + dms.get(2).copied().unwrap_or(0.0) / 3600.0;
+            let reference = find(ref_tag).and_then(|e| self.ascii(e)).unwrap_or_default();
+            Some(if reference.eq_ignore_ascii_case(negative_ref) { -degrees } else { degrees })
+        };
+
+        match (
+            coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S"),
+            coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W"),
+        ) {
+            (Some(latitude), Some(longitude)) => Ok(Some(GpsPosition { latitude, longitude })),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn type_size(field_type: u16) -> usize {
+    match field_type {
+        1 | 2 | 6 | 7 => 1,      // BYTE, ASCII, SBYTE, UNDEFINED
+        3 | 8 => 2,              // SHORT, SSHORT
+        4 | 9 | 11 => 4,         // LONG, SLONG, FLOAT
+        5 | 10 | 12 => 8,        // RATIONAL, SRATIONAL, DOUBLE
+        _ => 1,
+    }
+}
+
+pub fn read_metadata(bytes: &[u8]) -> Result<ExifMetadata> {
+    match find_tiff_block(bytes) {
+        Some(tiff) => TiffReader::new(tiff)?.metadata(),
+        None => Ok(ExifMetadata::default()),
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// Metadata-only JPEG segments: APP1 (Exif/XMP), APP13 (IPTC), COM
+const JPEG_METADATA_MARKERS: &[u8] = &[0xE1, 0xED, 0xFE];
+// PNG chunks that carry text or EXIF
+const PNG_METADATA_CHUNKS: &[&[u8; 4]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+// WebP chunks that carry EXIF or XMP, and the VP8X flag bits announcing them
+const WEBP_METADATA_CHUNKS: &[&[u8; 4]] = &[b"EXIF", b"XMP "];
+const VP8X_METADATA_FLAGS: u8 = 0x08 | 0x04;
+
+// Copy of the image with EXIF, XMP, IPTC and comments removed; pixel data is untouched.
+// Returns the stripped bytes and the names of the removed blocks.
+pub fn strip_metadata(bytes: &[u8]) -> Result<(Vec<u8>, Vec<String>)> {
+    let mut removed = Vec::new();
+
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        let mut out = bytes[..2].to_vec();
+        let mut image_data_start = 2;
+        for (marker, _, range) in jpeg_segments(bytes) {
+            image_data_start = range.end;
+            if JPEG_METADATA_MARKERS.contains(&marker) {
+                removed.push(format!("JPEG APP/COM segment 0x{:02X}", marker));
+            } else {
+                out.extend_from_slice(&bytes[range]);
+            }
+        }
+        // Anything but start-of-scan (or end of image) here means the segment walk gave up early,
+        // and copying the rest verbatim could keep metadata segments further on
+        let rest = &bytes[image_data_start..];
+        let fill = rest.iter().take_while(|b| **b == 0xFF).count();
+        if fill == 0 || !matches!(rest.get(fill), Some(0xDA) | Some(0xD9)) {
+            return Err(anyhow!("Malformed JPEG: no start-of-scan after the header segments"));
+        }
+        // Start-of-scan onwards is image data
+        out.extend_from_slice(&rest[fill - 1..]);
+        return Ok((out, removed));
+    }
+
+    if bytes.starts_with(PNG_SIGNATURE) {
+        let mut out = PNG_SIGNATURE.to_vec();
+        for (kind, _, range) in png_chunks(bytes) {
+            if PNG_METADATA_CHUNKS.iter().any(|k| **k == kind) {
+                removed.push(format!("PNG {} chunk", String::from_utf8_lossy(&kind)));
+            } else {
+                out.extend_from_slice(&bytes[range]);
+            }
+        }
+        return Ok((out, removed));
+    }
+
+    if is_webp(bytes) {
+        let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+        for (kind, _, range) in webp_chunks(bytes) {
+            if WEBP_METADATA_CHUNKS.iter().any(|k| **k == kind) {
+                removed.push(format!("WebP {} chunk", String::from_utf8_lossy(&kind).trim_end()));
+                continue;
+            }
+            let chunk_start = out.len();
+            out.extend_from_slice(&bytes[range]);
+            // The extended header must not announce chunks that are gone
+            if &kind == b"VP8X" && out.len() > chunk_start + 8 {
+                out[chunk_start + 8] &= !VP8X_METADATA_FLAGS;
+            }
+        }
+        let riff_size = (out.len() - 8) as u32;
+        out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        return Ok((out, removed));
+    }
+
+    Err(anyhow!("Metadata stripping is only supported for JPEG, PNG and WebP images"))
+}
+
+// (marker, payload, whole segment range) up to the start-of-scan marker. Markers may be
+// preceded by any number of 0xFF fill bytes, which are not part of the range.
+fn jpeg_segments(bytes: &[u8]) -> impl Iterator<Item = (u8, &[u8], std::ops::Range<usize>)> {
+    let mut pos = 2;
+    std::iter::from_fn(move || {
+        while bytes.get(pos) == Some(&0xFF) && bytes.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if pos + 4 > bytes.len() || bytes[pos] != 0xFF {
+            return None;
+        }
+        let marker = bytes[pos + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        let payload = bytes.get(pos + 4..end)?;
+        let range = pos..end;
+        pos = end;
+        Some((marker, payload, range))
+    })
+}
+
+// (type, data, whole chunk range including length and CRC)
+fn png_chunks(bytes: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8], std::ops::Range<usize>)> {
+    let mut pos = PNG_SIGNATURE.len();
+    std::iter::from_fn(move || {
+        let length = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = bytes.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+        let end = pos + 12 + length;
+        if end > bytes.len() {
+            return None;
+        }
+        let range = pos..end;
+        pos = end;
+        Some((kind, data, range))
+    })
+}
+
+fn is_webp(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP"
+}
+
+// (fourcc, data, whole chunk range); chunks are padded to even sizes
+fn webp_chunks(bytes: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8], std::ops::Range<usize>)> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let kind: [u8; 4] = bytes.get(pos..pos + 4)?.try_into().ok()?;
+        let length = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+        let end = (pos + 8 + length + (length & 1)).min(bytes.len());
+        let range = pos..end;
+        pos = end;
+        Some((kind, data, range))
+    })
+}
@@ -1,11 +1,72 @@
 use anyhow::{Result, anyhow};
+use calamine::Reader as _;
+use quick_xml::events::Event;
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard};
 use tokio::fs;
+use tokio::io::AsyncReadExt;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
+use crate::exif::{self, ExifMetadata};
+
+const IMAGE_FORMATS: &[&str] = &["jpg", "jpeg", "png", "tif", "tiff", "webp", "bmp"];
+
+// Text recovered from an image plus the metadata found in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageExtraction {
+    pub text: String,
+    pub metadata: ExifMetadata,
+    // False when no OCR engine is installed, so `text` is empty for lack of trying
+    pub ocr_available: bool,
+}
+
+// Field separator for CSV files; Auto picks whichever of the three splits the header line most
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvDelimiter {
+    #[default]
+    Auto,
+    Comma,
+    Semicolon,
+    Tab,
+}
+
+// User-adjustable extraction settings, saved to extraction_config.json
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtractionConfig {
+    pub csv_delimiter: CsvDelimiter,
+    // Speaker notes are appended under each slide's text when enabled
+    pub include_slide_notes: bool,
+    // Per-format overrides of the global size limit, in bytes, e.g. {"pdf": 104857600}
+    pub format_size_limits: HashMap<String, usize>,
+}
+
+impl ExtractionConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::data_dir::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some((format, _)) = self.format_size_limits.iter().find(|(_, bytes)| **bytes == 0) {
+            return Err(anyhow!("Size limit for .{} files must be greater than zero", format));
+        }
+        Ok(())
+    }
+}
+
 pub struct FileProcessor {
     max_file_size: usize,
     supported_formats: Vec<String>,
+    // Behind a lock so the shared processor picks up settings changes without a restart
+    config: RwLock<ExtractionConfig>,
 }
 
 impl FileProcessor {
@@ -27,22 +88,67 @@ impl FileProcessor {
                 "json".to_string(),
                 "xml".to_string(),
                 "html".to_string(),
+                "jpg".to_string(),
+                "jpeg".to_string(),
+                "png".to_string(),
+                "tif".to_string(),
+                "tiff".to_string(),
+                "webp".to_string(),
+                "bmp".to_string(),
             ],
+            config: RwLock::new(ExtractionConfig::default()),
         }
     }
 
-    pub async fn process_file(&self, file_path: &str, file_type: &str) -> Result<String> {
+    fn settings(&self) -> RwLockReadGuard<'_, ExtractionConfig> {
+        self.config.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn config(&self) -> ExtractionConfig {
+        self.settings().clone()
+    }
+
+    // Format keys are stored canonically, so a limit set for "jpeg" also covers "jpg"
+    pub fn apply_config(&self, config: &ExtractionConfig) {
+        let mut applied = config.clone();
+        applied.format_size_limits = config
+            .format_size_limits
+            .iter()
+            .map(|(format, bytes)| (canonical_format(format), *bytes))
+            .collect();
+        *self.config.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = applied;
+    }
+
+    // Limit for one format (e.g. "pdf" or ".csv"), shared with its aliases ("jpeg" and "jpg");
+    // formats without one use the global limit
+    pub fn max_file_size(&self, format: &str) -> usize {
+        self.settings()
+            .format_size_limits
+            .get(&canonical_format(format))
+            .copied()
+            .unwrap_or(self.max_file_size)
+    }
+
+    pub async fn check_file_size(&self, path: &Path, format: &str) -> Result<()> {
+        let limit = self.max_file_size(format);
+        let metadata = fs::metadata(path).await?;
+        if metadata.len() as usize > limit {
+            return Err(anyhow!(
+                "File size exceeds maximum limit of {:.1}MB for .{} files",
+                limit as f64 / (1024.0 * 1024.0),
+                format.trim_start_matches('.')
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn process_file(&self, file_path: &str, _file_type: &str) -> Result<String> {
         let path = Path::new(file_path);
 
         if !path.exists() {
             return Err(anyhow!("File does not exist: {}", file_path));
         }
 
-        let metadata = fs::metadata(path).await?;
-        if metadata.len() as usize > self.max_file_size {
-            return Err(anyhow!("File size exceeds maximum limit of 50MB"));
-        }
-
         let extension = path.extension()
             .and_then(|ext| ext.to_str())
             .ok_or_else(|| anyhow!("Could not determine file extension"))?;
@@ -51,57 +157,143 @@ impl FileProcessor {
             return Err(anyhow!("Unsupported file format: {}", extension));
         }
 
-        match extension.to_lowercase().as_str() {
+        let format = detect_format(path, &extension.to_lowercase()).await?;
+        if !format.eq_ignore_ascii_case(extension) {
+            eprintln!("{} has a .{} extension but contains {} data; parsing it as {}", file_path, extension, format, format);
+        }
+
+        // The limit of the format actually parsed, so renaming a file doesn't change its cap
+        self.check_file_size(path, &format).await?;
+
+        match format.as_str() {
             "txt" | "md" => self.process_text_file(file_path).await,
             "pdf" => self.process_pdf_file(file_path).await,
-            "docx" | "doc" => self.process_word_file(file_path).await,
-            "xlsx" | "xls" => self.process_excel_file(file_path).await,
+            "docx" => self.process_word_file(file_path).await,
+            "doc" => Err(anyhow!("Unsupported legacy format: .doc (Word 97-2003). Save the document as .docx and try again")),
+            "xlsx" | "xls" => self.process_excel_file(file_path, &format).await,
             "csv" => self.process_csv_file(file_path).await,
-            "pptx" | "ppt" => self.process_powerpoint_file(file_path).await,
+            "rtf" => self.process_rtf_file(file_path).await,
+            "pptx" => self.process_powerpoint_file(file_path).await,
+            "ppt" => Err(anyhow!("Unsupported legacy format: .ppt (PowerPoint 97-2003). Save the presentation as .pptx and try again")),
             "json" => self.process_json_file(file_path).await,
-            "xml" | "html" => self.process_markup_file(file_path).await,
+            "html" => self.process_html_file(file_path).await,
+            "xml" => self.process_markup_file(file_path).await,
+            "jpg" | "jpeg" | "png" | "tif" | "tiff" | "webp" | "bmp" => self.process_image_file(file_path).await,
             _ => Err(anyhow!("Unsupported file type: {}", extension)),
         }
     }
 
     async fn process_text_file(&self, file_path: &str) -> Result<String> {
-        let content = fs::read_to_string(file_path).await?;
+        let content = read_text(file_path).await?;
         Ok(content)
     }
 
     async fn process_pdf_file(&self, file_path: &str) -> Result<String> {
-        Ok(format!("PDF content from: {} (PDF parsing to be implemented)", file_path))
+        let bytes = fs::read(file_path).await?;
+        // The parser can panic on malformed files; on the blocking pool that becomes an error instead
+        tokio::task::spawn_blocking(move || extract_pdf_text(&bytes))
+            .await
+            .map_err(|_| anyhow!("PDF parser failed on {}", file_path))?
     }
 
     async fn process_word_file(&self, file_path: &str) -> Result<String> {
-        Ok(format!("Word document content from: {} (DOCX parsing to be implemented)", file_path))
+        let bytes = fs::read(file_path).await?;
+        tokio::task::spawn_blocking(move || extract_docx_text(&bytes)).await?
     }
 
-    async fn process_excel_file(&self, file_path: &str) -> Result<String> {
-        Ok(format!("Excel spreadsheet content from: {} (Excel parsing to be implemented)", file_path))
+    async fn process_excel_file(&self, file_path: &str, format: &str) -> Result<String> {
+        let bytes = fs::read(file_path).await?;
+        let max_chars = self.max_file_size(format);
+        tokio::task::spawn_blocking(move || extract_spreadsheet_text(bytes, max_chars)).await?
     }
 
     async fn process_csv_file(&self, file_path: &str) -> Result<String> {
-        let content = fs::read_to_string(file_path).await?;
-        Ok(content)
+        let bytes = read_text(file_path).await?.into_bytes();
+        let delimiter = self.settings().csv_delimiter;
+        let max_chars = self.max_file_size("csv");
+        tokio::task::spawn_blocking(move || extract_csv_text(&bytes, delimiter, max_chars)).await?
+    }
+
+    async fn process_rtf_file(&self, file_path: &str) -> Result<String> {
+        let bytes = fs::read(file_path).await?;
+        tokio::task::spawn_blocking(move || extract_rtf_text(&bytes)).await?
     }
 
     async fn process_powerpoint_file(&self, file_path: &str) -> Result<String> {
-        Ok(format!("PowerPoint presentation content from: {} (PPTX parsing to be implemented)", file_path))
+        let bytes = fs::read(file_path).await?;
+        let include_notes = self.settings().include_slide_notes;
+        let max_chars = self.max_file_size("pptx");
+        tokio::task::spawn_blocking(move || extract_pptx_text(&bytes, include_notes, max_chars)).await?
     }
 
     async fn process_json_file(&self, file_path: &str) -> Result<String> {
-        let content = fs::read_to_string(file_path).await?;
+        let content = read_text(file_path).await?;
         let json: JsonValue = serde_json::from_str(&content)?;
         Ok(serde_json::to_string_pretty(&json)?)
     }
 
+    async fn process_html_file(&self, file_path: &str) -> Result<String> {
+        let content = read_text(file_path).await?;
+        tokio::task::spawn_blocking(move || extract_html_text(&content))
+            .await
+            .map_err(|_| anyhow!("HTML extraction failed on {}", file_path))
+    }
+
     async fn process_markup_file(&self, file_path: &str) -> Result<String> {
-        let content = fs::read_to_string(file_path).await?;
+        let content = read_text(file_path).await?;
         let text = self.strip_html_tags(&content);
         Ok(text)
     }
 
+    // OCR text followed by the metadata as "Tag: value" lines, so the PII pipeline redacts
+    // GPS positions, owner names and serials along with the document text
+    async fn process_image_file(&self, file_path: &str) -> Result<String> {
+        let extraction = self.process_image(file_path).await?;
+        let mut text = extraction.text;
+
+        if !extraction.metadata.is_empty() {
+            text.push_str("\n\nImage metadata:\n");
+            if let Some(gps) = extraction.metadata.gps {
+                text.push_str(&format!("GPS location: {:.6}, {:.6}\n", gps.latitude, gps.longitude));
+            }
+            for (tag, value) in &extraction.metadata.fields {
+                text.push_str(&format!("{}: {}\n", tag, value));
+            }
+        }
+
+        Ok(text)
+    }
+
+    pub async fn process_image(&self, file_path: &str) -> Result<ImageExtraction> {
+        let bytes = fs::read(file_path).await?;
+        let metadata = exif::read_metadata(&bytes).unwrap_or_else(|e| {
+            eprintln!("Failed to read EXIF from {}: {}", file_path, e);
+            ExifMetadata::default()
+        });
+
+        let (text, ocr_available) = match run_ocr(file_path).await {
+            Ok(text) => (text, true),
+            Err(e) => {
+                eprintln!("OCR unavailable for {}: {}", file_path, e);
+                (String::new(), false)
+            }
+        };
+
+        Ok(ImageExtraction { text, metadata, ocr_available })
+    }
+
+    // Writes a copy of the image without EXIF/XMP/IPTC blocks; returns the removed block names
+    pub async fn write_stripped_image(&self, file_path: &str, output_path: &str) -> Result<Vec<String>> {
+        let bytes = fs::read(file_path).await?;
+        let (stripped, removed) = exif::strip_metadata(&bytes)?;
+        fs::write(output_path, stripped).await?;
+        Ok(removed)
+    }
+
+    pub fn is_image(&self, file_extension: &str) -> bool {
+        IMAGE_FORMATS.contains(&file_extension.to_lowercase().as_str())
+    }
+
     fn strip_html_tags(&self, html: &str) -> String {
         let tag_regex = regex::Regex::new(r"<[^>]+>").unwrap();
         let script_regex = regex::Regex::new(r"(?s)<script[^>]*>.*?</script>").unwrap();
@@ -123,8 +315,1032 @@ impl FileProcessor {
     pub fn is_supported(&self, file_extension: &str) -> bool {
         self.supported_formats.contains(&file_extension.to_lowercase())
     }
+}
+
+// Each page starts with a "[Page N]" line, which the RAG engine reads back as a page marker
+fn extract_pdf_text(bytes: &[u8]) -> Result<String> {
+    let document = lopdf::Document::load_mem(bytes).map_err(|e| anyhow!("Could not read PDF: {}", e))?;
+    if document.is_encrypted() {
+        return Err(anyhow!("PDF is encrypted; remove the password protection and try again"));
+    }
+
+    let mut pages = Vec::new();
+    for page_number in document.get_pages().keys() {
+        match document.extract_text(&[*page_number]) {
+            Ok(text) => pages.push((*page_number, text.trim().to_string())),
+            Err(e) => {
+                eprintln!("Skipping unreadable PDF page {}: {}", page_number, e);
+                pages.push((*page_number, String::new()));
+            }
+        }
+    }
+
+    if pages.iter().all(|(_, text)| text.is_empty()) {
+        return Err(anyhow!("PDF has no extractable text; it may be a scanned document"));
+    }
+
+    Ok(pages
+        .iter()
+        .map(|(page_number, text)| format!("[Page {}]\n{}", page_number, text))
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
+
+// Formats with no signature of their own; only the extension can tell them apart
+const TEXT_FORMATS: &[&str] = &["txt", "md", "csv", "json", "xml", "html"];
+
+// Format named by the file's leading bytes, where it has a signature. Zip and OLE containers
+// need a closer look to tell Office formats apart.
+// Text-format extensions only trust a PDF header at the very start, since a text file may quote
+// "%PDF-" anywhere
+fn sniff_magic(head: &[u8], file_len: u64, text_extension: bool) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"PK\x03\x04", "zip"),
+        (b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1", "ole"),
+        (b"{\\rtf", "rtf"),
+        (b"\xFF\xD8\xFF", "jpg"),
+        (b"\x89PNG\r\n\x1A\n", "png"),
+        (b"II*\x00", "tiff"),
+        (b"MM\x00*", "tiff"),
+    ];
+    if let Some((_, format)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(format);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+    if is_bmp_header(head, file_len) {
+        return Some("bmp");
+    }
+    // PDF readers accept junk before the header within the first kilobyte
+    let pdf = if text_extension {
+        head.starts_with(b"%PDF-")
+    } else {
+        head.windows(5).any(|window| window == b"%PDF-")
+    };
+    pdf.then_some("pdf")
+}
+
+// "BM" alone starts plenty of text ("BMW,..."), so the header's file size and reserved zero
+// bytes have to match as well
+fn is_bmp_header(head: &[u8], file_len: u64) -> bool {
+    if head.len() < 14 || !head.starts_with(b"BM") {
+        return false;
+    }
+    let declared_len = u32::from_le_bytes([head[2], head[3], head[4], head[5]]);
+    declared_len as u64 == file_len && head[6..10] == [0, 0, 0, 0]
+}
+
+// Text files as UTF-8, or UTF-16 when they start with a byte-order mark (as Notepad saves "Unicode")
+fn decode_text(bytes: Vec<u8>) -> Result<String> {
+    let utf16 = |bytes: &[u8], unit: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes.as_slice() {
+        [0xFF, 0xFE, rest @ ..] => Ok(utf16(rest, u16::from_le_bytes)),
+        [0xFE, 0xFF, rest @ ..] => Ok(utf16(rest, u16::from_be_bytes)),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => Ok(String::from_utf8(rest.to_vec())?),
+        _ => Ok(String::from_utf8(bytes)?),
+    }
+}
+
+fn has_utf16_bom(head: &[u8]) -> bool {
+    head.starts_with(b"\xFF\xFE") || head.starts_with(b"\xFE\xFF")
+}
+
+async fn read_text(file_path: &str) -> Result<String> {
+    decode_text(fs::read(file_path).await?)
+}
+
+// Which Office Open XML document a zip archive holds, by its main part
+fn sniff_zip_format(path: &Path) -> Option<&'static str> {
+    let archive = zip::ZipArchive::new(std::fs::File::open(path).ok()?).ok()?;
+    let names: Vec<&str> = archive.file_names().collect();
+    [("word/document.xml", "docx"), ("xl/workbook.xml", "xlsx"), ("ppt/presentation.xml", "pptx")]
+        .iter()
+        .find(|(part, _)| names.contains(part))
+        .map(|(_, format)| *format)
+}
+
+// Lowercase, without a leading dot, with aliases folded into one name
+fn canonical_format(format: &str) -> String {
+    match format.trim_start_matches('.').to_lowercase().as_str() {
+        "jpeg" => "jpg".to_string(),
+        "tif" => "tiff".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn same_format(a: &str, b: &str) -> bool {
+    canonical_format(a) == canonical_format(b)
+}
+
+// The format to parse the file as. The extension is a hint; when the content has a signature
+// for a different format, the content wins, so a PDF named .txt still goes to the PDF parser.
+async fn detect_format(path: &Path, extension: &str) -> Result<String> {
+    let mut head = Vec::with_capacity(1024);
+    let mut file = fs::File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    (&mut file).take(1024).read_to_end(&mut head).await?;
+
+    let text_extension = TEXT_FORMATS.contains(&extension);
+    let sniffed = match sniff_magic(&head, file_len, text_extension) {
+        Some("zip") => {
+            let archive_path = path.to_path_buf();
+            let format = tokio::task::spawn_blocking(move || sniff_zip_format(&archive_path)).await?;
+            Some(format.ok_or_else(|| anyhow!("{} is a zip archive, not a supported document", path_name(path))))
+        }
+        // Legacy Word, Excel and PowerPoint share one container; trust the extension among them
+        Some("ole") if matches!(extension, "doc" | "xls" | "ppt") => None,
+        Some("ole") => Some(Err(anyhow!("{} is a legacy Office file, not a .{} file", path_name(path), extension))),
+        Some(format) => Some(Ok(format)),
+        None => None,
+    };
+
+    match sniffed {
+        Some(format) => {
+            let format = format?;
+            Ok(if same_format(format, extension) { extension.to_string() } else { format.to_string() })
+        }
+        None if text_extension => {
+            if head.contains(&0) && !has_utf16_bom(&head) {
+                return Err(anyhow!("{} contains binary data, not .{} text", path_name(path), extension));
+            }
+            Ok(extension.to_string())
+        }
+        // The extension names a format with a signature, and the content doesn't carry it
+        None if !matches!(extension, "doc" | "xls" | "ppt" | "rtf") => {
+            Err(anyhow!("{} does not contain valid .{} data", path_name(path), extension))
+        }
+        None => Ok(extension.to_string()),
+    }
+}
+
+fn path_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| path.display().to_string())
+}
+
+// Office Open XML files are zip archives of XML parts
+fn read_zip_text(bytes: &[u8], entry: &str) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+        .map_err(|e| anyhow!("Not a valid Office document: {}", e))?;
+    let mut file = archive.by_name(entry)
+        .map_err(|e| anyhow!("Office document is missing {}: {}", entry, e))?;
+    let mut xml = String::new();
+    file.read_to_string(&mut xml)?;
+    Ok(xml)
+}
+
+fn zip_entry_text(archive: &mut zip::ZipArchive<std::io::Cursor<&[u8]>>, entry: &str) -> Option<String> {
+    let mut file = archive.by_name(entry).ok()?;
+    let mut xml = String::new();
+    file.read_to_string(&mut xml).ok()?;
+    Some(xml)
+}
+
+// Relationship id -> target path from a .rels part, resolved against `base_dir` of the part
+fn zip_relationships(xml: &str, base_dir: &str) -> Result<Vec<(String, String, String)>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut relationships = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"Relationship" => {
+                let attribute = |name: &[u8]| {
+                    e.try_get_attribute(name).ok().flatten()
+                        .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()))
+                        .unwrap_or_default()
+                };
+                let target = attribute(b"Target");
+                relationships.push((attribute(b"Id"), attribute(b"Type"), resolve_zip_path(base_dir, &target)));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(relationships)
+}
+
+// Relationship targets are relative to the part's folder and may climb out with `..`
+fn resolve_zip_path(base_dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut parts: Vec<&str> = base_dir.split('/').filter(|p| !p.is_empty()).collect();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                parts.pop();
+            }
+            "." | "" => {}
+            segment => parts.push(segment),
+        }
+    }
+    parts.join("/")
+}
+
+// Slide parts in presentation order: the order of p:sldIdLst, or the slide number when the
+// presentation part can't be read
+fn pptx_slide_paths(archive: &mut zip::ZipArchive<std::io::Cursor<&[u8]>>) -> Result<Vec<String>> {
+    let ordered = (|| {
+        let relationships = zip_relationships(&zip_entry_text(archive, "ppt/_rels/presentation.xml.rels")?, "ppt").ok()?;
+        let presentation = zip_entry_text(archive, "ppt/presentation.xml")?;
+        let mut reader = quick_xml::Reader::from_str(&presentation);
+        let mut paths = Vec::new();
+        loop {
+            match reader.read_event().ok()? {
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"p:sldId" => {
+                    let id = e.try_get_attribute("r:id").ok().flatten()?.unescape_value().ok()?.to_string();
+                    let (_, _, target) = relationships.iter().find(|(rel_id, _, _)| *rel_id == id)?;
+                    paths.push(target.clone());
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Some(paths).filter(|paths| !paths.is_empty())
+    })();
+    if let Some(paths) = ordered {
+        return Ok(paths);
+    }
+
+    let mut numbered: Vec<(u32, String)> = archive
+        .file_names()
+        .filter_map(|name| {
+            let number = name.strip_prefix("ppt/slides/slide")?.strip_suffix(".xml")?.parse().ok()?;
+            Some((number, name.to_string()))
+        })
+        .collect();
+    if numbered.is_empty() {
+        return Err(anyhow!("Presentation has no slides"));
+    }
+    numbered.sort();
+    Ok(numbered.into_iter().map(|(_, name)| name).collect())
+}
+
+// Text runs (a:t) of a slide or notes part, one paragraph per line
+fn drawingml_text(xml: &str) -> Result<String> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut text = String::new();
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"a:t" => in_text = true,
+            Event::Empty(e) if e.name().as_ref() == b"a:br" => text.push('\n'),
+            Event::Text(e) if in_text => text.push_str(&e.unescape()?),
+            Event::End(e) => match e.name().as_ref() {
+                b"a:t" => in_text = false,
+                b"a:p" if !text.is_empty() && !text.ends_with('\n') => text.push('\n'),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(text.trim_end().to_string())
+}
+
+// Each slide under a "[Slide N]" line, with its speaker notes after a "Notes:" line when
+// `include_notes`. Output stops at `max_chars`, since the archive can expand far beyond the file.
+fn extract_pptx_text(bytes: &[u8], include_notes: bool, max_chars: usize) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+        .map_err(|e| anyhow!("Not a valid PowerPoint presentation: {}", e))?;
+
+    let mut sections = Vec::new();
+    let mut total = 0;
+    for (index, path) in pptx_slide_paths(&mut archive)?.iter().enumerate() {
+        // One bad slide shouldn't cost the rest of the deck
+        let text = match zip_entry_text(&mut archive, path).map(|xml| drawingml_text(&xml)) {
+            Some(Ok(text)) => text,
+            Some(Err(e)) => {
+                eprintln!("Skipping malformed slide {}: {}", path, e);
+                continue;
+            }
+            None => {
+                eprintln!("Skipping unreadable slide {}", path);
+                continue;
+            }
+        };
+
+        let mut section = format!("[Slide {}]", index + 1);
+        if !text.is_empty() {
+            section.push('\n');
+            section.push_str(&text);
+        }
+
+        if include_notes {
+            let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+            let rels_path = format!("{}/_rels/{}.rels", dir, file);
+            let notes = zip_entry_text(&mut archive, &rels_path)
+                .and_then(|rels| zip_relationships(&rels, dir).ok())
+                .and_then(|rels| rels.into_iter().find(|(_, kind, _)| kind.ends_with("/notesSlide")))
+                .and_then(|(_, _, notes_path)| zip_entry_text(&mut archive, &notes_path))
+                .and_then(|notes_xml| drawingml_text(&notes_xml).ok())
+                .filter(|notes| !notes.is_empty());
+            if let Some(notes) = notes {
+                section.push_str("\nNotes:\n");
+                section.push_str(&notes);
+            }
+        }
+
+        if total + section.len() > max_chars {
+            eprintln!("Presentation text truncated at {} characters", max_chars);
+            break;
+        }
+        total += section.len() + 2;
+        sections.push(section);
+    }
+
+    Ok(sections.join("\n\n"))
+}
+
+// Paragraph text in document order, one paragraph per line. Table cells are tab-separated
+// with one row per line; formatting runs are ignored.
+fn extract_docx_text(bytes: &[u8]) -> Result<String> {
+    let xml = read_zip_text(bytes, "word/document.xml")?;
+    let mut reader = quick_xml::Reader::from_str(&xml);
+
+    let mut text = String::new();
+    let mut in_text = false;
+    let mut cell_depth = 0usize;
+    // w:tab is also a tab-stop definition inside w:pPr/w:tabs; only a tab within a run is text
+    let mut run_depth = 0usize;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"w:t" => in_text = true,
+                b"w:tc" => cell_depth += 1,
+                b"w:r" => run_depth += 1,
+                _ => {}
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"w:tab" if run_depth > 0 => text.push('\t'),
+                b"w:br" | b"w:cr" => text.push(if cell_depth > 0 { ' ' } else { '\n' }),
+                _ => {}
+            },
+            Event::Text(e) if in_text => text.push_str(&e.unescape()?),
+            Event::End(e) => match e.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:r" => run_depth = run_depth.saturating_sub(1),
+                b"w:p" if cell_depth == 0 => text.push('\n'),
+                // Paragraphs inside a cell stay on the row's line
+                b"w:p" if !text.ends_with(['\t', '\n', ' ']) => text.push(' '),
+                b"w:tc" => {
+                    cell_depth = cell_depth.saturating_sub(1);
+                    let trimmed = text.trim_end_matches(' ').len();
+                    text.truncate(trimmed);
+                    text.push('\t');
+                }
+                b"w:tr" => {
+                    if text.ends_with('\t') {
+                        text.pop();
+                    }
+                    text.push('\n');
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(text.trim_end().to_string())
+}
+
+// One section per sheet: a "Sheet: <name>" line followed by its rows as CSV. Shared strings
+// can expand far beyond the file size, so output stops at `max_chars`.
+fn extract_spreadsheet_text(bytes: Vec<u8>, max_chars: usize) -> Result<String> {
+    let mut workbook = calamine::open_workbook_auto_from_rs(std::io::Cursor::new(bytes))
+        .map_err(|e| anyhow!("Could not read spreadsheet: {}", e))?;
+
+    let mut sections = Vec::new();
+    let mut total = 0;
+
+    'sheets: for name in workbook.sheet_names() {
+        let range = match workbook.worksheet_range(&name) {
+            Ok(range) => range,
+            Err(e) => {
+                eprintln!("Skipping unreadable sheet {}: {}", name, e);
+                continue;
+            }
+        };
+
+        let mut section = format!("Sheet: {}", name);
+        for row in range.rows() {
+            let cells: Vec<String> = row.iter().map(|cell| csv_field(&cell.to_string())).collect();
+            // Empty cells keep their column position; trailing and fully empty rows are dropped
+            let used = cells.iter().rposition(|cell| !cell.is_empty()).map_or(0, |i| i + 1);
+            if used == 0 {
+                continue;
+            }
+
+            let line = cells[..used].join(",");
+            if total + section.len() + line.len() + 1 > max_chars {
+                eprintln!("Spreadsheet text truncated at {} characters", max_chars);
+                sections.push(section);
+                break 'sheets;
+            }
+            section.push('\n');
+            section.push_str(&line);
+        }
+        total += section.len() + 2;
+        sections.push(section);
+    }
+
+    if sections.is_empty() {
+        return Err(anyhow!("Spreadsheet has no readable sheets"));
+    }
+
+    Ok(sections.join("\n\n"))
+}
+
+// Each data row as one "Header: value; Header: value" line, so retrieval and the model see
+// labelled fields instead of bare commas. Empty values are left out; newlines inside quoted
+// fields become spaces to keep one row per line.
+fn extract_csv_text(bytes: &[u8], delimiter: CsvDelimiter, max_chars: usize) -> Result<String> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let delimiter = match delimiter {
+        CsvDelimiter::Auto => sniff_csv_delimiter(bytes),
+        CsvDelimiter::Comma => b',',
+        CsvDelimiter::Semicolon => b';',
+        CsvDelimiter::Tab => b'\t',
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(bytes);
+    let field = |value: &[u8]| String::from_utf8_lossy(value).split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut records = reader.byte_records();
+    let headers: Vec<String> = match records.next() {
+        Some(record) => record.map_err(|e| anyhow!("Could not parse CSV: {}", e))?.iter().map(field).collect(),
+        None => return Ok(String::new()),
+    };
+
+    let mut lines = Vec::new();
+    let mut total = 0;
+    for record in records {
+        let record = record.map_err(|e| anyhow!("Could not parse CSV: {}", e))?;
+        let line = record
+            .iter()
+            .map(field)
+            .enumerate()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(i, value)| match headers.get(i).filter(|h| !h.is_empty()) {
+                Some(header) => format!("{}: {}", header, value),
+                None => format!("Column {}: {}", i + 1, value),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        if line.is_empty() {
+            continue;
+        }
+        if total + line.len() + 1 > max_chars {
+            eprintln!("CSV text truncated at {} characters", max_chars);
+            break;
+        }
+        total += line.len() + 1;
+        lines.push(line);
+    }
+
+    // A header-only file still says what it would contain
+    if lines.is_empty() {
+        return Ok(headers.join("; "));
+    }
+    Ok(lines.join("\n"))
+}
+
+// Counts candidates on the first line outside quotes
+fn sniff_csv_delimiter(bytes: &[u8]) -> u8 {
+    let mut counts = [(b',', 0usize), (b';', 0), (b'\t', 0)];
+    let mut quoted = false;
+    for &byte in bytes.iter().take_while(|&&b| b != b'\n') {
+        if byte == b'"' {
+            quoted = !quoted;
+        } else if !quoted {
+            if let Some(count) = counts.iter_mut().find(|(candidate, _)| *candidate == byte) {
+                count.1 += 1;
+            }
+        }
+    }
+    // Ties (including a single column) go to the comma, listed first
+    counts.iter().fold((b',', 0), |best, &candidate| if candidate.1 > best.1 { candidate } else { best }).0
+}
+
+// Destinations holding fonts, styles, metadata, embedded objects and field instructions rather
+// than document text; groups starting with `\*` are skipped as well
+const RTF_SKIPPED_DESTINATIONS: &[&str] = &[
+    "fonttbl", "colortbl", "stylesheet", "info", "pict", "object", "objdata", "fldinst", "themedata",
+    "colorschememapping", "datastore", "xmlnstbl", "listtable", "listoverridetable", "rsidtbl",
+    "latentstyles", "filetbl", "revtbl", "generator", "header", "headerl", "headerr", "headerf",
+    "footer", "footerl", "footerr", "footerf", "footnote", "bkmkstart", "bkmkend", "pgdsctbl",
+];
+
+// Windows-1252 characters in 0x80..=0x9F, where it differs from Latin-1
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+fn cp1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => CP1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+// Plain text of an RTF document: control words are dropped except the ones that stand for text
+// (paragraph and line breaks, tabs, typographic quotes, `\uN` characters), `\'hh` escapes are
+// decoded as Windows-1252, and non-text destinations are skipped entirely.
+fn extract_rtf_text(bytes: &[u8]) -> Result<String> {
+    if !bytes.starts_with(b"{\\rtf") {
+        return Err(anyhow!("Not an RTF document"));
+    }
+
+    struct Group {
+        skip: bool,
+        // Fallback characters that follow each \uN (set by \ucN)
+        unicode_skip: usize,
+    }
+
+    let mut text = String::new();
+    let mut stack = vec![Group { skip: false, unicode_skip: 1 }];
+    // Fallback characters still to drop after a \uN
+    let mut pending_skip = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let (skip, unicode_skip) = stack.last().map_or((false, 1), |g| (g.skip, g.unicode_skip));
+        match byte {
+            b'{' => {
+                stack.push(Group { skip, unicode_skip });
+                // `{\*\dest ...}` marks a destination readers may ignore
+                if bytes[i + 1..].starts_with(b"\\*") {
+                    if let Some(group) = stack.last_mut() {
+                        group.skip = true;
+                    }
+                }
+                pending_skip = 0;
+                i += 1;
+            }
+            b'}' => {
+                stack.pop();
+                pending_skip = 0;
+                i += 1;
+            }
+            b'\\' => {
+                let Some(&next) = bytes.get(i + 1) else {
+                    break;
+                };
+                if next.is_ascii_alphabetic() {
+                    let start = i + 1;
+                    let mut end = start;
+                    while end < bytes.len() && bytes[end].is_ascii_alphabetic() {
+                        end += 1;
+                    }
+                    let word = std::str::from_utf8(&bytes[start..end]).unwrap_or_default();
+                    let param_start = end;
+                    if end < bytes.len() && bytes[end] == b'-' {
+                        end += 1;
+                    }
+                    while end < bytes.len() && bytes[end].is_ascii_digit() {
+                        end += 1;
+                    }
+                    let param: Option<i32> = std::str::from_utf8(&bytes[param_start..end]).ok().and_then(|p| p.parse().ok());
+                    // A single space after a control word is its delimiter, not text
+                    if end < bytes.len() && bytes[end] == b' ' {
+                        end += 1;
+                    }
+                    i = end;
+
+                    // `\binN` is followed by N raw bytes that may contain anything, braces included
+                    if word == "bin" {
+                        i = i.saturating_add(param.unwrap_or(0).max(0) as usize).min(bytes.len());
+                        continue;
+                    }
+                    if RTF_SKIPPED_DESTINATIONS.contains(&word) {
+                        if let Some(group) = stack.last_mut() {
+                            group.skip = true;
+                        }
+                        continue;
+                    }
+                    if word == "uc" {
+                        if let Some(group) = stack.last_mut() {
+                            group.unicode_skip = param.unwrap_or(1).max(0) as usize;
+                        }
+                        continue;
+                    }
+                    if skip {
+                        continue;
+                    }
+
+                    let replacement = match word {
+                        "par" | "line" | "sect" | "page" | "row" => Some("\n"),
+                        "tab" | "cell" => Some("\t"),
+                        "emdash" => Some("—"),
+                        "endash" => Some("–"),
+                        "bullet" => Some("•"),
+                        "lquote" => Some("‘"),
+                        "rquote" => Some("’"),
+                        "ldblquote" => Some("“"),
+                        "rdblquote" => Some("”"),
+                        _ => None,
+                    };
+                    if let Some(replacement) = replacement {
+                        text.push_str(replacement);
+                    } else if word == "u" {
+                        // Signed 16-bit: characters above U+7FFF are written negative
+                        let code = param.unwrap_or(0);
+                        let code = if code < 0 { code + 65536 } else { code };
+                        if let Some(c) = char::from_u32(code as u32) {
+                            text.push(c);
+                        }
+                        pending_skip = unicode_skip;
+                    }
+                } else {
+                    i += 2;
+                    match next {
+                        b'\'' => {
+                            let hex = bytes.get(i..i + 2).and_then(|h| std::str::from_utf8(h).ok());
+                            let value = hex.and_then(|h| u8::from_str_radix(h, 16).ok());
+                            if value.is_some() {
+                                i += 2;
+                            }
+                            if skip {
+                                continue;
+                            }
+                            if pending_skip > 0 {
+                                pending_skip -= 1;
+                            } else if let Some(value) = value {
+                                text.push(cp1252_char(value));
+                            }
+                        }
+                        b'\\' | b'{' | b'}' if !skip => text.push(next as char),
+                        b'~' if !skip => text.push(' '),
+                        b'_' if !skip => text.push('-'),
+                        // `\` before a line break is a paragraph mark
+                        b'\n' | b'\r' if !skip => text.push('\n'),
+                        _ => {}
+                    }
+                }
+            }
+            // Line breaks in the source are formatting only
+            b'\r' | b'\n' => i += 1,
+            _ => {
+                i += 1;
+                if skip {
+                    continue;
+                }
+                if pending_skip > 0 {
+                    pending_skip -= 1;
+                    continue;
+                }
+                text.push(cp1252_char(byte));
+            }
+        }
+    }
+
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let mut cleaned = String::new();
+    let mut blank_run = 0;
+    for line in lines {
+        if line.trim().is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
 
-    pub fn get_supported_formats(&self) -> Vec<String> {
-        self.supported_formats.clone()
+    Ok(cleaned.trim().to_string())
+}
+
+// Elements whose content never reaches the reader
+const HTML_SKIPPED_ELEMENTS: &[&str] = &["script", "style", "noscript", "template", "svg", "head"];
+// Separated from their neighbours by a blank line, which the paragraph chunker splits on
+const HTML_PARAGRAPH_ELEMENTS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre", "ul", "ol", "dl", "table",
+    "article", "section", "header", "footer", "aside", "nav", "main", "figure", "address", "hr",
+];
+// Start and end their own line
+const HTML_LINE_ELEMENTS: &[&str] = &["div", "li", "dt", "dd", "tr", "caption", "figcaption", "form", "fieldset"];
+// Deeper elements are flattened to their text, so nesting can't exhaust the stack
+const HTML_MAX_DEPTH: usize = 256;
+
+// Readable text with the page's block structure: headings and paragraphs become their own
+// paragraphs, list items and table rows their own lines, and inline markup (links, emphasis)
+// keeps its text in place
+fn extract_html_text(html: &str) -> String {
+    let document = scraper::Html::parse_document(html);
+    let mut raw = String::new();
+    html_text(document.root_element(), false, 0, &mut raw);
+
+    // Adjacent breaks collapse into the strongest of them
+    let mut text = String::new();
+    let mut line = String::new();
+    let mut pending_break: Option<&str> = None;
+    for c in raw.chars().chain(['\n']) {
+        if c != '\n' && c != HTML_PARAGRAPH_BREAK {
+            line.push(c);
+            continue;
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        line.clear();
+        if !words.is_empty() {
+            if let Some(separator) = pending_break.take() {
+                text.push_str(separator);
+            }
+            text.push_str(&words);
+        }
+        if !text.is_empty() && pending_break != Some("\n\n") {
+            pending_break = Some(if c == HTML_PARAGRAPH_BREAK { "\n\n" } else { "\n" });
+        }
     }
-}
\ No newline at end of file
+    text
+}
+
+const HTML_PARAGRAPH_BREAK: char = '\u{2029}';
+
+// Writes line breaks as '\n', paragraph breaks as HTML_PARAGRAPH_BREAK and source whitespace
+// as spaces, except inside <pre> where the line breaks are content
+fn html_text(element: scraper::ElementRef, preformatted: bool, depth: usize, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            scraper::Node::Text(text) if preformatted => out.push_str(text),
+            scraper::Node::Text(text) => {
+                out.extend(text.chars().map(|c| if c.is_whitespace() { ' ' } else { c }));
+            }
+            scraper::Node::Element(node) => {
+                let name = node.name();
+                if HTML_SKIPPED_ELEMENTS.contains(&name) {
+                    continue;
+                }
+                let Some(child_element) = scraper::ElementRef::wrap(child) else { continue };
+
+                let separator = if HTML_PARAGRAPH_ELEMENTS.contains(&name) {
+                    Some(HTML_PARAGRAPH_BREAK)
+                } else if HTML_LINE_ELEMENTS.contains(&name) || name == "br" {
+                    Some('\n')
+                } else if name == "td" || name == "th" {
+                    Some(' ')
+                } else {
+                    None
+                };
+
+                out.extend(separator);
+                if name == "li" {
+                    out.push_str("- ");
+                }
+                if depth < HTML_MAX_DEPTH {
+                    html_text(child_element, preformatted || name == "pre", depth + 1, out);
+                } else {
+                    out.extend(child_element.text().flat_map(str::chars).map(|c| if c.is_whitespace() { ' ' } else { c }));
+                }
+                out.extend(separator);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Uses a locally installed Tesseract; nothing leaves the machine
+async fn run_ocr(file_path: &str) -> Result<String> {
+    let output = tokio::process::Command::new("tesseract")
+        .arg(file_path)
+        .arg("stdout")
+        .output()
+        .await
+        .map_err(|e| anyhow!("tesseract not found: {}", e))?;
+
+    if !output.status.success() {
+        return Err(anyhow!("tesseract failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name).to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_pdf_sample_keeps_its_pages_apart() {
+        let processor = FileProcessor::new();
+        let text = processor.process_file(&fixture("retainer_agreement.pdf"), "pdf").await.unwrap();
+        assert_eq!(text, "[Page 1]\nRetainer Agreement\n\n[Page 2]\nFees are payable monthly.");
+    }
+
+    #[tokio::test]
+    async fn test_two_sheet_workbook_has_a_section_per_sheet() {
+        let processor = FileProcessor::new();
+        let text = processor.process_file(&fixture("billing_summary.xlsx"), "xlsx").await.unwrap();
+        assert_eq!(
+            text,
+            "Sheet: Billing\nMatter,Hours,Rate\nSmith v. Jones,12.5,250\nEstate of Doe,,300\n\nSheet: Contacts\nName,Role\nAlice Smith,Client"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pdf_named_txt_goes_to_the_pdf_parser() {
+        let dir = tempfile::tempdir().unwrap();
+        let renamed = dir.path().join("retainer.txt");
+        std::fs::copy(fixture("retainer_agreement.pdf"), &renamed).unwrap();
+
+        let processor = FileProcessor::new();
+        let text = processor.process_file(renamed.to_str().unwrap(), "txt").await.unwrap();
+        assert!(text.contains("Retainer Agreement"));
+        assert!(text.contains("Fees are payable monthly."));
+        assert!(!text.contains("%PDF-"));
+    }
+
+    #[tokio::test]
+    async fn test_text_that_resembles_a_signature_stays_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let processor = FileProcessor::new();
+
+        let csv = dir.path().join("fleet.csv");
+        std::fs::write(&csv, "BMW,Model\nBMW,X5\n").unwrap();
+        assert_eq!(processor.process_file(csv.to_str().unwrap(), "csv").await.unwrap(), "BMW: BMW; Model: X5");
+
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "A PDF file starts with %PDF-1.7 followed by its objects.").unwrap();
+        let text = processor.process_file(notes.to_str().unwrap(), "txt").await.unwrap();
+        assert!(text.starts_with("A PDF file"));
+
+        let bmi = dir.path().join("report.md");
+        std::fs::write(&bmi, "BMI report for the\0 claimant").unwrap();
+        let error = processor.process_file(bmi.to_str().unwrap(), "md").await.unwrap_err();
+        assert!(error.to_string().contains("binary data"));
+    }
+
+    #[test]
+    fn test_bmp_needs_its_full_header() {
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&30u32.to_le_bytes());
+        bmp.extend_from_slice(&[0, 0, 0, 0]);
+        bmp.extend_from_slice(&26u32.to_le_bytes());
+        bmp.resize(30, 0);
+        assert_eq!(sniff_magic(&bmp, 30, true), Some("bmp"));
+        assert_eq!(sniff_magic(&bmp, 31, true), None);
+        assert_eq!(sniff_magic(b"BMI report for the claimant", 27, true), None);
+    }
+
+    #[tokio::test]
+    async fn test_utf16_text_files_are_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notepad.txt");
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("Größe des Grundstücks".encode_utf16().flat_map(u16::to_le_bytes));
+        std::fs::write(&path, bytes).unwrap();
+
+        let processor = FileProcessor::new();
+        let text = processor.process_file(path.to_str().unwrap(), "txt").await.unwrap();
+        assert_eq!(text, "Größe des Grundstücks");
+    }
+
+    #[tokio::test]
+    async fn test_docx_paragraphs_and_table_cells() {
+        let text = FileProcessor::new().process_file(&fixture("engagement_letter.docx"), "docx").await.unwrap();
+        // The tab-stop definitions on the heading add no text
+        assert_eq!(text.trim_end(), "ENGAGEMENT LETTER\n\
+            Client:\tAcme Holdings B.V.\n\
+            Service\tRate\n\
+            Contract review\tEUR 250 per hour\n\
+            Fees are due within thirty days.");
+    }
+
+    #[test]
+    fn test_format_limits_apply_to_aliases() {
+        let processor = FileProcessor::new();
+        processor.apply_config(&ExtractionConfig {
+            format_size_limits: HashMap::from([(".JPEG".to_string(), 1024)]),
+            ..Default::default()
+        });
+        assert_eq!(processor.max_file_size("jpg"), 1024);
+        assert_eq!(processor.max_file_size("jpeg"), 1024);
+        assert_eq!(processor.max_file_size("png"), 50 * 1024 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_format_limit_overrides_global_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("rates.csv");
+        let txt = dir.path().join("notes.txt");
+        let rows = "name,rate\n".to_string() + &"Smith,250\n".repeat(50);
+        std::fs::write(&csv, &rows).unwrap();
+        std::fs::write(&txt, &rows).unwrap();
+
+        let processor = FileProcessor::new();
+        let limit = |bytes| ExtractionConfig {
+            format_size_limits: HashMap::from([("csv".to_string(), bytes)]),
+            ..Default::default()
+        };
+        processor.apply_config(&limit(100));
+        let error = processor.process_file(csv.to_str().unwrap(), "csv").await.unwrap_err();
+        assert!(error.to_string().contains(".csv"));
+        assert!(processor.process_file(txt.to_str().unwrap(), "txt").await.is_ok());
+
+        processor.apply_config(&limit(4096));
+        assert!(processor.process_file(csv.to_str().unwrap(), "csv").await.unwrap().contains("Smith"));
+    }
+
+    #[tokio::test]
+    async fn test_pptx_sample_follows_presentation_order_and_skips_malformed_slides() {
+        let processor = FileProcessor::new();
+        let text = processor.process_file(&fixture("case_briefing.pptx"), "pptx").await.unwrap();
+        // slide10.xml is third in the deck; slide3.xml, fourth, has mismatched tags
+        assert_eq!(text, "[Slide 1]\nCase Briefing\nAcme v. Globex\n\n\
+            [Slide 2]\nTimeline\nFiled March 2024\n\n\
+            [Slide 3]\nNext Steps");
+
+        processor.apply_config(&ExtractionConfig { include_slide_notes: true, ..Default::default() });
+        let text = processor.process_file(&fixture("case_briefing.pptx"), "pptx").await.unwrap();
+        assert!(text.starts_with("[Slide 1]\nCase Briefing\nAcme v. Globex\nNotes:\nMention the settlement offer.\n\n"));
+    }
+
+    #[test]
+    fn test_pptx_without_a_slide_list_sorts_slides_numerically() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for number in [10, 2, 1] {
+            zip.start_file(format!("ppt/slides/slide{}.xml", number), zip::write::SimpleFileOptions::default()).unwrap();
+            let xml = format!("<p:sld><a:p><a:r><a:t>Slide file {}</a:t></a:r></a:p></p:sld>", number);
+            std::io::Write::write_all(&mut zip, xml.as_bytes()).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let text = extract_pptx_text(&bytes, false, usize::MAX).unwrap();
+        assert_eq!(text, "[Slide 1]\nSlide file 1\n\n[Slide 2]\nSlide file 2\n\n[Slide 3]\nSlide file 10");
+    }
+
+    #[tokio::test]
+    async fn test_rtf_sample_keeps_bold_and_italic_text() {
+        let processor = FileProcessor::new();
+        let text = processor.process_file(&fixture("memo.rtf"), "rtf").await.unwrap();
+        // The picture's binary run contains braces, which must not end its group early
+        assert_eq!(text, "MEMORANDUM\n\n\
+            The lessee shall not assign the lease without the lessor’s consent.\n\
+            Signed at Zürich by Renée Dubois.\n\
+            Attachment: follows.");
+    }
+
+    #[tokio::test]
+    async fn test_csv_quoted_commas_stay_in_their_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("clients.csv");
+        std::fs::write(&csv, "name,address\n\"Smith, J.\",\"1 Main St, Springfield\"\n").unwrap();
+
+        let processor = FileProcessor::new();
+        let text = processor.process_file(csv.to_str().unwrap(), "csv").await.unwrap();
+        assert_eq!(text, "name: Smith, J.; address: 1 Main St, Springfield");
+    }
+
+    #[tokio::test]
+    async fn test_configured_csv_delimiter_overrides_sniffing() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("rates.csv");
+        // The comma in the header would win the sniff
+        std::fs::write(&csv, "name, firm;rate\nSmith, Jones LLP;250,00\n").unwrap();
+
+        let processor = FileProcessor::new();
+        processor.apply_config(&ExtractionConfig { csv_delimiter: CsvDelimiter::Semicolon, ..Default::default() });
+        let text = processor.process_file(csv.to_str().unwrap(), "csv").await.unwrap();
+        assert_eq!(text, "name, firm: Smith, Jones LLP; rate: 250,00");
+    }
+
+    #[test]
+    fn test_html_headings_and_lists_keep_their_structure() {
+        let html = "<html><head><title>Ignored</title></head><body>\
+                    <h1>Lease</h1><p>Terms <b>apply</b> here.</p>\
+                    <ul><li>Rent</li><li>Deposit</li></ul><h2>Signatures</h2></body></html>";
+        assert_eq!(extract_html_text(html), "Lease\n\nTerms apply here.\n\n- Rent\n- Deposit\n\nSignatures");
+    }
+
+    #[test]
+    fn test_deeply_nested_html_is_flattened() {
+        let depth = 2_000;
+        let html = format!("{}Deep clause{}", "<div>".repeat(depth), "</div>".repeat(depth));
+        assert_eq!(extract_html_text(&html), "Deep clause");
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::{Result, anyhow};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc;
+
+use crate::path_filter::{FilterPatterns, PathFilter};
+use crate::AppState;
+
+pub const WATCH_EVENT: &str = "folder-watch-indexing";
+
+// A file must be quiet this long before we index it, so half-written copies are skipped
+const DEBOUNCE: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchConfig {
+    pub enabled: bool,
+    pub path: Option<PathBuf>,
+    // Overrides the global ingestion filters for this watch
+    #[serde(default)]
+    pub filters: Option<FilterPatterns>,
+}
+
+impl WatchConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::data_dir::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchEventStatus {
+    Started,
+    Finished,
+    Skipped,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchEvent {
+    pub path: String,
+    pub status: WatchEventStatus,
+    pub document_id: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct FolderWatcher {
+    watcher: Option<RecommendedWatcher>,
+    task: Option<tauri::async_runtime::JoinHandle<()>>,
+    watched_path: Option<PathBuf>,
+}
+
+impl FolderWatcher {
+    pub fn new() -> Self {
+        Self {
+            watcher: None,
+            task: None,
+            watched_path: None,
+        }
+    }
+
+    pub fn start(&mut self, path: PathBuf, filters: &FilterPatterns, app: AppHandle, state: AppState) -> Result<()> {
+        if !path.is_dir() {
+            return Err(anyhow!("Not a directory: {}", path.display()));
+        }
+        let filter = PathFilter::new(filters)?;
+
+        self.stop();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        let _ = tx.send(path);
+                    }
+                }
+            }
+        })?;
+        watcher.watch(&path, RecursiveMode::Recursive)?;
+
+        // Filters match relative to the root, so compare canonical forms of both sides
+        let root = path.canonicalize().unwrap_or_else(|_| path.clone());
+        self.task = Some(tauri::async_runtime::spawn(debounce_loop(rx, root, filter, app, state)));
+        self.watcher = Some(watcher);
+        self.watched_path = Some(path);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        // Dropping the watcher closes the channel, which also ends the debounce loop
+        self.watcher = None;
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.watched_path = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    pub fn watched_path(&self) -> Option<&Path> {
+        self.watched_path.as_deref()
+    }
+}
+
+async fn debounce_loop(
+    mut rx: mpsc::UnboundedReceiver<PathBuf>,
+    root: PathBuf,
+    filter: PathFilter,
+    app: AppHandle,
+    state: AppState,
+) {
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some(path) => {
+                    let canonical = canonical_event_path(&path);
+                    if !is_temp_or_lock_file(&path) && filter.allows_path(&root, &canonical) {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                let ready: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, last_seen)| last_seen.elapsed() >= DEBOUNCE)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+
+                for path in ready {
+                    pending.remove(&path);
+                    if path.is_file() {
+                        index_file(&app, &state, &path).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn index_file(app: &AppHandle, state: &AppState, path: &Path) {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if !state.file_processor.is_supported(extension) {
+        return;
+    }
+
+    let path_str = path.to_string_lossy().to_string();
+    emit(app, &path_str, WatchEventStatus::Started, None, None);
+
+    match state.ingest_file(&path_str).await {
+        Ok(Some(document_id)) => emit(app, &path_str, WatchEventStatus::Finished, Some(document_id), None),
+        Ok(None) => emit(app, &path_str, WatchEventStatus::Skipped, None, None),
+        Err(e) => emit(app, &path_str, WatchEventStatus::Error, None, Some(e.to_string())),
+    }
+}
+
+fn emit(app: &AppHandle, path: &str, status: WatchEventStatus, document_id: Option<String>, error: Option<String>) {
+    let event = WatchEvent {
+        path: path.to_string(),
+        status,
+        document_id,
+        error,
+    };
+    if let Err(e) = app.emit(WATCH_EVENT, &event) {
+        eprintln!("Failed to emit folder watch event: {}", e);
+    }
+}
+
+// A deleted file can't be canonicalized, but its directory usually still can
+fn canonical_event_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    match (path.parent().and_then(|parent| parent.canonicalize().ok()), path.file_name()) {
+        (Some(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+// Office lock files, editor swap files, partial downloads and hidden files
+fn is_temp_or_lock_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name.to_lowercase(),
+        None => return true,
+    };
+
+    name.starts_with("~$")
+        || name.starts_with(".~lock")
+        || name.starts_with('.')
+        || [".tmp", ".temp", ".part", ".crdownload", ".swp", ".lock", "~"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn deleted_files_resolve_through_their_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let deleted = link.join("gone.txt");
+        std::fs::write(&deleted, "x").unwrap();
+        std::fs::remove_file(&deleted).unwrap();
+
+        assert_eq!(canonical_event_path(&deleted), real.canonicalize().unwrap().join("gone.txt"));
+    }
+}
@@ -0,0 +1,155 @@
+// Minimal GGUF header reader: pulls the few metadata keys we need without loading tensors
+// Spec: https://github.com/ggerganov/ggml/blob/master/docs/gguf.md
+
+use anyhow::{Result, anyhow};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+// Guards against corrupt headers asking us to allocate gigabytes for a key or string
+const MAX_STRING_LEN: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct GgufMetadata {
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    pub file_type: Option<u32>,
+    pub context_length: Option<u64>,
+}
+
+// llama.cpp `llama_ftype` names for the values we recognise
+pub fn file_type_name(file_type: u32) -> &'static str {
+    match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19..=31 => "IQ (importance matrix)",
+        32 => "BF16",
+        _ => "Unknown",
+    }
+}
+
+pub fn is_gguf_file(path: &Path) -> bool {
+    path.is_file()
+        && path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("gguf"))
+            .unwrap_or(false)
+}
+
+pub fn read_metadata(path: &Path) -> Result<GgufMetadata> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != GGUF_MAGIC {
+        return Err(anyhow!("Not a GGUF file: {}", path.display()));
+    }
+
+    let version = read_u32(&mut reader)?;
+    let kv_count = match version {
+        1 => {
+            let _tensor_count = read_u32(&mut reader)?;
+            read_u32(&mut reader)? as u64
+        }
+        2 | 3 => {
+            let _tensor_count = read_u64(&mut reader)?;
+            read_u64(&mut reader)?
+        }
+        v => return Err(anyhow!("Unsupported GGUF version: {}", v)),
+    };
+
+    let mut metadata = GgufMetadata::default();
+
+    for _ in 0..kv_count {
+        let key = read_string(&mut reader, version)?;
+        let value_type = read_u32(&mut reader)?;
+
+        match (key.as_str(), value_type) {
+            ("general.architecture", 8) => metadata.architecture = Some(read_string(&mut reader, version)?),
+            ("general.name", 8) => metadata.name = Some(read_string(&mut reader, version)?),
+            ("general.file_type", 4) => metadata.file_type = Some(read_u32(&mut reader)?),
+            (k, 4) if k.ends_with(".context_length") => metadata.context_length = Some(read_u32(&mut reader)? as u64),
+            (k, 10) if k.ends_with(".context_length") => metadata.context_length = Some(read_u64(&mut reader)?),
+            _ => skip_value(&mut reader, value_type, version)?,
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn skip_value<R: Read>(reader: &mut R, value_type: u32, version: u32) -> Result<()> {
+    match value_type {
+        0 | 1 | 7 => skip_bytes(reader, 1),
+        2 | 3 => skip_bytes(reader, 2),
+        4..=6 => skip_bytes(reader, 4),
+        10..=12 => skip_bytes(reader, 8),
+        8 => {
+            let len = read_len(reader, version)?;
+            skip_bytes(reader, len)
+        }
+        9 => {
+            let item_type = read_u32(reader)?;
+            let count = read_len(reader, version)?;
+            for _ in 0..count {
+                skip_value(reader, item_type, version)?;
+            }
+            Ok(())
+        }
+        t => Err(anyhow!("Unknown GGUF value type: {}", t)),
+    }
+}
+
+fn skip_bytes<R: Read>(reader: &mut R, count: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.by_ref().take(count), &mut std::io::sink())?;
+    if skipped != count {
+        return Err(anyhow!("Unexpected end of GGUF header"));
+    }
+    Ok(())
+}
+
+// v1 used 32-bit lengths, v2+ 64-bit
+fn read_len<R: Read>(reader: &mut R, version: u32) -> Result<u64> {
+    if version == 1 {
+        Ok(read_u32(reader)? as u64)
+    } else {
+        read_u64(reader)
+    }
+}
+
+fn read_string<R: Read>(reader: &mut R, version: u32) -> Result<String> {
+    let len = read_len(reader, version)?;
+    if len > MAX_STRING_LEN {
+        return Err(anyhow!("GGUF string too long: {} bytes", len));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -0,0 +1,596 @@
+use nvml_wrapper::Nvml;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use std::process::Command;
+#[cfg(any(target_os = "linux", test))]
+use std::path::{Path, PathBuf};
+
+use crate::system_monitor::GpuInfo;
+
+// GPU vendors report through different channels: NVML for NVIDIA, the DRM sysfs files (plus
+// rocm-smi for names) for AMD and Intel on Linux, and the display adapter registry/WMI for
+// everything on Windows. Each adapter returns the devices it can see; detect() orders NVML
+// first so the leading indices match CUDA device ordinals. When none of them finds a device,
+// wgpu lists whatever the graphics drivers expose, by name only.
+
+const BYTES_PER_MB: u64 = 1_048_576;
+
+pub trait GpuAdapter: Send + Sync {
+    // Indices are local to the adapter; the caller renumbers across adapters. Called on every
+    // monitor poll, so adapters gather static details in detect() and only read counters here.
+    fn gpus(&self) -> Vec<GpuInfo>;
+}
+
+pub fn detect() -> Vec<Box<dyn GpuAdapter>> {
+    let mut adapters: Vec<Box<dyn GpuAdapter>> = Vec::new();
+    if let Ok(nvml) = Nvml::init() {
+        adapters.push(Box::new(NvmlAdapter { nvml }));
+    }
+
+    #[cfg(target_os = "linux")]
+    adapters.push(Box::new(DrmAdapter::detect(Path::new(DRM_ROOT), &rocm_smi_gpus())));
+
+    // NVIDIA cards are skipped here when NVML already reports them
+    #[cfg(target_os = "windows")]
+    adapters.push(Box::new(WindowsAdapter::detect(!adapters.is_empty())));
+
+    #[cfg(feature = "wgpu-fallback")]
+    if all_gpus(&adapters).is_empty() {
+        adapters.push(Box::new(WgpuAdapter::detect()));
+    }
+
+    adapters
+}
+
+// Every device from every adapter, indexed in that order
+pub fn all_gpus(adapters: &[Box<dyn GpuAdapter>]) -> Vec<GpuInfo> {
+    adapters
+        .iter()
+        .flat_map(|adapter| adapter.gpus())
+        .enumerate()
+        .map(|(index, mut gpu)| {
+            gpu.index = index as u32;
+            gpu
+        })
+        .collect()
+}
+
+// Index 0 is often the display/integrated GPU, so the default is the largest card
+pub fn largest_vram_index(gpus: &[GpuInfo]) -> Option<u32> {
+    gpus.iter().max_by_key(|gpu| gpu.vram_total_mb).map(|gpu| gpu.index)
+}
+
+fn gpu_info(index: u32, name: String, vram_total_mb: u64, vram_used_mb: u64) -> GpuInfo {
+    GpuInfo {
+        available: true,
+        index,
+        name,
+        vram_total_mb,
+        vram_used_mb,
+        vram_free_mb: vram_total_mb.saturating_sub(vram_used_mb),
+        temperature: 0.0,
+        utilization: 0,
+        power_watts: 0,
+        cuda_available: false,
+        compute_capability: "N/A".to_string(),
+        driver_version: "Unknown".to_string(),
+    }
+}
+
+struct NvmlAdapter {
+    nvml: Nvml,
+}
+
+impl GpuAdapter for NvmlAdapter {
+    fn gpus(&self) -> Vec<GpuInfo> {
+        let driver_version = self.nvml.sys_driver_version().unwrap_or_else(|_| "Unknown".to_string());
+        let count = self.nvml.device_count().unwrap_or(0);
+
+        (0..count)
+            .filter_map(|index| {
+                let device = self.nvml.device_by_index(index).ok()?;
+                let name = device.name().unwrap_or_else(|_| "Unknown GPU".to_string());
+                let (total, used) = device.memory_info().map(|m| (m.total, m.used)).unwrap_or((0, 0));
+
+                let mut gpu = gpu_info(index, name, total / BYTES_PER_MB, used / BYTES_PER_MB);
+                gpu.temperature = device
+                    .temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu)
+                    .unwrap_or(0) as f32;
+                gpu.utilization = device.utilization_rates().map(|u| u.gpu).unwrap_or(0);
+                gpu.power_watts = device.power_usage().unwrap_or(0) / 1000;
+                gpu.cuda_available = true;
+                gpu.compute_capability = device
+                    .cuda_compute_capability()
+                    .map(|cc| format!("{}.{}", cc.major, cc.minor))
+                    .unwrap_or_else(|_| "Unknown".to_string());
+                gpu.driver_version = driver_version.clone();
+                Some(gpu)
+            })
+            .collect()
+    }
+}
+
+// AMD and Intel devices on Linux, found through the kernel's DRM sysfs files. Names and total
+// VRAM are read once at detect time (from rocm-smi when ROCm is installed, which names cards
+// better than sysfs); each poll only reads the usage counters, so nothing is spawned per poll.
+#[cfg(any(target_os = "linux", test))]
+struct DrmAdapter {
+    cards: Vec<DrmCard>,
+}
+
+#[cfg(any(target_os = "linux", test))]
+struct DrmCard {
+    device: PathBuf,
+    hwmon: Option<PathBuf>,
+    // Static fields only; usage is filled in per poll
+    info: GpuInfo,
+}
+
+#[cfg(target_os = "linux")]
+const DRM_ROOT: &str = "/sys/class/drm";
+
+#[cfg(any(target_os = "linux", test))]
+const AMD_PCI_VENDOR: &str = "0x1002";
+#[cfg(any(target_os = "linux", test))]
+const INTEL_PCI_VENDOR: &str = "0x8086";
+
+#[cfg(any(target_os = "linux", test))]
+fn read_sysfs(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn rocm_smi_gpus() -> Vec<GpuInfo> {
+    Command::new("rocm-smi")
+        .args(["--showproductname", "--showmeminfo", "vram", "--showdriverversion", "--json"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| parse_rocm_smi_json(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl DrmAdapter {
+    // `from_rocm_smi` is indexed by card number, like the cardN directories
+    fn detect(drm_root: &Path, from_rocm_smi: &[GpuInfo]) -> Self {
+        let mut cards: Vec<(u32, PathBuf)> = std::fs::read_dir(drm_root)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        // card0, not card0-DP-1 connectors
+                        let index = name.strip_prefix("card")?.parse().ok()?;
+                        Some((index, entry.path().join("device")))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        cards.sort();
+
+        let cards = cards
+            .into_iter()
+            .filter_map(|(index, device)| {
+                let vendor = read_sysfs(&device.join("vendor"))?;
+                let total: u64 = read_sysfs(&device.join("mem_info_vram_total"))
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                let product_name = read_sysfs(&device.join("product_name")).filter(|name| !name.is_empty());
+
+                let mut info = match vendor.as_str() {
+                    AMD_PCI_VENDOR => {
+                        let mut info = gpu_info(index, product_name.unwrap_or_else(|| "AMD GPU".to_string()), total / BYTES_PER_MB, 0);
+                        info.compute_capability = "ROCm".to_string();
+                        if let Some(smi) = from_rocm_smi.iter().find(|gpu| gpu.index == index) {
+                            info.name = smi.name.clone();
+                            info.vram_total_mb = info.vram_total_mb.max(smi.vram_total_mb);
+                            info.driver_version = smi.driver_version.clone();
+                        }
+                        info
+                    }
+                    // Integrated Intel graphics share system memory, so there is usually no VRAM to report
+                    INTEL_PCI_VENDOR => {
+                        let name = product_name.unwrap_or_else(|| match read_sysfs(&device.join("device")) {
+                            Some(device_id) => format!("Intel Graphics ({})", device_id),
+                            None => "Intel Graphics".to_string(),
+                        });
+                        gpu_info(index, name, total / BYTES_PER_MB, 0)
+                    }
+                    _ => return None,
+                };
+                if let Some(driver) = std::fs::read_link(device.join("driver"))
+                    .ok()
+                    .and_then(|link| link.file_name().map(|name| name.to_string_lossy().to_string()))
+                    .filter(|_| info.driver_version == "Unknown")
+                {
+                    info.driver_version = driver;
+                }
+
+                let hwmon = std::fs::read_dir(device.join("hwmon"))
+                    .ok()
+                    .and_then(|mut dirs| dirs.next())
+                    .and_then(|dir| dir.ok())
+                    .map(|dir| dir.path());
+                Some(DrmCard { device, hwmon, info })
+            })
+            .collect();
+
+        Self { cards }
+    }
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl GpuAdapter for DrmAdapter {
+    fn gpus(&self) -> Vec<GpuInfo> {
+        self.cards
+            .iter()
+            .map(|card| {
+                let counter = |path: PathBuf| read_sysfs(&path).and_then(|v| v.parse::<u64>().ok());
+                let mut gpu = card.info.clone();
+                gpu.vram_used_mb = counter(card.device.join("mem_info_vram_used")).unwrap_or(0) / BYTES_PER_MB;
+                gpu.vram_free_mb = gpu.vram_total_mb.saturating_sub(gpu.vram_used_mb);
+                gpu.utilization = counter(card.device.join("gpu_busy_percent")).unwrap_or(0) as u32;
+                if let Some(hwmon) = &card.hwmon {
+                    // hwmon temperatures are in millidegrees and power in microwatts
+                    gpu.temperature = counter(hwmon.join("temp1_input")).map_or(0.0, |milli| milli as f32 / 1000.0);
+                    gpu.power_watts = counter(hwmon.join("power1_average")).map_or(0, |micro| (micro / 1_000_000) as u32);
+                }
+                gpu
+            })
+            .collect()
+    }
+}
+
+// `rocm-smi --json` prints one object per "cardN" plus a "system" object with the driver.
+// Values are all strings; key names vary slightly between ROCm releases.
+#[cfg(any(target_os = "linux", test))]
+fn parse_rocm_smi_json(output: &str) -> Vec<GpuInfo> {
+    let json: serde_json::Value = match serde_json::from_str(output.trim()) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+    let Some(cards) = json.as_object() else {
+        return Vec::new();
+    };
+
+    let field = |card: &serde_json::Value, prefixes: &[&str]| -> Option<String> {
+        let card = card.as_object()?;
+        prefixes.iter().find_map(|prefix| {
+            card.iter()
+                .find(|(key, _)| key.starts_with(prefix))
+                .and_then(|(_, value)| value.as_str())
+                .map(|value| value.trim().to_string())
+        })
+    };
+    let number = |card: &serde_json::Value, prefixes: &[&str]| -> Option<f64> {
+        field(card, prefixes)?.parse().ok()
+    };
+
+    let driver_version = cards
+        .get("system")
+        .and_then(|system| field(system, &["Driver version"]));
+
+    let mut gpus: Vec<GpuInfo> = cards
+        .iter()
+        .filter_map(|(key, card)| {
+            let index: u32 = key.strip_prefix("card")?.parse().ok()?;
+            let name = field(card, &["Card series", "Card SKU", "Card model"]).unwrap_or_else(|| "AMD GPU".to_string());
+            let total = number(card, &["VRAM Total Memory (B)"])? as u64;
+            let used = number(card, &["VRAM Total Used Memory (B)"]).unwrap_or(0.0) as u64;
+
+            let mut gpu = gpu_info(index, name, total / BYTES_PER_MB, used / BYTES_PER_MB);
+            gpu.temperature = number(card, &["Temperature (Sensor edge)", "Temperature (Sensor junction)"]).unwrap_or(0.0) as f32;
+            gpu.utilization = number(card, &["GPU use (%)"]).unwrap_or(0.0) as u32;
+            gpu.power_watts = number(card, &["Average Graphics Package Power", "Current Socket Graphics Package Power"])
+                .unwrap_or(0.0) as u32;
+            gpu.compute_capability = "ROCm".to_string();
+            if let Some(driver_version) = &driver_version {
+                gpu.driver_version = driver_version.clone();
+            }
+            Some(gpu)
+        })
+        .collect();
+    gpus.sort_by_key(|gpu| gpu.index);
+    gpus
+}
+
+// AMD, Intel and anything else Windows knows about. Only sizes and names are available here,
+// and they don't change while the app runs, so wmic and reg are queried once at detect time.
+#[cfg(target_os = "windows")]
+struct WindowsAdapter {
+    gpus: Vec<GpuInfo>,
+}
+
+// Display adapter class key; each numbered subkey is one adapter
+#[cfg(target_os = "windows")]
+const DISPLAY_ADAPTER_CLASS_KEY: &str =
+    r"HKLM\SYSTEM\CurrentControlSet\Control\Class\{4d36e968-e325-11ce-bfc1-08002be10318}";
+
+// Software renderers that show up as adapters but have no memory of their own
+#[cfg(any(target_os = "windows", test))]
+const VIRTUAL_ADAPTERS: &[&str] = &["Microsoft Basic Display", "Microsoft Remote Display", "Hyper-V"];
+
+#[cfg(target_os = "windows")]
+impl WindowsAdapter {
+    fn detect(skip_nvidia: bool) -> Self {
+        let wmic = Command::new("wmic")
+            .args(["path", "win32_VideoController", "get", "AdapterRAM,DriverVersion,Name", "/format:csv"])
+            .output()
+            .map(|output| parse_wmic_video_controllers(&String::from_utf8_lossy(&output.stdout)))
+            .unwrap_or_default();
+        Self { gpus: merge_windows_adapters(wmic, windows_registry_adapters(), skip_nvidia) }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl GpuAdapter for WindowsAdapter {
+    fn gpus(&self) -> Vec<GpuInfo> {
+        self.gpus.clone()
+    }
+}
+
+// The 64-bit HardwareInformation.qwMemorySize registry value is preferred; WMI's AdapterRAM
+// is a 32-bit field that caps at 4GB. Adapters without the registry value (most integrated
+// GPUs) keep the WMI figure.
+#[cfg(any(target_os = "windows", test))]
+fn merge_windows_adapters(wmic: Vec<GpuInfo>, registry: Vec<(String, u64)>, skip_nvidia: bool) -> Vec<GpuInfo> {
+    let mut gpus = wmic;
+    for (name, vram_bytes) in registry {
+        match gpus.iter_mut().find(|gpu| gpu.name == name) {
+            Some(gpu) => {
+                let vram_total_mb = vram_bytes / BYTES_PER_MB;
+                gpu.vram_total_mb = gpu.vram_total_mb.max(vram_total_mb);
+                gpu.vram_free_mb = gpu.vram_total_mb.saturating_sub(gpu.vram_used_mb);
+            }
+            None => gpus.push(gpu_info(gpus.len() as u32, name, vram_bytes / BYTES_PER_MB, 0)),
+        }
+    }
+
+    gpus.retain(|gpu| {
+        let virtual_adapter = VIRTUAL_ADAPTERS.iter().any(|virtual_name| gpu.name.contains(virtual_name));
+        let reported_by_nvml = skip_nvidia && gpu.name.to_uppercase().contains("NVIDIA");
+        !virtual_adapter && !reported_by_nvml
+    });
+    for (index, gpu) in gpus.iter_mut().enumerate() {
+        gpu.index = index as u32;
+    }
+    gpus
+}
+
+// `wmic path win32_VideoController get AdapterRAM,DriverVersion,Name /format:csv` prints a
+// blank line, a "Node,AdapterRAM,DriverVersion,Name" header, then one row per adapter
+#[cfg(any(target_os = "windows", test))]
+fn parse_wmic_video_controllers(output: &str) -> Vec<GpuInfo> {
+    let mut lines = output.lines().map(str::trim).filter(|line| !line.is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some(header) => header.split(',').map(|column| column.trim().to_lowercase()).collect(),
+        None => return Vec::new(),
+    };
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let (Some(ram_column), Some(driver_column), Some(name_column)) =
+        (column("adapterram"), column("driverversion"), column("name"))
+    else {
+        return Vec::new();
+    };
+
+    lines
+        .filter_map(|line| {
+            // Name is the last column, so commas inside it stay in one field
+            let fields: Vec<&str> = line.splitn(header.len(), ',').collect();
+            let name = fields.get(name_column)?.trim();
+            if name.is_empty() {
+                return None;
+            }
+            let ram: u64 = fields.get(ram_column).and_then(|ram| ram.trim().parse().ok()).unwrap_or(0);
+
+            let mut gpu = gpu_info(0, name.to_string(), ram / BYTES_PER_MB, 0);
+            if let Some(driver) = fields.get(driver_column).map(|d| d.trim()).filter(|d| !d.is_empty()) {
+                gpu.driver_version = driver.to_string();
+            }
+            Some(gpu)
+        })
+        .enumerate()
+        .map(|(index, mut gpu)| {
+            gpu.index = index as u32;
+            gpu
+        })
+        .collect()
+}
+
+// Reads DriverDesc and HardwareInformation.qwMemorySize for every adapter subkey
+#[cfg(target_os = "windows")]
+fn windows_registry_adapters() -> Vec<(String, u64)> {
+    use std::collections::BTreeMap;
+
+    let query = |value: &str| -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        let output = match Command::new("reg")
+            .args(["query", DISPLAY_ADAPTER_CLASS_KEY, "/s", "/v", value])
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => return values,
+        };
+
+        let mut current_key = None;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if line.starts_with("HKEY_") {
+                current_key = Some(line.trim().to_string());
+            } else if let (Some(key), Some(rest)) = (&current_key, line.trim().strip_prefix(value)) {
+                // "    <name>    REG_QWORD    0x200000000" / "    DriverDesc    REG_SZ    AMD Radeon RX 6800"
+                if let Some((_, data)) = rest.trim().split_once(char::is_whitespace) {
+                    values.insert(key.clone(), data.trim().to_string());
+                }
+            }
+        }
+        values
+    };
+
+    let names = query("DriverDesc");
+    query("HardwareInformation.qwMemorySize")
+        .into_iter()
+        .filter_map(|(key, data)| {
+            let vram = u64::from_str_radix(data.trim_start_matches("0x"), 16).ok()?;
+            let name = names.get(&key).cloned().unwrap_or_else(|| "Unknown GPU".to_string());
+            Some((name, vram))
+        })
+        .collect()
+}
+
+// Names from the graphics API, for GPUs no vendor channel above can read (NVIDIA without a
+// working NVML, macOS, unusual Linux drivers). wgpu doesn't report memory, so VRAM stays 0.
+#[cfg(feature = "wgpu-fallback")]
+struct WgpuAdapter {
+    gpus: Vec<GpuInfo>,
+}
+
+#[cfg(feature = "wgpu-fallback")]
+impl WgpuAdapter {
+    fn detect() -> Self {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapters = pollster::block_on(instance.enumerate_adapters(wgpu::Backends::PRIMARY));
+
+        let mut gpus: Vec<GpuInfo> = Vec::new();
+        for info in adapters.iter().map(|adapter| adapter.get_info()) {
+            // Software rasterizers, and the same card seen through a second backend
+            if info.device_type == wgpu::DeviceType::Cpu
+                || gpus.iter().any(|gpu| gpu.name == info.name)
+            {
+                continue;
+            }
+            let mut gpu = gpu_info(gpus.len() as u32, info.name, 0, 0);
+            gpu.compute_capability = format!("{:?}", info.backend);
+            if !info.driver_info.is_empty() {
+                gpu.driver_version = info.driver_info;
+            }
+            gpus.push(gpu);
+        }
+        Self { gpus }
+    }
+}
+
+#[cfg(feature = "wgpu-fallback")]
+impl GpuAdapter for WgpuAdapter {
+    fn gpus(&self) -> Vec<GpuInfo> {
+        self.gpus.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROCM_SMI_OUTPUT: &str = r#"{
+        "card0": {
+            "Card series": "Navi 21 [Radeon RX 6800]",
+            "Card model": "0x73bf",
+            "VRAM Total Memory (B)": "17163091968",
+            "VRAM Total Used Memory (B)": "1073741824",
+            "GPU use (%)": "12",
+            "Temperature (Sensor edge) (C)": "45.0",
+            "Average Graphics Package Power (W)": "35.0"
+        },
+        "system": {"Driver version": "6.3.6"}
+    }"#;
+
+    #[test]
+    fn test_rocm_smi_json_becomes_gpu_info() {
+        let gpus = parse_rocm_smi_json(ROCM_SMI_OUTPUT);
+        assert_eq!(gpus.len(), 1);
+        let gpu = &gpus[0];
+        assert_eq!(gpu.index, 0);
+        assert_eq!(gpu.name, "Navi 21 [Radeon RX 6800]");
+        assert_eq!(gpu.vram_total_mb, 16368);
+        assert_eq!(gpu.vram_used_mb, 1024);
+        assert_eq!(gpu.vram_free_mb, 15344);
+        assert_eq!(gpu.utilization, 12);
+        assert_eq!(gpu.temperature, 45.0);
+        assert_eq!(gpu.power_watts, 35);
+        assert_eq!(gpu.driver_version, "6.3.6");
+
+        assert!(parse_rocm_smi_json("ERROR: GPU[0] : Unable to get VRAM info").is_empty());
+    }
+
+    #[test]
+    fn test_wmic_csv_becomes_gpu_info() {
+        let output = "\r\nNode,AdapterRAM,DriverVersion,Name\r\n\
+                      DESKTOP-1,4293918720,31.0.101.4502,Intel(R) Arc(TM) A770 Graphics\r\n\
+                      DESKTOP-1,1073741824,31.0.15.3699,NVIDIA GeForce GTX 1650, Rev. A\r\n";
+        let gpus = parse_wmic_video_controllers(output);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].name, "Intel(R) Arc(TM) A770 Graphics");
+        assert_eq!(gpus[0].vram_total_mb, 4095);
+        assert_eq!(gpus[0].driver_version, "31.0.101.4502");
+        assert_eq!(gpus[1].index, 1);
+        assert_eq!(gpus[1].name, "NVIDIA GeForce GTX 1650, Rev. A");
+        assert_eq!(gpus[1].vram_total_mb, 1024);
+    }
+
+    #[test]
+    fn test_registry_vram_lifts_the_wmi_4gb_cap() {
+        let wmic = parse_wmic_video_controllers(
+            "Node,AdapterRAM,DriverVersion,Name\n\
+             PC,4293918720,31.0.24027.1012,AMD Radeon RX 6800\n\
+             PC,0,10.0.19041.1,Microsoft Basic Display Adapter\n\
+             PC,4293918720,31.0.15.3699,NVIDIA GeForce RTX 3080\n",
+        );
+        let registry = vec![("AMD Radeon RX 6800".to_string(), 0x4_0000_0000)];
+
+        let gpus = merge_windows_adapters(wmic, registry, true);
+        assert_eq!(gpus.len(), 1);
+        assert_eq!(gpus[0].name, "AMD Radeon RX 6800");
+        assert_eq!(gpus[0].vram_total_mb, 16384);
+    }
+
+    #[test]
+    fn test_drm_adapter_reads_amd_and_intel_and_caches_static_details() {
+        let root = tempfile::tempdir().unwrap();
+        let card = |name: &str, files: &[(&str, &str)]| {
+            let device = root.path().join(name).join("device");
+            for (file, content) in files {
+                let path = device.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+            device
+        };
+        card("card0", &[("vendor", "0x8086\n"), ("device", "0x9a49\n")]);
+        std::fs::create_dir_all(root.path().join("card0-eDP-1")).unwrap();
+        let amd = card("card1", &[
+            ("vendor", "0x1002\n"),
+            ("product_name", "Radeon RX 6600\n"),
+            ("mem_info_vram_total", "8589934592\n"),
+            ("mem_info_vram_used", "536870912\n"),
+            ("gpu_busy_percent", "7\n"),
+            ("hwmon/hwmon3/temp1_input", "51000\n"),
+            ("hwmon/hwmon3/power1_average", "23000000\n"),
+        ]);
+        card("card2", &[("vendor", "0x10de\n")]);
+
+        let adapter = DrmAdapter::detect(root.path(), &[]);
+        let gpus = adapter.gpus();
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].name, "Intel Graphics (0x9a49)");
+        assert_eq!(gpus[0].vram_total_mb, 0);
+        assert_eq!(gpus[1].index, 1);
+        assert_eq!(gpus[1].name, "Radeon RX 6600");
+        assert_eq!(gpus[1].vram_total_mb, 8192);
+        assert_eq!(gpus[1].vram_used_mb, 512);
+        assert_eq!(gpus[1].utilization, 7);
+        assert_eq!(gpus[1].temperature, 51.0);
+        assert_eq!(gpus[1].power_watts, 23);
+
+        // Counters are re-read on each poll; the name was read once
+        std::fs::write(amd.join("mem_info_vram_used"), "1073741824\n").unwrap();
+        std::fs::write(amd.join("product_name"), "renamed\n").unwrap();
+        let gpus = adapter.gpus();
+        assert_eq!(gpus[1].vram_used_mb, 1024);
+        assert_eq!(gpus[1].name, "Radeon RX 6600");
+
+        // rocm-smi names the card better than sysfs when it is installed
+        let from_smi = parse_rocm_smi_json(&ROCM_SMI_OUTPUT.replace("card0", "card1"));
+        let gpus = DrmAdapter::detect(root.path(), &from_smi).gpus();
+        assert_eq!(gpus[1].name, "Navi 21 [Radeon RX 6800]");
+        assert_eq!(gpus[1].driver_version, "6.3.6");
+    }
+}
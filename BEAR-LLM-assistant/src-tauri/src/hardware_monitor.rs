@@ -1,22 +1,159 @@
 use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use sysinfo::{System, SystemExt, CpuExt, ProcessExt, PidExt};
 use std::time::Duration;
-
-#[cfg(target_os = "windows")]
-use nvml_wrapper::Nvml;
+use tokio::sync::{watch, RwLock};
+use tokio_util::sync::CancellationToken;
 
 use crate::SystemStatus;
+use crate::gpu_adapter::{self, GpuAdapter};
+
+// Persisted hardware settings, stored as hardware_config.json in the data dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareConfig {
+    // None selects the largest-VRAM device
+    #[serde(default)]
+    pub gpu_index: Option<u32>,
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    // Cadence while a generation is running; None pauses polling until it finishes
+    #[serde(default = "default_inference_poll_interval_secs")]
+    pub inference_poll_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    #[serde(default)]
+    pub thresholds: SafetyThresholds,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_inference_poll_interval_secs() -> Option<u64> {
+    Some(30)
+}
+
+impl Default for HardwareConfig {
+    fn default() -> Self {
+        Self {
+            gpu_index: None,
+            poll_interval_secs: default_poll_interval_secs(),
+            inference_poll_interval_secs: default_inference_poll_interval_secs(),
+            resource_limits: ResourceLimits::default(),
+            thresholds: SafetyThresholds::default(),
+        }
+    }
+}
+
+// Readings above these count toward check_safety's consecutive-high limit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SafetyThresholds {
+    pub cpu: f32,
+    pub memory: f32,
+    pub gpu: f32,
+    // Degrees Celsius
+    pub temperature: f32,
+}
+
+// Below this a threshold would trip on an idle machine; above it hardware is already throttling
+const MIN_TEMPERATURE_THRESHOLD: f32 = 40.0;
+const MAX_TEMPERATURE_THRESHOLD: f32 = 105.0;
+
+impl Default for SafetyThresholds {
+    fn default() -> Self {
+        Self {
+            cpu: 85.0,
+            memory: 90.0,
+            gpu: 85.0,
+            temperature: 80.0,
+        }
+    }
+}
+
+impl SafetyThresholds {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("CPU", self.cpu), ("Memory", self.memory), ("GPU", self.gpu)] {
+            if !(0.0..=100.0).contains(&value) {
+                return Err(anyhow!("{} threshold must be between 0 and 100", name));
+            }
+        }
+        if !(MIN_TEMPERATURE_THRESHOLD..=MAX_TEMPERATURE_THRESHOLD).contains(&self.temperature) {
+            return Err(anyhow!(
+                "Temperature threshold must be between {} and {} °C",
+                MIN_TEMPERATURE_THRESHOLD, MAX_TEMPERATURE_THRESHOLD
+            ));
+        }
+        Ok(())
+    }
+}
+
+// User-set usage caps in percent, separate from the fixed safety thresholds. 100 means no cap.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    pub max_gpu_usage: f32,
+    pub max_cpu_usage: f32,
+    pub max_ram_usage: f32,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_gpu_usage: 100.0,
+            max_cpu_usage: 100.0,
+            max_ram_usage: 100.0,
+        }
+    }
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("GPU", self.max_gpu_usage), ("CPU", self.max_cpu_usage), ("RAM", self.max_ram_usage)] {
+            if !(0.0..=100.0).contains(&value) {
+                return Err(anyhow!("{} usage limit must be between 0 and 100", name));
+            }
+        }
+        Ok(())
+    }
+
+    // Describes the first limit the readings exceed
+    pub fn exceeded_by(&self, status: &SystemStatus) -> Option<String> {
+        if let Some(gpu) = status.gpu_usage.filter(|gpu| *gpu > self.max_gpu_usage) {
+            return Some(format!("GPU usage {:.0}% is above the {:.0}% limit", gpu, self.max_gpu_usage));
+        }
+        if status.cpu_usage > self.max_cpu_usage {
+            return Some(format!("CPU usage {:.0}% is above the {:.0}% limit", status.cpu_usage, self.max_cpu_usage));
+        }
+        if status.memory_usage > self.max_ram_usage {
+            return Some(format!("RAM usage {:.0}% is above the {:.0}% limit", status.memory_usage, self.max_ram_usage));
+        }
+        None
+    }
+}
+
+impl HardwareConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::data_dir::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+}
 
 pub struct HardwareMonitor {
     system: System,
-    cpu_threshold: f32,
-    memory_threshold: f32,
-    gpu_threshold: f32,
-    temperature_threshold: f32,
+    thresholds: SafetyThresholds,
     consecutive_high_readings: usize,
     max_consecutive_high: usize,
-    #[cfg(target_os = "windows")]
-    nvml: Option<Nvml>,
+    // Metrics refreshes so far, and the last one check_safety counted
+    refreshes: u64,
+    counted_refresh: Option<u64>,
+    gpu_index: u32,
+    resource_limits: ResourceLimits,
+    adapters: Vec<Box<dyn GpuAdapter>>,
 }
 
 impl HardwareMonitor {
@@ -24,19 +161,19 @@ impl HardwareMonitor {
         let mut system = System::new_all();
         system.refresh_all();
 
-        #[cfg(target_os = "windows")]
-        let nvml = Nvml::init().ok();
+        let adapters = gpu_adapter::detect();
+        let gpu_index = gpu_adapter::largest_vram_index(&gpu_adapter::all_gpus(&adapters)).unwrap_or(0);
 
         Self {
             system,
-            cpu_threshold: 85.0,
-            memory_threshold: 90.0,
-            gpu_threshold: 85.0,
-            temperature_threshold: 80.0,
+            thresholds: SafetyThresholds::default(),
             consecutive_high_readings: 0,
             max_consecutive_high: 3,
-            #[cfg(target_os = "windows")]
-            nvml,
+            refreshes: 0,
+            counted_refresh: None,
+            gpu_index,
+            resource_limits: ResourceLimits::default(),
+            adapters,
         }
     }
 
@@ -45,6 +182,7 @@ impl HardwareMonitor {
         self.system.refresh_memory();
         self.system.refresh_processes();
         self.system.refresh_components_list();
+        self.refreshes += 1;
         Ok(())
     }
 
@@ -65,19 +203,23 @@ impl HardwareMonitor {
         })
     }
 
+    // The send_message gate and every running generation's SafetyWatch all check; a reading
+    // counts toward the consecutive-high limit once, however many of them see it
     pub async fn check_safety(&mut self) -> Result<bool> {
         let status = self.get_status().await?;
+        Ok(self.count_reading(status.is_safe))
+    }
 
-        if !status.is_safe {
-            self.consecutive_high_readings += 1;
-            if self.consecutive_high_readings >= self.max_consecutive_high {
-                return Ok(false);
+    fn count_reading(&mut self, is_safe: bool) -> bool {
+        if self.counted_refresh != Some(self.refreshes) {
+            self.counted_refresh = Some(self.refreshes);
+            if is_safe {
+                self.consecutive_high_readings = 0;
+            } else {
+                self.consecutive_high_readings += 1;
             }
-        } else {
-            self.consecutive_high_readings = 0;
         }
-
-        Ok(true)
+        self.consecutive_high_readings < self.max_consecutive_high
     }
 
     fn get_cpu_usage(&self) -> f32 {
@@ -107,49 +249,33 @@ impl HardwareMonitor {
     }
 
     async fn get_gpu_usage(&self) -> Result<Option<f32>> {
-        #[cfg(target_os = "windows")]
-        {
-            if let Some(ref nvml) = self.nvml {
-                let device_count = nvml.device_count()?;
-                if device_count > 0 {
-                    let device = nvml.device_by_index(0)?;
-                    let utilization = device.utilization_rates()?;
-                    return Ok(Some(utilization.gpu as f32));
-                }
-            }
-        }
-
-        Ok(None)
+        Ok(gpu_adapter::all_gpus(&self.adapters)
+            .into_iter()
+            .find(|gpu| gpu.index == self.gpu_index)
+            .map(|gpu| gpu.utilization as f32))
     }
 
     fn get_temperature(&self) -> Option<f32> {
-        use sysinfo::ComponentExt;
-
-        for component in self.system.components() {
-            if component.label().contains("CPU") || component.label().contains("Core") {
-                return Some(component.temperature());
-            }
-        }
-        None
+        crate::thermal::cpu_temperature(&self.system)
     }
 
     fn check_thresholds(&self, cpu: f32, memory: f32, gpu: Option<f32>, temp: Option<f32>) -> bool {
-        if cpu > self.cpu_threshold {
+        if cpu > self.thresholds.cpu {
             return false;
         }
 
-        if memory > self.memory_threshold {
+        if memory > self.thresholds.memory {
             return false;
         }
 
         if let Some(gpu_usage) = gpu {
-            if gpu_usage > self.gpu_threshold {
+            if gpu_usage > self.thresholds.gpu {
                 return false;
             }
         }
 
         if let Some(temperature) = temp {
-            if temperature > self.temperature_threshold {
+            if temperature > self.thresholds.temperature {
                 return false;
             }
         }
@@ -157,17 +283,41 @@ impl HardwareMonitor {
         true
     }
 
-    pub fn set_thresholds(&mut self, cpu: f32, memory: f32, gpu: f32, temperature: f32) {
-        self.cpu_threshold = cpu;
-        self.memory_threshold = memory;
-        self.gpu_threshold = gpu;
-        self.temperature_threshold = temperature;
+    pub fn set_gpu_index(&mut self, index: u32) {
+        self.gpu_index = index;
+    }
+
+    pub fn resource_limits(&self) -> ResourceLimits {
+        self.resource_limits
+    }
+
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) {
+        self.resource_limits = limits;
+    }
+
+    // Checks the latest readings against the user's limits; None when within all of them
+    pub async fn check_resource_limits(&self) -> Result<Option<String>> {
+        let status = self.get_status().await?;
+        Ok(self.resource_limits.exceeded_by(&status))
+    }
+
+    pub fn thresholds(&self) -> SafetyThresholds {
+        self.thresholds
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: SafetyThresholds) {
+        self.thresholds = thresholds;
     }
 
-    pub async fn get_process_info(&self) -> Vec<ProcessInfo> {
+    // Heaviest processes first, from the last metrics refresh
+    pub async fn get_process_info(&self, limit: usize, sort: ProcessSort, exclude_self: bool) -> Vec<ProcessInfo> {
         let mut processes = Vec::new();
+        let own_pid = std::process::id();
 
         for (pid, process) in self.system.processes() {
+            if exclude_self && pid.as_u32() == own_pid {
+                continue;
+            }
             processes.push(ProcessInfo {
                 pid: pid.as_u32(),
                 name: process.name().to_string(),
@@ -176,12 +326,16 @@ impl HardwareMonitor {
             });
         }
 
-        processes.sort_by(|a, b| b.cpu_usage.partial_cmp(&a.cpu_usage).unwrap());
-        processes.truncate(10);
+        match sort {
+            ProcessSort::Cpu => processes.sort_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage)),
+            ProcessSort::Memory => processes.sort_by(|a, b| b.memory_usage.total_cmp(&a.memory_usage)),
+        }
+        processes.truncate(limit);
         processes
     }
 
-    pub async fn emergency_throttle(&mut self) -> Result<()> {
+    // Needs no monitor state, so callers don't hold the monitor's lock through the cool-down
+    pub async fn emergency_throttle() -> Result<()> {
         println!("EMERGENCY: System resources critically high. Implementing throttling...");
 
         #[cfg(target_os = "windows")]
@@ -192,16 +346,306 @@ impl HardwareMonitor {
                 .output()?;
         }
 
-        std::thread::sleep(Duration::from_secs(5));
+        tokio::time::sleep(Duration::from_secs(5)).await;
 
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessInfo {
     pub pid: u32,
     pub name: String,
     pub cpu_usage: f32,
+    // MB
     pub memory_usage: f32,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessSort {
+    #[default]
+    Cpu,
+    Memory,
+}
+
+// Shortest allowed polling interval; refresh_all itself takes a noticeable slice of a second
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How often a paused poller checks whether generation has finished
+const PAUSED_RECHECK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PollSettings {
+    pub running: bool,
+    pub interval_secs: u64,
+    pub inference_interval_secs: Option<u64>,
+}
+
+impl PollSettings {
+    pub fn from_config(config: &HardwareConfig) -> Self {
+        Self {
+            running: true,
+            interval_secs: config.poll_interval_secs,
+            inference_interval_secs: config.inference_poll_interval_secs,
+        }
+    }
+
+    // None while stopped, or paused for a running generation
+    fn current_interval(&self, generating: bool) -> Option<Duration> {
+        if !self.running {
+            return None;
+        }
+        let secs = if generating { self.inference_interval_secs? } else { self.interval_secs };
+        Some(Duration::from_secs(secs).max(MIN_POLL_INTERVAL))
+    }
+}
+
+// Counts generations in flight; hold one for the duration of each request
+#[derive(Debug, Clone, Default)]
+pub struct GenerationTracker {
+    active: Arc<AtomicUsize>,
+}
+
+pub struct GenerationGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl GenerationTracker {
+    pub fn start(&self) -> GenerationGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        GenerationGuard { active: self.active.clone() }
+    }
+
+    pub fn is_generating(&self) -> bool {
+        self.active.load(Ordering::SeqCst) > 0
+    }
+}
+
+impl Drop for GenerationGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// How long each generated token is held back while usage is over the user's limits
+const THROTTLE_DELAY: Duration = Duration::from_millis(250);
+
+// Set while usage is over the user's limits, by the poll loop and by the SafetyWatch of each
+// running generation (polling may be paused then); generation slows down until the next reading
+// is back under them
+#[derive(Debug, Clone, Default)]
+pub struct ResourceThrottle {
+    over_limit: Arc<AtomicBool>,
+}
+
+impl ResourceThrottle {
+    pub fn is_throttled(&self) -> bool {
+        self.over_limit.load(Ordering::SeqCst)
+    }
+
+    fn set(&self, over_limit: bool) {
+        self.over_limit.store(over_limit, Ordering::SeqCst);
+    }
+
+    // Compares the monitor's latest readings with its limits
+    async fn refresh(&self, monitor: &HardwareMonitor) {
+        match monitor.check_resource_limits().await {
+            Ok(exceeded) => {
+                if let Some(reason) = &exceeded {
+                    if !self.is_throttled() {
+                        eprintln!("Throttling inference: {}", reason);
+                    }
+                }
+                self.set(exceeded.is_some());
+            }
+            Err(e) => eprintln!("Failed to check resource limits: {}", e),
+        }
+    }
+
+    // Called from the generation loop between tokens, which runs on a blocking thread
+    pub fn pause_if_throttled(&self) {
+        if self.is_throttled() {
+            std::thread::sleep(THROTTLE_DELAY);
+        }
+    }
+}
+
+// Cadence of the safety checks made while a generation runs; check_safety needs several
+// consecutive high readings, so a breach stops generation after a few of these
+const GENERATION_SAFETY_INTERVAL: Duration = Duration::from_secs(2);
+
+// Runs check_safety alongside one generation and cancels it when the check fails, and keeps
+// the throttle current meanwhile. Stops when dropped.
+pub struct SafetyWatch {
+    tripped: Arc<AtomicBool>,
+    stop: CancellationToken,
+}
+
+impl SafetyWatch {
+    pub fn start(monitor: Arc<RwLock<HardwareMonitor>>, throttle: ResourceThrottle, generation: CancellationToken) -> Self {
+        let tripped = Arc::new(AtomicBool::new(false));
+        let stop = CancellationToken::new();
+
+        let (task_tripped, task_stop) = (tripped.clone(), stop.clone());
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(GENERATION_SAFETY_INTERVAL) => {}
+                    _ = task_stop.cancelled() => break,
+                    _ = generation.cancelled() => break,
+                }
+
+                let mut monitor = monitor.write().await;
+                if let Err(e) = monitor.update_metrics().await {
+                    eprintln!("Failed to update hardware metrics: {}", e);
+                    continue;
+                }
+                throttle.refresh(&monitor).await;
+                if let Ok(false) = monitor.check_safety().await {
+                    eprintln!("Stopping generation: system resources critically high");
+                    task_tripped.store(true, Ordering::SeqCst);
+                    generation.cancel();
+                    break;
+                }
+            }
+        });
+
+        Self { tripped, stop }
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SafetyWatch {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+// Background metrics refresh. Settings changes take effect immediately instead of after the
+// current sleep; ends when the settings sender is dropped.
+pub async fn poll_loop(
+    monitor: Arc<RwLock<HardwareMonitor>>,
+    generations: GenerationTracker,
+    throttle: ResourceThrottle,
+    mut settings: watch::Receiver<PollSettings>,
+) {
+    loop {
+        let interval = settings.borrow().current_interval(generations.is_generating());
+        let wait = interval.unwrap_or(PAUSED_RECHECK);
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            changed = settings.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            }
+        }
+
+        // Settings or generation state may have changed while sleeping
+        if interval.is_none() || settings.borrow().current_interval(generations.is_generating()).is_none() {
+            continue;
+        }
+
+        let mut monitor = monitor.write().await;
+        if let Err(e) = monitor.update_metrics().await {
+            eprintln!("Failed to update hardware metrics: {}", e);
+            continue;
+        }
+        throttle.refresh(&monitor).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generation_watch_throttles_while_polling_is_paused() {
+        let mut monitor = HardwareMonitor::new();
+        // Any machine uses some RAM, so a 0% cap is always exceeded
+        monitor.set_resource_limits(ResourceLimits { max_ram_usage: 0.0, ..ResourceLimits::default() });
+        monitor.set_thresholds(SafetyThresholds { cpu: 100.0, memory: 100.0, gpu: 100.0, temperature: 105.0 });
+        let monitor = Arc::new(RwLock::new(monitor));
+
+        let throttle = ResourceThrottle::default();
+        let generation = CancellationToken::new();
+        let _watch = SafetyWatch::start(monitor, throttle.clone(), generation.clone());
+        assert!(!throttle.is_throttled());
+
+        tokio::time::sleep(GENERATION_SAFETY_INTERVAL + Duration::from_millis(500)).await;
+        assert!(throttle.is_throttled());
+        assert!(!generation.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_generation_watch_stops_generation_past_the_thresholds() {
+        let mut monitor = HardwareMonitor::new();
+        // Any machine uses some RAM, so a 0% memory threshold is always exceeded
+        monitor.set_thresholds(SafetyThresholds { memory: 0.0, ..SafetyThresholds::default() });
+        monitor.max_consecutive_high = 1;
+        let monitor = Arc::new(RwLock::new(monitor));
+
+        let generation = CancellationToken::new();
+        let watch = SafetyWatch::start(monitor, ResourceThrottle::default(), generation.clone());
+
+        // Stands in for the token loop, which checks the token between tokens
+        let max_tokens = 200;
+        let mut generated = 0;
+        while generated < max_tokens && !generation.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            generated += 1;
+        }
+
+        assert!(generation.is_cancelled());
+        assert!(watch.tripped());
+        assert!(generated < max_tokens, "generated {} tokens", generated);
+    }
+
+    #[test]
+    fn test_each_reading_counts_once_toward_the_safety_limit() {
+        let mut monitor = HardwareMonitor::new();
+
+        // Three checks of the same reading, as from concurrent generations
+        for _ in 0..3 {
+            assert!(monitor.count_reading(false));
+        }
+        assert_eq!(monitor.consecutive_high_readings, 1);
+
+        monitor.refreshes += 1;
+        assert!(monitor.count_reading(false));
+        monitor.refreshes += 1;
+        assert!(!monitor.count_reading(false));
+        assert!(!monitor.count_reading(false));
+
+        monitor.refreshes += 1;
+        assert!(monitor.count_reading(true));
+        assert_eq!(monitor.consecutive_high_readings, 0);
+    }
+
+    #[test]
+    fn test_thresholds_survive_a_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hardware_config.json");
+        assert_eq!(HardwareConfig::load(&path).unwrap().thresholds, SafetyThresholds::default());
+
+        let thresholds = SafetyThresholds { cpu: 70.0, memory: 95.5, gpu: 60.0, temperature: 90.0 };
+        thresholds.validate().unwrap();
+        let config = HardwareConfig { thresholds, ..HardwareConfig::default() };
+        config.save(&path).unwrap();
+        assert_eq!(HardwareConfig::load(&path).unwrap().thresholds, thresholds);
+    }
+
+    #[test]
+    fn test_thresholds_outside_their_ranges_are_rejected() {
+        let defaults = SafetyThresholds::default();
+        assert!(SafetyThresholds { cpu: 101.0, ..defaults }.validate().is_err());
+        assert!(SafetyThresholds { memory: -1.0, ..defaults }.validate().is_err());
+        assert!(SafetyThresholds { temperature: 20.0, ..defaults }.validate().is_err());
+        assert!(SafetyThresholds { temperature: 120.0, ..defaults }.validate().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use sysinfo::{DiskExt, System, SystemExt};
+
+use crate::commands::AppState as CommandState;
+use crate::AppState;
+
+// Free space on the data drive below which ingestion and downloads start failing
+const DISK_WARN_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+const DISK_ERROR_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthLevel {
+    Ok,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubsystemHealth {
+    pub level: HealthLevel,
+    pub message: String,
+}
+
+impl SubsystemHealth {
+    fn new(level: HealthLevel, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub overall: HealthLevel,
+    pub embedding: SubsystemHealth,
+    pub index: SubsystemHealth,
+    pub llm: SubsystemHealth,
+    pub hardware: SubsystemHealth,
+    pub gpu: SubsystemHealth,
+    pub disk: SubsystemHealth,
+}
+
+// Read-only snapshot of every subsystem. Locks are only tried, never awaited, so a long
+// generation or ingestion holding a write lock shows up as "busy" instead of stalling the check.
+pub async fn check(state: &AppState, command_state: &CommandState) -> HealthReport {
+    let (embedding, index) = match state.rag_engine.try_read() {
+        Ok(rag) => {
+            let stats = rag.get_stats();
+            let embedding = match rag.index_error() {
+                Some(error) => SubsystemHealth::new(HealthLevel::Error, error),
+                None => SubsystemHealth::new(
+                    HealthLevel::Ok,
+                    format!("{} ({} dimensions)", stats.embedding_model, stats.embedding_dim),
+                ),
+            };
+            let index = if stats.document_count == 0 {
+                SubsystemHealth::new(HealthLevel::Warn, "Knowledge base is empty")
+            } else {
+                SubsystemHealth::new(
+                    HealthLevel::Ok,
+                    format!("{} documents, {} chunks", stats.document_count, stats.chunk_count),
+                )
+            };
+            (embedding, index)
+        }
+        Err(_) => (
+            SubsystemHealth::new(HealthLevel::Ok, "Busy embedding documents"),
+            SubsystemHealth::new(HealthLevel::Ok, "Busy updating the index"),
+        ),
+    };
+
+    let llm = match state.llm_manager.try_read() {
+        Ok(llm) => match llm.get_active_model() {
+            Some(model) => SubsystemHealth::new(HealthLevel::Ok, format!("{} loaded", model)),
+            None if llm.has_usable_model() => SubsystemHealth::new(HealthLevel::Warn, "No model loaded"),
+            None => SubsystemHealth::new(HealthLevel::Error, "No model downloaded"),
+        },
+        Err(_) => SubsystemHealth::new(HealthLevel::Ok, "Busy generating"),
+    };
+
+    let hardware = match state.hardware_monitor.try_read() {
+        Ok(monitor) => match monitor.get_status().await {
+            Ok(status) if status.is_safe => SubsystemHealth::new(
+                HealthLevel::Ok,
+                format!("CPU {:.0}%, memory {:.0}%", status.cpu_usage, status.memory_usage),
+            ),
+            Ok(status) => SubsystemHealth::new(
+                HealthLevel::Warn,
+                format!("Above safety thresholds: CPU {:.0}%, memory {:.0}%", status.cpu_usage, status.memory_usage),
+            ),
+            Err(e) => SubsystemHealth::new(HealthLevel::Error, e.to_string()),
+        },
+        Err(_) => SubsystemHealth::new(HealthLevel::Ok, "Busy refreshing metrics"),
+    };
+
+    let gpu = match command_state.try_monitor() {
+        Some(monitor) => {
+            let gpus = monitor.list_gpus();
+            match gpus.iter().find(|gpu| gpu.selected) {
+                Some(gpu) => SubsystemHealth::new(
+                    HealthLevel::Ok,
+                    format!("{} ({} MB VRAM)", gpu.name, gpu.vram_total_mb),
+                ),
+                None => SubsystemHealth::new(HealthLevel::Warn, "No supported GPU detected; inference runs on CPU"),
+            }
+        }
+        None => SubsystemHealth::new(HealthLevel::Ok, "Busy"),
+    };
+
+    let disk = disk_health(&state.data_dir);
+
+    let overall = [&embedding, &index, &llm, &hardware, &gpu, &disk]
+        .iter()
+        .map(|subsystem| subsystem.level)
+        .max()
+        .unwrap_or(HealthLevel::Ok);
+
+    HealthReport {
+        overall,
+        embedding,
+        index,
+        llm,
+        hardware,
+        gpu,
+        disk,
+    }
+}
+
+// Picks the disk with the longest mount point containing the data dir
+fn disk_health(data_dir: &Path) -> SubsystemHealth {
+    let mut system = System::new();
+    system.refresh_disks_list();
+
+    let data_dir = data_dir.canonicalize().unwrap_or_else(|_| data_dir.to_path_buf());
+    let disk = system
+        .disks()
+        .iter()
+        .filter(|disk| data_dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len());
+
+    match disk {
+        Some(disk) => {
+            let free = disk.available_space();
+            let message = format!("{:.1} GB free on {}", free as f64 / 1_073_741_824.0, disk.mount_point().display());
+            let level = match free {
+                f if f < DISK_ERROR_BYTES => HealthLevel::Error,
+                f if f < DISK_WARN_BYTES => HealthLevel::Warn,
+                _ => HealthLevel::Ok,
+            };
+            SubsystemHealth::new(level, message)
+        }
+        None => SubsystemHealth::new(HealthLevel::Warn, "Could not determine free space for the data directory"),
+    }
+}
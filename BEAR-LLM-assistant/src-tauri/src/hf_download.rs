@@ -0,0 +1,379 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+
+// Downloads model files from the Hugging Face Hub straight into a target directory.
+// hf_hub is used to list the repo; the files themselves are fetched with reqwest so they can
+// land outside the hub cache and resume from a `.part` file after an interruption.
+
+pub const HUB_URL: &str = "https://huggingface.co";
+// Read by the hub tooling as well; needed for gated repos the user has been granted
+const HF_TOKEN_ENV: &str = "HF_TOKEN";
+const PARTIAL_SUFFIX: &str = "part";
+
+// Files a sentence-transformers / safetensors model needs when the repo has no GGUF
+const SAFETENSORS_FILES: &[&str] = &["config.json", "tokenizer.json", "model.safetensors", "sentence_bert_config.json"];
+// Preferred GGUF quantizations, best size/quality trade-off first
+const PREFERRED_QUANTS: &[&str] = &["Q4_K_M", "Q5_K_M", "Q4_0", "Q8_0"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    // Bytes fetched in this session only; resumed bytes don't inflate the speed
+    pub bytes_per_second: f64,
+}
+
+// Problems the user has to act on, as opposed to transient network errors
+#[derive(Debug)]
+pub enum DownloadError {
+    NotFound(String),
+    Gated(String),
+    Cancelled,
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::NotFound(what) => write!(f, "{} was not found on Hugging Face", what),
+            DownloadError::Gated(repo) => write!(
+                f,
+                "{} is gated or private. Accept its license on huggingface.co and set {} to an access token",
+                repo, HF_TOKEN_ENV
+            ),
+            DownloadError::Cancelled => write!(f, "Download cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+fn token() -> Option<String> {
+    std::env::var(HF_TOKEN_ENV).ok().filter(|token| !token.trim().is_empty())
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    let mut partial = target.as_os_str().to_os_string();
+    partial.push(format!(".{}", PARTIAL_SUFFIX));
+    PathBuf::from(partial)
+}
+
+fn target_path(save_dir: &Path, filename: &str) -> Result<PathBuf> {
+    // Repo paths may contain directories; only the file name is kept
+    let name = Path::new(filename)
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid file name: {}", filename))?;
+    Ok(save_dir.join(name))
+}
+
+fn file_url(hub_url: &str, model_id: &str, filename: &str) -> String {
+    format!("{}/{}/resolve/main/{}", hub_url, model_id, filename)
+}
+
+// Adds the user's access token, if any, to a hub request
+pub fn authorized(builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match token() {
+        Some(token) => builder.bearer_auth(token),
+        None => builder,
+    }
+}
+
+fn check_status(status: reqwest::StatusCode, model_id: &str, filename: &str) -> Result<()> {
+    match status.as_u16() {
+        401 | 403 => Err(DownloadError::Gated(model_id.to_string()).into()),
+        404 => Err(DownloadError::NotFound(format!("{}/{}", model_id, filename)).into()),
+        _ if status.is_success() => Ok(()),
+        code => Err(anyhow!("Hugging Face returned HTTP {} for {}/{}", code, model_id, filename)),
+    }
+}
+
+// Size of a file from a HEAD response. content_length() can't be used: for a HEAD request it
+// reports the empty body. LFS files carry their real size in x-linked-size.
+fn head_size(headers: &reqwest::header::HeaderMap) -> u64 {
+    ["x-linked-size", reqwest::header::CONTENT_LENGTH.as_str()]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok()?.trim().parse::<u64>().ok())
+        .find(|size| *size > 0)
+        .unwrap_or(0)
+}
+
+// Which files to fetch: the named one, else the preferred GGUF plus its tokenizer, else the
+// files of a safetensors model
+pub async fn select_files(model_id: &str, filename: Option<&str>) -> Result<Vec<String>> {
+    if let Some(filename) = filename {
+        return Ok(vec![filename.to_string()]);
+    }
+
+    let mut builder = hf_hub::api::tokio::ApiBuilder::new().with_progress(false);
+    if let Some(token) = token() {
+        builder = builder.with_token(Some(token));
+    }
+    let api = builder.build()?;
+    // info() would try to parse an error page as repo info, so the status is checked first
+    let response = api
+        .model(model_id.to_string())
+        .info_request()
+        .send()
+        .await
+        .map_err(|e| anyhow!("Could not list {}: {}", model_id, e))?;
+    match response.status().as_u16() {
+        401 | 403 => return Err(DownloadError::Gated(model_id.to_string()).into()),
+        404 => return Err(DownloadError::NotFound(model_id.to_string()).into()),
+        code if !(200..300).contains(&code) => {
+            return Err(anyhow!("Hugging Face returned HTTP {} listing {}", code, model_id));
+        }
+        _ => {}
+    }
+    let info: hf_hub::api::RepoInfo = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Could not read the file list of {}: {}", model_id, e))?;
+    let files: Vec<String> = info.siblings.into_iter().map(|s| s.rfilename).collect();
+
+    let ggufs: Vec<&String> = files.iter().filter(|f| f.to_lowercase().ends_with(".gguf")).collect();
+    if !ggufs.is_empty() {
+        let chosen = PREFERRED_QUANTS
+            .iter()
+            .find_map(|quant| ggufs.iter().find(|f| f.to_uppercase().contains(quant)))
+            .unwrap_or(&ggufs[0]);
+        let mut selected = vec![chosen.to_string()];
+        if files.iter().any(|f| f == "tokenizer.json") {
+            selected.push("tokenizer.json".to_string());
+        }
+        return Ok(selected);
+    }
+
+    let selected: Vec<String> = SAFETENSORS_FILES
+        .iter()
+        .filter(|name| files.iter().any(|f| f == *name))
+        .map(|name| name.to_string())
+        .collect();
+    if selected.is_empty() {
+        return Err(anyhow!("{} has no GGUF or safetensors model files", model_id));
+    }
+    Ok(selected)
+}
+
+// Downloads `files` into `save_dir`, calling `on_progress` at most every `interval` and once
+// at the end. Finished files are skipped; partial ones resume where they stopped. Cancelling
+// fails with DownloadError::Cancelled and leaves the `.part` file for a later resume.
+pub async fn download_files(
+    model_id: &str,
+    files: &[String],
+    save_dir: &Path,
+    interval: Duration,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(Progress),
+) -> Result<Vec<PathBuf>> {
+    download_files_from(HUB_URL, model_id, files, save_dir, interval, cancel, on_progress).await
+}
+
+async fn download_files_from(
+    hub_url: &str,
+    model_id: &str,
+    files: &[String],
+    save_dir: &Path,
+    interval: Duration,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(Progress),
+) -> Result<Vec<PathBuf>> {
+    tokio::fs::create_dir_all(save_dir).await?;
+    let client = reqwest::Client::new();
+
+    // Sizes up front so progress covers every file, not just the current one
+    let mut sizes = Vec::with_capacity(files.len());
+    for filename in files {
+        let response = authorized(client.head(file_url(hub_url, model_id, filename))).send().await?;
+        check_status(response.status(), model_id, filename)?;
+        sizes.push(head_size(response.headers()));
+    }
+    let total_bytes: u64 = sizes.iter().sum();
+
+    let started = Instant::now();
+    let mut last_report = Instant::now();
+    let mut fetched_this_session = 0u64;
+    let mut completed_bytes = 0u64;
+    let mut saved = Vec::with_capacity(files.len());
+
+    for (filename, size) in files.iter().zip(sizes) {
+        if cancel.is_cancelled() {
+            return Err(DownloadError::Cancelled.into());
+        }
+        let target = target_path(save_dir, filename)?;
+        let partial = partial_path(&target);
+
+        let already_done = tokio::fs::metadata(&target).await.map(|m| m.len() == size && size > 0).unwrap_or(false);
+        if already_done {
+            completed_bytes += size;
+            saved.push(target);
+            continue;
+        }
+
+        let mut offset = tokio::fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0);
+        // Fully fetched before an interruption but never renamed; a range request would get 416
+        if size > 0 && offset == size {
+            tokio::fs::rename(&partial, &target).await?;
+            completed_bytes += size;
+            saved.push(target);
+            continue;
+        }
+        let mut builder = authorized(client.get(file_url(hub_url, model_id, filename)));
+        if offset > 0 {
+            builder = builder.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        let mut response = builder.send().await?;
+        check_status(response.status(), model_id, filename)?;
+
+        // A server that ignores the range sends the whole file again
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            offset = 0;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&partial)
+            .await?;
+
+        let mut written = offset;
+        loop {
+            // Racing the cancel token stops a stalled connection too, not just a busy one
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk?,
+                _ = cancel.cancelled() => {
+                    file.flush().await?;
+                    return Err(DownloadError::Cancelled.into());
+                }
+            };
+            let Some(chunk) = chunk else { break };
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            fetched_this_session += chunk.len() as u64;
+
+            if last_report.elapsed() >= interval {
+                on_progress(progress(completed_bytes + written, total_bytes, fetched_this_session, started));
+                last_report = Instant::now();
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        tokio::fs::rename(&partial, &target).await?;
+        completed_bytes += written;
+        saved.push(target);
+    }
+
+    on_progress(progress(completed_bytes, total_bytes.max(completed_bytes), fetched_this_session, started));
+    Ok(saved)
+}
+
+// Deletes whatever `.part` files a cancelled or failed download left in `save_dir`
+pub async fn remove_partial_files(files: &[String], save_dir: &Path) -> Result<()> {
+    for filename in files {
+        let partial = partial_path(&target_path(save_dir, filename)?);
+        match tokio::fs::remove_file(&partial).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("Cannot remove {}: {}", partial.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+fn progress(downloaded_bytes: u64, total_bytes: u64, fetched: u64, started: Instant) -> Progress {
+    let elapsed = started.elapsed().as_secs_f64();
+    Progress {
+        downloaded_bytes,
+        total_bytes,
+        bytes_per_second: if elapsed > 0.0 { fetched as f64 / elapsed } else { 0.0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
+
+    #[test]
+    fn head_size_prefers_linked_size_and_ignores_empty_lengths() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        assert_eq!(head_size(&headers), 0);
+
+        headers.insert("x-linked-size", HeaderValue::from_static("4368439584"));
+        assert_eq!(head_size(&headers), 4_368_439_584);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1024"));
+        assert_eq!(head_size(&headers), 1024);
+    }
+
+    // Serves a 1 MB file that sends its first 4 KB and then stalls, like a dead connection
+    async fn stalling_hub() -> String {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    loop {
+                        let n = socket.read(&mut buf).await.unwrap_or(0);
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                        if !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            continue;
+                        }
+                        let head = "HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n";
+                        socket.write_all(head.as_bytes()).await.unwrap();
+                        if request.starts_with(b"GET") {
+                            socket.write_all(&[b'x'; 4096]).await.unwrap();
+                            std::future::pending::<()>().await;
+                        }
+                        request.clear();
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_stalled_download_stops_it_promptly_and_keeps_the_partial_file() {
+        let hub_url = stalling_hub().await;
+        let dir = tempfile::tempdir().unwrap();
+        let save_dir = dir.path().to_path_buf();
+        let files = vec!["model.gguf".to_string()];
+        let cancel = CancellationToken::new();
+
+        let download = tokio::spawn({
+            let (cancel, files, save_dir) = (cancel.clone(), files.clone(), save_dir.clone());
+            async move {
+                download_files_from(&hub_url, "test/model", &files, &save_dir, Duration::from_millis(10), &cancel, |_| {})
+                    .await
+            }
+        });
+        let partial = partial_path(&save_dir.join("model.gguf"));
+        while tokio::fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0) < 4096 {
+            assert!(!download.is_finished(), "download ended before it was cancelled");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        cancel.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(2), download).await.expect("download kept running").unwrap();
+        let error = result.unwrap_err();
+        assert!(matches!(error.downcast_ref::<DownloadError>(), Some(DownloadError::Cancelled)));
+        assert_eq!(std::fs::metadata(&partial).unwrap().len(), 4096);
+        assert!(!save_dir.join("model.gguf").exists());
+
+        remove_partial_files(&files, &save_dir).await.unwrap();
+        assert!(!partial.exists());
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::commands::{HuggingFaceModel, HuggingFaceSearchPage};
+use crate::hf_download::{self, HUB_URL};
+
+// Hugging Face Hub model search. The API pages with a cursor in the `Link` header; size
+// filtering happens here because the API has no parameter-count filter, so a page can come
+// back shorter than `limit`.
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+// Fields requested per model; anything not listed is omitted from the response
+const EXPAND_FIELDS: &[&str] = &["author", "downloads", "likes", "tags", "pipeline_tag", "lastModified", "safetensors", "gguf", "usedStorage"];
+
+// Parameter-count buckets accepted by `filter_size`
+const SMALL_MAX_PARAMS: u64 = 4_000_000_000;
+const MEDIUM_MAX_PARAMS: u64 = 14_000_000_000;
+
+lazy_static! {
+    static ref NEXT_LINK_REGEX: Regex = Regex::new(r#"<([^>]+)>;\s*rel="next""#).unwrap();
+    // "7b", "1.5B", "70B" in a repo name
+    static ref NAME_PARAMS_REGEX: Regex = Regex::new(r"(?i)(?:^|[-_./])(\d+(?:\.\d+)?)b(?:$|[-_./])").unwrap();
+}
+
+#[derive(Deserialize)]
+struct ParamTotals {
+    total: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiModel {
+    id: String,
+    author: Option<String>,
+    #[serde(default)]
+    downloads: u64,
+    #[serde(default)]
+    likes: u32,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(rename = "pipeline_tag")]
+    pipeline_tag: Option<String>,
+    last_modified: Option<String>,
+    safetensors: Option<ParamTotals>,
+    gguf: Option<ParamTotals>,
+    used_storage: Option<u64>,
+}
+
+impl ApiModel {
+    fn param_count(&self) -> Option<u64> {
+        self.safetensors.as_ref().and_then(|p| p.total)
+            .or_else(|| self.gguf.as_ref().and_then(|p| p.total))
+            .or_else(|| {
+                let caps = NAME_PARAMS_REGEX.captures(&self.id)?;
+                let billions: f64 = caps[1].parse().ok()?;
+                Some((billions * 1e9) as u64)
+            })
+    }
+
+    fn into_model(self) -> HuggingFaceModel {
+        let param_count = self.param_count();
+        let model_name = self.id.rsplit('/').next().unwrap_or(&self.id).to_string();
+        let author = self.author.clone()
+            .or_else(|| self.id.split_once('/').map(|(author, _)| author.to_string()))
+            .unwrap_or_default();
+        let license = self.tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix("license:"))
+            .unwrap_or("unknown")
+            .to_string();
+        let pipeline_tag = self.pipeline_tag.unwrap_or_default();
+        let description = match (param_count, pipeline_tag.is_empty()) {
+            (Some(params), false) => format!("{} model, {:.1}B parameters", pipeline_tag, params as f64 / 1e9),
+            (Some(params), true) => format!("{:.1}B parameters", params as f64 / 1e9),
+            (None, false) => format!("{} model", pipeline_tag),
+            (None, true) => String::new(),
+        };
+
+        HuggingFaceModel {
+            model_id: self.id,
+            author,
+            model_name,
+            likes: self.likes,
+            downloads: self.downloads,
+            tags: self.tags,
+            size_bytes: self.used_storage.unwrap_or(0),
+            last_modified: self.last_modified.unwrap_or_default(),
+            description,
+            license,
+            pipeline_tag,
+            param_count,
+        }
+    }
+}
+
+// "small" (< 4B), "medium" (4-14B), "large" (> 14B), or a literal size such as "7b" matched
+// against the repo name. Models of unknown size only pass a literal filter.
+fn matches_size(model: &HuggingFaceModel, filter: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    match (filter.as_str(), model.param_count) {
+        ("" | "any" | "all", _) => true,
+        ("small", Some(params)) => params < SMALL_MAX_PARAMS,
+        ("medium", Some(params)) => (SMALL_MAX_PARAMS..=MEDIUM_MAX_PARAMS).contains(&params),
+        ("large", Some(params)) => params > MEDIUM_MAX_PARAMS,
+        ("small" | "medium" | "large", None) => false,
+        (literal, _) => model.model_id.to_lowercase().contains(literal),
+    }
+}
+
+// `cursor` is the opaque value returned as `next_cursor` by the previous page
+pub async fn search_models(
+    query: &str,
+    filter_size: Option<&str>,
+    filter_type: Option<&str>,
+    limit: Option<usize>,
+    cursor: Option<&str>,
+) -> Result<HuggingFaceSearchPage> {
+    let url = match cursor {
+        // Only follow cursors that point back at the hub
+        Some(cursor) if cursor.starts_with(&format!("{}/api/models", HUB_URL)) => cursor.to_string(),
+        Some(_) => return Err(anyhow!("Invalid search cursor")),
+        None => {
+            let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+            let mut params = vec![
+                ("search".to_string(), query.trim().to_string()),
+                ("limit".to_string(), limit.to_string()),
+                ("sort".to_string(), "downloads".to_string()),
+                ("direction".to_string(), "-1".to_string()),
+            ];
+            // Pipeline tags ("text-generation") and libraries ("gguf") are both plain tags
+            if let Some(filter_type) = filter_type.filter(|f| !f.trim().is_empty()) {
+                params.push(("filter".to_string(), filter_type.trim().to_string()));
+            }
+            params.extend(EXPAND_FIELDS.iter().map(|field| ("expand[]".to_string(), field.to_string())));
+            reqwest::Url::parse_with_params(&format!("{}/api/models", HUB_URL), &params)?.to_string()
+        }
+    };
+
+    let response = hf_download::authorized(reqwest::Client::new().get(&url))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Could not reach Hugging Face: {}", e))?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Hugging Face search failed with HTTP {}", response.status()));
+    }
+
+    let link = response
+        .headers()
+        .get(reqwest::header::LINK)
+        .and_then(|link| link.to_str().ok())
+        .map(str::to_string);
+    let body = response
+        .text()
+        .await
+        .map_err(|e| anyhow!("Could not read Hugging Face search results: {}", e))?;
+
+    parse_page(&body, link.as_deref(), filter_size)
+}
+
+// One page of the search API: the JSON body and the response's `Link` header
+fn parse_page(body: &str, link: Option<&str>, filter_size: Option<&str>) -> Result<HuggingFaceSearchPage> {
+    let next_cursor = link
+        .and_then(|link| NEXT_LINK_REGEX.captures(link))
+        .map(|caps| caps[1].to_string());
+
+    let models: Vec<ApiModel> = serde_json::from_str(body)
+        .map_err(|e| anyhow!("Unexpected response from Hugging Face search: {}", e))?;
+
+    let models = models
+        .into_iter()
+        .map(ApiModel::into_model)
+        .filter(|model| filter_size.is_none_or(|filter| matches_size(model, filter)))
+        .collect();
+
+    Ok(HuggingFaceSearchPage { models, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed from a real /api/models?search=mistral response
+    const RECORDED_PAGE: &str = r#"[
+        {"id": "mistralai/Mistral-7B-Instruct-v0.2", "author": "mistralai", "downloads": 2500000, "likes": 2600,
+         "tags": ["transformers", "safetensors", "text-generation", "license:apache-2.0"], "pipeline_tag": "text-generation",
+         "lastModified": "2024-09-27T10:41:20.000Z", "safetensors": {"total": 7241732096}, "usedStorage": 44000000000},
+        {"id": "TheBloke/Mistral-7B-Instruct-v0.2-GGUF", "author": "TheBloke", "downloads": 90000, "likes": 400,
+         "tags": ["gguf", "mistral"], "pipeline_tag": "text-generation", "gguf": {"total": 7241732096}},
+        {"id": "someone/tiny-mistral-0.5b"}
+    ]"#;
+    const RECORDED_LINK: &str =
+        r#"<https://huggingface.co/api/models?search=mistral&cursor=eyIkb3IiOlt7Il9pZCI6eyIkZ3QiOiI2In19XX0%3D>; rel="next""#;
+
+    #[test]
+    fn test_recorded_page_parses_every_model_and_the_cursor() {
+        let page = parse_page(RECORDED_PAGE, Some(RECORDED_LINK), None).unwrap();
+        assert_eq!(page.models.len(), 3);
+        assert!(page.next_cursor.unwrap().starts_with(&format!("{}/api/models?search=mistral&cursor=", HUB_URL)));
+
+        let instruct = &page.models[0];
+        assert_eq!(instruct.model_name, "Mistral-7B-Instruct-v0.2");
+        assert_eq!(instruct.license, "apache-2.0");
+        assert_eq!(instruct.param_count, Some(7241732096));
+        assert_eq!(instruct.size_bytes, 44000000000);
+        assert_eq!(page.models[1].param_count, Some(7241732096));
+        assert_eq!(page.models[1].license, "unknown");
+        // No author or size fields: both come from the repo id
+        assert_eq!(page.models[2].author, "someone");
+        assert_eq!(page.models[2].param_count, Some(500_000_000));
+
+        let small = parse_page(RECORDED_PAGE, None, Some("small")).unwrap();
+        assert_eq!(small.models.len(), 1);
+        assert!(small.next_cursor.is_none());
+        assert!(parse_page("{\"error\": \"rate limited\"}", None, None).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::{Result, anyhow};
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::{quantized_llama, quantized_phi, quantized_phi3, quantized_qwen2};
+use std::path::{Path, PathBuf};
+use tokenizers::Tokenizer;
+
+use crate::llm_manager::{FinishReason, LLMError};
+
+// GGUF inference with candle. GGUF files carry the vocabulary but candle can't build a
+// tokenizer from it, so a tokenizer.json from the original model repo must sit next to the file.
+
+// End-of-turn markers used by common chat models, in addition to the file's own EOS token
+const STOP_TOKENS: &[&str] = &["</s>", "<|endoftext|>", "<|end|>", "<|im_end|>", "<|eot_id|>", "<end_of_turn>"];
+
+enum Weights {
+    Llama(quantized_llama::ModelWeights),
+    Phi2(quantized_phi::ModelWeights),
+    Phi3(quantized_phi3::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+}
+
+impl Weights {
+    fn forward(&mut self, input: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+        match self {
+            Weights::Llama(model) => model.forward(input, position),
+            Weights::Phi2(model) => model.forward(input, position),
+            Weights::Phi3(model) => model.forward(input, position),
+            Weights::Qwen2(model) => model.forward(input, position),
+        }
+    }
+}
+
+// A model resident in memory, ready to generate
+pub struct LoadedModel {
+    pub name: String,
+    pub path: PathBuf,
+    // Size of the weights as stored on the device; the KV cache comes on top during generation
+    pub resident_bytes: u64,
+    weights: Weights,
+    tokenizer: Tokenizer,
+    device: Device,
+    stop_tokens: Vec<u32>,
+}
+
+impl LoadedModel {
+    pub fn load(name: &str, model_file: &Path, device: Device) -> Result<Self> {
+        let tokenizer_file = model_file
+            .parent()
+            .map(|dir| dir.join("tokenizer.json"))
+            .filter(|path| path.exists())
+            .ok_or_else(|| anyhow!(
+                "No tokenizer.json next to {}; download it from the model's original repository",
+                model_file.display()
+            ))?;
+        let tokenizer = Tokenizer::from_file(&tokenizer_file).map_err(|e| anyhow!(e))?;
+
+        let mut file = std::fs::File::open(model_file)
+            .map_err(|e| anyhow!("Cannot open model file {}: {}", model_file.display(), e))?;
+        let content = gguf_file::Content::read(&mut file)
+            .map_err(|e| anyhow!("Cannot read GGUF file {}: {}", model_file.display(), e))?;
+
+        let mut stop_tokens: Vec<u32> = STOP_TOKENS
+            .iter()
+            .filter_map(|token| tokenizer.token_to_id(token))
+            .collect();
+        if let Some(eos) = content
+            .metadata
+            .get("tokenizer.ggml.eos_token_id")
+            .and_then(|value| value.to_u32().ok())
+        {
+            stop_tokens.push(eos);
+        }
+
+        let resident_bytes = content
+            .tensor_infos
+            .values()
+            .map(|info| {
+                let dtype = info.ggml_dtype;
+                (info.shape.elem_count() / dtype.block_size() * dtype.type_size()) as u64
+            })
+            .sum();
+
+        let architecture = content
+            .metadata
+            .get("general.architecture")
+            .and_then(|value| value.to_string().ok())
+            .cloned()
+            .unwrap_or_default();
+        let weights = match architecture.as_str() {
+            "llama" | "mistral" => Weights::Llama(quantized_llama::ModelWeights::from_gguf(content, &mut file, &device)?),
+            "phi2" => Weights::Phi2(quantized_phi::ModelWeights::from_gguf(content, &mut file, &device)?),
+            "phi3" => Weights::Phi3(quantized_phi3::ModelWeights::from_gguf(false, content, &mut file, &device)?),
+            "qwen2" => Weights::Qwen2(quantized_qwen2::ModelWeights::from_gguf(content, &mut file, &device)?),
+            other => return Err(LLMError::UnsupportedArchitecture(other.to_string()).into()),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            path: model_file.to_path_buf(),
+            resident_bytes,
+            weights,
+            tokenizer,
+            device,
+            stop_tokens,
+        })
+    }
+
+    pub fn tokenize(&self, prompt: &str) -> Result<Vec<u32>> {
+        Ok(self.tokenizer.encode(prompt, true).map_err(|e| anyhow!(e))?.get_ids().to_vec())
+    }
+
+    // Decodes until a stop token or `max_tokens`. `on_token` is called once per generated token
+    // with the text it completed (a token that ends mid-character yields None until the next
+    // one) and returns false to stop early.
+    pub fn generate(
+        &mut self,
+        prompt_tokens: &[u32],
+        max_tokens: usize,
+        sampler: &mut LogitsProcessor,
+        on_token: impl FnMut(Option<&str>) -> bool,
+    ) -> Result<(String, FinishReason)> {
+        if prompt_tokens.is_empty() {
+            return Err(anyhow!("Prompt is empty"));
+        }
+
+        let mut stream = TokenStream::default();
+
+        // Position 0 resets the key/value cache left over from the previous request
+        let input = Tensor::new(prompt_tokens, &self.device)?.unsqueeze(0)?;
+        let first = sampler.sample(&last_logits(self.weights.forward(&input, 0)?)?)?;
+
+        let (weights, device, tokenizer) = (&mut self.weights, &self.device, &self.tokenizer);
+        let (mut text, finish_reason) = decode_tokens(
+            first,
+            max_tokens,
+            &self.stop_tokens,
+            |token| stream.push(tokenizer, token),
+            |token, generated| {
+                let input = Tensor::new(&[token], device)?.unsqueeze(0)?;
+                let logits = weights.forward(&input, prompt_tokens.len() + generated)?;
+                Ok(sampler.sample(&last_logits(logits)?)?)
+            },
+            on_token,
+        )?;
+
+        if let Some(rest) = stream.flush(tokenizer)? {
+            text.push_str(&rest);
+        }
+        Ok((text, finish_reason))
+    }
+}
+
+// The decoding loop without the model. `detokenize` turns each token into the text it completes,
+// and `step(token, generated)` feeds `token` in as the `generated`th output and samples the next.
+fn decode_tokens(
+    mut next: u32,
+    max_tokens: usize,
+    stop_tokens: &[u32],
+    mut detokenize: impl FnMut(u32) -> Result<Option<String>>,
+    mut step: impl FnMut(u32, usize) -> Result<u32>,
+    mut on_token: impl FnMut(Option<&str>) -> bool,
+) -> Result<(String, FinishReason)> {
+    let mut text = String::new();
+    for generated in 0..max_tokens {
+        if stop_tokens.contains(&next) {
+            return Ok((text, FinishReason::Stop));
+        }
+
+        let piece = detokenize(next)?;
+        if let Some(piece) = &piece {
+            text.push_str(piece);
+        }
+        if !on_token(piece.as_deref()) {
+            return Ok((text, FinishReason::Cancelled));
+        }
+
+        if generated + 1 == max_tokens {
+            break;
+        }
+        next = step(next, generated)?;
+    }
+    Ok((text, FinishReason::Length))
+}
+
+// Logits for the final position as a flat f32 vector, whatever shape the architecture returns
+fn last_logits(logits: Tensor) -> Result<Tensor> {
+    let logits = logits.squeeze(0)?;
+    let logits = if logits.rank() == 2 { logits.get(logits.dim(0)? - 1)? } else { logits };
+    Ok(logits.to_dtype(DType::F32)?)
+}
+
+// Incremental detokenizer: decoding tokens one at a time breaks multi-byte characters and
+// drops the leading spaces sentencepiece encodes into the next token
+#[derive(Default)]
+struct TokenStream {
+    tokens: Vec<u32>,
+    prev_index: usize,
+    current_index: usize,
+}
+
+impl TokenStream {
+    fn decode(&self, tokenizer: &Tokenizer, tokens: &[u32]) -> Result<String> {
+        tokenizer.decode(tokens, true).map_err(|e| anyhow!(e))
+    }
+
+    fn push(&mut self, tokenizer: &Tokenizer, token: u32) -> Result<Option<String>> {
+        let prev_text = self.decode(tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        self.tokens.push(token);
+        let text = self.decode(tokenizer, &self.tokens[self.prev_index..])?;
+
+        if text.len() > prev_text.len() && !text.ends_with('\u{FFFD}') {
+            let piece = text[prev_text.len()..].to_string();
+            self.prev_index = self.current_index;
+            self.current_index = self.tokens.len();
+            Ok(Some(piece))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn flush(&mut self, tokenizer: &Tokenizer) -> Result<Option<String>> {
+        let prev_text = self.decode(tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        let text = self.decode(tokenizer, &self.tokens[self.prev_index..])?;
+        self.prev_index = self.tokens.len();
+        self.current_index = self.tokens.len();
+        Ok(text.get(prev_text.len()..).filter(|rest| !rest.is_empty()).map(str::to_string))
+    }
+}
+
+// Writes a one-layer llama GGUF with random weights and a matching tokenizer.json under `dir`
+// and returns the model file. Small enough to load and generate with in any test.
+#[cfg(test)]
+pub(crate) fn write_tiny_model(dir: &Path) -> PathBuf {
+    use candle_core::quantized::{GgmlDType, QTensor};
+    use gguf_file::Value;
+
+    const VOCAB: usize = 16;
+    const DIM: usize = 8;
+    const HIDDEN: usize = 16;
+    const HEADS: u32 = 2;
+
+    let weight = |shape: &[usize]| {
+        let tensor = Tensor::randn(0f32, 1.0, shape, &Device::Cpu).unwrap();
+        QTensor::quantize(&tensor, GgmlDType::F32).unwrap()
+    };
+    let norm = || QTensor::quantize(&Tensor::ones(DIM, DType::F32, &Device::Cpu).unwrap(), GgmlDType::F32).unwrap();
+    let tensors = [
+        ("token_embd.weight", weight(&[VOCAB, DIM])),
+        ("output_norm.weight", norm()),
+        ("output.weight", weight(&[VOCAB, DIM])),
+        ("blk.0.attn_q.weight", weight(&[DIM, DIM])),
+        ("blk.0.attn_k.weight", weight(&[DIM, DIM])),
+        ("blk.0.attn_v.weight", weight(&[DIM, DIM])),
+        ("blk.0.attn_output.weight", weight(&[DIM, DIM])),
+        ("blk.0.ffn_gate.weight", weight(&[HIDDEN, DIM])),
+        ("blk.0.ffn_up.weight", weight(&[HIDDEN, DIM])),
+        ("blk.0.ffn_down.weight", weight(&[DIM, HIDDEN])),
+        ("blk.0.attn_norm.weight", norm()),
+        ("blk.0.ffn_norm.weight", norm()),
+    ];
+    let metadata = [
+        ("general.architecture", Value::String("llama".to_string())),
+        ("llama.context_length", Value::U32(64)),
+        ("llama.embedding_length", Value::U32(DIM as u32)),
+        ("llama.block_count", Value::U32(1)),
+        ("llama.attention.head_count", Value::U32(HEADS)),
+        ("llama.attention.head_count_kv", Value::U32(HEADS)),
+        ("llama.rope.dimension_count", Value::U32(DIM as u32 / HEADS)),
+        ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-5)),
+    ];
+
+    let model_file = dir.join("tiny-llama.gguf");
+    let mut file = std::fs::File::create(&model_file).unwrap();
+    gguf_file::write(
+        &mut file,
+        &metadata.iter().map(|(key, value)| (*key, value)).collect::<Vec<_>>(),
+        &tensors.iter().map(|(name, tensor)| (*name, tensor)).collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    // No stop tokens in the vocabulary, so generation always runs to max_tokens
+    let vocab: serde_json::Map<String, serde_json::Value> = (0..VOCAB)
+        .map(|id| (format!("w{}", id), serde_json::json!(id)))
+        .collect();
+    let tokenizer = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "Whitespace" },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "w0" },
+    });
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+    model_file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts up from the first token; each token reads as its number
+    fn decode_counting(first: u32, max_tokens: usize, stop_tokens: &[u32], mut on_token: impl FnMut(Option<&str>) -> bool) -> (String, FinishReason, usize) {
+        let mut steps = 0;
+        let (text, finish_reason) = decode_tokens(
+            first,
+            max_tokens,
+            stop_tokens,
+            |token| Ok(Some(format!("{} ", token))),
+            |token, generated| {
+                assert_eq!(generated, steps);
+                steps += 1;
+                Ok(token + 1)
+            },
+            &mut on_token,
+        )
+        .unwrap();
+        (text, finish_reason, steps)
+    }
+
+    #[test]
+    fn test_decoding_stops_at_max_tokens() {
+        let mut emitted = 0;
+        let (text, finish_reason, steps) = decode_counting(1, 5, &[], |_| {
+            emitted += 1;
+            true
+        });
+        assert_eq!(text, "1 2 3 4 5 ");
+        assert_eq!(finish_reason, FinishReason::Length);
+        assert_eq!(emitted, 5);
+        // No forward pass for a token that would be dropped
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn test_decoding_ends_at_a_stop_token_or_when_on_token_declines() {
+        let (text, finish_reason, _) = decode_counting(1, 10, &[4], |_| true);
+        assert_eq!((text.as_str(), finish_reason), ("1 2 3 ", FinishReason::Stop));
+
+        let (text, finish_reason, steps) = decode_counting(1, 10, &[], |piece| piece != Some("2 "));
+        assert_eq!((text.as_str(), finish_reason, steps), ("1 2 ", FinishReason::Cancelled, 1));
+    }
+}
@@ -1,8 +1,60 @@
 use anyhow::{Result, anyhow};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
 use tokio::fs;
+use candle_transformers::generation::LogitsProcessor;
+use tokio_util::sync::CancellationToken;
+
+use crate::gguf;
+use crate::inference::LoadedModel;
+
+// GGUF `general.architecture` values the inference backend can run
+pub const SUPPORTED_ARCHITECTURES: &[&str] = &["llama", "mistral", "phi2", "phi3", "qwen2"];
+
+// GGUF `general.file_type` values the backend can dequantize (IQ formats are not supported)
+const SUPPORTED_FILE_TYPES: &[u32] = &[0, 1, 2, 3, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 32];
+
+#[derive(Debug)]
+pub enum LLMError {
+    UnsupportedArchitecture(String),
+    UnsupportedQuantization(String),
+    NoModelAvailable(String),
+    ContextOverflow { prompt_tokens: usize, max_tokens: usize, context_length: usize },
+}
+
+impl std::fmt::Display for LLMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LLMError::UnsupportedArchitecture(name) => write!(
+                f,
+                "Unsupported model architecture '{}'. Supported architectures: {}",
+                name,
+                SUPPORTED_ARCHITECTURES.join(", ")
+            ),
+            LLMError::UnsupportedQuantization(name) => write!(
+                f,
+                "Unsupported quantization '{}'. Use an F32/F16/BF16, Q4-Q8 or K-quant GGUF file instead",
+                name
+            ),
+            LLMError::NoModelAvailable(name) => write!(
+                f,
+                "No model available: '{}' has not been downloaded. Download a model (or pick one you already have) before chatting",
+                name
+            ),
+            LLMError::ContextOverflow { prompt_tokens, max_tokens, context_length } => write!(
+                f,
+                "Prompt ({} tokens) plus max_tokens ({}) exceeds the model's context length of {} tokens. Shorten the prompt or lower max_tokens",
+                prompt_tokens, max_tokens, context_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LLMError {}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
@@ -14,23 +66,253 @@ pub struct ModelConfig {
     pub context_length: usize,
 }
 
+// Per-request sampling overrides; unset fields use the model's config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    // Same seed, prompt and params give the same tokens; None draws a fresh seed per request
+    pub seed: Option<u64>,
+    // Hard cap on generated tokens for this request; defaults to the model's max_tokens
+    pub max_tokens: Option<usize>,
+}
+
+impl GenerationParams {
+    // Sampler for the decode loop. Temperature <= 0 means greedy decoding, which is deterministic anyway.
+    pub fn logits_processor(&self, default_temperature: f32) -> LogitsProcessor {
+        let seed = self.seed.unwrap_or_else(random_seed);
+        let temperature = self.temperature.unwrap_or(default_temperature);
+        let temperature = if temperature > 0.0 { Some(temperature as f64) } else { None };
+        LogitsProcessor::new(seed, temperature, None)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    // The model ended the answer itself
+    Stop,
+    // Cut off at max_tokens
+    Length,
+    // Stopped by the caller; the text is what was generated up to that point
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    pub text: String,
+    pub finish_reason: FinishReason,
+    pub truncated: bool,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    // Cancelled because the hardware safety check failed while generating
+    pub stopped_for_safety: bool,
+}
+
+impl Generation {
+    fn new(text: String, finish_reason: FinishReason, prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            text,
+            finish_reason,
+            truncated: finish_reason == FinishReason::Length,
+            prompt_tokens,
+            completion_tokens,
+            stopped_for_safety: false,
+        }
+    }
+}
+
+fn random_seed() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos ^ (std::process::id() as u64).rotate_left(32)
+}
+
+// Throughput is averaged over the last few seconds so it tracks slowdowns quickly
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenEvent {
+    pub token: String,
+    pub index: usize,
+    pub tokens_per_second: f32,
+    // Upper bound: assumes generation runs to max_tokens
+    pub eta_seconds: Option<f32>,
+}
+
+// Rolling tokens/sec and ETA for one generation request
+pub struct ThroughputTracker {
+    window: VecDeque<Instant>,
+    generated: usize,
+    max_tokens: usize,
+}
+
+impl ThroughputTracker {
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            window: VecDeque::new(),
+            generated: 0,
+            max_tokens,
+        }
+    }
+
+    // Call once per generated token; returns (tokens/sec, eta seconds)
+    pub fn record_token(&mut self) -> (f32, Option<f32>) {
+        let now = Instant::now();
+        self.generated += 1;
+        self.window.push_back(now);
+
+        while let Some(oldest) = self.window.front() {
+            if now.duration_since(*oldest) > THROUGHPUT_WINDOW {
+                self.window.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let tokens_per_second = match self.window.front() {
+            Some(oldest) if self.window.len() > 1 => {
+                let elapsed = now.duration_since(*oldest).as_secs_f32();
+                if elapsed > 0.0 { (self.window.len() - 1) as f32 / elapsed } else { 0.0 }
+            }
+            _ => 0.0,
+        };
+
+        let remaining = self.max_tokens.saturating_sub(self.generated);
+        let eta_seconds = if tokens_per_second > 0.0 {
+            Some(remaining as f32 / tokens_per_second)
+        } else {
+            None
+        };
+
+        (tokens_per_second, eta_seconds)
+    }
+
+    pub fn generated(&self) -> usize {
+        self.generated
+    }
+}
+
+// Requests kept in the metrics history; older ones drop out of the recent list but stay in the totals
+const METRICS_HISTORY_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferenceRecord {
+    pub model: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub duration_ms: u64,
+    pub tokens_per_second: f32,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub requests: usize,
+    pub completion_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferenceMetricsSummary {
+    pub total_requests: usize,
+    pub total_prompt_tokens: usize,
+    pub total_completion_tokens: usize,
+    pub average_duration_ms: f64,
+    pub average_tokens_per_second: f64,
+    pub per_model: HashMap<String, ModelUsage>,
+    // Newest first
+    pub recent: Vec<InferenceRecord>,
+}
+
+// Running totals since startup plus a bounded history of individual requests
+#[derive(Debug, Default)]
+pub struct InferenceMetrics {
+    history: VecDeque<InferenceRecord>,
+    total_requests: usize,
+    total_prompt_tokens: usize,
+    total_completion_tokens: usize,
+    total_duration_ms: u64,
+    per_model: HashMap<String, ModelUsage>,
+}
+
+impl InferenceMetrics {
+    pub fn record(&mut self, record: InferenceRecord) {
+        self.total_requests += 1;
+        self.total_prompt_tokens += record.prompt_tokens;
+        self.total_completion_tokens += record.completion_tokens;
+        self.total_duration_ms += record.duration_ms;
+
+        let usage = self.per_model.entry(record.model.clone()).or_default();
+        usage.requests += 1;
+        usage.completion_tokens += record.completion_tokens;
+
+        self.history.push_back(record);
+        while self.history.len() > METRICS_HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn summary(&self, recent: usize) -> InferenceMetricsSummary {
+        let average_duration_ms = if self.total_requests > 0 {
+            self.total_duration_ms as f64 / self.total_requests as f64
+        } else {
+            0.0
+        };
+        let average_tokens_per_second = if self.total_duration_ms > 0 {
+            self.total_completion_tokens as f64 / (self.total_duration_ms as f64 / 1000.0)
+        } else {
+            0.0
+        };
+
+        InferenceMetricsSummary {
+            total_requests: self.total_requests,
+            total_prompt_tokens: self.total_prompt_tokens,
+            total_completion_tokens: self.total_completion_tokens,
+            average_duration_ms,
+            average_tokens_per_second,
+            per_model: self.per_model.clone(),
+            recent: self.history.iter().rev().take(recent).cloned().collect(),
+        }
+    }
+}
+
+// For scanned GGUF files whose header doesn't say
+const DEFAULT_CONTEXT_LENGTH: usize = 4096;
+const DEFAULT_MAX_TOKENS: usize = 2048;
+
 pub struct LLMManager {
     models: HashMap<String, ModelConfig>,
     active_model: Option<String>,
     models_dir: PathBuf,
+    gpu_index: Option<u32>,
+    metrics: Arc<Mutex<InferenceMetrics>>,
+    // Weights of the active model, kept resident between requests
+    loaded: Option<ResidentModel>,
+}
+
+// The weights sit behind their own mutex so a generation can run without holding the manager;
+// requests for the model queue on it instead
+type SharedModel = Arc<Mutex<LoadedModel>>;
+
+struct ResidentModel {
+    name: String,
+    path: PathBuf,
+    resident_bytes: u64,
+    model: SharedModel,
 }
 
 impl LLMManager {
-    pub fn new() -> Self {
-        let models_dir = dirs::data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("./"))
-            .join("legal-ai-assistant")
-            .join("models");
+    pub fn new(data_dir: &Path) -> Self {
+        let models_dir = data_dir.join("models");
 
         Self {
             models: HashMap::new(),
             active_model: None,
             models_dir,
+            gpu_index: None,
+            metrics: Arc::new(Mutex::new(InferenceMetrics::default())),
+            loaded: None,
         }
     }
 
@@ -72,11 +354,84 @@ impl LLMManager {
             self.models.insert(model.name.clone(), model);
         }
 
+        self.scan_models_dir();
         Ok(())
     }
 
+    pub fn models_dir(&self) -> &Path {
+        &self.models_dir
+    }
+
+    // Brings the registry in line with the GGUF files under models_dir: see model_files_changes
+    pub fn scan_models_dir(&mut self) -> usize {
+        let files = find_model_files(&self.models_dir);
+        self.apply_model_files(files)
+    }
+
+    // Files in `files` (a find_model_files listing) not covered by a configured model, and the
+    // names of models registered from a .gguf file that is no longer there. The loaded model is
+    // never reported as gone, and catalog entries pointing at a directory are kept for download.
+    pub fn model_files_changes(&self, files: &[PathBuf]) -> (Vec<PathBuf>, Vec<String>) {
+        let known: Vec<PathBuf> = self.models
+            .values()
+            .filter_map(|model| find_gguf_file(&model.path))
+            .collect();
+        let added = files
+            .iter()
+            .filter(|file| !known.contains(file))
+            .cloned()
+            .collect();
+
+        let removed = self.models
+            .values()
+            .filter(|model| has_gguf_extension(&model.path) && !files.contains(&model.path))
+            .filter(|model| self.loaded_model() != Some(model.name.as_str()))
+            .map(|model| model.name.clone())
+            .collect();
+
+        (added, removed)
+    }
+
+    // Registers new files and drops models whose file was deleted; returns how many were added
+    pub fn apply_model_files(&mut self, files: Vec<PathBuf>) -> usize {
+        let (new_files, removed) = self.model_files_changes(&files);
+        for name in removed {
+            self.models.remove(&name);
+        }
+
+        let mut added = 0;
+        for file in new_files {
+            let name = match file.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) if !self.models.contains_key(stem) => stem.to_string(),
+                _ => continue,
+            };
+
+            // A file with an unreadable header is still listed; loading it reports the problem
+            let metadata = gguf::read_metadata(&file).ok();
+            let context_length = metadata
+                .as_ref()
+                .and_then(|m| m.context_length)
+                .map(|n| n as usize)
+                .unwrap_or(DEFAULT_CONTEXT_LENGTH);
+
+            self.models.insert(name.clone(), ModelConfig {
+                name,
+                model_type: metadata
+                    .and_then(|m| m.architecture)
+                    .unwrap_or_else(|| "unknown".to_string()),
+                path: file,
+                max_tokens: (context_length / 2).min(DEFAULT_MAX_TOKENS),
+                temperature: 0.7,
+                context_length,
+            });
+            added += 1;
+        }
+
+        added
+    }
+
     pub async fn download_model(&mut self, model_name: &str) -> Result<()> {
-        let model_config = self.models.get(model_name)
+        self.models.get(model_name)
             .ok_or_else(|| anyhow!("Model not found: {}", model_name))?;
 
         println!("Downloading model: {}", model_name);
@@ -85,44 +440,505 @@ impl LLMManager {
     }
 
     pub async fn load_model(&mut self, model_name: &str) -> Result<()> {
-        if !self.models.contains_key(model_name) {
-            return Err(anyhow!("Model not found: {}", model_name));
+        let model_config = self.models.get(model_name)
+            .ok_or_else(|| anyhow!("Model not found: {}", model_name))?;
+        let model_file = find_gguf_file(&model_config.path)
+            .ok_or_else(|| LLMError::NoModelAvailable(model_name.to_string()))?;
+
+        if self.loaded.as_ref().is_some_and(|m| m.name == model_name && m.path == model_file) {
+            self.active_model = Some(model_name.to_string());
+            return Ok(());
         }
 
+        // Cheap header check so incompatible files fail before reading gigabytes of weights
+        validate_gguf(&model_file)?;
+
+        // Drop the previous weights first so two models are never resident at once; until the
+        // new ones load, no model is active
+        self.loaded = None;
+        self.active_model = None;
+        let device = candle_core::Device::cuda_if_available(self.gpu_index.unwrap_or(0) as usize)?;
+        let name = model_name.to_string();
+        let file = model_file.clone();
+        let loaded = tokio::task::spawn_blocking(move || LoadedModel::load(&name, &file, device))
+            .await
+            .map_err(|e| anyhow!("Model loading task failed: {}", e))??;
+
+        self.loaded = Some(ResidentModel {
+            name: loaded.name.clone(),
+            path: loaded.path.clone(),
+            resident_bytes: loaded.resident_bytes,
+            model: Arc::new(Mutex::new(loaded)),
+        });
         self.active_model = Some(model_name.to_string());
         println!("Loaded model: {}", model_name);
         Ok(())
     }
 
-    pub async fn generate_response(&mut self, prompt: &str, model_name: &str) -> Result<String> {
-        if self.active_model.as_deref() != Some(model_name) {
-            self.load_model(model_name).await?;
-        }
+    // Loads the model if needed and hands back what a request needs to generate with it. The
+    // manager lock is only needed for this step; unloading while a session is generating frees
+    // the weights once that generation ends.
+    pub async fn session(&mut self, model_name: &str) -> Result<GenerationSession> {
+        // Loads the weights on first use, or when the file on disk changed
+        self.load_model(model_name).await?;
+
+        let config = self.models
+            .get(model_name)
+            .ok_or_else(|| anyhow!("Model not found: {}", model_name))?;
+        let resident = self.loaded
+            .as_ref()
+            .ok_or_else(|| LLMError::NoModelAvailable(model_name.to_string()))?;
 
-        let response = format!(
-            "This is a placeholder response from model '{}'. \
-             In production, this would generate actual AI responses locally. \
-             Your prompt was: {}",
-            model_name, prompt
-        );
+        Ok(GenerationSession {
+            model_name: model_name.to_string(),
+            model: resident.model.clone(),
+            max_tokens: config.max_tokens,
+            context_length: config.context_length,
+            temperature: config.temperature,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    pub fn metrics_summary(&self, recent: usize) -> InferenceMetricsSummary {
+        lock_metrics(&self.metrics).summary(recent)
+    }
 
-        Ok(response)
+    // A model is usable once it is registered and its GGUF file is on disk
+    pub fn is_model_usable(&self, model_name: &str) -> bool {
+        self.models
+            .get(model_name)
+            .map(|model| find_gguf_file(&model.path).is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn has_usable_model(&self) -> bool {
+        self.models.keys().any(|name| self.is_model_usable(name))
+    }
+
+    pub fn ensure_model_available(&self, model_name: &str) -> Result<()> {
+        if self.is_model_usable(model_name) {
+            Ok(())
+        } else {
+            Err(LLMError::NoModelAvailable(model_name.to_string()).into())
+        }
     }
 
     pub async fn list_models(&self) -> Vec<String> {
         self.models.keys().cloned().collect()
     }
 
-    pub async fn get_model_info(&self, model_name: &str) -> Option<ModelConfig> {
-        self.models.get(model_name).cloned()
+    // Drops the weights; returns how many bytes they occupied
+    pub async fn unload_model(&mut self) -> Result<u64> {
+        let freed = self.resident_bytes();
+        self.loaded = None;
+        self.active_model = None;
+        Ok(freed)
     }
 
-    pub async fn unload_model(&mut self) -> Result<()> {
-        self.active_model = None;
-        Ok(())
+    // Bytes held by the resident model's weights; 0 when none is loaded
+    pub fn resident_bytes(&self) -> u64 {
+        self.loaded.as_ref().map_or(0, |model| model.resident_bytes)
+    }
+
+    pub fn loaded_model(&self) -> Option<&str> {
+        self.loaded.as_ref().map(|model| model.name.as_str())
+    }
+
+    // Size of the model's GGUF file, a close upper bound on what loading it will take
+    pub fn model_file_bytes(&self, model_name: &str) -> Result<u64> {
+        let model = self.models.get(model_name)
+            .ok_or_else(|| anyhow!("Model not found: {}", model_name))?;
+        let model_file = find_gguf_file(&model.path)
+            .ok_or_else(|| LLMError::NoModelAvailable(model_name.to_string()))?;
+        Ok(std::fs::metadata(model_file)?.len())
+    }
+
+    // Whether load_model puts weights in VRAM (CUDA build with a GPU) or system RAM
+    pub fn loads_to_gpu(&self) -> bool {
+        candle_core::utils::cuda_is_available()
+    }
+
+    // Device used for inference; None lets the backend pick
+    pub fn set_gpu_index(&mut self, index: Option<u32>) {
+        self.gpu_index = index;
     }
 
     pub fn get_active_model(&self) -> Option<String> {
         self.active_model.clone()
     }
-}
\ No newline at end of file
+}
+
+// Completion with the active model, as the agent and the legal tools need it. The app passes the
+// shared manager; tests script the replies.
+#[async_trait]
+pub trait TextGenerator: Send + Sync {
+    // Model that generate() will use; None when no model is loaded
+    async fn active_model(&self) -> Option<String>;
+
+    async fn generate(&self, prompt: &str, params: &GenerationParams) -> Result<Generation>;
+}
+
+#[async_trait]
+impl TextGenerator for tokio::sync::RwLock<LLMManager> {
+    async fn active_model(&self) -> Option<String> {
+        self.read().await.get_active_model()
+    }
+
+    // The manager lock is held to pick and load the model, not while generating
+    async fn generate(&self, prompt: &str, params: &GenerationParams) -> Result<Generation> {
+        let session = {
+            let mut llm = self.write().await;
+            let model = llm.get_active_model()
+                .ok_or_else(|| anyhow!("No model loaded; load a model first"))?;
+            llm.ensure_model_available(&model)?;
+            llm.session(&model).await?
+        };
+        session.generate(prompt, params, &CancellationToken::new(), |_| {}).await
+    }
+}
+
+// One request's view of a resident model: the shared weights plus the config it generates with
+pub struct GenerationSession {
+    model_name: String,
+    model: SharedModel,
+    max_tokens: usize,
+    context_length: usize,
+    temperature: f32,
+    metrics: Arc<Mutex<InferenceMetrics>>,
+}
+
+impl GenerationSession {
+    // Calls `on_token` for every generated token with rolling throughput stats; the tracker
+    // is created per call so stats never leak between requests.
+    // Generation stops at max_tokens; the result says whether it was cut off there.
+    // Decoding runs on a blocking thread and so does `on_token`, which may block it (the
+    // resource throttle pauses there). `cancel` is checked between tokens: a cancelled request
+    // stops after the token in flight and returns the partial text with FinishReason::Cancelled
+    // rather than an error. A request waiting for another to finish with the model is cancelled
+    // as soon as it gets the model, before generating anything.
+    pub async fn generate(
+        &self,
+        prompt: &str,
+        params: &GenerationParams,
+        cancel: &CancellationToken,
+        mut on_token: impl FnMut(TokenEvent) + Send + 'static,
+    ) -> Result<Generation> {
+        let max_tokens = params.max_tokens.unwrap_or(self.max_tokens);
+        if max_tokens == 0 {
+            return Err(anyhow!("max_tokens must be at least 1"));
+        }
+        let context_length = self.context_length;
+
+        // All randomness in decoding goes through this sampler, so a fixed seed reproduces the output
+        let mut sampler = params.logits_processor(self.temperature);
+        let model = self.model.clone();
+        let prompt = prompt.to_string();
+        let cancel = cancel.clone();
+        let started = Instant::now();
+
+        let generation = tokio::task::spawn_blocking(move || -> Result<Generation> {
+            let mut loaded = model
+                .lock()
+                .map_err(|_| anyhow!("The model failed during an earlier request; reload it"))?;
+            let prompt_ids = loaded.tokenize(&prompt)?;
+            let prompt_tokens = prompt_ids.len();
+            if prompt_tokens + max_tokens > context_length {
+                return Err(LLMError::ContextOverflow { prompt_tokens, max_tokens, context_length }.into());
+            }
+
+            // Cancelled while waiting for the model
+            if cancel.is_cancelled() {
+                return Ok(Generation::new(String::new(), FinishReason::Cancelled, prompt_tokens, 0));
+            }
+
+            let mut tracker = ThroughputTracker::new(max_tokens);
+            let (text, finish_reason) = loaded.generate(&prompt_ids, max_tokens, &mut sampler, |piece| {
+                let (tokens_per_second, eta_seconds) = tracker.record_token();
+                if let Some(piece) = piece {
+                    on_token(TokenEvent {
+                        token: piece.to_string(),
+                        index: tracker.generated() - 1,
+                        tokens_per_second,
+                        eta_seconds,
+                    });
+                }
+                !cancel.is_cancelled()
+            })?;
+
+            Ok(Generation::new(text, finish_reason, prompt_tokens, tracker.generated()))
+        })
+        .await
+        .map_err(|e| anyhow!("Generation task failed: {}", e))??;
+
+        let duration = started.elapsed();
+        lock_metrics(&self.metrics).record(InferenceRecord {
+            model: self.model_name.clone(),
+            prompt_tokens: generation.prompt_tokens,
+            completion_tokens: generation.completion_tokens,
+            duration_ms: duration.as_millis() as u64,
+            tokens_per_second: if duration.as_secs_f32() > 0.0 {
+                generation.completion_tokens as f32 / duration.as_secs_f32()
+            } else {
+                0.0
+            },
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+
+        Ok(generation)
+    }
+}
+
+// Metrics are plain counters, so a panic mid-update can't leave them unusable
+fn lock_metrics(metrics: &Mutex<InferenceMetrics>) -> std::sync::MutexGuard<'_, InferenceMetrics> {
+    metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Every GGUF file in `models_dir` or one directory below it, sorted. A missing or unreadable
+// directory simply yields no files.
+pub fn find_model_files(models_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in read_dir_paths(models_dir) {
+        if entry.is_dir() {
+            files.extend(read_dir_paths(&entry).into_iter().filter(|p| gguf::is_gguf_file(p)));
+        } else if gguf::is_gguf_file(&entry) {
+            files.push(entry);
+        }
+    }
+    files.sort();
+    files
+}
+
+fn has_gguf_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"))
+}
+
+fn read_dir_paths(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default()
+}
+
+// A model path may point at the .gguf file itself or at a directory containing one
+fn find_gguf_file(path: &Path) -> Option<PathBuf> {
+    if gguf::is_gguf_file(path) {
+        return Some(path.to_path_buf());
+    }
+
+    std::fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|p| gguf::is_gguf_file(p))
+}
+
+fn validate_gguf(path: &Path) -> Result<()> {
+    let metadata = gguf::read_metadata(path)?;
+
+    let architecture = metadata.architecture.clone().unwrap_or_else(|| "unknown".to_string());
+    if !SUPPORTED_ARCHITECTURES.contains(&architecture.as_str()) {
+        return Err(LLMError::UnsupportedArchitecture(architecture).into());
+    }
+
+    if let Some(file_type) = metadata.file_type {
+        if !SUPPORTED_FILE_TYPES.contains(&file_type) {
+            return Err(LLMError::UnsupportedQuantization(gguf::file_type_name(file_type).to_string()).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn missing_model_file_is_an_error_not_a_placeholder() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.initialize().await.unwrap();
+
+        let err = llm.session("phi-2").await.err().expect("no weights on disk");
+        assert!(matches!(err.downcast_ref::<LLMError>(), Some(LLMError::NoModelAvailable(name)) if name == "phi-2"));
+    }
+
+    #[tokio::test]
+    async fn models_dir_scan_registers_new_files_and_prunes_deleted_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.initialize().await.unwrap();
+        let catalog = llm.list_models().await.len();
+
+        // A dummy file: the header can't be parsed, but the model is still listed
+        let models_dir = dir.path().join("models");
+        std::fs::write(models_dir.join("tiny-llama.gguf"), b"not really gguf").unwrap();
+        std::fs::create_dir(models_dir.join("nested")).unwrap();
+        std::fs::write(models_dir.join("nested").join("qwen.GGUF"), b"").unwrap();
+        std::fs::write(models_dir.join("notes.txt"), b"").unwrap();
+
+        let files = find_model_files(&models_dir);
+        assert_eq!(files.len(), 2);
+        assert_eq!(llm.model_files_changes(&files).0.len(), 2);
+        assert_eq!(llm.apply_model_files(files), 2);
+        let config = llm.models.get("tiny-llama").cloned().unwrap();
+        assert_eq!((config.model_type.as_str(), config.context_length), ("unknown", DEFAULT_CONTEXT_LENGTH));
+
+        // Nothing changed, so listing needs no write lock
+        let (added, removed) = llm.model_files_changes(&find_model_files(&models_dir));
+        assert!(added.is_empty() && removed.is_empty());
+
+        std::fs::remove_file(models_dir.join("tiny-llama.gguf")).unwrap();
+        assert_eq!(llm.scan_models_dir(), 0);
+        let models = llm.list_models().await;
+        assert_eq!(models.len(), catalog + 1);
+        assert!(models.contains(&"qwen".to_string()) && !models.contains(&"tiny-llama".to_string()));
+
+        // Catalog entries stay listed even though nothing has been downloaded for them
+        assert!(models.contains(&"phi-2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn unloading_frees_the_weights_and_clears_the_active_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.initialize().await.unwrap();
+        let model_file = crate::inference::write_tiny_model(llm.models_dir());
+        assert_eq!(llm.apply_model_files(vec![model_file]), 1);
+
+        llm.load_model("tiny-llama").await.unwrap();
+        assert_eq!(llm.get_active_model().as_deref(), Some("tiny-llama"));
+        assert!(llm.resident_bytes() > 0);
+        let weights = Arc::downgrade(&llm.loaded.as_ref().unwrap().model);
+
+        assert!(llm.unload_model().await.unwrap() > 0);
+        assert!(llm.get_active_model().is_none());
+        assert!(weights.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_failed_load_leaves_no_model_active() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.initialize().await.unwrap();
+        let model_file = crate::inference::write_tiny_model(llm.models_dir());
+        llm.apply_model_files(vec![model_file]);
+        llm.load_model("tiny-llama").await.unwrap();
+
+        // Passes the header check, then fails once the previous weights are gone
+        std::fs::remove_file(llm.models_dir().join("tokenizer.json")).unwrap();
+        std::fs::copy(llm.models_dir().join("tiny-llama.gguf"), llm.models_dir().join("other.gguf")).unwrap();
+        llm.scan_models_dir();
+        assert!(llm.load_model("other").await.is_err());
+        assert!(llm.loaded_model().is_none());
+        assert!(llm.get_active_model().is_none());
+    }
+
+    #[test]
+    fn missing_models_dir_yields_no_files() {
+        assert!(find_model_files(Path::new("/nonexistent/bear/models")).is_empty());
+    }
+
+    #[test]
+    fn same_seed_samples_the_same_tokens() {
+        let logits = candle_core::Tensor::new(
+            &(0..64).map(|i| (i % 7) as f32 * 0.3).collect::<Vec<f32>>()[..],
+            &candle_core::Device::Cpu,
+        )
+        .unwrap();
+        let sample = |seed| {
+            let params = GenerationParams { temperature: Some(0.9), seed: Some(seed), max_tokens: None };
+            let mut sampler = params.logits_processor(0.7);
+            (0..32).map(|_| sampler.sample(&logits).unwrap()).collect::<Vec<u32>>()
+        };
+
+        assert_eq!(sample(42), sample(42));
+        assert_ne!(sample(42), sample(43));
+    }
+
+    #[test]
+    fn only_a_cut_at_max_tokens_is_flagged_as_truncated() {
+        let generation = |finish_reason| Generation::new("text".to_string(), finish_reason, 10, 4);
+        assert!(generation(FinishReason::Length).truncated);
+        assert!(!generation(FinishReason::Stop).truncated);
+        assert!(!generation(FinishReason::Cancelled).truncated);
+
+        let json = serde_json::to_value(generation(FinishReason::Length)).unwrap();
+        assert_eq!(json["finish_reason"], "length");
+        assert_eq!(json["truncated"], true);
+    }
+
+    #[tokio::test]
+    async fn generation_stops_at_the_model_max_tokens_unless_the_request_overrides_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.initialize().await.unwrap();
+        let model_file = crate::inference::write_tiny_model(llm.models_dir());
+        llm.apply_model_files(vec![model_file]);
+        llm.models.get_mut("tiny-llama").unwrap().max_tokens = 6;
+        let session = llm.session("tiny-llama").await.unwrap();
+
+        // The tiny model has no stop tokens, so only max_tokens ends these
+        let generation = session.generate("w1 w2", &GenerationParams::default(), &CancellationToken::new(), |_| {}).await.unwrap();
+        assert_eq!(generation.completion_tokens, 6);
+        assert!(generation.truncated);
+
+        let capped = GenerationParams { max_tokens: Some(2), ..Default::default() };
+        let generation = session.generate("w1 w2", &capped, &CancellationToken::new(), |_| {}).await.unwrap();
+        assert_eq!(generation.completion_tokens, 2);
+        assert_eq!(generation.finish_reason, FinishReason::Length);
+    }
+
+    #[test]
+    fn context_overflow_names_both_limits() {
+        let error = LLMError::ContextOverflow { prompt_tokens: 3900, max_tokens: 512, context_length: 4096 };
+        let message = error.to_string();
+        assert!(message.contains("3900 tokens") && message.contains("(512)") && message.contains("4096 tokens"));
+    }
+
+    // cargo test --features tiny-model-test -- --ignored, with BEAR_TEST_MODEL pointing at a
+    // small GGUF file of a supported architecture (a tokenizer.json must sit next to it)
+    #[cfg(feature = "tiny-model-test")]
+    #[tokio::test]
+    #[ignore]
+    async fn generates_with_a_tiny_model() {
+        let model = PathBuf::from(std::env::var("BEAR_TEST_MODEL").expect("BEAR_TEST_MODEL is not set"));
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("models")).unwrap();
+        let mut llm = LLMManager::new(dir.path());
+        llm.models.insert("tiny".to_string(), ModelConfig {
+            name: "tiny".to_string(),
+            model_type: "llama".to_string(),
+            path: model,
+            max_tokens: 16,
+            temperature: 0.0,
+            context_length: 512,
+        });
+
+        let session = llm.session("tiny").await.unwrap();
+        let streamed = Arc::new(Mutex::new(String::new()));
+        let sink = streamed.clone();
+        let generation = session
+            .generate("Once upon a time", &GenerationParams::default(), &CancellationToken::new(), move |event| {
+                sink.lock().unwrap().push_str(&event.token)
+            })
+            .await
+            .unwrap();
+
+        assert!(generation.completion_tokens > 0 && generation.completion_tokens <= 16);
+        assert_eq!(*streamed.lock().unwrap(), generation.text);
+        assert_eq!(llm.metrics_summary(1).total_requests, 1);
+
+        let capped = GenerationParams { max_tokens: Some(1), ..Default::default() };
+        let short = session.generate("Once upon a time", &capped, &CancellationToken::new(), |_| {}).await.unwrap();
+        assert!(short.completion_tokens <= 1);
+        assert_eq!(short.truncated, short.finish_reason == FinishReason::Length);
+
+        // Sampled, not greedy: only the seed makes the two runs agree
+        let seeded = GenerationParams { temperature: Some(0.8), seed: Some(7), max_tokens: None };
+        let first = session.generate("Once upon a time", &seeded, &CancellationToken::new(), |_| {}).await.unwrap();
+        let second = session.generate("Once upon a time", &seeded, &CancellationToken::new(), |_| {}).await.unwrap();
+        assert_eq!(first.text, second.text);
+    }
+}
@@ -1,13 +1,13 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use sysinfo::{System, SystemExt, CpuExt};
-use tauri::State;
-use tokio::sync::RwLock;
-use regex::Regex;
-use lazy_static::lazy_static;
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::{watch, RwLock};
+use tokio_util::sync::CancellationToken;
 
 mod pii_detector;
 mod hardware_monitor;
@@ -15,23 +15,67 @@ mod llm_manager;
 mod file_processor;
 mod rag_engine;
 mod system_monitor;
+mod gpu_adapter;
+mod thermal;
 mod commands;
+mod data_dir;
+mod folder_watcher;
+mod gguf;
+mod hf_download;
+mod hf_search;
+mod inference;
+mod embeddings;
+mod vector_store;
+mod text_normalizer;
+mod health;
+mod exif;
+mod path_filter;
+mod citations;
+mod pii_audit;
+mod mcp_server;
 
-use pii_detector::PIIDetector;
-use hardware_monitor::HardwareMonitor;
-use llm_manager::LLMManager;
-use file_processor::FileProcessor;
+use pii_detector::{Locale, PIIDetector, PIIMatch, PiiConfig, PiiMap, PiiReport, PiiRestorer, PseudonymLabel, PiiStats, AllowedTerm, NameDetection, RedactionProfile, PROFILE_EXPORT, PROFILE_LLM, PROFILE_STORAGE};
+use rag_engine::{ChunkOptions, ChunkParams, IndexStats};
+use folder_watcher::{FolderWatcher, WatchConfig};
+use path_filter::{FilterPatterns, PathFilter};
+use pii_audit::{AuditEntry, AuditOperation, AuditQuery, ChainVerification, PiiAuditLog};
+use hardware_monitor::{GenerationTracker, HardwareMonitor, HardwareConfig, PollSettings, ProcessInfo, ProcessSort, ResourceLimits, ResourceThrottle, SafetyThresholds, SafetyWatch};
+use llm_manager::{Generation, GenerationParams, InferenceMetricsSummary, LLMManager, TokenEvent};
+use file_processor::{ExtractionConfig, FileProcessor};
 use rag_engine::RAGEngine;
+use mcp_server::{AgentConfig, AgentOrchestrator, AgentRun, Tool, ToolAuditLog, ToolCall, ToolResult};
+
+// How long a knowledge-base clear token stays valid
+const CLEAR_TOKEN_TTL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 struct AppState {
-    pii_detector: Arc<PIIDetector>,
+    pii_detector: Arc<RwLock<PIIDetector>>,
     hardware_monitor: Arc<RwLock<HardwareMonitor>>,
     llm_manager: Arc<RwLock<LLMManager>>,
     file_processor: Arc<FileProcessor>,
     rag_engine: Arc<RwLock<RAGEngine>>,
+    data_dir: PathBuf,
+    kb_clear_token: Arc<RwLock<Option<(String, std::time::Instant)>>>,
+    folder_watcher: Arc<RwLock<FolderWatcher>>,
+    processing_jobs: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    search_jobs: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    // Chat requests by request id, for cancel_message and emergency_stop
+    chat_jobs: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    generations: GenerationTracker,
+    throttle: ResourceThrottle,
+    monitor_polling: Arc<watch::Sender<PollSettings>>,
+    pii_audit: Arc<RwLock<PiiAuditLog>>,
+    agent: Arc<RwLock<AgentOrchestrator>>,
 }
 
+const PII_CONFIG_FILE: &str = "pii_config.json";
+const WATCH_CONFIG_FILE: &str = "watch_config.json";
+const INGEST_FILTERS_FILE: &str = "ingest_filters.json";
+const HARDWARE_CONFIG_FILE: &str = "hardware_config.json";
+const AGENT_CONFIG_FILE: &str = "agent_config.json";
+const EXTRACTION_CONFIG_FILE: &str = "extraction_config.json";
+
 // Add the new AppState for commands
 use commands::AppState as CommandState;
 
@@ -44,75 +88,659 @@ struct SystemStatus {
     is_safe: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
-    timestamp: i64,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 struct ProcessedDocument {
     id: String,
     filename: String,
     content: String,
     pii_removed: bool,
+    pii_report: PiiReport,
     metadata: serde_json::Value,
 }
 
+#[tauri::command]
+async fn health_check(
+    state: State<'_, AppState>,
+    command_state: State<'_, CommandState>,
+) -> Result<health::HealthReport, String> {
+    Ok(health::check(&state, &command_state).await)
+}
+
 #[tauri::command]
 async fn check_system_status(state: State<'_, AppState>) -> Result<SystemStatus, String> {
     let monitor = state.hardware_monitor.read().await;
     monitor.get_status().await.map_err(|e| e.to_string())
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProcessingStage {
+    // Reading and parsing the file
+    Extracting,
+    Redacting,
+    Chunking,
+    Embedding,
+    Indexing,
+    Done,
+    Cancelled,
+    Failed,
+}
+
+impl ProcessingStage {
+    // Rough share of a whole job each stage takes, as a (start, end) percentage
+    fn span(self) -> (f32, f32) {
+        match self {
+            ProcessingStage::Extracting => (0.0, 40.0),
+            ProcessingStage::Redacting => (40.0, 55.0),
+            ProcessingStage::Chunking => (55.0, 60.0),
+            ProcessingStage::Embedding => (60.0, 95.0),
+            ProcessingStage::Indexing => (95.0, 100.0),
+            ProcessingStage::Done | ProcessingStage::Cancelled | ProcessingStage::Failed => (100.0, 100.0),
+        }
+    }
+
+    fn percent(self, progress: f32) -> f32 {
+        let (start, end) = self.span();
+        start + (end - start) * progress.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProcessingProgress {
+    job_id: String,
+    stage: ProcessingStage,
+    progress: f32, // 0.0 - 1.0 within the stage
+    percent: f32,  // 0 - 100 over the whole job
+}
+
+const PROCESSING_EVENT: &str = "document-processing-progress";
+
+// Where progress events go: the app's windows, or a recorder in tests
+trait ProgressSink: Send + Sync {
+    fn send(&self, event: &str, payload: serde_json::Value);
+}
+
+impl ProgressSink for AppHandle {
+    fn send(&self, event: &str, payload: serde_json::Value) {
+        if let Err(e) = self.emit(event, payload) {
+            eprintln!("Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+fn emit_processing_progress(sink: &dyn ProgressSink, job_id: &str, stage: ProcessingStage, progress: f32) {
+    let event = ProcessingProgress {
+        job_id: job_id.to_string(),
+        stage,
+        progress,
+        percent: stage.percent(progress),
+    };
+    sink.send(PROCESSING_EVENT, serde_json::json!(event));
+}
+
+// Runs `fut` unless the job is cancelled first
+async fn cancellable<T>(cancel: &CancellationToken, fut: impl std::future::Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    tokio::select! {
+        result = fut => result,
+        _ = cancel.cancelled() => Err(anyhow::anyhow!("Document processing cancelled")),
+    }
+}
+
 #[tauri::command]
 async fn process_document(
+    app: AppHandle,
     state: State<'_, AppState>,
     file_path: String,
     file_type: String,
+    job_id: Option<String>,
+    add_to_index: Option<bool>,
 ) -> Result<ProcessedDocument, String> {
-    let content = state.file_processor
-        .process_file(&file_path, &file_type)
-        .await
-        .map_err(|e| e.to_string())?;
+    let job_id = job_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let cancel = CancellationToken::new();
+    state.processing_jobs.write().await.insert(job_id.clone(), cancel.clone());
 
-    let cleaned_content = state.pii_detector
-        .remove_pii(&content)
-        .await
-        .map_err(|e| e.to_string())?;
+    let result = run_document_pipeline(
+        &app,
+        &state,
+        &job_id,
+        file_path,
+        file_type,
+        add_to_index.unwrap_or(false),
+        &cancel,
+    ).await;
+
+    state.processing_jobs.write().await.remove(&job_id);
+
+    let final_stage = match &result {
+        Ok(_) => ProcessingStage::Done,
+        Err(_) if cancel.is_cancelled() => ProcessingStage::Cancelled,
+        Err(_) => ProcessingStage::Failed,
+    };
+    emit_processing_progress(&app, &job_id, final_stage, 1.0);
+
+    result.map_err(|e| e.to_string())
+}
+
+// Nothing is written to the index until the final stage, so a cancelled job leaves no partial chunks behind
+async fn run_document_pipeline(
+    sink: &dyn ProgressSink,
+    state: &AppState,
+    job_id: &str,
+    file_path: String,
+    file_type: String,
+    add_to_index: bool,
+    cancel: &CancellationToken,
+) -> anyhow::Result<ProcessedDocument> {
+    emit_processing_progress(sink, job_id, ProcessingStage::Extracting, 0.0);
+    let content = cancellable(cancel, state.file_processor.process_file(&file_path, &file_type)).await?;
+
+    emit_processing_progress(sink, job_id, ProcessingStage::Redacting, 0.0);
+    let detector = state.pii_detector.read().await;
+    let (cleaned_content, pii_report) = cancellable(cancel, detector.remove_pii_with_report(&content)).await?;
+    let storage_redaction = if add_to_index {
+        Some(cancellable(cancel, detector.redact_with_profile(&content, PROFILE_STORAGE)).await?)
+    } else {
+        None
+    };
+    drop(detector);
+
+    let metadata = serde_json::json!({
+        "type": file_type,
+        "citations": citations::extract_citations(&content, true),
+    });
+
+    let id = match storage_redaction {
+        Some(storage_redaction) => {
+            emit_processing_progress(sink, job_id, ProcessingStage::Chunking, 0.0);
+            let prepared = {
+                let rag = state.rag_engine.read().await;
+                rag.prepare_document(&storage_redaction.text, metadata.clone(), None, None, cancel, |done, total| {
+                    emit_processing_progress(sink, job_id, ProcessingStage::Embedding, done as f32 / total.max(1) as f32);
+                }).await?
+            };
+
+            if cancel.is_cancelled() {
+                return Err(anyhow::anyhow!("Document processing cancelled"));
+            }
+
+            // Audited first, so no indexed document lacks its entry even if a write fails
+            emit_processing_progress(sink, job_id, ProcessingStage::Indexing, 0.0);
+            state.pii_audit.write().await.record(
+                AuditOperation::AddToKnowledgeBase,
+                prepared.doc_id(),
+                &content,
+                &storage_redaction.counts,
+            )?;
+            state.rag_engine.write().await.commit_document(prepared).await?
+        }
+        None => uuid::Uuid::new_v4().to_string(),
+    };
 
     Ok(ProcessedDocument {
-        id: uuid::Uuid::new_v4().to_string(),
+        id,
         filename: file_path,
         content: cleaned_content,
         pii_removed: true,
-        metadata: serde_json::json!({"type": file_type}),
+        pii_report,
+        metadata,
     })
 }
 
 #[tauri::command]
-async fn send_message(
+async fn cancel_document_processing(
     state: State<'_, AppState>,
-    message: String,
-    model_name: String,
-) -> Result<String, String> {
+    job_id: String,
+) -> Result<bool, String> {
+    match state.processing_jobs.read().await.get(&job_id) {
+        Some(cancel) => {
+            cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RedactedExport {
+    output_path: String,
+    profile: String,
+    redaction_counts: BTreeMap<String, usize>,
+    total_redactions: usize,
+}
+
+// Formats we can only extract from, not write back with their layout intact
+const NON_TEXT_EXPORT_EXTENSIONS: [&str; 6] = ["pdf", "docx", "doc", "xlsx", "xls", "pptx"];
+
+// Extracts, redacts (export profile unless overridden) and writes the scrubbed text to `output_path`
+#[tauri::command]
+async fn export_redacted_document(
+    state: State<'_, AppState>,
+    file_path: String,
+    file_type: String,
+    output_path: String,
+    profile: Option<String>,
+) -> Result<RedactedExport, String> {
+    let output = PathBuf::from(&output_path);
+    let extension = output.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if NON_TEXT_EXPORT_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(format!("Redacted export is written as plain text; choose a .txt or .md output instead of .{}", extension));
+    }
+
+    let source = PathBuf::from(&file_path);
+    if output == source || (output.exists() && output.canonicalize().ok() == source.canonicalize().ok()) {
+        return Err("Output path must differ from the source document".to_string());
+    }
+
+    let profile = profile.unwrap_or_else(|| PROFILE_EXPORT.to_string());
+    // A snapshot, so settings changes aren't blocked while a large document is redacted
+    let detector = {
+        let detector = state.pii_detector.read().await;
+        detector.profile(&profile).map_err(|e| e.to_string())?;
+        detector.clone()
+    };
+
+    // Written next to the output and renamed into place, so a failed export leaves no partial file
+    let partial = output.with_file_name(format!(
+        ".{}.partial",
+        output.file_name().and_then(|n| n.to_str()).unwrap_or("export")
+    ));
+    let result = write_redacted_export(&state, &detector, &file_path, &file_type, &partial, &profile).await;
+    let (total_redactions, redaction_counts) = match result {
+        Ok(counts) => counts,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+    };
+    if let Err(e) = tokio::fs::rename(&partial, &output).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(format!("Failed to write {}: {}", output.display(), e));
+    }
+
+    Ok(RedactedExport {
+        output_path,
+        profile,
+        total_redactions,
+        redaction_counts,
+    })
+}
+
+async fn write_redacted_export(
+    state: &State<'_, AppState>,
+    detector: &PIIDetector,
+    file_path: &str,
+    file_type: &str,
+    destination: &Path,
+    profile: &str,
+) -> Result<(usize, BTreeMap<String, usize>), String> {
+    // Plain text needs no extraction, so stream it instead of loading large files whole
+    let file_type = file_type.to_lowercase();
+    if matches!(file_type.as_str(), "txt" | "md") {
+        state.file_processor
+            .check_file_size(Path::new(file_path), &file_type)
+            .await
+            .map_err(|e| e.to_string())?;
+        let reader = tokio::fs::File::open(file_path).await.map_err(|e| e.to_string())?;
+        let writer = tokio::fs::File::create(destination)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", destination.display(), e))?;
+        let report = detector
+            .remove_pii_stream(tokio::io::BufReader::new(reader), tokio::io::BufWriter::new(writer), profile)
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok((report.total, report.counts));
+    }
+
+    let content = state.file_processor
+        .process_file(file_path, &file_type)
+        .await
+        .map_err(|e| e.to_string())?;
+    // Page markers are for locating search hits, not part of the document
+    let (content, _) = rag_engine::strip_page_markers(&content);
+
+    let redaction = detector
+        .redact_with_profile(&content, profile)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tokio::fs::write(destination, &redaction.text)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", destination.display(), e))?;
+
+    Ok((redaction.total(), redaction.counts))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ImageProcessingResult {
+    // OCR text, redacted
+    text: String,
+    ocr_available: bool,
+    // Tag -> redacted value; identifying tags (GPS, owner, serials) are always fully redacted
+    metadata: BTreeMap<String, String>,
+    redacted_tags: Vec<String>,
+    stripped_copy: Option<String>,
+    removed_blocks: Vec<String>,
+}
+
+// OCR + EXIF for one image; with `stripped_output_path`, also writes a metadata-free copy there
+#[tauri::command]
+async fn process_image(
+    state: State<'_, AppState>,
+    file_path: String,
+    stripped_output_path: Option<String>,
+) -> Result<ImageProcessingResult, String> {
+    let extension = Path::new(&file_path).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    if !state.file_processor.is_image(extension) {
+        return Err(format!("{} is not a supported image format", file_path));
+    }
+
+    let extraction = state.file_processor
+        .process_image(&file_path)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let detector = state.pii_detector.read().await;
+    let text = detector.remove_pii(&extraction.text).await.map_err(|e| e.to_string())?;
+
+    let mut metadata = BTreeMap::new();
+    let mut redacted_tags = Vec::new();
+    if extraction.metadata.gps.is_some() {
+        metadata.insert("GPSPosition".to_string(), "[REDACTED]".to_string());
+        redacted_tags.push("GPSPosition".to_string());
+    }
+    for (tag, value) in extraction.metadata.fields {
+        let scrubbed = if exif::IDENTIFYING_TAGS.contains(&tag.as_str()) {
+            "[REDACTED]".to_string()
+        } else {
+            detector.remove_pii(&value).await.map_err(|e| e.to_string())?
+        };
+        if scrubbed != value {
+            redacted_tags.push(tag.clone());
+        }
+        metadata.insert(tag, scrubbed);
+    }
+    drop(detector);
+
+    let (stripped_copy, removed_blocks) = match stripped_output_path {
+        Some(output_path) => {
+            let (source, output) = (PathBuf::from(&file_path), PathBuf::from(&output_path));
+            if output == source || (output.exists() && output.canonicalize().ok() == source.canonicalize().ok()) {
+                return Err("Output path must differ from the source image".to_string());
+            }
+            let removed = state.file_processor
+                .write_stripped_image(&file_path, &output_path)
+                .await
+                .map_err(|e| e.to_string())?;
+            (Some(output_path), removed)
+        }
+        None => (None, Vec::new()),
+    };
+
+    Ok(ImageProcessingResult {
+        text,
+        ocr_available: extraction.ocr_available,
+        metadata,
+        redacted_tags,
+        stripped_copy,
+        removed_blocks,
+    })
+}
+
+// Safety gate and LLM-profile redaction shared by the chat commands. The message is only
+// released to the model once its redaction is in the audit log; the returned map turns the
+// tokens the model echoes back into the original values for the user.
+async fn prepare_chat_message(state: &AppState, message: &str, request_id: &str) -> Result<(String, PiiMap), String> {
     let mut hw_monitor = state.hardware_monitor.write().await;
     if !hw_monitor.check_safety().await.map_err(|e| e.to_string())? {
         return Err("System resources are critically high. Please wait before sending another message.".to_string());
     }
+    if let Some(reason) = hw_monitor.check_resource_limits().await.map_err(|e| e.to_string())? {
+        return Err(format!("Resource limit exceeded: {}. Please wait before sending another message.", reason));
+    }
+    drop(hw_monitor);
 
-    let cleaned_message = state.pii_detector
-        .remove_pii(&message)
+    let (redaction, map) = state.pii_detector
+        .read()
+        .await
+        .redact_reversible_with_profile(message, PROFILE_LLM)
         .await
         .map_err(|e| e.to_string())?;
 
-    let mut llm = state.llm_manager.write().await;
-    let response = llm.generate_response(&cleaned_message, &model_name)
+    state.pii_audit
+        .write()
+        .await
+        .record(AuditOperation::SendMessage, request_id, message, &redaction.counts)
+        .map_err(|e| format!("Failed to write PII audit entry: {}", e))?;
+
+    Ok((redaction.text, map))
+}
+
+#[tauri::command]
+async fn send_message(
+    state: State<'_, AppState>,
+    message: String,
+    model_name: String,
+    params: Option<GenerationParams>,
+) -> Result<Generation, String> {
+    chat(&state, &message, &model_name, params).await
+}
+
+async fn chat(state: &AppState, message: &str, model_name: &str, params: Option<GenerationParams>) -> Result<Generation, String> {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let (cleaned_message, pii_map) = prepare_chat_message(state, message, &request_id).await?;
+
+    // Registered so emergency_stop can cut it short
+    let cancel = CancellationToken::new();
+    state.chat_jobs.write().await.insert(request_id.clone(), cancel.clone());
+
+    let result = async {
+        let session = {
+            let mut llm = state.llm_manager.write().await;
+            llm.ensure_model_available(model_name).map_err(|e| e.to_string())?;
+            llm.session(model_name).await.map_err(|e| e.to_string())?
+        };
+        let _generating = state.generations.start();
+        let throttle = state.throttle.clone();
+        let safety = SafetyWatch::start(state.hardware_monitor.clone(), throttle.clone(), cancel.clone());
+        let mut generation = session
+            .generate(&cleaned_message, &params.unwrap_or_default(), &cancel, move |_| {
+                throttle.pause_if_throttled()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        generation.stopped_for_safety = safety.tripped();
+        generation.text = pii_map.restore(&generation.text);
+        Ok(generation)
+    }
+    .await;
+
+    state.chat_jobs.write().await.remove(&request_id);
+    result
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatTokenEvent {
+    request_id: String,
+    #[serde(flatten)]
+    token: TokenEvent,
+}
+
+const CHAT_TOKEN_EVENT: &str = "chat-token";
+
+// Like send_message, but emits each token (with tokens/sec and ETA) as a `chat-token` event.
+// The hardware safety check runs before any token is generated and every few seconds while
+// generating; if it fails mid-answer the partial text comes back with stopped_for_safety set.
+// cancel_message(request_id) stops generation after the current token; the command then returns
+// the partial text with finish_reason "cancelled". A request still waiting for the model is
+// cancelled as soon as it gets it, before generating anything.
+#[tauri::command]
+async fn send_message_streaming(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    message: String,
+    model_name: String,
+    request_id: String,
+    params: Option<GenerationParams>,
+) -> Result<Generation, String> {
+    let (cleaned_message, pii_map) = prepare_chat_message(&state, &message, &request_id).await?;
+
+    let cancel = CancellationToken::new();
+    state.chat_jobs.write().await.insert(request_id.clone(), cancel.clone());
+
+    let result = async {
+        let session = {
+            let mut llm = state.llm_manager.write().await;
+            llm.ensure_model_available(&model_name).map_err(|e| e.to_string())?;
+            llm.session(&model_name).await.map_err(|e| e.to_string())?
+        };
+        let _generating = state.generations.start();
+        let throttle = state.throttle.clone();
+        let safety = SafetyWatch::start(state.hardware_monitor.clone(), throttle.clone(), cancel.clone());
+
+        // Tokens are restored before they reach the UI. A PII token the model splits across
+        // several tokens is held back until complete; the last event sent is kept so anything
+        // still held at the end goes out with the same stats.
+        let restoring = Arc::new(std::sync::Mutex::new((PiiRestorer::new(pii_map.clone()), None::<TokenEvent>)));
+        let (token_app, token_request_id, token_restoring) = (app.clone(), request_id.clone(), restoring.clone());
+        let mut generation = session.generate(&cleaned_message, &params.unwrap_or_default(), &cancel, move |mut token| {
+            let mut restoring = token_restoring.lock().unwrap_or_else(|e| e.into_inner());
+            token.token = restoring.0.push(&token.token);
+            emit_chat_token(&token_app, &token_request_id, &token);
+            restoring.1 = Some(token);
+            drop(restoring);
+            throttle.pause_if_throttled();
+        })
         .await
         .map_err(|e| e.to_string())?;
 
-    Ok(response)
+        let mut restoring = restoring.lock().unwrap_or_else(|e| e.into_inner());
+        let tail = restoring.0.finish();
+        if let Some(mut last) = restoring.1.take().filter(|_| !tail.is_empty()) {
+            last.token = tail;
+            emit_chat_token(&app, &request_id, &last);
+        }
+        drop(restoring);
+
+        generation.stopped_for_safety = safety.tripped();
+        generation.text = pii_map.restore(&generation.text);
+        Ok(generation)
+    }
+    .await;
+
+    state.chat_jobs.write().await.remove(&request_id);
+    result
+}
+
+fn emit_chat_token(app: &AppHandle, request_id: &str, token: &TokenEvent) {
+    let event = ChatTokenEvent {
+        request_id: request_id.to_string(),
+        token: token.clone(),
+    };
+    if let Err(e) = app.emit(CHAT_TOKEN_EVENT, &event) {
+        eprintln!("Failed to emit chat token: {}", e);
+    }
+}
+
+// Returns false if no streaming request with this id is running
+#[tauri::command]
+async fn cancel_message(
+    state: State<'_, AppState>,
+    request_id: String,
+) -> Result<bool, String> {
+    match state.chat_jobs.read().await.get(&request_id) {
+        Some(cancel) => {
+            cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Only the tools the agent policy currently allows
+#[tauri::command]
+async fn list_agent_tools(state: State<'_, AppState>) -> Result<Vec<Tool>, String> {
+    Ok(state.agent.read().await.list_tools())
+}
+
+#[tauri::command]
+async fn execute_agent_tool(
+    state: State<'_, AppState>,
+    tool: String,
+    parameters: serde_json::Value,
+) -> Result<ToolResult, String> {
+    state.agent
+        .read()
+        .await
+        .execute_tool(ToolCall { tool, parameters })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Runs the agent loop with the active model; the result lists every tool call it made
+#[tauri::command]
+async fn run_agent_task(
+    state: State<'_, AppState>,
+    task: String,
+    context: Option<String>,
+) -> Result<AgentRun, String> {
+    state.agent
+        .read()
+        .await
+        .execute_agent_task(&task, context.as_deref().unwrap_or(""))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_agent_config(state: State<'_, AppState>) -> Result<AgentConfig, String> {
+    Ok(state.agent.read().await.config())
+}
+
+// Validated and saved first, then applied, so the running agent never has settings the next
+// start won't
+#[tauri::command]
+async fn set_agent_config(
+    state: State<'_, AppState>,
+    config: AgentConfig,
+) -> Result<AgentConfig, String> {
+    config.validate().map_err(|e| e.to_string())?;
+    config.save(&state.data_dir.join(AGENT_CONFIG_FILE)).map_err(|e| e.to_string())?;
+    state.agent.write().await.apply_config(&config).map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+#[tauri::command]
+async fn get_extraction_config(state: State<'_, AppState>) -> Result<ExtractionConfig, String> {
+    Ok(state.file_processor.config())
+}
+
+#[tauri::command]
+async fn set_extraction_config(
+    state: State<'_, AppState>,
+    config: ExtractionConfig,
+) -> Result<ExtractionConfig, String> {
+    config.validate().map_err(|e| e.to_string())?;
+    config.save(&state.data_dir.join(EXTRACTION_CONFIG_FILE)).map_err(|e| e.to_string())?;
+    state.file_processor.apply_config(&config);
+    Ok(state.file_processor.config())
+}
+
+// Turns one tool on or off and saves the change
+#[tauri::command]
+async fn set_agent_tool_enabled(
+    state: State<'_, AppState>,
+    tool: String,
+    enabled: bool,
+) -> Result<AgentConfig, String> {
+    let mut agent = state.agent.write().await;
+    let mut config = agent.config();
+    config.policy = config.policy.with_tool(&tool, enabled);
+    config.save(&state.data_dir.join(AGENT_CONFIG_FILE)).map_err(|e| e.to_string())?;
+    agent.apply_config(&config).map_err(|e| e.to_string())?;
+    Ok(config)
 }
 
 #[tauri::command]
@@ -120,107 +748,1572 @@ async fn search_knowledge_base(
     state: State<'_, AppState>,
     query: String,
     limit: usize,
+    collection: Option<String>,
+    // Vector share of a hybrid vector + keyword ranking; omitted for vector-only search
+    alpha: Option<f32>,
 ) -> Result<Vec<serde_json::Value>, String> {
     let cleaned_query = state.pii_detector
-        .remove_pii(&query)
+        .read()
+        .await
+        .remove_pii_with_profile(&query, PROFILE_STORAGE)
         .await
         .map_err(|e| e.to_string())?;
 
     let rag = state.rag_engine.read().await;
-    rag.search(&cleaned_query, limit)
-        .await
-        .map_err(|e| e.to_string())
+    match alpha {
+        Some(alpha) => rag.search_hybrid_collection(&cleaned_query, collection.as_deref(), limit, alpha).await,
+        None => rag.search_collection(&cleaned_query, collection.as_deref(), limit).await,
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SearchResultsEvent {
+    search_id: String,
+    scanned: usize,
+    total: usize,
+    results: Vec<serde_json::Value>,
+    // The final event carries the same results the command returns
+    done: bool,
 }
 
+const SEARCH_RESULTS_EVENT: &str = "search-results";
+
+// Emits provisional top-k results on SEARCH_RESULTS_EVENT while scanning large indexes;
+// cancel with `cancel_search`
 #[tauri::command]
-async fn add_to_knowledge_base(
+async fn search_knowledge_base_streaming(
+    app: AppHandle,
     state: State<'_, AppState>,
-    content: String,
-    metadata: serde_json::Value,
-) -> Result<String, String> {
-    let cleaned_content = state.pii_detector
-        .remove_pii(&content)
+    query: String,
+    limit: usize,
+    collection: Option<String>,
+    search_id: Option<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let search_id = search_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let cleaned_query = state.pii_detector
+        .read()
+        .await
+        .remove_pii_with_profile(&query, PROFILE_STORAGE)
         .await
         .map_err(|e| e.to_string())?;
 
-    let mut rag = state.rag_engine.write().await;
-    rag.add_document(&cleaned_content, metadata)
+    let cancel = CancellationToken::new();
+    state.search_jobs.write().await.insert(search_id.clone(), cancel.clone());
+
+    let emit = {
+        let search_id = search_id.clone();
+        move |scanned: usize, total: usize, results: Vec<serde_json::Value>, done: bool| {
+            let event = SearchResultsEvent {
+                search_id: search_id.clone(),
+                scanned,
+                total,
+                results,
+                done,
+            };
+            if let Err(e) = app.emit(SEARCH_RESULTS_EVENT, &event) {
+                eprintln!("Failed to emit search results: {}", e);
+            }
+        }
+    };
+
+    let result = async {
+        let scan = state.rag_engine
+            .read()
+            .await
+            .prepare_search(&cleaned_query, collection.as_deref())
+            .map_err(|e| e.to_string())?;
+        let total = scan.chunk_count();
+
+        // The scan runs off the async workers and without the engine's lock, which is only taken
+        // briefly to build each partial result
+        let rag = state.rag_engine.clone();
+        let (emit, cancel) = (emit.clone(), cancel.clone());
+        let hits = tokio::task::spawn_blocking(move || {
+            scan.rank(limit, Some(&cancel), |scanned, hits| {
+                let results = rag.blocking_read().result_json(&hits);
+                emit(scanned, total, results, false);
+            })
+        })
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+        let results = state.rag_engine.read().await.result_json(&hits);
+        Ok::<_, String>((results, total))
+    }
+    .await;
+
+    state.search_jobs.write().await.remove(&search_id);
+
+    let (results, total) = result?;
+    emit(total, total, results.clone(), true);
+    Ok(results)
 }
 
 #[tauri::command]
-async fn list_available_models(
+async fn cancel_search(
     state: State<'_, AppState>,
-) -> Result<Vec<String>, String> {
-    let llm = state.llm_manager.read().await;
-    Ok(llm.list_models().await)
+    search_id: String,
+) -> Result<bool, String> {
+    match state.search_jobs.read().await.get(&search_id) {
+        Some(cancel) => {
+            cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
 }
 
+// Structured citations (case reporters, US Code, CFR, EU acts, ECLI) found in `text`
 #[tauri::command]
-async fn download_model(
-    state: State<'_, AppState>,
-    model_name: String,
-) -> Result<String, String> {
-    let mut llm = state.llm_manager.write().await;
-    llm.download_model(&model_name)
-        .await
-        .map_err(|e| e.to_string())?;
-    Ok(format!("Model {} downloaded successfully", model_name))
+async fn extract_citations(text: String, link: Option<bool>) -> Result<Vec<citations::Citation>, String> {
+    Ok(citations::extract_citations(&text, link.unwrap_or(true)))
 }
 
-fn main() {
-    let app_state = AppState {
-        pii_detector: Arc::new(PIIDetector::new()),
-        hardware_monitor: Arc::new(RwLock::new(HardwareMonitor::new())),
-        llm_manager: Arc::new(RwLock::new(LLMManager::new())),
-        file_processor: Arc::new(FileProcessor::new()),
-        rag_engine: Arc::new(RwLock::new(RAGEngine::new())),
-    };
+#[derive(Debug, Serialize, Deserialize)]
+struct CitingDocument {
+    document_id: String,
+    metadata: serde_json::Value,
+}
 
-    // Initialize the system monitor state
-    let command_state = CommandState {
-        system_monitor: std::sync::Mutex::new(system_monitor::SystemMonitor::new()),
-    };
+#[tauri::command]
+async fn find_documents_by_citation(
+    state: State<'_, AppState>,
+    citation: String,
+) -> Result<Vec<CitingDocument>, String> {
+    let identifier = citations::citation_identifier(&citation);
+    let rag = state.rag_engine.read().await;
+    Ok(rag.documents_citing(&identifier)
+        .into_iter()
+        .map(|(document_id, metadata)| CitingDocument { document_id, metadata })
+        .collect())
+}
 
-    tauri::Builder::default()
-        .plugin(tauri_plugin_fs::init())
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_shell::init())
-        .plugin(tauri_plugin_os::init())
-        .manage(app_state.clone())
-        .manage(command_state)
-        .setup(move |app| {
-            let state = app_state.clone();
-            tauri::async_runtime::spawn(async move {
-                loop {
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                    let mut monitor = state.hardware_monitor.write().await;
-                    if let Err(e) = monitor.update_metrics().await {
-                        eprintln!("Failed to update hardware metrics: {}", e);
-                    }
-                }
+#[tauri::command]
+async fn search_knowledge_base_by_embedding(
+    state: State<'_, AppState>,
+    embedding: Vec<f32>,
+    limit: usize,
+) -> Result<Vec<serde_json::Value>, String> {
+    let rag = state.rag_engine.read().await;
+    rag.search_by_embedding(&embedding, limit)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn find_similar_chunks(
+    state: State<'_, AppState>,
+    chunk_id: String,
+    limit: usize,
+) -> Result<Vec<serde_json::Value>, String> {
+    let rag = state.rag_engine.read().await;
+    let embedding = rag.chunk_embedding(&chunk_id)
+        .ok_or_else(|| format!("Chunk not found: {}", chunk_id))?
+        .to_vec();
+
+    // The chunk itself always ranks first, so fetch one extra and drop it
+    let mut results = rag.search_by_embedding(&embedding, limit + 1)
+        .map_err(|e| e.to_string())?;
+    results.retain(|r| r["id"].as_str() != Some(chunk_id.as_str()));
+    results.truncate(limit);
+    Ok(results)
+}
+
+#[tauri::command]
+async fn add_to_knowledge_base(
+    state: State<'_, AppState>,
+    content: String,
+    metadata: serde_json::Value,
+    chunking: Option<ChunkOptions>,
+) -> Result<String, String> {
+    let redaction = state.pii_detector
+        .read()
+        .await
+        .redact_with_profile(&content, PROFILE_STORAGE)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut rag = state.rag_engine.write().await;
+    let doc_id = rag.add_document(&redaction.text, metadata, chunking, None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // The write lock is still held, so a document whose audit entry fails is gone before anyone sees it
+    if let Err(e) = state.pii_audit
+        .write()
+        .await
+        .record(AuditOperation::AddToKnowledgeBase, &doc_id, &content, &redaction.counts)
+    {
+        rag.remove_document(&doc_id).await.map_err(|e| e.to_string())?;
+        return Err(format!("Failed to write PII audit entry: {}", e));
+    }
+    Ok(doc_id)
+}
+
+#[tauri::command]
+async fn get_chunking_defaults(state: State<'_, AppState>) -> Result<ChunkParams, String> {
+    Ok(state.rag_engine.read().await.chunk_defaults())
+}
+
+#[tauri::command]
+async fn set_chunking_defaults(state: State<'_, AppState>, params: ChunkParams) -> Result<(), String> {
+    state.rag_engine
+        .write()
+        .await
+        .set_chunk_defaults(params)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_collection_embedding_models(state: State<'_, AppState>) -> Result<HashMap<String, String>, String> {
+    Ok(state.rag_engine.read().await.collection_models().clone())
+}
+
+#[tauri::command]
+async fn set_collection_embedding_model(
+    state: State<'_, AppState>,
+    collection: String,
+    model_id: String,
+) -> Result<(), String> {
+    state.rag_engine
+        .write()
+        .await
+        .assign_collection_model(&collection, &model_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Chunk and document counts, size on disk and embedding model, for showing index health
+#[tauri::command]
+async fn rag_stats(state: State<'_, AppState>) -> Result<IndexStats, String> {
+    let rag = state.rag_engine.read().await;
+    Ok(rag.get_stats())
+}
+
+// First step of clearing the knowledge base; the returned token must be passed to clear_knowledge_base
+#[tauri::command]
+async fn request_knowledge_base_clear(state: State<'_, AppState>) -> Result<String, String> {
+    let token = uuid::Uuid::new_v4().to_string();
+    *state.kb_clear_token.write().await = Some((token.clone(), std::time::Instant::now()));
+    Ok(token)
+}
+
+#[tauri::command]
+async fn clear_knowledge_base(
+    state: State<'_, AppState>,
+    confirmation_token: String,
+) -> Result<usize, String> {
+    let pending = state.kb_clear_token.write().await.take();
+    match pending {
+        Some((token, issued)) if token == confirmation_token && issued.elapsed() <= CLEAR_TOKEN_TTL => {}
+        Some((_, issued)) if issued.elapsed() > CLEAR_TOKEN_TTL => {
+            return Err("Confirmation token expired. Please confirm again.".to_string());
+        }
+        _ => return Err("Invalid confirmation token".to_string()),
+    }
+
+    let mut rag = state.rag_engine.write().await;
+    let removed = rag.get_stats().document_count;
+    rag.clear_index().await.map_err(|e| e.to_string())?;
+    Ok(removed)
+}
+
+// Migration for documents indexed before chunks carried a page or section location: re-ingests
+// each one whose source file is still on disk. Returns the sources ingested again; documents
+// whose files are gone keep working without locations until they are added again.
+#[tauri::command]
+async fn refresh_source_locations(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let sources = state.rag_engine.read().await.sources_missing_locations();
+    let mut refreshed = Vec::new();
+
+    for source in sources {
+        if !Path::new(&source).is_file() {
+            continue;
+        }
+        match state.ingest_file_with_progress(&source, true, |_, _| {}).await {
+            Ok(Some(_)) => refreshed.push(source),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to refresh locations for {}: {}", source, e),
+        }
+    }
+
+    Ok(refreshed)
+}
+
+// Re-embeds the indexed content with the installed embedding model, e.g. after it changed and
+// the index reports a mismatch. Returns the number of chunks re-embedded.
+#[tauri::command]
+async fn reindex_knowledge_base(state: State<'_, AppState>) -> Result<usize, String> {
+    let data_dir = state.data_dir.clone();
+    let embedder = tokio::task::spawn_blocking(move || embeddings::default_embedder(&data_dir))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Searches keep working on the old vectors until the new ones are swapped in
+    let prepared = state.rag_engine
+        .read()
+        .await
+        .prepare_reindex(embedder)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.rag_engine
+        .write()
+        .await
+        .commit_reindex(prepared)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_available_models(
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    // Picks up files downloaded, copied in or deleted since startup. The directory walk runs off
+    // the async workers and without the lock; the write lock is only taken when something changed.
+    let models_dir = state.llm_manager.read().await.models_dir().to_path_buf();
+    let files = tokio::task::spawn_blocking(move || llm_manager::find_model_files(&models_dir))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    {
+        let llm = state.llm_manager.read().await;
+        let (added, removed) = llm.model_files_changes(&files);
+        if added.is_empty() && removed.is_empty() {
+            return Ok(llm.list_models().await);
+        }
+    }
+
+    let mut llm = state.llm_manager.write().await;
+    llm.apply_model_files(files);
+    Ok(llm.list_models().await)
+}
+
+// Usage totals since startup plus the `recent` newest requests (default 20)
+#[tauri::command]
+async fn get_inference_metrics(
+    state: State<'_, AppState>,
+    recent: Option<usize>,
+) -> Result<InferenceMetricsSummary, String> {
+    let llm = state.llm_manager.read().await;
+    Ok(llm.metrics_summary(recent.unwrap_or(20)))
+}
+
+#[tauri::command]
+async fn download_model(
+    state: State<'_, AppState>,
+    model_name: String,
+) -> Result<String, String> {
+    let mut llm = state.llm_manager.write().await;
+    llm.download_model(&model_name)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(format!("Model {} downloaded successfully", model_name))
+}
+
+impl AppState {
+    fn pii_config_path(&self) -> PathBuf {
+        self.data_dir.join(PII_CONFIG_FILE)
+    }
+
+    fn ingest_filters(&self) -> Result<FilterPatterns, String> {
+        FilterPatterns::load(&self.data_dir.join(INGEST_FILTERS_FILE)).map_err(|e| e.to_string())
+    }
+
+    fn save_pii_config(&self, detector: &PIIDetector) -> Result<(), String> {
+        detector.config()
+            .save(&self.pii_config_path())
+            .map_err(|e| e.to_string())
+    }
+
+    // Shared ingestion pipeline: extract -> redact -> embed -> index.
+    // Returns None when identical content is already in the index.
+    async fn ingest_file(&self, file_path: &str) -> anyhow::Result<Option<String>> {
+        self.ingest_file_with_progress(file_path, false, |_, _| {}).await
+    }
+
+    // `on_progress` gets each stage as it starts, and embedding progress within its stage.
+    // With `reindex_unchanged`, content already in the index is ingested again, replacing the
+    // earlier version from the same source.
+    async fn ingest_file_with_progress(
+        &self,
+        file_path: &str,
+        reindex_unchanged: bool,
+        mut on_progress: impl FnMut(ProcessingStage, f32) + Send,
+    ) -> anyhow::Result<Option<String>> {
+        let file_type = Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        on_progress(ProcessingStage::Extracting, 0.0);
+        let content = self.file_processor.process_file(file_path, &file_type).await?;
+
+        let content_hash = rag_engine::content_hash(&content);
+        if !reindex_unchanged && self.rag_engine.read().await.contains_content_hash(&content_hash) {
+            return Ok(None);
+        }
+
+        on_progress(ProcessingStage::Redacting, 0.0);
+        let redaction = self.pii_detector
+            .read()
+            .await
+            .redact_with_profile(&content, PROFILE_STORAGE)
+            .await?;
+        let metadata = serde_json::json!({
+            "source": file_path,
+            "type": file_type,
+            "content_hash": content_hash,
+            "citations": citations::extract_citations(&content, true),
+        });
+
+        // Embedding under the read lock lets concurrent ingestions overlap; only the insert is exclusive
+        on_progress(ProcessingStage::Chunking, 0.0);
+        let prepared = self.rag_engine
+            .read()
+            .await
+            .prepare_document(&redaction.text, metadata, None, None, &CancellationToken::new(), |done, total| {
+                on_progress(ProcessingStage::Embedding, done as f32 / total.max(1) as f32);
+            })
+            .await?;
+        on_progress(ProcessingStage::Indexing, 0.0);
+        let doc_id = {
+            let mut rag = self.rag_engine.write().await;
+            // Another ingestion may have committed the same content while this one was embedding
+            if !reindex_unchanged && rag.contains_content_hash(&content_hash) {
+                return Ok(None);
+            }
+            // Audited first, so no indexed document lacks its entry even if a write fails
+            self.pii_audit.write().await.record(
+                AuditOperation::AddToKnowledgeBase,
+                prepared.doc_id(),
+                &content,
+                &redaction.counts,
+            )?;
+            // A changed file replaces its earlier version instead of sitting beside it
+            rag.remove_documents_from_source(file_path).await?;
+            rag.commit_document(prepared).await?
+        };
+
+        Ok(Some(doc_id))
+    }
+}
+
+#[tauri::command]
+async fn get_monitor_polling(state: State<'_, AppState>) -> Result<PollSettings, String> {
+    Ok(*state.monitor_polling.borrow())
+}
+
+#[tauri::command]
+async fn start_monitor_polling(state: State<'_, AppState>) -> Result<PollSettings, String> {
+    state.monitor_polling.send_modify(|settings| settings.running = true);
+    Ok(*state.monitor_polling.borrow())
+}
+
+// Stops background refreshes until restarted; on-demand status checks still work
+#[tauri::command]
+async fn stop_monitor_polling(state: State<'_, AppState>) -> Result<PollSettings, String> {
+    state.monitor_polling.send_modify(|settings| settings.running = false);
+    Ok(*state.monitor_polling.borrow())
+}
+
+// `inference_interval_secs` applies while a generation is running; None pauses polling then
+#[tauri::command]
+async fn set_monitor_poll_interval(
+    state: State<'_, AppState>,
+    interval_secs: u64,
+    inference_interval_secs: Option<u64>,
+) -> Result<PollSettings, String> {
+    if interval_secs == 0 || inference_interval_secs == Some(0) {
+        return Err("Polling intervals must be at least 1 second".to_string());
+    }
+
+    let config_path = state.data_dir.join(HARDWARE_CONFIG_FILE);
+    let mut config = HardwareConfig::load(&config_path).map_err(|e| e.to_string())?;
+    config.poll_interval_secs = interval_secs;
+    config.inference_poll_interval_secs = inference_interval_secs;
+    config.save(&config_path).map_err(|e| e.to_string())?;
+
+    state.monitor_polling.send_modify(|settings| {
+        settings.interval_secs = interval_secs;
+        settings.inference_interval_secs = inference_interval_secs;
+    });
+    Ok(*state.monitor_polling.borrow())
+}
+
+// Lets the UI point at what to close when memory is tight. `exclude_self` (default true)
+// leaves this app out of the list.
+#[tauri::command]
+async fn get_top_processes(
+    state: State<'_, AppState>,
+    limit: usize,
+    sort_by: Option<ProcessSort>,
+    exclude_self: Option<bool>,
+) -> Result<Vec<ProcessInfo>, String> {
+    top_processes(&state, limit, sort_by.unwrap_or_default(), exclude_self.unwrap_or(true))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn top_processes(state: &AppState, limit: usize, sort_by: ProcessSort, exclude_self: bool) -> anyhow::Result<Vec<ProcessInfo>> {
+    let mut monitor = state.hardware_monitor.write().await;
+    monitor.update_metrics().await?;
+    Ok(monitor.get_process_info(limit, sort_by, exclude_self).await)
+}
+
+#[tauri::command]
+async fn get_resource_limits(state: State<'_, AppState>) -> Result<ResourceLimits, String> {
+    Ok(state.hardware_monitor.read().await.resource_limits())
+}
+
+// Usage caps in percent. New messages are refused while usage is above them, and a running
+// generation slows down until the background monitor reads usage back under them.
+#[tauri::command]
+async fn set_resource_limits(
+    state: State<'_, AppState>,
+    max_gpu_usage: f32,
+    max_cpu_usage: f32,
+    max_ram_usage: f32,
+) -> Result<ResourceLimits, String> {
+    let limits = ResourceLimits { max_gpu_usage, max_cpu_usage, max_ram_usage };
+    limits.validate().map_err(|e| e.to_string())?;
+
+    let config_path = state.data_dir.join(HARDWARE_CONFIG_FILE);
+    let mut config = HardwareConfig::load(&config_path).map_err(|e| e.to_string())?;
+    config.resource_limits = limits;
+    config.save(&config_path).map_err(|e| e.to_string())?;
+
+    state.hardware_monitor.write().await.set_resource_limits(limits);
+    Ok(limits)
+}
+
+#[tauri::command]
+async fn get_thresholds(state: State<'_, AppState>) -> Result<SafetyThresholds, String> {
+    Ok(state.hardware_monitor.read().await.thresholds())
+}
+
+// Percentages for cpu/memory/gpu, °C for temperature. The next safety check uses them.
+#[tauri::command]
+async fn set_thresholds(
+    state: State<'_, AppState>,
+    thresholds: SafetyThresholds,
+) -> Result<SafetyThresholds, String> {
+    thresholds.validate().map_err(|e| e.to_string())?;
+
+    let config_path = state.data_dir.join(HARDWARE_CONFIG_FILE);
+    let mut config = HardwareConfig::load(&config_path).map_err(|e| e.to_string())?;
+    config.thresholds = thresholds;
+    config.save(&config_path).map_err(|e| e.to_string())?;
+
+    state.hardware_monitor.write().await.set_thresholds(thresholds);
+    Ok(thresholds)
+}
+
+// Selects the GPU used for both monitoring and inference; None picks the largest-VRAM device
+#[tauri::command]
+async fn set_gpu_index(
+    state: State<'_, AppState>,
+    command_state: State<'_, CommandState>,
+    index: Option<u32>,
+) -> Result<u32, String> {
+    let selected = command_state
+        .with_monitor(move |monitor| monitor.set_gpu_index(index).map_err(|e| e.to_string()))
+        .await??;
+
+    state.hardware_monitor.write().await.set_gpu_index(selected);
+    state.llm_manager.write().await.set_gpu_index(Some(selected));
+
+    let config_path = state.data_dir.join(HARDWARE_CONFIG_FILE);
+    let mut config = HardwareConfig::load(&config_path).map_err(|e| e.to_string())?;
+    config.gpu_index = index;
+    config.save(&config_path).map_err(|e| e.to_string())?;
+
+    Ok(selected)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FolderWatchStatus {
+    enabled: bool,
+    running: bool,
+    path: Option<PathBuf>,
+    filters: Option<FilterPatterns>,
+}
+
+// `include`/`exclude` override the global ingestion filters for this watch and are remembered with it
+#[tauri::command]
+async fn start_folder_watch(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+) -> Result<FolderWatchStatus, String> {
+    let path = PathBuf::from(path);
+    let overrides = if include.is_some() || exclude.is_some() {
+        Some(state.ingest_filters()?.with_overrides(include, exclude))
+    } else {
+        None
+    };
+    let filters = match &overrides {
+        Some(filters) => filters.clone(),
+        None => state.ingest_filters()?,
+    };
+
+    state.folder_watcher
+        .write()
+        .await
+        .start(path.clone(), &filters, app, state.inner().clone())
+        .map_err(|e| e.to_string())?;
+
+    let config = WatchConfig { enabled: true, path: Some(path), filters: overrides };
+    config.save(&state.data_dir.join(WATCH_CONFIG_FILE)).map_err(|e| e.to_string())?;
+    get_folder_watch_status(state).await
+}
+
+#[tauri::command]
+async fn stop_folder_watch(state: State<'_, AppState>) -> Result<FolderWatchStatus, String> {
+    let path = {
+        let mut watcher = state.folder_watcher.write().await;
+        let path = watcher.watched_path().map(Path::to_path_buf);
+        watcher.stop();
+        path
+    };
+
+    let config_path = state.data_dir.join(WATCH_CONFIG_FILE);
+    let mut config = WatchConfig::load(&config_path).map_err(|e| e.to_string())?;
+    config.enabled = false;
+    config.path = path.or(config.path);
+    config.save(&config_path).map_err(|e| e.to_string())?;
+    get_folder_watch_status(state).await
+}
+
+#[tauri::command]
+async fn get_ingestion_filters(state: State<'_, AppState>) -> Result<FilterPatterns, String> {
+    state.ingest_filters()
+}
+
+// Validated before saving so a bad glob can't break the next watch resume
+#[tauri::command]
+async fn set_ingestion_filters(
+    state: State<'_, AppState>,
+    filters: FilterPatterns,
+) -> Result<FilterPatterns, String> {
+    PathFilter::new(&filters).map_err(|e| e.to_string())?;
+    filters.save(&state.data_dir.join(INGEST_FILTERS_FILE)).map_err(|e| e.to_string())?;
+    Ok(filters)
+}
+
+// Most files a single directory ingestion will pick up
+const MAX_DIRECTORY_INGEST_FILES: usize = 10_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DirectoryIngestResult {
+    indexed: Vec<String>,
+    duplicates: Vec<String>,
+    unsupported: Vec<String>,
+    failed: Vec<(String, String)>,
+    truncated: bool,
+}
+
+#[tauri::command]
+async fn ingest_directory(
+    state: State<'_, AppState>,
+    path: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+) -> Result<DirectoryIngestResult, String> {
+    let root = PathBuf::from(&path);
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", path));
+    }
+
+    let patterns = state.ingest_filters()?.with_overrides(include, exclude);
+    let filter = PathFilter::new(&patterns).map_err(|e| e.to_string())?;
+    let (files, truncated) = tokio::task::spawn_blocking(move || {
+        path_filter::walk_files(&root, &filter, MAX_DIRECTORY_INGEST_FILES)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+
+    let mut result = DirectoryIngestResult {
+        indexed: Vec::new(),
+        duplicates: Vec::new(),
+        unsupported: Vec::new(),
+        failed: Vec::new(),
+        truncated,
+    };
+
+    for file in files {
+        let file = file.to_string_lossy().to_string();
+        let extension = Path::new(&file).extension().and_then(|e| e.to_str()).unwrap_or("");
+        if !state.file_processor.is_supported(extension) {
+            result.unsupported.push(file);
+            continue;
+        }
+        match state.ingest_file(&file).await {
+            Ok(Some(_)) => result.indexed.push(file),
+            Ok(None) => result.duplicates.push(file),
+            Err(e) => result.failed.push((file, e.to_string())),
+        }
+    }
+
+    Ok(result)
+}
+
+// Files a batch ingestion extracts and embeds at the same time; each can hold a whole
+// document's text and embeddings in memory
+const MAX_CONCURRENT_INGESTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IngestOutcome {
+    Indexed,
+    Duplicate,
+    Unsupported,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FileIngestResult {
+    path: String,
+    outcome: IngestOutcome,
+    document_id: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BatchProgress {
+    batch_id: String,
+    // Index into the `paths` the batch was started with
+    file_index: usize,
+    path: String,
+    stage: ProcessingStage,
+    progress: f32, // 0.0 - 1.0 within the stage
+    percent: f32,  // 0 - 100 for this file
+    files_done: usize,
+    files_total: usize,
+    overall_percent: f32,
+}
+
+const BATCH_PROCESSING_EVENT: &str = "batch-processing-progress";
+
+// Per-file percentages of a running batch, for the overall figure in each event
+struct BatchTracker {
+    sink: Box<dyn ProgressSink>,
+    batch_id: String,
+    percents: std::sync::Mutex<Vec<f32>>,
+}
+
+impl BatchTracker {
+    fn report(&self, file_index: usize, path: &str, stage: ProcessingStage, progress: f32) {
+        let percent = stage.percent(progress);
+        let (files_done, files_total, overall_percent) = {
+            let mut percents = self.percents.lock().unwrap_or_else(|e| e.into_inner());
+            percents[file_index] = percent;
+            let done = percents.iter().filter(|p| **p >= 100.0).count();
+            let overall = percents.iter().sum::<f32>() / percents.len().max(1) as f32;
+            (done, percents.len(), overall)
+        };
+
+        let event = BatchProgress {
+            batch_id: self.batch_id.clone(),
+            file_index,
+            path: path.to_string(),
+            stage,
+            progress,
+            percent,
+            files_done,
+            files_total,
+            overall_percent,
+        };
+        self.sink.send(BATCH_PROCESSING_EVENT, serde_json::json!(event));
+    }
+}
+
+// Adds each file to the knowledge base through the same pipeline as ingest_directory, several at
+// a time. One file failing doesn't stop the rest; results come back in the order of `paths`.
+// Progress for each file and the batch as a whole goes out on BATCH_PROCESSING_EVENT.
+#[tauri::command]
+async fn process_documents(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+    batch_id: Option<String>,
+) -> Result<Vec<FileIngestResult>, String> {
+    Ok(ingest_batch(state.inner().clone(), Box::new(app), &paths, batch_id).await)
+}
+
+async fn ingest_batch(state: AppState, sink: Box<dyn ProgressSink>, paths: &[String], batch_id: Option<String>) -> Vec<FileIngestResult> {
+    let tracker = Arc::new(BatchTracker {
+        sink,
+        batch_id: batch_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        percents: std::sync::Mutex::new(vec![0.0; paths.len()]),
+    });
+
+    ingest_all(paths, move |index, path| {
+        let state = state.clone();
+        let tracker = tracker.clone();
+        async move {
+            let extension = Path::new(&path).extension().and_then(|e| e.to_str()).unwrap_or("");
+            if !state.file_processor.is_supported(extension) {
+                tracker.report(index, &path, ProcessingStage::Failed, 1.0);
+                return (IngestOutcome::Unsupported, None, Some(format!("Unsupported file format: {}", path)));
+            }
+
+            let result = state
+                .ingest_file_with_progress(&path, false, |stage, progress| tracker.report(index, &path, stage, progress))
+                .await;
+            let final_stage = if result.is_ok() { ProcessingStage::Done } else { ProcessingStage::Failed };
+            tracker.report(index, &path, final_stage, 1.0);
+
+            match result {
+                Ok(Some(id)) => (IngestOutcome::Indexed, Some(id), None),
+                Ok(None) => (IngestOutcome::Duplicate, None, None),
+                Err(e) => (IngestOutcome::Failed, None, Some(e.to_string())),
+            }
+        }
+    })
+    .await
+}
+
+// Runs `ingest` (given each path's index and the path) for every path, MAX_CONCURRENT_INGESTS at
+// a time. A task that panics fails only its own file; results keep the order of `paths`.
+async fn ingest_all<F, Fut>(paths: &[String], ingest: F) -> Vec<FileIngestResult>
+where
+    F: Fn(usize, String) -> Fut,
+    Fut: std::future::Future<Output = (IngestOutcome, Option<String>, Option<String>)> + Send + 'static,
+{
+    let permits = Arc::new(tokio::sync::Semaphore::new(MAX_CONCURRENT_INGESTS));
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut task_indices = HashMap::new();
+
+    for (index, path) in paths.iter().cloned().enumerate() {
+        let permits = permits.clone();
+        let ingest = ingest(index, path);
+        let handle = tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            ingest.await
+        });
+        task_indices.insert(handle.id(), index);
+    }
+
+    let mut results: Vec<FileIngestResult> = paths
+        .iter()
+        .map(|path| FileIngestResult {
+            path: path.clone(),
+            outcome: IngestOutcome::Failed,
+            document_id: None,
+            error: None,
+        })
+        .collect();
+    while let Some(joined) = tasks.join_next_with_id().await {
+        match joined {
+            Ok((id, (outcome, document_id, error))) => {
+                let result = &mut results[task_indices[&id]];
+                result.outcome = outcome;
+                result.document_id = document_id;
+                result.error = error;
+            }
+            Err(e) => {
+                let result = &mut results[task_indices[&e.id()]];
+                result.error = Some(format!("Ingestion stopped unexpectedly: {}", e));
+            }
+        }
+    }
+
+    results
+}
+
+#[tauri::command]
+async fn get_folder_watch_status(state: State<'_, AppState>) -> Result<FolderWatchStatus, String> {
+    let config = WatchConfig::load(&state.data_dir.join(WATCH_CONFIG_FILE)).map_err(|e| e.to_string())?;
+    let watcher = state.folder_watcher.read().await;
+    Ok(FolderWatchStatus {
+        enabled: config.enabled,
+        running: watcher.is_running(),
+        path: watcher.watched_path().map(Path::to_path_buf).or(config.path),
+        filters: config.filters,
+    })
+}
+
+#[tauri::command]
+async fn detect_pii(
+    state: State<'_, AppState>,
+    text: String,
+    min_confidence: Option<f32>,
+) -> Result<Vec<PIIMatch>, String> {
+    let detector = state.pii_detector.read().await;
+    detector.detect_pii_above(&text, min_confidence.unwrap_or(0.0)).await.map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReportedRedaction {
+    text: String,
+    report: PiiReport,
+}
+
+#[tauri::command]
+async fn redact_pii_with_report(
+    state: State<'_, AppState>,
+    text: String,
+) -> Result<ReportedRedaction, String> {
+    let detector = state.pii_detector.read().await;
+    let (text, report) = detector.remove_pii_with_report(&text).await.map_err(|e| e.to_string())?;
+    Ok(ReportedRedaction { text, report })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReversibleRedaction {
+    text: String,
+    map: PiiMap,
+}
+
+// The map holds the original PII; it stays with the caller and is never stored or sent to the model
+#[tauri::command]
+async fn redact_pii_reversible(
+    state: State<'_, AppState>,
+    text: String,
+) -> Result<ReversibleRedaction, String> {
+    let detector = state.pii_detector.read().await;
+    let (text, map) = detector.remove_pii_reversible(&text).await.map_err(|e| e.to_string())?;
+    Ok(ReversibleRedaction { text, map })
+}
+
+#[tauri::command]
+async fn pseudonymize_pii(
+    state: State<'_, AppState>,
+    text: String,
+) -> Result<String, String> {
+    let detector = state.pii_detector.read().await;
+    detector.remove_pii_pseudonymized(&text).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_pseudonym_label(
+    state: State<'_, AppState>,
+    pii_type: String,
+    label: PseudonymLabel,
+) -> Result<(), String> {
+    let mut detector = state.pii_detector.write().await;
+    detector.set_pseudonym_label(&pii_type, label).map_err(|e| e.to_string())?;
+    state.save_pii_config(&detector)
+}
+
+#[tauri::command]
+async fn restore_pii(
+    state: State<'_, AppState>,
+    text: String,
+    map: PiiMap,
+) -> Result<String, String> {
+    Ok(state.pii_detector.read().await.restore_pii(&text, &map))
+}
+
+// Per-type hit counts, lengths and confidence over a set of files; unreadable files are skipped
+#[tauri::command]
+async fn get_pii_corpus_stats(
+    state: State<'_, AppState>,
+    file_paths: Vec<String>,
+) -> Result<PiiStats, String> {
+    let mut texts = Vec::with_capacity(file_paths.len());
+    for file_path in &file_paths {
+        let file_type = Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        match state.file_processor.process_file(file_path, &file_type).await {
+            Ok(content) => texts.push(content),
+            Err(e) => eprintln!("Skipping {} in PII stats: {}", file_path, e),
+        }
+    }
+
+    let detector = state.pii_detector.read().await;
+    detector.corpus_stats(&texts).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_pii_allowlist(state: State<'_, AppState>) -> Result<Vec<AllowedTerm>, String> {
+    let detector = state.pii_detector.read().await;
+    Ok(detector.config().allowlist.terms())
+}
+
+#[tauri::command]
+async fn add_pii_allowlist_term(
+    state: State<'_, AppState>,
+    term: String,
+    case_sensitive: Option<bool>,
+) -> Result<Vec<AllowedTerm>, String> {
+    let mut detector = state.pii_detector.write().await;
+    match case_sensitive {
+        Some(true) => detector.allowlist_mut().add(&term, true),
+        _ => detector.add_allowed_term(term),
+    }
+    state.save_pii_config(&detector)?;
+    Ok(detector.config().allowlist.terms())
+}
+
+#[tauri::command]
+async fn remove_pii_allowlist_term(
+    state: State<'_, AppState>,
+    term: String,
+) -> Result<Vec<AllowedTerm>, String> {
+    let mut detector = state.pii_detector.write().await;
+    if !detector.allowlist_mut().remove(&term) {
+        return Err(format!("Term not in allowlist: {}", term));
+    }
+    state.save_pii_config(&detector)?;
+    Ok(detector.config().allowlist.terms())
+}
+
+#[tauri::command]
+async fn get_custom_pii_patterns(state: State<'_, AppState>) -> Result<BTreeMap<String, String>, String> {
+    let detector = state.pii_detector.read().await;
+    Ok(detector.config().custom_patterns.clone())
+}
+
+// Returns the type the matches are reported and counted under, e.g. CUSTOM_MATTER
+#[tauri::command]
+async fn add_custom_pii_pattern(
+    state: State<'_, AppState>,
+    name: String,
+    pattern: String,
+) -> Result<String, String> {
+    let mut detector = state.pii_detector.write().await;
+    let pii_type = detector.add_custom_pattern(name, pattern).map_err(|e| e.to_string())?;
+    state.save_pii_config(&detector)?;
+    Ok(pii_type)
+}
+
+#[tauri::command]
+async fn remove_custom_pii_pattern(
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<(), String> {
+    let mut detector = state.pii_detector.write().await;
+    if !detector.remove_custom_pattern(&name) {
+        return Err(format!("No custom pattern named {}", name));
+    }
+    state.save_pii_config(&detector)
+}
+
+#[tauri::command]
+async fn get_redaction_profiles(
+    state: State<'_, AppState>,
+) -> Result<BTreeMap<String, RedactionProfile>, String> {
+    let detector = state.pii_detector.read().await;
+    Ok(detector.config().profiles.clone())
+}
+
+#[tauri::command]
+async fn set_redaction_profile(
+    state: State<'_, AppState>,
+    name: String,
+    profile: RedactionProfile,
+) -> Result<BTreeMap<String, RedactionProfile>, String> {
+    let mut detector = state.pii_detector.write().await;
+    detector.set_profile(name, profile).map_err(|e| e.to_string())?;
+    state.save_pii_config(&detector)?;
+    Ok(detector.config().profiles.clone())
+}
+
+#[tauri::command]
+async fn import_pii_allowlist(
+    state: State<'_, AppState>,
+    file_path: String,
+) -> Result<usize, String> {
+    let contents = tokio::fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut detector = state.pii_detector.write().await;
+    let added = detector.allowlist_mut().import_text(&contents);
+    state.save_pii_config(&detector)?;
+    Ok(added)
+}
+
+// Switches the phone, date and national ID formats the detector looks for
+#[tauri::command]
+async fn set_pii_locale(
+    state: State<'_, AppState>,
+    locale: Locale,
+) -> Result<(), String> {
+    let mut detector = state.pii_detector.write().await;
+    detector.set_locale(locale);
+    state.save_pii_config(&detector)
+}
+
+// Whether 10.x, 192.168.x, 127.x and other internal addresses are redacted like public ones
+#[tauri::command]
+async fn set_redact_private_ips(
+    state: State<'_, AppState>,
+    redact: bool,
+) -> Result<(), String> {
+    let mut detector = state.pii_detector.write().await;
+    detector.set_redact_private_ips(redact);
+    state.save_pii_config(&detector)
+}
+
+#[tauri::command]
+async fn set_name_detection(
+    state: State<'_, AppState>,
+    mode: NameDetection,
+) -> Result<(), String> {
+    let mut detector = state.pii_detector.write().await;
+    detector.set_name_detection(mode);
+    state.save_pii_config(&detector)
+}
+
+// Adds local names to the gazetteer dictionaries; returns how many were new
+#[tauri::command]
+async fn add_gazetteer_names(
+    state: State<'_, AppState>,
+    first_names: Vec<String>,
+    last_names: Vec<String>,
+) -> Result<usize, String> {
+    let mut detector = state.pii_detector.write().await;
+    let added = detector.add_gazetteer_names(&first_names, &last_names);
+    state.save_pii_config(&detector)?;
+    Ok(added)
+}
+
+#[tauri::command]
+async fn query_pii_audit(
+    state: State<'_, AppState>,
+    query: Option<AuditQuery>,
+) -> Result<Vec<AuditEntry>, String> {
+    state.pii_audit
+        .read()
+        .await
+        .query(&query.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_pii_audit(state: State<'_, AppState>) -> Result<ChainVerification, String> {
+    state.pii_audit
+        .read()
+        .await
+        .verify()
+        .map_err(|e| e.to_string())
+}
+
+// Cuts a damaged log back to its last valid entry; the damaged file is kept alongside it
+#[tauri::command]
+async fn repair_pii_audit(state: State<'_, AppState>) -> Result<ChainVerification, String> {
+    state.pii_audit
+        .write()
+        .await
+        .repair()
+        .map_err(|e| e.to_string())
+}
+
+fn main() {
+    let data_dir = data_dir::resolve_data_dir()
+        .and_then(|dir| data_dir::ensure_writable(&dir).map(|_| dir))
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to prepare data directory: {}", e);
+            std::process::exit(1);
+        });
+
+    let pii_config = PiiConfig::load(&data_dir.join(PII_CONFIG_FILE)).unwrap_or_else(|e| {
+        eprintln!("Failed to load PII config, using defaults: {}", e);
+        PiiConfig::default()
+    });
+
+    let hardware_config = HardwareConfig::load(&data_dir.join(HARDWARE_CONFIG_FILE)).unwrap_or_else(|e| {
+        eprintln!("Failed to load hardware config, using defaults: {}", e);
+        HardwareConfig::default()
+    });
+
+    // Without the audit log nothing may be sent to the model, so this is fatal like the data dir
+    let pii_audit = PiiAuditLog::open(&data_dir).unwrap_or_else(|e| {
+        eprintln!("Failed to open PII audit log: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut system_monitor = system_monitor::SystemMonitor::new();
+    if let Err(e) = system_monitor.set_gpu_index(hardware_config.gpu_index) {
+        eprintln!("Configured GPU unavailable, using default: {}", e);
+    }
+    let gpu_index = system_monitor.gpu_index();
+
+    let mut hardware_monitor = HardwareMonitor::new();
+    hardware_monitor.set_gpu_index(gpu_index);
+    hardware_monitor.set_resource_limits(hardware_config.resource_limits);
+    // A hand-edited config out of range falls back to the defaults rather than disabling checks
+    match hardware_config.thresholds.validate() {
+        Ok(()) => hardware_monitor.set_thresholds(hardware_config.thresholds),
+        Err(e) => eprintln!("Ignoring saved hardware thresholds: {}", e),
+    }
+
+    let mut llm_manager = LLMManager::new(&data_dir);
+    llm_manager.set_gpu_index(Some(gpu_index));
+
+    let (poll_settings, poll_receiver) = watch::channel(PollSettings::from_config(&hardware_config));
+
+    let pii_detector = Arc::new(RwLock::new(PIIDetector::with_config(pii_config)));
+    let llm_manager = Arc::new(RwLock::new(llm_manager));
+    // The embedding models load after the window is up, see setup
+    let rag_engine = Arc::new(RwLock::new(RAGEngine::new(&data_dir)));
+
+    let file_processor = Arc::new(FileProcessor::new());
+    match ExtractionConfig::load(&data_dir.join(EXTRACTION_CONFIG_FILE)).and_then(|config| {
+        config.validate()?;
+        Ok(config)
+    }) {
+        Ok(config) => file_processor.apply_config(&config),
+        Err(e) => eprintln!("Ignoring saved extraction settings: {}", e),
+    }
+
+    let agent_config = AgentConfig::load(&data_dir.join(AGENT_CONFIG_FILE)).unwrap_or_else(|e| {
+        eprintln!("Failed to load agent config, using defaults: {}", e);
+        AgentConfig::default()
+    });
+    let mut agent = AgentOrchestrator::new(true, agent_config.policy.clone());
+    // The saved policy, paths and limits replace the defaults
+    if let Err(e) = agent.apply_config(&agent_config) {
+        eprintln!("Ignoring saved agent settings: {}", e);
+    }
+    agent.attach_rag(rag_engine.clone());
+    agent.attach_file_processor(file_processor.clone());
+    agent.attach_llm(llm_manager.clone(), pii_detector.clone());
+    match ToolAuditLog::open(&data_dir) {
+        Ok(audit_log) => agent.set_audit_log(audit_log),
+        Err(e) => eprintln!("Failed to open tool audit log, agent tool calls will not be logged: {}", e),
+    }
+
+    let app_state = AppState {
+        pii_detector,
+        hardware_monitor: Arc::new(RwLock::new(hardware_monitor)),
+        llm_manager,
+        file_processor,
+        rag_engine,
+        data_dir,
+        kb_clear_token: Arc::new(RwLock::new(None)),
+        folder_watcher: Arc::new(RwLock::new(FolderWatcher::new())),
+        processing_jobs: Arc::new(RwLock::new(HashMap::new())),
+        search_jobs: Arc::new(RwLock::new(HashMap::new())),
+        chat_jobs: Arc::new(RwLock::new(HashMap::new())),
+        generations: GenerationTracker::default(),
+        throttle: ResourceThrottle::default(),
+        monitor_polling: Arc::new(poll_settings),
+        pii_audit: Arc::new(RwLock::new(pii_audit)),
+        agent: Arc::new(RwLock::new(agent)),
+    };
+
+    // Initialize the system monitor state
+    let command_state = CommandState::new(system_monitor);
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_os::init())
+        .manage(app_state.clone())
+        .manage(command_state)
+        .setup(move |app| {
+            let watch_config = WatchConfig::load(&app_state.data_dir.join(WATCH_CONFIG_FILE)).unwrap_or_default();
+            if let (true, Some(path)) = (watch_config.enabled, watch_config.path) {
+                let handle = app.handle().clone();
+                let watch_state = app_state.clone();
+                let filters = watch_config.filters
+                    .unwrap_or_else(|| watch_state.ingest_filters().unwrap_or_default());
+                tauri::async_runtime::spawn(async move {
+                    let mut watcher = watch_state.folder_watcher.write().await;
+                    if let Err(e) = watcher.start(path, &filters, handle, watch_state.clone()) {
+                        eprintln!("Failed to resume folder watch: {}", e);
+                    }
+                });
+            }
+
+            let rag_state = app_state.clone();
+            tauri::async_runtime::spawn(async move {
+                // Loading a sentence model takes seconds, so it runs on the blocking pool; the
+                // index stays closed until the model is in place, since it is checked against it
+                let data_dir = rag_state.data_dir.clone();
+                let embedders = tokio::task::spawn_blocking(move || {
+                    (embeddings::default_embedder(&data_dir), embeddings::collection_embedders(&data_dir))
+                })
+                .await;
+                let mut rag = rag_state.rag_engine.write().await;
+                match embedders {
+                    Ok((embedder, collection_embedders)) => {
+                        rag.set_embedder(embedder);
+                        for embedder in collection_embedders {
+                            rag.register_embedder(embedder);
+                        }
+                    }
+                    Err(e) => eprintln!("Embedding model loading task failed: {}", e),
+                }
+                if let Err(e) = rag.initialize().await {
+                    eprintln!("Failed to load knowledge base index: {}", e);
+                }
+                drop(rag);
+                if let Err(e) = rag_state.llm_manager.write().await.initialize().await {
+                    eprintln!("Failed to initialize model registry: {}", e);
+                }
             });
+
+            tauri::async_runtime::spawn(hardware_monitor::poll_loop(
+                app_state.hardware_monitor.clone(),
+                app_state.generations.clone(),
+                app_state.throttle.clone(),
+                poll_receiver,
+            ));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             check_system_status,
+            health_check,
             process_document,
+            cancel_document_processing,
+            export_redacted_document,
+            process_image,
             send_message,
+            get_inference_metrics,
+            send_message_streaming,
+            cancel_message,
+            list_agent_tools,
+            execute_agent_tool,
+            run_agent_task,
+            get_agent_config,
+            get_extraction_config,
+            set_extraction_config,
+            set_agent_config,
+            set_agent_tool_enabled,
             search_knowledge_base,
+            search_knowledge_base_streaming,
+            cancel_search,
+            search_knowledge_base_by_embedding,
+            extract_citations,
+            find_documents_by_citation,
+            query_pii_audit,
+            redact_pii_with_report,
+            redact_pii_reversible,
+            restore_pii,
+            pseudonymize_pii,
+            set_pseudonym_label,
+            verify_pii_audit,
+            repair_pii_audit,
+            find_similar_chunks,
             add_to_knowledge_base,
+            rag_stats,
+            get_chunking_defaults,
+            set_chunking_defaults,
+            get_collection_embedding_models,
+            set_collection_embedding_model,
+            request_knowledge_base_clear,
+            clear_knowledge_base,
+            reindex_knowledge_base,
+            refresh_source_locations,
+            start_folder_watch,
+            stop_folder_watch,
+            get_folder_watch_status,
+            get_ingestion_filters,
+            set_ingestion_filters,
+            ingest_directory,
+            process_documents,
             list_available_models,
             download_model,
+            detect_pii,
+            get_pii_corpus_stats,
+            get_pii_allowlist,
+            add_pii_allowlist_term,
+            remove_pii_allowlist_term,
+            import_pii_allowlist,
+            get_custom_pii_patterns,
+            add_custom_pii_pattern,
+            remove_custom_pii_pattern,
+            get_redaction_profiles,
+            set_redaction_profile,
+            set_name_detection,
+            set_pii_locale,
+            set_redact_private_ips,
+            add_gazetteer_names,
+            set_gpu_index,
+            get_monitor_polling,
+            start_monitor_polling,
+            stop_monitor_polling,
+            set_monitor_poll_interval,
+            commands::list_gpus,
             commands::get_system_specs,
             commands::check_model_compatibility,
             commands::get_resource_usage,
             commands::download_model_from_huggingface,
+            commands::cancel_download,
             commands::search_huggingface_models,
             commands::load_model,
             commands::unload_model,
             commands::emergency_stop,
-            commands::set_resource_limits,
+            set_resource_limits,
+            get_resource_limits,
+            get_top_processes,
+            get_thresholds,
+            set_thresholds,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
+
+// AppState over a temporary data dir, for command tests
+#[cfg(test)]
+pub(crate) async fn test_state(dir: &Path) -> AppState {
+    let pii_detector = Arc::new(RwLock::new(PIIDetector::default()));
+    let llm_manager = Arc::new(RwLock::new(LLMManager::new(dir)));
+    let mut rag_engine = RAGEngine::new(dir);
+    rag_engine.initialize().await.unwrap();
+    let (poll_settings, _) = watch::channel(PollSettings::from_config(&HardwareConfig::default()));
+    AppState {
+        pii_detector,
+        hardware_monitor: Arc::new(RwLock::new(HardwareMonitor::new())),
+        llm_manager,
+        file_processor: Arc::new(FileProcessor::new()),
+        rag_engine: Arc::new(RwLock::new(rag_engine)),
+        data_dir: dir.to_path_buf(),
+        kb_clear_token: Arc::new(RwLock::new(None)),
+        folder_watcher: Arc::new(RwLock::new(FolderWatcher::new())),
+        processing_jobs: Arc::new(RwLock::new(HashMap::new())),
+        search_jobs: Arc::new(RwLock::new(HashMap::new())),
+        chat_jobs: Arc::new(RwLock::new(HashMap::new())),
+        generations: GenerationTracker::default(),
+        throttle: ResourceThrottle::default(),
+        monitor_polling: Arc::new(poll_settings),
+        pii_audit: Arc::new(RwLock::new(PiiAuditLog::open(dir).unwrap())),
+        agent: Arc::new(RwLock::new(AgentOrchestrator::new(true, Default::default()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingSink(std::sync::Mutex<Vec<(String, serde_json::Value)>>);
+
+    impl ProgressSink for RecordingSink {
+        fn send(&self, event: &str, payload: serde_json::Value) {
+            self.0.lock().unwrap().push((event.to_string(), payload));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_is_refused_over_the_ram_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path()).await;
+        let models_dir = dir.path().join("models");
+        std::fs::create_dir_all(&models_dir).unwrap();
+        let model_file = inference::write_tiny_model(&models_dir);
+        state.llm_manager.write().await.apply_model_files(vec![model_file]);
+        let params = GenerationParams { max_tokens: Some(4), ..Default::default() };
+
+        // Within the limits the message is answered
+        let generation = chat(&state, "w1 w2", "tiny-llama", Some(params.clone())).await.unwrap();
+        assert_eq!(generation.completion_tokens, 4);
+
+        // Any machine uses some RAM, so a 0% cap is always exceeded
+        {
+            let mut monitor = state.hardware_monitor.write().await;
+            monitor.set_resource_limits(ResourceLimits { max_ram_usage: 0.0, ..ResourceLimits::default() });
+            monitor.update_metrics().await.unwrap();
+        }
+        let err = chat(&state, "w1 w2", "tiny-llama", Some(params)).await.unwrap_err();
+        assert!(err.starts_with("Resource limit exceeded"), "{}", err);
+        assert!(state.chat_jobs.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_emits_stages_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path()).await;
+        let file = dir.path().join("memo.txt");
+        std::fs::write(&file, "Please send the signed lease to jane.doe@example.com by Friday.").unwrap();
+
+        let sink = RecordingSink::default();
+        let document = run_document_pipeline(
+            &sink,
+            &state,
+            "job-1",
+            file.to_string_lossy().to_string(),
+            "txt".to_string(),
+            true,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert!(!document.content.contains("jane.doe@example.com"));
+
+        let events = sink.0.lock().unwrap();
+        assert!(events.iter().all(|(event, payload)| event == PROCESSING_EVENT && payload["job_id"] == "job-1"));
+        let mut stages: Vec<&str> = events.iter().filter_map(|(_, payload)| payload["stage"].as_str()).collect();
+        stages.dedup();
+        assert_eq!(stages, ["extracting", "redacting", "chunking", "embedding", "indexing"]);
+
+        let percents: Vec<f64> = events.iter().filter_map(|(_, payload)| payload["percent"].as_f64()).collect();
+        assert!(percents.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_top_processes_respect_the_limit_and_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path()).await;
+
+        let by_memory = top_processes(&state, 3, ProcessSort::Memory, true).await.unwrap();
+        assert!(!by_memory.is_empty() && by_memory.len() <= 3);
+        assert!(by_memory.windows(2).all(|pair| pair[0].memory_usage >= pair[1].memory_usage));
+
+        let by_cpu = top_processes(&state, 5, ProcessSort::Cpu, true).await.unwrap();
+        assert!(by_cpu.len() <= 5);
+        assert!(by_cpu.windows(2).all(|pair| pair[0].cpu_usage >= pair[1].cpu_usage));
+
+        let own_pid = std::process::id();
+        let everything = top_processes(&state, usize::MAX, ProcessSort::Memory, false).await.unwrap();
+        assert!(everything.iter().any(|process| process.pid == own_pid));
+        let others = top_processes(&state, usize::MAX, ProcessSort::Memory, true).await.unwrap();
+        assert!(others.iter().all(|process| process.pid != own_pid));
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_partial_success() {
+        let paths: Vec<String> = ["a.txt", "panics.txt", "b.txt", "fails.txt"].iter().map(|p| p.to_string()).collect();
+        let results = ingest_all(&paths, |index, path| async move {
+            match path.as_str() {
+                "panics.txt" => panic!("extractor crashed"),
+                "fails.txt" => (IngestOutcome::Failed, None, Some("unreadable".to_string())),
+                _ => (IngestOutcome::Indexed, Some(format!("doc-{}", index)), None),
+            }
+        })
+        .await;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), ["a.txt", "panics.txt", "b.txt", "fails.txt"]);
+        assert!(matches!(results[0].outcome, IngestOutcome::Indexed));
+        assert_eq!(results[0].document_id.as_deref(), Some("doc-0"));
+        assert!(matches!(results[1].outcome, IngestOutcome::Failed));
+        assert!(results[1].error.is_some());
+        assert_eq!(results[2].document_id.as_deref(), Some("doc-2"));
+        assert!(matches!(results[3].outcome, IngestOutcome::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_batch_indexes_readable_files_and_reports_missing_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path()).await;
+        let file = dir.path().join("notice.txt");
+        std::fs::write(&file, "Notice of termination under the services agreement.").unwrap();
+        let paths = vec![
+            file.to_string_lossy().to_string(),
+            dir.path().join("missing.txt").to_string_lossy().to_string(),
+        ];
+
+        let results = ingest_batch(state.clone(), Box::new(RecordingSink::default()), &paths, None).await;
+
+        assert!(matches!(results[0].outcome, IngestOutcome::Indexed), "{:?}", results[0].error);
+        assert!(results[0].document_id.is_some());
+        assert_eq!(state.rag_engine.read().await.get_stats().document_count, 1);
+        assert!(matches!(results[1].outcome, IngestOutcome::Failed));
+        assert!(results[1].document_id.is_none());
+        assert!(results[1].error.is_some());
+    }
+}
@@ -3,9 +3,29 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock};
 use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::file_processor::FileProcessor;
+use crate::llm_manager::{GenerationParams, TextGenerator};
+use crate::path_filter::{FilterPatterns, PathFilter};
+use crate::pii_detector::{PIIDetector, PROFILE_LLM};
+use crate::rag_engine::RAGEngine;
+
+// Defaults for the agent-facing filesystem tools; adjustable per server
+const DEFAULT_MAX_READ_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_DIRECTORY_ENTRIES: usize = 1000;
+const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const MAX_SEARCH_LIMIT: usize = 50;
+const TOOL_AUDIT_LOG_FILE: &str = "tool_audit.jsonl";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tool {
@@ -45,14 +65,268 @@ pub struct MCPServer {
     tools: HashMap<String, Tool>,
     sandboxed: bool,
     allowed_paths: Vec<PathBuf>,
+    max_read_bytes: u64,
+    max_directory_entries: usize,
+    io_timeout: Duration,
+    list_filters: FilterPatterns,
+    file_processor: Arc<FileProcessor>,
+    // Shared with the app; tools that need the model fail cleanly when these are not attached
+    generator: Option<Arc<dyn TextGenerator>>,
+    pii_detector: Option<Arc<RwLock<PIIDetector>>>,
+    rag_engine: Option<Arc<RwLock<RAGEngine>>>,
+    python_denylist: PythonDenylist,
+    policy: ToolPolicy,
+    audit_log: Option<Arc<Mutex<ToolAuditLog>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolAuditPhase {
+    Started,
+    Finished,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolAuditRecord {
+    // Unix milliseconds when the record was written
+    pub timestamp: i64,
+    // Shared by a call's started and finished records
+    pub call_id: String,
+    pub tool: String,
+    pub phase: ToolAuditPhase,
+    // Started records only. String values go through PII removal, or are masked entirely when
+    // no detector is attached, since unredacted parameters must not be written.
+    pub parameters: Option<serde_json::Value>,
+    // Finished records only
+    pub success: Option<bool>,
+    pub error: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+// Append-only JSON lines, two records per execute_tool call (denied calls included): one before
+// the tool runs and one after. A started record without a finished one means the call was
+// interrupted or its outcome could not be logged.
+pub struct ToolAuditLog {
+    path: PathBuf,
+}
+
+impl ToolAuditLog {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { path: dir.join(TOOL_AUDIT_LOG_FILE) })
+    }
+
+    async fn append(&mut self, record: &ToolAuditRecord) -> Result<()> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(format!("{}\n", serde_json::to_string(record)?).as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+// Tools that change something outside the server: files on disk, or arbitrary code
+const WRITE_TOOLS: &[&str] = &["write_file", "run_python"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolAccess {
+    ReadOnly,
+    ReadWrite,
+}
+
+// Which tools execute_tool will dispatch. A tool runs when it isn't disabled and, for the write
+// tools, when access is read-write. Tools missing from `enabled` are on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolPolicy {
+    pub access: ToolAccess,
+    #[serde(default)]
+    pub enabled: HashMap<String, bool>,
+}
+
+impl Default for ToolPolicy {
+    fn default() -> Self {
+        Self::read_only()
+    }
+}
+
+impl ToolPolicy {
+    pub fn read_only() -> Self {
+        Self { access: ToolAccess::ReadOnly, enabled: HashMap::new() }
+    }
+
+    pub fn with_tool(mut self, tool: &str, enabled: bool) -> Self {
+        self.enabled.insert(tool.to_string(), enabled);
+        self
+    }
+
+    // Why `tool` may not run, or None if it may
+    pub fn denial(&self, tool: &str) -> Option<String> {
+        if self.enabled.get(tool) == Some(&false) {
+            return Some(format!("Permission denied: {} is disabled", tool));
+        }
+        if self.access == ToolAccess::ReadOnly && WRITE_TOOLS.contains(&tool) {
+            return Some(format!("Permission denied: {} needs read-write access", tool));
+        }
+        None
+    }
+}
+
+// Agent settings the user can change, saved as JSON in the data dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentConfig {
+    #[serde(default)]
+    pub policy: ToolPolicy,
+    // Directories the filesystem tools may use in sandbox mode; empty means none
+    #[serde(default)]
+    pub allowed_paths: Vec<PathBuf>,
+    #[serde(default = "default_max_read_bytes")]
+    pub max_read_bytes: u64,
+    #[serde(default = "default_max_directory_entries")]
+    pub max_directory_entries: usize,
+    #[serde(default = "default_io_timeout_secs")]
+    pub io_timeout_secs: u64,
+    // What run_python refuses in sandbox mode
+    #[serde(default)]
+    pub python_denylist: PythonDenylist,
+    // Default include/exclude patterns for recursive listings
+    #[serde(default)]
+    pub list_filters: FilterPatterns,
+}
+
+fn default_max_read_bytes() -> u64 {
+    DEFAULT_MAX_READ_BYTES
+}
+
+fn default_max_directory_entries() -> usize {
+    DEFAULT_MAX_DIRECTORY_ENTRIES
+}
+
+fn default_io_timeout_secs() -> u64 {
+    DEFAULT_IO_TIMEOUT.as_secs()
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            policy: ToolPolicy::default(),
+            allowed_paths: Vec::new(),
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            max_directory_entries: DEFAULT_MAX_DIRECTORY_ENTRIES,
+            io_timeout_secs: DEFAULT_IO_TIMEOUT.as_secs(),
+            python_denylist: PythonDenylist::default(),
+            list_filters: FilterPatterns::default(),
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::data_dir::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        PathFilter::new(&self.list_filters)?;
+        Ok(())
+    }
+}
+
+// What run_python refuses in sandbox mode. Checked against the tokenized code, so a name only
+// matches as a whole identifier and text inside strings and comments is ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PythonDenylist {
+    // Modules that may not be imported; "urllib" also covers "urllib.request"
+    pub modules: Vec<String>,
+    // Builtins that may not be referenced by bare name (attribute access like `re.compile` is fine)
+    pub builtins: Vec<String>,
+    // Names refused anywhere, including as attributes: the introspection escape hatches
+    pub names: Vec<String>,
+}
+
+impl Default for PythonDenylist {
+    fn default() -> Self {
+        let owned = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Self {
+            modules: owned(&[
+                "os", "sys", "subprocess", "shutil", "socket", "ctypes", "importlib", "builtins",
+                "multiprocessing", "pty", "signal", "pickle", "marshal", "shelve", "urllib", "http",
+                "requests", "ftplib", "smtplib", "telnetlib", "asyncio", "pathlib", "io", "tempfile",
+                "glob", "code", "codeop", "runpy", "inspect", "gc", "resource", "webbrowser", "posix", "nt",
+            ]),
+            builtins: owned(&[
+                "eval", "exec", "compile", "__import__", "open", "input", "breakpoint", "getattr",
+                "setattr", "delattr", "globals", "locals", "vars", "exit", "quit", "help",
+            ]),
+            names: owned(&[
+                "__builtins__", "__import__", "__globals__", "__code__", "__class__", "__base__",
+                "__bases__", "__mro__", "__subclasses__", "__dict__", "__loader__", "__spec__",
+                "__getattribute__", "__reduce__", "__reduce_ex__", "f_globals", "f_locals",
+                "f_builtins", "f_back", "gi_frame", "tb_frame",
+            ]),
+        }
+    }
+}
+
+// Fields analyze_contract asks the model for; each comes back as a list of strings
+const CONTRACT_FIELDS: &[&str] = &["parties", "dates", "obligations", "risks", "key_terms"];
+
+lazy_static! {
+    // Organisations named with a legal-form suffix
+    static ref COMPANY_REGEX: Regex = Regex::new(
+        r"\b(?:[A-Z][\w&'.-]*,?\s+){1,6}(?:Inc\.?|LLC|L\.L\.C\.|Ltd\.?|Limited|Corp\.?|Corporation|Company|LLP|LP|GmbH|B\.V\.|N\.V\.|S\.A\.|PLC|plc)"
+    ).unwrap();
+    // Leading run of capitalised words (with "of", "&" and initials inside)
+    static ref CAPITALIZED_RUN_REGEX: Regex = Regex::new(
+        r"^(?:the\s+)?((?:[A-Z][\w&'.-]*)(?:,?\s+(?:[A-Z][\w&'.-]*|of|and|&))*)"
+    ).unwrap();
+    static ref CONTRACT_DATE_REGEX: Regex = Regex::new(
+        r"(?i)\b(?:(?:January|February|March|April|May|June|July|August|September|October|November|December|Jan|Feb|Mar|Apr|Jun|Jul|Aug|Sep|Sept|Oct|Nov|Dec)\.?\s+\d{1,2}(?:st|nd|rd|th)?,?\s+\d{4}|\d{1,2}(?:st|nd|rd|th)?\s+(?:day\s+of\s+)?(?:January|February|March|April|May|June|July|August|September|October|November|December),?\s+\d{4}|\d{4}-\d{2}-\d{2}|\d{1,2}/\d{1,2}/\d{2,4})\b"
+    ).unwrap();
+    static ref AMOUNT_REGEX: Regex = Regex::new(
+        r"(?i)(?:[$€£¥]\s?\d[\d,]*(?:\.\d+)?(?:\s?(?:million|billion|thousand|[mk]\b))?|\b\d[\d,]*(?:\.\d+)?\s?(?:USD|EUR|GBP|dollars|euros|pounds)\b)"
+    ).unwrap();
+    static ref OBLIGATION_REGEX: Regex = Regex::new(
+        r"(?i)\b(?:shall|must|agrees?\s+to|is\s+required\s+to|are\s+required\s+to|undertakes?\s+to|is\s+responsible\s+for|will\s+be\s+responsible\s+for)\b"
+    ).unwrap();
+    static ref RISK_REGEX: Regex = Regex::new(
+        r"(?i)\b(?:indemnif\w*|hold\s+harmless|liabilit\w*|liable|terminat\w*|penalt\w*|liquidated\s+damages|breach\w*|default|forfeit\w*|sole\s+discretion|without\s+(?:prior\s+)?notice|automatic(?:ally)?\s+renew\w*|non-compet\w*|exclusiv\w*|as\s+is|waive\w*)\b"
+    ).unwrap();
+    // Defined terms: ("Effective Date"), "Services" means ...
+    static ref DEFINED_TERM_REGEX: Regex = Regex::new(r#"["“]([A-Z][A-Za-z\- ]{1,40}?)["”]"#).unwrap();
 }
 
+// Standard clauses worth flagging when present, by heading or phrase
+const KEY_CLAUSES: &[&str] = &[
+    "Governing Law", "Confidentiality", "Non-Compete", "Non-Solicitation", "Termination", "Indemnification",
+    "Limitation of Liability", "Force Majeure", "Arbitration", "Assignment", "Intellectual Property",
+    "Warranties", "Payment Terms", "Renewal", "Severability", "Entire Agreement",
+];
+const SENTENCE_ABBREVIATIONS: &[&str] = &[
+    "inc.", "corp.", "ltd.", "co.", "no.", "mr.", "ms.", "mrs.", "dr.", "st.", "vs.", "e.g.", "i.e.", "u.s.", "art.", "sec.",
+];
+// Longest obligation or risk sentence returned; longer ones are cut with an ellipsis
+const MAX_SENTENCE_CHARS: usize = 300;
+
 impl MCPServer {
-    pub fn new(sandboxed: bool) -> Self {
+    pub fn new(sandboxed: bool, policy: ToolPolicy) -> Self {
         let mut server = Self {
             tools: HashMap::new(),
             sandboxed,
             allowed_paths: vec![],
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            max_directory_entries: DEFAULT_MAX_DIRECTORY_ENTRIES,
+            io_timeout: DEFAULT_IO_TIMEOUT,
+            list_filters: FilterPatterns::default(),
+            file_processor: Arc::new(FileProcessor::new()),
+            generator: None,
+            pii_detector: None,
+            rag_engine: None,
+            python_denylist: PythonDenylist::default(),
+            policy,
+            audit_log: None,
         };
 
         server.register_default_tools();
@@ -109,6 +383,21 @@ impl MCPServer {
                         description: "Path to the directory".to_string(),
                         r#enum: None,
                     }),
+                    ("recursive".to_string(), ParameterProperty {
+                        r#type: "boolean".to_string(),
+                        description: "List subdirectories too (default false)".to_string(),
+                        r#enum: None,
+                    }),
+                    ("include".to_string(), ParameterProperty {
+                        r#type: "array".to_string(),
+                        description: "Glob patterns files must match in recursive mode".to_string(),
+                        r#enum: None,
+                    }),
+                    ("exclude".to_string(), ParameterProperty {
+                        r#type: "array".to_string(),
+                        description: "Glob patterns to skip in recursive mode (replaces the defaults, e.g. .git, node_modules)".to_string(),
+                        r#enum: None,
+                    }),
                 ]),
                 required: vec!["path".to_string()],
             },
@@ -131,6 +420,16 @@ impl MCPServer {
                         description: "Maximum results to return".to_string(),
                         r#enum: None,
                     }),
+                    ("alpha".to_string(), ParameterProperty {
+                        r#type: "number".to_string(),
+                        description: "Weight of meaning versus exact keywords (0-1); lower finds statute and case numbers".to_string(),
+                        r#enum: None,
+                    }),
+                    ("collections".to_string(), ParameterProperty {
+                        r#type: "array".to_string(),
+                        description: "Collections to search instead of the main index".to_string(),
+                        r#enum: None,
+                    }),
                 ]),
                 required: vec!["query".to_string()],
             },
@@ -180,6 +479,11 @@ impl MCPServer {
                             "purchase".to_string(),
                         ]),
                     }),
+                    ("use_model".to_string(), ParameterProperty {
+                        r#type: "boolean".to_string(),
+                        description: "Also ask the loaded language model and merge its findings".to_string(),
+                        r#enum: None,
+                    }),
                 ]),
                 required: vec!["content".to_string()],
             },
@@ -249,11 +553,92 @@ impl MCPServer {
         self.tools.insert(tool.name.clone(), tool);
     }
 
+    // Only the tools the policy lets run, so an agent isn't offered ones it can't use
     pub fn list_tools(&self) -> Vec<Tool> {
-        self.tools.values().cloned().collect()
+        self.tools.values()
+            .filter(|tool| self.policy.denial(&tool.name).is_none())
+            .cloned()
+            .collect()
     }
 
+    // Fail-closed: a call whose start can't be logged is not run. Once the tool has run, a
+    // failure to log its outcome is reported on stderr but doesn't turn its result into an error,
+    // since the side effects have already happened.
     pub async fn execute_tool(&self, call: ToolCall) -> Result<ToolResult> {
+        let Some(audit_log) = &self.audit_log else {
+            return self.dispatch_tool(call).await;
+        };
+
+        let call_id = uuid::Uuid::new_v4().to_string();
+        let tool = call.tool.clone();
+        let started_record = ToolAuditRecord {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            call_id: call_id.clone(),
+            tool: tool.clone(),
+            phase: ToolAuditPhase::Started,
+            parameters: Some(self.redacted_parameters(&call.parameters).await?),
+            success: None,
+            error: None,
+            duration_ms: None,
+        };
+        audit_log
+            .lock()
+            .await
+            .append(&started_record)
+            .await
+            .map_err(|e| anyhow::anyhow!("Tool call not run: failed to write the audit log: {}", e))?;
+
+        let started = Instant::now();
+        let outcome = self.dispatch_tool(call).await;
+        let (success, error) = match &outcome {
+            Ok(result) => (result.success, result.error.clone()),
+            Err(e) => (false, Some(e.to_string())),
+        };
+        let finished_record = ToolAuditRecord {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            call_id,
+            tool,
+            phase: ToolAuditPhase::Finished,
+            parameters: None,
+            success: Some(success),
+            error,
+            duration_ms: Some(started.elapsed().as_millis() as u64),
+        };
+        if let Err(e) = audit_log.lock().await.append(&finished_record).await {
+            eprintln!("Failed to log the outcome of tool call {}: {}", finished_record.call_id, e);
+        }
+
+        outcome
+    }
+
+    async fn redacted_parameters(&self, parameters: &serde_json::Value) -> Result<serde_json::Value> {
+        let mut redacted = parameters.clone();
+        match &self.pii_detector {
+            Some(detector) => {
+                let detector = detector.read().await;
+                for text in string_leaves(&mut redacted) {
+                    *text = detector.remove_pii(text).await?;
+                }
+            }
+            // The keys and non-text values still show what the call was
+            None => {
+                for text in string_leaves(&mut redacted) {
+                    *text = "[REDACTED]".to_string();
+                }
+            }
+        }
+        Ok(redacted)
+    }
+
+    async fn dispatch_tool(&self, call: ToolCall) -> Result<ToolResult> {
+        if let Some(denial) = self.policy.denial(&call.tool) {
+            return Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some(denial),
+            });
+        }
+
         match call.tool.as_str() {
             "read_file" => self.handle_read_file(call.parameters).await,
             "write_file" => self.handle_write_file(call.parameters).await,
@@ -277,25 +662,38 @@ impl MCPServer {
             .ok_or_else(|| anyhow::anyhow!("Missing path parameter"))?;
 
         // Security check for sandboxed mode
-        if self.sandboxed && !self.is_path_allowed(path) {
+        let Some(resolved) = self.resolve_path(path, false) else {
             return Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
                 error: Some("Access denied: Path not in allowed directories".to_string()),
             });
-        }
+        };
 
-        match fs::read_to_string(path).await {
-            Ok(content) => Ok(ToolResult {
+        let read = async {
+            let file = self.open_resolved(&resolved, fs::OpenOptions::new().read(true)).await?;
+            read_capped(file, self.max_read_bytes).await
+        };
+        match tokio::time::timeout(self.io_timeout, read).await {
+            Ok(Ok((content, truncated))) => Ok(ToolResult {
                 success: true,
-                result: serde_json::json!({ "content": content }),
+                result: serde_json::json!({
+                    "content": content,
+                    "truncated": truncated,
+                    "max_bytes": self.max_read_bytes,
+                }),
                 error: None,
             }),
-            Err(e) => Ok(ToolResult {
+            Ok(Err(e)) => Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
                 error: Some(e.to_string()),
             }),
+            Err(_) => Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some(format!("Timed out reading {} after {:?}", path, self.io_timeout)),
+            }),
         }
     }
 
@@ -306,15 +704,22 @@ impl MCPServer {
             .ok_or_else(|| anyhow::anyhow!("Missing content parameter"))?;
 
         // Security check for sandboxed mode
-        if self.sandboxed && !self.is_path_allowed(path) {
+        let Some(resolved) = self.resolve_path(path, true) else {
             return Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
                 error: Some("Access denied: Path not in allowed directories".to_string()),
             });
-        }
+        };
 
-        match fs::write(path, content).await {
+        let write = async {
+            let mut file = self.open_resolved(&resolved, fs::OpenOptions::new().write(true).create(true)).await?;
+            file.set_len(0).await?;
+            file.write_all(content.as_bytes()).await?;
+            file.flush().await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        match write.await {
             Ok(_) => Ok(ToolResult {
                 success: true,
                 result: serde_json::json!({ "path": path }),
@@ -332,93 +737,242 @@ impl MCPServer {
         let path = params["path"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing path parameter"))?;
 
-        if self.sandboxed && !self.is_path_allowed(path) {
+        let Some(resolved) = self.resolve_path(path, false) else {
             return Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
                 error: Some("Access denied: Path not in allowed directories".to_string()),
             });
-        }
+        };
 
-        match fs::read_dir(path).await {
-            Ok(mut entries) => {
-                let mut files = Vec::new();
-                while let Ok(Some(entry)) = entries.next_entry().await {
-                    if let Ok(metadata) = entry.metadata().await {
-                        files.push(serde_json::json!({
-                            "name": entry.file_name().to_string_lossy(),
-                            "is_dir": metadata.is_dir(),
-                            "size": metadata.len(),
-                        }));
-                    }
-                }
-                Ok(ToolResult {
-                    success: true,
-                    result: serde_json::json!({ "files": files }),
-                    error: None,
+        let recursive = params["recursive"].as_bool().unwrap_or(false);
+        let listing = if recursive {
+            let patterns = |key: &str| -> Option<Vec<String>> {
+                params[key].as_array().map(|values| {
+                    values.iter().filter_map(|v| v.as_str().map(str::to_string)).collect()
                 })
-            },
-            Err(e) => Ok(ToolResult {
+            };
+            let filter = PathFilter::new(&self.list_filters.with_overrides(patterns("include"), patterns("exclude")))?;
+            tokio::time::timeout(self.io_timeout, list_recursive(&resolved, filter, self.max_directory_entries)).await
+        } else {
+            tokio::time::timeout(self.io_timeout, list_capped(&resolved, self.max_directory_entries)).await
+        };
+
+        match listing {
+            Ok(Ok(listing)) => Ok(ToolResult {
+                success: true,
+                result: serde_json::json!({
+                    "files": listing.files,
+                    "truncated": listing.truncated,
+                    "max_entries": self.max_directory_entries,
+                    "unreadable": listing.unreadable,
+                }),
+                error: None,
+            }),
+            Ok(Err(e)) => Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
                 error: Some(e.to_string()),
             }),
+            Err(_) => Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some(format!("Timed out listing {} after {:?}", path, self.io_timeout)),
+            }),
         }
     }
 
     async fn handle_search_documents(&self, params: serde_json::Value) -> Result<ToolResult> {
         let query = params["query"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing query parameter"))?;
-        let limit = params["limit"].as_u64().unwrap_or(10);
+        let limit = params["limit"]
+            .as_u64()
+            .map_or(DEFAULT_SEARCH_LIMIT, |limit| (limit as usize).clamp(1, MAX_SEARCH_LIMIT));
+
+        let Some(rag) = &self.rag_engine else {
+            return Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some("Document search needs the knowledge base, which is not available to this agent".to_string()),
+            });
+        };
+
+        // Several collections are searched independently, so one that can't be searched is
+        // reported next to the others' results instead of failing the call
+        let collections: Vec<Option<String>> = params["collections"]
+            .as_array()
+            .map(|names| names.iter().filter_map(|name| name.as_str()).map(|name| Some(name.to_string())).collect())
+            .unwrap_or_default();
+        let alpha = params["alpha"].as_f64().map(|alpha| alpha.clamp(0.0, 1.0) as f32);
+        let (hits, errors) = if collections.is_empty() {
+            let rag = rag.read().await;
+            let hits = match alpha {
+                Some(alpha) => rag.search_hybrid(query, limit, alpha).await?,
+                None => rag.search(query, limit).await?,
+            };
+            (hits, Vec::new())
+        } else {
+            let found = rag.read().await.multi_search(query, &collections, limit).await?;
+            (found.results, found.errors)
+        };
+        let results: Vec<serde_json::Value> = hits
+            .iter()
+            .map(|hit| serde_json::json!({
+                "id": hit["id"],
+                "source": hit["metadata"]["source"],
+                "content": hit["content"],
+                "relevance": hit["score"],
+                "location": hit["location"],
+            }))
+            .collect();
 
-        // This would integrate with the RAG engine
-        // For now, return a mock response
         Ok(ToolResult {
             success: true,
             result: serde_json::json!({
-                "results": [
-                    {
-                        "title": "Sample Document",
-                        "snippet": format!("...content matching '{}'...", query),
-                        "relevance": 0.95,
-                    }
-                ],
-                "total": 1,
+                "total": results.len(),
+                "results": results,
+                "errors": errors,
             }),
             error: None,
         })
     }
 
-    async fn handle_extract_text(&self, _params: serde_json::Value) -> Result<ToolResult> {
-        // This would use the file_processor module
+    // Extracted text is capped at max_read_bytes like read_file; the format comes from the file
+    // extension, and a `format` that disagrees with it is refused rather than guessed at
+    async fn handle_extract_text(&self, params: serde_json::Value) -> Result<ToolResult> {
+        let path = params["path"].as_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing path parameter"))?;
+
+        let Some(resolved) = self.resolve_path(path, false) else {
+            return Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some("Access denied: Path not in allowed directories".to_string()),
+            });
+        };
+
+        let extension = resolved
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        if let Some(format) = params["format"].as_str() {
+            if !format.eq_ignore_ascii_case(&extension) {
+                return Ok(ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some(format!("File is .{}, not {}", extension, format)),
+                });
+            }
+        }
+
+        let resolved = resolved.to_string_lossy().to_string();
+        let extraction = self.file_processor.process_file(&resolved, &extension);
+        let mut text = match tokio::time::timeout(self.io_timeout, extraction).await {
+            Ok(Ok(text)) => text,
+            Ok(Err(e)) => return Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some(e.to_string()),
+            }),
+            Err(_) => return Ok(ToolResult {
+                success: false,
+                result: serde_json::Value::Null,
+                error: Some(format!("Timed out extracting {} after {:?}", path, self.io_timeout)),
+            }),
+        };
+
+        let total_chars = text.chars().count();
+        let truncated = text.len() as u64 > self.max_read_bytes;
+        if truncated {
+            let mut cut = self.max_read_bytes as usize;
+            while !text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            text.truncate(cut);
+        }
+
         Ok(ToolResult {
             success: true,
-            result: serde_json::json!({ "text": "Extracted text content" }),
+            result: serde_json::json!({
+                "text": text,
+                "format": extension,
+                "truncated": truncated,
+                "total_chars": total_chars,
+                "note": truncated.then(|| format!(
+                    "Text truncated to the first {} bytes of {} characters", self.max_read_bytes, total_chars
+                )),
+            }),
             error: None,
         })
     }
 
+    // Rule-based extraction over the text; no model needed and the same input always gives the
+    // same answer. With `use_model` the loaded model's findings are merged in. The contract is
+    // redacted with the LLM profile before it reaches the model, so model-found parties come
+    // back as placeholders when they are personal names.
     async fn handle_analyze_contract(&self, params: serde_json::Value) -> Result<ToolResult> {
         let content = params["content"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing content parameter"))?;
+        let contract_type = params["type"].as_str().unwrap_or("general");
+        let use_model = params["use_model"].as_bool().unwrap_or(false);
+
+        let mut analysis = extract_contract_terms(content);
+        let mut result = serde_json::json!({ "method": "rules" });
+
+        if use_model {
+            let (generator, detector) = match (&self.generator, &self.pii_detector) {
+                (Some(generator), Some(detector)) => (generator, detector),
+                _ => return Ok(ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some("Contract analysis needs a language model, which is not available to this agent".to_string()),
+                }),
+            };
+
+            let redacted = detector.read().await.remove_pii_with_profile(content, PROFILE_LLM).await?;
+            let prompt = contract_extraction_prompt(&redacted, contract_type);
+
+            let model = match generator.active_model().await {
+                Some(model) => model,
+                None => return Ok(ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some("No model loaded; load a model before analyzing contracts".to_string()),
+                }),
+            };
+
+            // Greedy decoding: extraction should not vary between runs
+            let params = GenerationParams { temperature: Some(0.0), ..GenerationParams::default() };
+            let output = generator.generate(&prompt, &params).await?;
+
+            let (model_analysis, parsed) = parse_contract_analysis(&output.text);
+            for field in CONTRACT_FIELDS {
+                merge_unique(&mut analysis, field, &model_analysis[*field]);
+            }
+            result = serde_json::json!({
+                "method": "rules+model",
+                "model": model,
+                // False when the model's answer held no usable JSON and added nothing
+                "parsed": parsed,
+                // JSON cut off at max_tokens usually fails to parse; this tells the two apart
+                "truncated": output.truncated,
+            });
+        }
 
-        // This would perform actual contract analysis
-        // For now, return a structured analysis
+        for (key, value) in analysis {
+            result[key] = value;
+        }
+        result["type"] = serde_json::Value::String(contract_type.to_string());
         Ok(ToolResult {
             success: true,
-            result: serde_json::json!({
-                "key_terms": ["Term 1", "Term 2"],
-                "risks": ["Risk 1", "Risk 2"],
-                "obligations": ["Obligation 1"],
-                "dates": ["2024-01-01"],
-                "parties": ["Party A", "Party B"],
-            }),
+            result,
             error: None,
         })
     }
 
     async fn handle_find_precedents(&self, params: serde_json::Value) -> Result<ToolResult> {
-        let case_description = params["case_description"].as_str()
+        params["case_description"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing case_description parameter"))?;
 
         // This would search a legal database
@@ -442,12 +996,13 @@ impl MCPServer {
         let query = params["query"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing query parameter"))?;
 
-        // Security: Only allow SELECT in sandbox mode
-        if self.sandboxed && !query.trim().to_uppercase().starts_with("SELECT") {
+        // Security: Only allow SELECT in sandbox mode or under a read-only policy
+        let read_only = self.sandboxed || self.policy.access == ToolAccess::ReadOnly;
+        if read_only && !query.trim().to_uppercase().starts_with("SELECT") {
             return Ok(ToolResult {
                 success: false,
                 result: serde_json::Value::Null,
-                error: Some("Only SELECT queries allowed in sandbox mode".to_string()),
+                error: Some("Only SELECT queries allowed in sandbox mode or with read-only tools".to_string()),
             });
         }
 
@@ -466,20 +1021,42 @@ impl MCPServer {
         let code = params["code"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing code parameter"))?;
 
+        // Fast path: plain arithmetic never reaches the interpreter or the dangerous-code checks
+        if let Some(evaluated) = evaluate_arithmetic(code) {
+            return Ok(match evaluated {
+                Ok(value) => ToolResult {
+                    success: true,
+                    result: serde_json::json!({
+                        "output": value.to_string(),
+                        "return_value": value.to_json(),
+                        "evaluator": "arithmetic",
+                    }),
+                    error: None,
+                },
+                Err(e) => ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some(e),
+                },
+            });
+        }
+
         // Security: This should run in a sandboxed Python environment
         // Using something like RustPython or PyO3 with restrictions
 
         if self.sandboxed {
-            // Check for dangerous imports/functions
-            let dangerous = ["os", "subprocess", "eval", "exec", "__import__"];
-            for d in dangerous {
-                if code.contains(d) {
-                    return Ok(ToolResult {
-                        success: false,
-                        result: serde_json::Value::Null,
-                        error: Some(format!("Forbidden operation: {}", d)),
-                    });
-                }
+            let requested = params["imports"].as_array().into_iter().flatten().filter_map(|i| i.as_str());
+            let violation = requested
+                .filter(|module| self.python_denylist.blocks_module(module))
+                .map(|module| format!("import {}", module))
+                .next()
+                .or_else(|| python_violation(code, &self.python_denylist));
+            if let Some(violation) = violation {
+                return Ok(ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some(format!("Forbidden operation: {}", violation)),
+                });
             }
         }
 
@@ -494,54 +1071,1595 @@ impl MCPServer {
         })
     }
 
-    fn is_path_allowed(&self, path: &str) -> bool {
-        let path = PathBuf::from(path);
+    // The path tools should operate on, or None if the sandbox refuses it. In sandbox mode both
+    // sides are canonicalized first, so `..` components and symlinks can't lead outside an
+    // allowed directory, and the tools then use the resolved path rather than the raw input.
+    // A file that doesn't exist yet is resolved through its parent directory when `may_create`.
+    fn resolve_path(&self, path: &str, may_create: bool) -> Option<PathBuf> {
+        if !self.sandboxed {
+            return Some(PathBuf::from(path));
+        }
 
-        // Check if path is within allowed directories
-        for allowed in &self.allowed_paths {
-            if path.starts_with(allowed) {
-                return true;
+        let requested = Path::new(path);
+        let resolved = match std::fs::canonicalize(requested) {
+            Ok(resolved) => resolved,
+            Err(_) if may_create => {
+                let name = requested.file_name()?;
+                let parent = requested.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                std::fs::canonicalize(parent).ok()?.join(name)
             }
-        }
+            Err(_) => return None,
+        };
 
-        false
+        self.is_allowed(&resolved).then_some(resolved)
+    }
+
+    fn is_allowed(&self, canonical: &Path) -> bool {
+        self.allowed_paths
+            .iter()
+            .filter_map(|allowed| std::fs::canonicalize(allowed).ok())
+            .any(|allowed| canonical.starts_with(&allowed))
+    }
+
+    // Opens a path from resolve_path and checks the handle is still that file inside the sandbox,
+    // so a component swapped for a symlink between the check and the open can't redirect the
+    // tool. Nothing is truncated here; writers truncate through the handle once it is verified.
+    async fn open_resolved(&self, resolved: &Path, options: &fs::OpenOptions) -> Result<fs::File> {
+        let file = options.open(resolved).await?;
+        if self.sandboxed {
+            let unchanged = std::fs::canonicalize(resolved).is_ok_and(|now| now == resolved && self.is_allowed(&now))
+                && same_file(&file.metadata().await?, &fs::symlink_metadata(resolved).await?);
+            if !unchanged {
+                return Err(anyhow::anyhow!("Access denied: {} changed while it was being opened", resolved.display()));
+            }
+        }
+        Ok(file)
     }
 
     pub fn add_allowed_path(&mut self, path: PathBuf) {
         self.allowed_paths.push(path);
     }
+
+    // Replaces everything AgentConfig covers; an invalid config changes nothing
+    pub fn apply_config(&mut self, config: &AgentConfig) -> Result<()> {
+        config.validate()?;
+        self.set_list_filters(config.list_filters.clone())?;
+        self.set_policy(config.policy.clone());
+        self.allowed_paths.clear();
+        for path in &config.allowed_paths {
+            self.add_allowed_path(path.clone());
+        }
+        self.set_max_read_bytes(config.max_read_bytes);
+        self.set_max_directory_entries(config.max_directory_entries);
+        self.set_io_timeout(Duration::from_secs(config.io_timeout_secs));
+        self.set_python_denylist(config.python_denylist.clone());
+        Ok(())
+    }
+
+    // The settings in effect, in the shape apply_config takes
+    pub fn config(&self) -> AgentConfig {
+        AgentConfig {
+            policy: self.policy().clone(),
+            allowed_paths: self.allowed_paths.clone(),
+            max_read_bytes: self.max_read_bytes(),
+            max_directory_entries: self.max_directory_entries(),
+            io_timeout_secs: self.io_timeout.as_secs(),
+            python_denylist: self.python_denylist().clone(),
+            list_filters: self.list_filters.clone(),
+        }
+    }
+
+    pub fn set_max_read_bytes(&mut self, bytes: u64) {
+        self.max_read_bytes = bytes;
+    }
+
+    pub fn max_read_bytes(&self) -> u64 {
+        self.max_read_bytes
+    }
+
+    pub fn set_max_directory_entries(&mut self, entries: usize) {
+        self.max_directory_entries = entries;
+    }
+
+    pub fn max_directory_entries(&self) -> usize {
+        self.max_directory_entries
+    }
+
+    pub fn set_io_timeout(&mut self, timeout: Duration) {
+        self.io_timeout = timeout;
+    }
+
+    pub fn set_audit_log(&mut self, audit_log: ToolAuditLog) {
+        self.audit_log = Some(Arc::new(Mutex::new(audit_log)));
+    }
+
+    pub fn set_policy(&mut self, policy: ToolPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn policy(&self) -> &ToolPolicy {
+        &self.policy
+    }
+
+    pub fn set_python_denylist(&mut self, denylist: PythonDenylist) {
+        self.python_denylist = denylist;
+    }
+
+    pub fn python_denylist(&self) -> &PythonDenylist {
+        &self.python_denylist
+    }
+
+    pub fn attach_llm(&mut self, generator: Arc<dyn TextGenerator>, pii_detector: Arc<RwLock<PIIDetector>>) {
+        self.generator = Some(generator);
+        self.pii_detector = Some(pii_detector);
+    }
+
+    pub fn attach_rag(&mut self, rag_engine: Arc<RwLock<RAGEngine>>) {
+        self.rag_engine = Some(rag_engine);
+    }
+
+    // Shares the app's processor, so read_file extracts with the user's settings
+    pub fn attach_file_processor(&mut self, file_processor: Arc<FileProcessor>) {
+        self.file_processor = file_processor;
+    }
+
+    // Default filters for recursive listings; callers can still override per call
+    pub fn set_list_filters(&mut self, filters: FilterPatterns) -> Result<()> {
+        PathFilter::new(&filters)?;
+        self.list_filters = filters;
+        Ok(())
+    }
 }
 
-// Agent orchestrator that uses MCP tools
-pub struct AgentOrchestrator {
-    mcp_server: MCPServer,
+#[cfg(unix)]
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
 }
 
-impl AgentOrchestrator {
-    pub fn new(sandboxed: bool) -> Self {
-        Self {
-            mcp_server: MCPServer::new(sandboxed),
-        }
+// No stable file identity here; the path re-check still applies
+#[cfg(not(unix))]
+fn same_file(_a: &std::fs::Metadata, _b: &std::fs::Metadata) -> bool {
+    true
+}
+
+fn string_leaves(value: &mut serde_json::Value) -> Vec<&mut String> {
+    match value {
+        serde_json::Value::String(text) => vec![text],
+        serde_json::Value::Array(items) => items.iter_mut().flat_map(string_leaves).collect(),
+        serde_json::Value::Object(fields) => fields.values_mut().flat_map(string_leaves).collect(),
+        _ => Vec::new(),
     }
+}
 
-    pub async fn execute_agent_task(&self, task: &str, context: &str) -> Result<String> {
-        // This would:
-        // 1. Send task to LLM with available tools
-        // 2. Parse LLM's tool calls
-        // 3. Execute tools via MCP server
-        // 4. Return results to LLM
-        // 5. Repeat until task complete
+// Reads at most `max_bytes` (never the whole file) and reports whether more was left
+async fn read_capped(file: fs::File, max_bytes: u64) -> Result<(String, bool)> {
+    let mut buf = Vec::new();
+    file.take(max_bytes + 1).read_to_end(&mut buf).await?;
 
-        let tools = self.mcp_server.list_tools();
-        let tools_json = serde_json::to_string(&tools)?;
+    let truncated = buf.len() as u64 > max_bytes;
+    buf.truncate(max_bytes as usize);
 
-        // Format prompt with tools
-        let prompt = format!(
-            "Task: {}\nContext: {}\nAvailable tools: {}\nExecute the task using the available tools.",
-            task, context, tools_json
-        );
+    let content = match String::from_utf8(buf) {
+        Ok(content) => content,
+        // The cutoff may land inside a multi-byte character; drop the partial tail
+        Err(e) if truncated && e.utf8_error().error_len().is_none() => {
+            let valid = e.utf8_error().valid_up_to();
+            let mut bytes = e.into_bytes();
+            bytes.truncate(valid);
+            String::from_utf8(bytes)?
+        }
+        Err(e) => return Err(anyhow::anyhow!("File is not valid UTF-8: {}", e)),
+    };
+
+    Ok((content, truncated))
+}
 
-        // This would interact with the LLM
-        Ok("Task completed".to_string())
+struct Listing {
+    files: Vec<serde_json::Value>,
+    truncated: bool,
+    // Subdirectories (relative to the listed path) that could not be read and were skipped
+    unreadable: Vec<String>,
+}
+
+async fn list_capped(path: &Path, max_entries: usize) -> Result<Listing> {
+    let mut entries = fs::read_dir(path).await?;
+    let mut files = Vec::new();
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if files.len() >= max_entries {
+            return Ok(Listing { files, truncated: true, unreadable: Vec::new() });
+        }
+        if let Ok(metadata) = entry.metadata().await {
+            files.push(serde_json::json!({
+                "name": entry.file_name().to_string_lossy(),
+                "is_dir": metadata.is_dir(),
+                "size": metadata.len(),
+            }));
+        }
     }
-}
\ No newline at end of file
+
+    Ok(Listing { files, truncated: false, unreadable: Vec::new() })
+}
+
+fn contract_extraction_prompt(contract: &str, contract_type: &str) -> String {
+    format!(
+        "Extract information from the following {} contract. Respond with a single JSON object and nothing else, \
+         using exactly these keys, each an array of short strings: \"parties\", \"dates\", \"obligations\", \
+         \"risks\", \"key_terms\". Only include items stated in the contract; use an empty array when there are none. \
+         Keep placeholders such as [NAME_REDACTED_1] as they appear.\n\nContract:\n{}\n\nJSON:",
+        contract_type, contract
+    )
+}
+
+// Appends the strings in `items` that `analysis[field]` doesn't already hold
+fn merge_unique(analysis: &mut serde_json::Map<String, serde_json::Value>, field: &str, items: &serde_json::Value) {
+    let Some(serde_json::Value::Array(existing)) = analysis.get_mut(field) else {
+        return;
+    };
+    for item in items.as_array().into_iter().flatten() {
+        let duplicate = existing.iter().any(|e| {
+            e.as_str().zip(item.as_str()).is_some_and(|(a, b)| a.eq_ignore_ascii_case(b))
+        });
+        if !duplicate {
+            existing.push(item.clone());
+        }
+    }
+}
+
+// Sentences, split after . ; ! ? or a blank line, with whitespace collapsed
+fn contract_sentences(content: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let paragraph_break = c == '\n' && chars.peek() == Some(&'\n');
+        if !paragraph_break {
+            current.push(if c.is_whitespace() { ' ' } else { c });
+        }
+        let ends = matches!(c, '.' | ';' | '!' | '?')
+            && chars.peek().is_none_or(|next| next.is_whitespace())
+            && !(c == '.' && ends_with_abbreviation(&current));
+        if ends || paragraph_break {
+            let sentence = current.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            current.clear();
+        }
+    }
+    let sentence = current.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !sentence.is_empty() {
+        sentences.push(sentence);
+    }
+    sentences
+}
+
+// "Acme Corp. and ..." or "John A. Smith" must not end a sentence
+fn ends_with_abbreviation(text: &str) -> bool {
+    let word = text.rsplit(' ').next().unwrap_or("");
+    let is_initial = word.len() == 2 && word.starts_with(|c: char| c.is_ascii_uppercase());
+    is_initial || SENTENCE_ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+fn clip_sentence(sentence: &str) -> String {
+    if sentence.chars().count() <= MAX_SENTENCE_CHARS {
+        return sentence.to_string();
+    }
+    let clipped: String = sentence.chars().take(MAX_SENTENCE_CHARS).collect();
+    format!("{}…", clipped.trim_end())
+}
+
+// A trailing period is ignored when comparing, so "Beta LLC." and "Beta LLC" are one entry
+fn push_unique(items: &mut Vec<String>, item: &str) {
+    let item = item.trim().trim_end_matches(',').trim();
+    let key = item.trim_end_matches('.');
+    if !item.is_empty() && !items.iter().any(|existing| existing.trim_end_matches('.').eq_ignore_ascii_case(key)) {
+        items.push(item.to_string());
+    }
+}
+
+// Parties named in the "between X and Y" preamble, then any organisation with a legal-form
+// suffix elsewhere in the text
+fn extract_parties(content: &str, sentences: &[String]) -> Vec<String> {
+    let mut parties = Vec::new();
+
+    if let Some(preamble) = sentences.iter().find(|s| s.contains(" between ")) {
+        let after = &preamble[preamble.find(" between ").unwrap_or(0) + " between ".len()..];
+        // "X, a Delaware corporation ("Seller"), and Y" -> one part per party
+        for part in after.split(" and ") {
+            let part = part.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if let Some(name) = CAPITALIZED_RUN_REGEX.captures(part).and_then(|caps| caps.get(1)) {
+                let name = name.as_str().trim_end_matches([',', '&']);
+                let name = name.strip_suffix(" and").unwrap_or(name);
+                push_unique(&mut parties, name);
+            }
+        }
+    }
+
+    for company in COMPANY_REGEX.find_iter(content) {
+        push_unique(&mut parties, company.as_str());
+    }
+    // A bare suffix or a leading "This" / "The" is not a party
+    parties.retain(|p| p.contains(' ') || !matches!(p.as_str(), "This" | "The" | "Agreement"));
+    parties
+}
+
+// Deterministic contract extraction; the keys match what the model is asked for, plus amounts
+fn extract_contract_terms(content: &str) -> serde_json::Map<String, serde_json::Value> {
+    let sentences = contract_sentences(content);
+
+    let mut dates = Vec::new();
+    for date in CONTRACT_DATE_REGEX.find_iter(content) {
+        push_unique(&mut dates, date.as_str());
+    }
+
+    let mut amounts = Vec::new();
+    for amount in AMOUNT_REGEX.find_iter(content) {
+        push_unique(&mut amounts, amount.as_str());
+    }
+
+    let mut obligations = Vec::new();
+    let mut risks = Vec::new();
+    for sentence in &sentences {
+        if OBLIGATION_REGEX.is_match(sentence) {
+            push_unique(&mut obligations, &clip_sentence(sentence));
+        }
+        if RISK_REGEX.is_match(sentence) {
+            push_unique(&mut risks, &clip_sentence(sentence));
+        }
+    }
+
+    let mut key_terms = Vec::new();
+    let lower = content.to_lowercase();
+    for clause in KEY_CLAUSES {
+        if lower.contains(&clause.to_lowercase()) {
+            push_unique(&mut key_terms, clause);
+        }
+    }
+    for term in DEFINED_TERM_REGEX.captures_iter(content) {
+        push_unique(&mut key_terms, &term[1]);
+    }
+
+    let to_json = |items: Vec<String>| serde_json::Value::Array(items.into_iter().map(serde_json::Value::String).collect());
+    let mut analysis = serde_json::Map::new();
+    analysis.insert("parties".to_string(), to_json(extract_parties(content, &sentences)));
+    analysis.insert("dates".to_string(), to_json(dates));
+    analysis.insert("amounts".to_string(), to_json(amounts));
+    analysis.insert("obligations".to_string(), to_json(obligations));
+    analysis.insert("risks".to_string(), to_json(risks));
+    analysis.insert("key_terms".to_string(), to_json(key_terms));
+    analysis
+}
+
+// Keeps whatever the model got right: the first {...} block in the output is parsed, each known
+// field is kept if it is an array (non-string items dropped) or a lone string, and anything
+// missing or malformed becomes an empty list. Returns the fields and whether any JSON was found.
+fn parse_contract_analysis(output: &str) -> (serde_json::Map<String, serde_json::Value>, bool) {
+    let parsed = output.find('{')
+        .zip(output.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .and_then(|(start, end)| serde_json::from_str::<serde_json::Value>(&output[start..=end]).ok())
+        .and_then(|value| value.as_object().cloned());
+
+    let mut analysis = serde_json::Map::new();
+    for field in CONTRACT_FIELDS {
+        let items: Vec<serde_json::Value> = match parsed.as_ref().and_then(|p| p.get(*field)) {
+            Some(serde_json::Value::Array(items)) => items.iter()
+                .filter_map(|item| item.as_str())
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| serde_json::Value::String(item.to_string()))
+                .collect(),
+            Some(serde_json::Value::String(item)) if !item.trim().is_empty() => {
+                vec![serde_json::Value::String(item.trim().to_string())]
+            }
+            _ => Vec::new(),
+        };
+        analysis.insert(field.to_string(), serde_json::Value::Array(items));
+    }
+
+    (analysis, parsed.is_some())
+}
+
+// Walks below `root`, never descending into excluded directories; entries carry paths relative to root.
+// Only an unreadable root is an error; an unreadable subdirectory is listed and noted as such.
+async fn list_recursive(root: &Path, filter: PathFilter, max_entries: usize) -> Result<Listing> {
+    let mut files = Vec::new();
+    let mut unreadable = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+
+    while let Some(dir) = stack.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(_) if dir != root => {
+                unreadable.push(dir.strip_prefix(root).unwrap_or(&dir).to_string_lossy().into_owned());
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            let file_type = match entry.file_type().await {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+
+            let listed = if file_type.is_dir() {
+                filter.allows_dir(&relative)
+            } else {
+                filter.allows_file(&relative)
+            };
+            if !listed {
+                continue;
+            }
+            if files.len() >= max_entries {
+                return Ok(Listing { files, truncated: true, unreadable });
+            }
+
+            let size = entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+            files.push(serde_json::json!({
+                "name": entry.file_name().to_string_lossy(),
+                "path": relative.to_string_lossy(),
+                "is_dir": file_type.is_dir(),
+                "size": size,
+            }));
+
+            // Symlinked directories are listed but not followed
+            if file_type.is_dir() {
+                stack.push(path);
+            }
+        }
+    }
+
+    Ok(Listing { files, truncated: false, unreadable })
+}
+
+// Agent orchestrator that uses MCP tools
+pub struct AgentOrchestrator {
+    mcp_server: MCPServer,
+}
+
+impl AgentOrchestrator {
+    pub fn new(sandboxed: bool, policy: ToolPolicy) -> Self {
+        Self {
+            mcp_server: MCPServer::new(sandboxed, policy),
+        }
+    }
+
+    pub fn attach_llm(&mut self, generator: Arc<dyn TextGenerator>, pii_detector: Arc<RwLock<PIIDetector>>) {
+        self.mcp_server.attach_llm(generator, pii_detector);
+    }
+
+    pub fn attach_rag(&mut self, rag_engine: Arc<RwLock<RAGEngine>>) {
+        self.mcp_server.attach_rag(rag_engine);
+    }
+
+    pub fn attach_file_processor(&mut self, file_processor: Arc<FileProcessor>) {
+        self.mcp_server.attach_file_processor(file_processor);
+    }
+
+    pub fn set_audit_log(&mut self, audit_log: ToolAuditLog) {
+        self.mcp_server.set_audit_log(audit_log);
+    }
+
+    pub fn apply_config(&mut self, config: &AgentConfig) -> Result<()> {
+        self.mcp_server.apply_config(config)
+    }
+
+    pub fn config(&self) -> AgentConfig {
+        self.mcp_server.config()
+    }
+
+    pub fn list_tools(&self) -> Vec<Tool> {
+        self.mcp_server.list_tools()
+    }
+
+    pub async fn execute_tool(&self, call: ToolCall) -> Result<ToolResult> {
+        self.mcp_server.execute_tool(call).await
+    }
+
+    // ReAct-style loop: the model answers each turn with either a tool call or a final answer as
+    // JSON; tool results go back to it as observations. Everything sent to the model is
+    // redacted with the LLM profile first. Stops after MAX_AGENT_STEPS tool calls.
+    pub async fn execute_agent_task(&self, task: &str, context: &str) -> Result<AgentRun> {
+        let (generator, detector) = match (&self.mcp_server.generator, &self.mcp_server.pii_detector) {
+            (Some(generator), Some(detector)) => (generator, detector),
+            _ => return Err(anyhow::anyhow!("The agent needs a language model, which is not attached")),
+        };
+
+        let tools = self.mcp_server.list_tools();
+        let tools_json = serde_json::to_string(&tools)?;
+        let redact = |text: String| async move {
+            detector.read().await.remove_pii_with_profile(&text, PROFILE_LLM).await
+        };
+
+        let mut transcript = agent_prompt(
+            &redact(task.to_string()).await?,
+            &redact(context.to_string()).await?,
+            &tools_json,
+        );
+        let mut steps = Vec::new();
+
+        for _ in 0..=MAX_AGENT_STEPS {
+            // Greedy decoding: tool-call JSON should not vary between runs
+            let params = GenerationParams { temperature: Some(0.0), ..GenerationParams::default() };
+            let reply = generator.generate(&transcript, &params).await?.text;
+
+            let call = match parse_agent_reply(&reply) {
+                AgentReply::FinalAnswer(answer) => {
+                    return Ok(AgentRun { answer, steps, finished: true });
+                }
+                AgentReply::ToolCall(call) => call,
+            };
+
+            if steps.len() == MAX_AGENT_STEPS {
+                break;
+            }
+
+            let tool = call.tool.clone();
+            let parameters = call.parameters.clone();
+            let result = match self.mcp_server.execute_tool(call).await {
+                Ok(result) => result,
+                // Missing parameters and the like are the model's mistake; let it see and retry
+                Err(e) => ToolResult {
+                    success: false,
+                    result: serde_json::Value::Null,
+                    error: Some(e.to_string()),
+                },
+            };
+
+            let mut observation = serde_json::to_string(&result)?;
+            if observation.chars().count() > MAX_OBSERVATION_CHARS {
+                observation = observation.chars().take(MAX_OBSERVATION_CHARS).collect::<String>() + "…(truncated)";
+            }
+            let observation = redact(observation).await?;
+            transcript.push_str(&format!("{}\nObservation: {}\n", reply.trim(), observation));
+
+            steps.push(AgentStep { tool, parameters, result });
+        }
+
+        Ok(AgentRun {
+            answer: format!("Stopped after {} tool calls without a final answer", MAX_AGENT_STEPS),
+            steps,
+            finished: false,
+        })
+    }
+}
+
+// Tool calls the agent may make for one task before it is stopped
+const MAX_AGENT_STEPS: usize = 8;
+// Tool output fed back to the model is cut to this, so one large file can't fill the context
+const MAX_OBSERVATION_CHARS: usize = 4000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentStep {
+    pub tool: String,
+    pub parameters: serde_json::Value,
+    pub result: ToolResult,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub answer: String,
+    // Every tool call in order, with its result
+    pub steps: Vec<AgentStep>,
+    // False when the step limit was reached first
+    pub finished: bool,
+}
+
+enum AgentReply {
+    ToolCall(ToolCall),
+    FinalAnswer(String),
+}
+
+fn agent_prompt(task: &str, context: &str, tools_json: &str) -> String {
+    format!(
+        "You are an assistant that completes tasks by calling tools.\n\
+         Available tools (JSON schema): {}\n\n\
+         Reply with exactly one JSON object and nothing else. To call a tool:\n\
+         {{\"tool\": \"<name>\", \"parameters\": {{...}}}}\n\
+         When you have the answer:\n\
+         {{\"final_answer\": \"<answer>\"}}\n\
+         After each tool call you will see its result as an Observation.\n\n\
+         Task: {}\nContext: {}\n",
+        tools_json, task, context
+    )
+}
+
+// The first JSON object in the reply that is a tool call or a final answer decides, so braces in
+// prose before it don't hide it; a reply with neither is taken as the answer itself
+fn parse_agent_reply(reply: &str) -> AgentReply {
+    for (start, _) in reply.match_indices('{') {
+        let value = match serde_json::Deserializer::from_str(&reply[start..])
+            .into_iter::<serde_json::Value>()
+            .next()
+        {
+            Some(Ok(value)) => value,
+            _ => continue,
+        };
+
+        if let Some(answer) = value.get("final_answer") {
+            let answer = answer.as_str().map(str::to_string).unwrap_or_else(|| answer.to_string());
+            return AgentReply::FinalAnswer(answer);
+        }
+        if let Some(tool) = value.get("tool").and_then(|tool| tool.as_str()) {
+            return AgentReply::ToolCall(ToolCall {
+                tool: tool.to_string(),
+                parameters: value.get("parameters").cloned().unwrap_or_else(|| serde_json::json!({})),
+            });
+        }
+    }
+
+    AgentReply::FinalAnswer(reply.trim().to_string())
+}
+
+// Safe evaluator for pure numeric expressions (+ - * / // % ** and parentheses, optionally
+// wrapped in print(...)), following Python semantics. Returns None for anything else so the
+// caller falls through to the sandbox.
+const MAX_EXPRESSION_LEN: usize = 1000;
+const MAX_NESTING: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        match self {
+            Number::Int(i) => serde_json::json!(i),
+            Number::Float(f) => serde_json::json!(f),
+        }
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Float(v) if v.is_finite() && v.fract() == 0.0 && v.abs() < 1e16 => write!(f, "{:.1}", v),
+            Number::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+// Inner Err means "valid arithmetic, but it fails" (e.g. division by zero); None means "not ours"
+type EvalResult = Option<std::result::Result<Number, String>>;
+
+fn evaluate_arithmetic(code: &str) -> EvalResult {
+    let mut expr = code.trim();
+    if expr.is_empty() || expr.len() > MAX_EXPRESSION_LEN || expr.contains('\n') {
+        return None;
+    }
+
+    if let Some(inner) = expr.strip_prefix("print(").and_then(|rest| rest.strip_suffix(')')) {
+        expr = inner;
+    }
+
+    if !expr.chars().all(|c| c.is_ascii_digit() || c.is_whitespace() || "+-*/%().".contains(c)) {
+        return None;
+    }
+
+    let tokens = tokenize(expr)?;
+    let mut parser = ExprParser { tokens, pos: 0, depth: 0 };
+    let value = match parser.expression()? {
+        Ok(value) => value,
+        Err(e) => return Some(Err(e)),
+    };
+
+    if parser.pos != parser.tokens.len() {
+        return None;
+    }
+    Some(Ok(value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Num(Number),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(expr: &str) -> Option<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => { tokens.push(Token::Open); i += 1; }
+            ')' => { tokens.push(Token::Close); i += 1; }
+            '*' | '/' if chars.get(i + 1) == Some(&c) => {
+                tokens.push(Token::Op(if c == '*' { "**" } else { "//" }));
+                i += 2;
+            }
+            '+' => { tokens.push(Token::Op("+")); i += 1; }
+            '-' => { tokens.push(Token::Op("-")); i += 1; }
+            '*' => { tokens.push(Token::Op("*")); i += 1; }
+            '/' => { tokens.push(Token::Op("/")); i += 1; }
+            '%' => { tokens.push(Token::Op("%")); i += 1; }
+            _ => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let number = if literal.contains('.') {
+                    Number::Float(literal.parse().ok()?)
+                } else {
+                    // Beyond i64 Python would use a bigint; leave that to the interpreter
+                    Number::Int(literal.parse().ok()?)
+                };
+                tokens.push(Token::Num(number));
+            }
+        }
+    }
+
+    Some(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl ExprParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> EvalResult {
+        let mut left = match self.term()? { Ok(v) => v, err => return Some(err) };
+        while let Some(op @ ("+" | "-")) = self.peek_op() {
+            self.pos += 1;
+            let right = match self.term()? { Ok(v) => v, err => return Some(err) };
+            left = match apply(op, left, right)? { Ok(v) => v, err => return Some(err) };
+        }
+        Some(Ok(left))
+    }
+
+    // term := factor (('*' | '/' | '//' | '%') factor)*
+    fn term(&mut self) -> EvalResult {
+        let mut left = match self.factor()? { Ok(v) => v, err => return Some(err) };
+        while let Some(op @ ("*" | "/" | "//" | "%")) = self.peek_op() {
+            self.pos += 1;
+            let right = match self.factor()? { Ok(v) => v, err => return Some(err) };
+            left = match apply(op, left, right)? { Ok(v) => v, err => return Some(err) };
+        }
+        Some(Ok(left))
+    }
+
+    // factor := ('+' | '-') factor | power   (so -2 ** 2 == -4, as in Python)
+    fn factor(&mut self) -> EvalResult {
+        match self.peek_op() {
+            Some("-") => {
+                self.pos += 1;
+                let value = match self.nested(Self::factor)? { Ok(v) => v, err => return Some(err) };
+                apply("-", Number::Int(0), value)
+            }
+            Some("+") => {
+                self.pos += 1;
+                self.nested(Self::factor)
+            }
+            _ => self.power(),
+        }
+    }
+
+    // power := atom ('**' factor)?
+    fn power(&mut self) -> EvalResult {
+        let base = match self.atom()? { Ok(v) => v, err => return Some(err) };
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            let exponent = match self.nested(Self::factor)? { Ok(v) => v, err => return Some(err) };
+            return apply("**", base, exponent);
+        }
+        Some(Ok(base))
+    }
+
+    // atom := number | '(' expression ')'
+    fn atom(&mut self) -> EvalResult {
+        match self.tokens.get(self.pos).copied()? {
+            Token::Num(n) => {
+                self.pos += 1;
+                Some(Ok(n))
+            }
+            Token::Open => {
+                self.pos += 1;
+                let value = self.nested(Self::expression)?;
+                if self.tokens.get(self.pos) != Some(&Token::Close) {
+                    return None;
+                }
+                self.pos += 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    fn nested(&mut self, rule: fn(&mut Self) -> EvalResult) -> EvalResult {
+        if self.depth >= MAX_NESTING {
+            return None;
+        }
+        self.depth += 1;
+        let result = rule(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+// Integer ops that overflow return None so the interpreter (with bigints) handles them
+fn apply(op: &str, left: Number, right: Number) -> EvalResult {
+    use Number::{Float, Int};
+
+    let zero_division = || Some(Err("ZeroDivisionError: division by zero".to_string()));
+
+    let value = match (op, left, right) {
+        ("+", Int(a), Int(b)) => Int(a.checked_add(b)?),
+        ("-", Int(a), Int(b)) => Int(a.checked_sub(b)?),
+        ("*", Int(a), Int(b)) => Int(a.checked_mul(b)?),
+        ("//", Int(_), Int(0)) | ("%", Int(_), Int(0)) => return zero_division(),
+        ("//", Int(a), Int(b)) => Int(floor_div(a, b)?),
+        ("%", Int(a), Int(b)) => Int(a.checked_sub(b.checked_mul(floor_div(a, b)?)?)?),
+        ("**", Int(a), Int(b)) if b >= 0 => Int(a.checked_pow(u32::try_from(b).ok()?)?),
+        (_, a, b) => {
+            let (a, b) = (a.as_f64(), b.as_f64());
+            match op {
+                "+" => Float(a + b),
+                "-" => Float(a - b),
+                "*" => Float(a * b),
+                "/" | "//" | "%" if b == 0.0 => return zero_division(),
+                "/" => Float(a / b),
+                "//" => Float((a / b).floor()),
+                "%" => Float(a - b * (a / b).floor()),
+                "**" if a == 0.0 && b < 0.0 => return zero_division(),
+                "**" => Float(a.powf(b)),
+                _ => return None,
+            }
+        }
+    };
+
+    match value {
+        // Overflow to inf, or a negative base to a fractional power (complex in Python)
+        Float(f) if !f.is_finite() => None,
+        v => Some(Ok(v)),
+    }
+}
+
+// Python rounds integer division toward negative infinity
+fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let q = a.checked_div(b)?;
+    if (a % b != 0) && ((a < 0) != (b < 0)) {
+        Some(q - 1)
+    } else {
+        Some(q)
+    }
+}
+
+impl PythonDenylist {
+    fn blocks_module(&self, module: &str) -> bool {
+        self.modules.iter().any(|blocked| {
+            module == blocked || module.strip_prefix(blocked.as_str()).is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PyToken {
+    Name(String),
+    Op(char),
+    // Strings and numbers; their contents never matter to the checks
+    Literal,
+    // Newline outside brackets, or `;`
+    StatementEnd,
+}
+
+const STRING_PREFIXES: &[&str] = &["r", "u", "b", "f", "br", "rb", "fr", "rf"];
+
+// Just enough of Python's lexer to tell identifiers from strings and comments. f-string
+// replacement fields are code, so they are tokenized too.
+fn python_tokens(code: &str) -> std::result::Result<Vec<PyToken>, String> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '\\' && chars.get(i + 1) == Some(&'\n') {
+            i += 2;
+        } else if c == '\n' || c == ';' {
+            if depth == 0 || c == ';' {
+                tokens.push(PyToken::StatementEnd);
+            }
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            let is_prefix = STRING_PREFIXES.contains(&name.to_lowercase().as_str());
+            if is_prefix && matches!(chars.get(i), Some('\'') | Some('"')) {
+                let body = python_string(&chars, &mut i)?;
+                if name.to_lowercase().contains('f') {
+                    for expression in fstring_expressions(&body) {
+                        tokens.extend(python_tokens(&expression)?.into_iter().filter(|t| *t != PyToken::StatementEnd));
+                    }
+                }
+                tokens.push(PyToken::Literal);
+            } else {
+                tokens.push(PyToken::Name(name));
+            }
+        } else if c == '\'' || c == '"' {
+            python_string(&chars, &mut i)?;
+            tokens.push(PyToken::Literal);
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(PyToken::Literal);
+        } else {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            tokens.push(PyToken::Op(c));
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Consumes a string literal starting at the opening quote and returns its body
+fn python_string(chars: &[char], i: &mut usize) -> std::result::Result<String, String> {
+    let quote = chars[*i];
+    let triple = chars.get(*i + 1) == Some(&quote) && chars.get(*i + 2) == Some(&quote);
+    *i += if triple { 3 } else { 1 };
+
+    let mut body = String::new();
+    while *i < chars.len() {
+        let c = chars[*i];
+        if c == '\\' {
+            // Escapes the next character even in raw strings, as far as termination goes
+            body.push(c);
+            if let Some(next) = chars.get(*i + 1) {
+                body.push(*next);
+            }
+            *i += 2;
+            continue;
+        }
+        if c == quote && (!triple || (chars.get(*i + 1) == Some(&quote) && chars.get(*i + 2) == Some(&quote))) {
+            *i += if triple { 3 } else { 1 };
+            return Ok(body);
+        }
+        if c == '\n' && !triple {
+            break;
+        }
+        body.push(c);
+        *i += 1;
+    }
+
+    Err("unterminated string literal".to_string())
+}
+
+// The `{...}` replacement fields of an f-string body; `{{` is a literal brace
+fn fstring_expressions(body: &str) -> Vec<String> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut expressions = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] == '{' && chars.get(i + 1) == Some(&'{') {
+            i += 2;
+        } else if chars[i] == '{' {
+            let mut depth = 1;
+            let start = i + 1;
+            i += 1;
+            while i < chars.len() && depth > 0 {
+                match chars[i] {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+            let end = if depth == 0 { i - 1 } else { i };
+            expressions.push(chars[start..end].iter().collect());
+        } else {
+            i += 1;
+        }
+    }
+
+    expressions
+}
+
+// First thing in `code` the denylist forbids, described for the error message
+fn python_violation(code: &str, denylist: &PythonDenylist) -> Option<String> {
+    let tokens = match python_tokens(code) {
+        Ok(tokens) => tokens,
+        Err(e) => return Some(format!("code could not be parsed ({})", e)),
+    };
+    let name_at = |i: usize| match tokens.get(i) {
+        Some(PyToken::Name(name)) => Some(name.as_str()),
+        _ => None,
+    };
+    // A dotted module path starting at `i`; returns it and the index after it
+    let dotted_at = |mut i: usize| {
+        let mut path = String::new();
+        while tokens.get(i) == Some(&PyToken::Op('.')) {
+            path.push('.');
+            i += 1;
+        }
+        while let Some(part) = name_at(i) {
+            path.push_str(part);
+            i += 1;
+            if tokens.get(i) == Some(&PyToken::Op('.')) && name_at(i + 1).is_some() {
+                path.push('.');
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        (path, i)
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let Some(name) = name_at(i) else {
+            i += 1;
+            continue;
+        };
+
+        match name {
+            // `from X import ...`; `yield from` and `raise ... from` never reach the `import`
+            "from" => {
+                let (module, next) = dotted_at(i + 1);
+                if !module.is_empty() && name_at(next) == Some("import") {
+                    if denylist.blocks_module(&module) {
+                        return Some(format!("import {}", module));
+                    }
+                    i = next + 1;
+                    continue;
+                }
+            }
+            // `import a.b as c, d`
+            "import" => {
+                let mut next = i + 1;
+                loop {
+                    let (module, after) = dotted_at(next);
+                    if denylist.blocks_module(&module) {
+                        return Some(format!("import {}", module));
+                    }
+                    next = after;
+                    if name_at(next) == Some("as") {
+                        next += 2;
+                    }
+                    if tokens.get(next) == Some(&PyToken::Op(',')) {
+                        next += 1;
+                    } else {
+                        break;
+                    }
+                }
+                i = next;
+                continue;
+            }
+            _ => {}
+        }
+
+        if denylist.names.iter().any(|blocked| blocked == name) {
+            return Some(name.to_string());
+        }
+        let is_attribute = i > 0 && tokens[i - 1] == PyToken::Op('.');
+        if !is_attribute && denylist.builtins.iter().any(|blocked| blocked == name) {
+            return Some(name.to_string());
+        }
+        i += 1;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_manager::{FinishReason, Generation};
+    use std::collections::VecDeque;
+
+    // Replays canned model replies in order and keeps the prompts it was given
+    struct ScriptedGenerator {
+        replies: std::sync::Mutex<VecDeque<String>>,
+        prompts: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl ScriptedGenerator {
+        fn new(replies: &[&str]) -> Arc<Self> {
+            Arc::new(Self {
+                replies: std::sync::Mutex::new(replies.iter().map(|reply| reply.to_string()).collect()),
+                prompts: std::sync::Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl TextGenerator for ScriptedGenerator {
+        async fn active_model(&self) -> Option<String> {
+            Some("scripted".to_string())
+        }
+
+        async fn generate(&self, prompt: &str, _params: &GenerationParams) -> Result<Generation> {
+            self.prompts.lock().unwrap().push(prompt.to_string());
+            let text = self.replies.lock().unwrap().pop_front().expect("no scripted reply left");
+            Ok(Generation {
+                text,
+                finish_reason: FinishReason::Stop,
+                truncated: false,
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                stopped_for_safety: false,
+            })
+        }
+    }
+
+    fn read_write() -> ToolPolicy {
+        ToolPolicy { access: ToolAccess::ReadWrite, enabled: HashMap::new() }
+    }
+
+    fn scripted_agent(generator: Arc<ScriptedGenerator>) -> AgentOrchestrator {
+        let mut agent = AgentOrchestrator::new(true, read_write());
+        agent.attach_llm(generator, Arc::new(RwLock::new(PIIDetector::default())));
+        agent
+    }
+
+    #[tokio::test]
+    async fn agent_makes_one_tool_call_then_answers() {
+        let generator = ScriptedGenerator::new(&[
+            r#"Let me add them. {"tool": "run_python", "parameters": {"code": "2 + 3"}}"#,
+            r#"{"final_answer": "The total is 5"}"#,
+        ]);
+        let agent = scripted_agent(generator.clone());
+
+        let run = agent.execute_agent_task("Add 2 and 3", "").await.unwrap();
+
+        assert!(run.finished);
+        assert_eq!(run.answer, "The total is 5");
+        assert_eq!(run.steps.len(), 1);
+        assert_eq!(run.steps[0].tool, "run_python");
+        assert!(run.steps[0].result.success);
+        let prompts = generator.prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].contains("Observation: ") && prompts[1].contains(r#""output":"5""#));
+    }
+
+    #[tokio::test]
+    async fn agent_stops_at_the_step_limit() {
+        let call = r#"{"tool": "run_python", "parameters": {"code": "1 + 1"}}"#;
+        let generator = ScriptedGenerator::new(&[call; MAX_AGENT_STEPS + 1]);
+        let agent = scripted_agent(generator);
+
+        let run = agent.execute_agent_task("Loop forever", "").await.unwrap();
+
+        assert!(!run.finished);
+        assert_eq!(run.steps.len(), MAX_AGENT_STEPS);
+    }
+
+    #[tokio::test]
+    async fn search_documents_surfaces_an_indexed_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = RAGEngine::new(dir.path());
+        rag.initialize().await.unwrap();
+        rag.add_document(
+            "The lease may be terminated on thirty days written notice.",
+            serde_json::json!({ "source": "lease.txt" }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        let call = || ToolCall {
+            tool: "search_documents".to_string(),
+            parameters: serde_json::json!({ "query": "lease termination notice", "limit": 3 }),
+        };
+        let detached = server.execute_tool(call()).await.unwrap();
+        assert!(!detached.success);
+
+        server.attach_rag(Arc::new(RwLock::new(rag)));
+        let result = server.execute_tool(call()).await.unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.result["total"], 1);
+        assert_eq!(result.result["results"][0]["source"], "lease.txt");
+        assert!(result.result["results"][0]["content"].as_str().unwrap().contains("thirty days"));
+
+        let keyword = server.execute_tool(ToolCall {
+            tool: "search_documents".to_string(),
+            parameters: serde_json::json!({ "query": "terminated", "alpha": 0.0 }),
+        })
+        .await
+        .unwrap();
+        assert_eq!(keyword.result["total"], 1);
+    }
+
+    #[tokio::test]
+    async fn analyze_contract_returns_what_the_model_extracts() {
+        let generator = ScriptedGenerator::new(&[
+            r#"Here you go: {"parties": ["Acme Corp"], "dates": "1 March 2024", "obligations": [], "risks": [42]}"#,
+        ]);
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        server.attach_llm(generator.clone(), Arc::new(RwLock::new(PIIDetector::default())));
+
+        let result = server.execute_tool(ToolCall {
+            tool: "analyze_contract".to_string(),
+            parameters: serde_json::json!({ "content": "This agreement is made on 1 March 2024.", "use_model": true }),
+        })
+        .await
+        .unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.result["method"], "rules+model");
+        assert_eq!(result.result["model"], "scripted");
+        assert!(result.result["parties"].as_array().unwrap().iter().any(|party| party == "Acme Corp"));
+        assert!(result.result["dates"].as_array().unwrap().iter().any(|date| date == "1 March 2024"));
+        assert_eq!(result.result["risks"], serde_json::json!([]));
+        assert!(generator.prompts.lock().unwrap()[0].contains("This agreement is made on 1 March 2024."));
+    }
+
+    #[tokio::test]
+    async fn analyze_contract_without_a_model_fails_cleanly() {
+        let server = MCPServer::new(true, ToolPolicy::read_only());
+        let result = server.execute_tool(ToolCall {
+            tool: "analyze_contract".to_string(),
+            parameters: serde_json::json!({ "content": "Any text", "use_model": true }),
+        })
+        .await
+        .unwrap();
+
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("language model"));
+    }
+
+    fn audit_records(dir: &Path) -> Vec<ToolAuditRecord> {
+        std::fs::read_to_string(dir.join(TOOL_AUDIT_LOG_FILE))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn a_read_file_call_is_logged_before_and_after_it_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_dir = dir.path().join("logs");
+        let file = dir.path().join("brief.txt");
+        std::fs::write(&file, "Brief").unwrap();
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        server.add_allowed_path(dir.path().to_path_buf());
+        server.set_audit_log(ToolAuditLog::open(&log_dir).unwrap());
+
+        let result = server.execute_tool(ToolCall {
+            tool: "read_file".to_string(),
+            parameters: serde_json::json!({ "path": file.to_str().unwrap(), "max_lines": 5 }),
+        })
+        .await
+        .unwrap();
+        assert!(result.success);
+
+        let records = audit_records(&log_dir);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].phase, ToolAuditPhase::Started);
+        assert_eq!(records[1].phase, ToolAuditPhase::Finished);
+        assert_eq!(records[0].call_id, records[1].call_id);
+        assert!(records.iter().all(|record| record.tool == "read_file"));
+        // No detector attached: text is masked, the shape of the call is kept
+        assert_eq!(records[0].parameters, Some(serde_json::json!({ "path": "[REDACTED]", "max_lines": 5 })));
+        assert_eq!(records[1].success, Some(true));
+        assert_eq!(records[1].error, None);
+        assert!(records[1].duration_ms.is_some());
+        assert!(records[1].parameters.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_call_that_cannot_be_logged_is_not_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_dir = dir.path().join("logs");
+        let audit_log = ToolAuditLog::open(&log_dir).unwrap();
+        // A directory where the log file belongs makes every append fail
+        std::fs::create_dir(log_dir.join(TOOL_AUDIT_LOG_FILE)).unwrap();
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+
+        let mut server = MCPServer::new(true, read_write());
+        server.add_allowed_path(work.clone());
+        server.set_audit_log(audit_log);
+
+        let target = work.join("out.txt");
+        let outcome = server.execute_tool(ToolCall {
+            tool: "write_file".to_string(),
+            parameters: serde_json::json!({ "path": target.to_str().unwrap(), "content": "written" }),
+        })
+        .await;
+
+        assert!(outcome.is_err());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn contract_terms_are_extracted_from_a_sample() {
+        let contract = "This Services Agreement is entered into on March 1, 2024 between Acme Corp., a Delaware \
+            corporation (\"Client\"), and Beta Consulting LLC (\"Provider\").\n\n\
+            The Provider shall deliver the monthly report by 2024-04-15. The Client agrees to pay $12,500 per month. \
+            Either party may terminate this Agreement on thirty days notice.";
+
+        let terms = extract_contract_terms(contract);
+        let strings = |field: &str| -> Vec<String> {
+            terms[field].as_array().unwrap().iter().map(|v| v.as_str().unwrap().to_string()).collect()
+        };
+
+        assert_eq!(strings("parties"), ["Acme Corp.", "Beta Consulting LLC"]);
+        assert_eq!(strings("dates"), ["March 1, 2024", "2024-04-15"]);
+        assert_eq!(strings("amounts"), ["$12,500"]);
+        let obligations = strings("obligations");
+        assert_eq!(obligations.len(), 2);
+        assert!(obligations[0].starts_with("The Provider shall deliver"));
+        assert!(obligations[1].starts_with("The Client agrees to pay"));
+    }
+
+    #[test]
+    fn contract_analysis_keeps_the_usable_fields() {
+        let (fields, parsed) = parse_contract_analysis(r#"Sure! {"parties": ["Acme"], "dates": "2024-01-01", "risks": [1, "Late fees"]}"#);
+        assert!(parsed);
+        assert_eq!(fields["parties"], serde_json::json!(["Acme"]));
+        assert_eq!(fields["dates"], serde_json::json!(["2024-01-01"]));
+        assert_eq!(fields["risks"], serde_json::json!(["Late fees"]));
+        assert_eq!(fields["obligations"], serde_json::json!([]));
+
+        let (fields, parsed) = parse_contract_analysis("I could not find anything.");
+        assert!(!parsed);
+        assert_eq!(fields["parties"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn denied_tools_return_an_error_without_side_effects() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("out.txt");
+        let write = || ToolCall {
+            tool: "write_file".to_string(),
+            parameters: serde_json::json!({ "path": target.to_str().unwrap(), "content": "written" }),
+        };
+
+        for policy in [ToolPolicy::default(), read_write().with_tool("write_file", false)] {
+            let mut server = MCPServer::new(true, policy);
+            server.add_allowed_path(dir.path().to_path_buf());
+
+            let result = server.execute_tool(write()).await.unwrap();
+            assert!(!result.success);
+            assert!(result.error.unwrap().starts_with("Permission denied"));
+            assert!(!target.exists());
+            assert!(!server.list_tools().iter().any(|tool| tool.name == "write_file"));
+        }
+
+        let mut server = MCPServer::new(true, read_write().with_tool("run_python", false));
+        server.add_allowed_path(dir.path().to_path_buf());
+        assert!(server.execute_tool(write()).await.unwrap().success);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "written");
+    }
+
+    async fn list_names(server: &MCPServer, path: &Path) -> serde_json::Value {
+        let result = server.execute_tool(ToolCall {
+            tool: "list_directory".to_string(),
+            parameters: serde_json::json!({ "path": path.to_str().unwrap(), "recursive": true }),
+        })
+        .await
+        .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        result.result
+    }
+
+    #[tokio::test]
+    async fn recursive_listing_uses_the_configured_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/pkg")).unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/brief.txt"), "brief").unwrap();
+        std::fs::write(dir.path().join("docs/draft.tmp"), "draft").unwrap();
+
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        let config = AgentConfig {
+            allowed_paths: vec![dir.path().to_path_buf()],
+            list_filters: FilterPatterns { include: Vec::new(), exclude: vec!["node_modules".to_string(), "*.tmp".to_string()] },
+            ..AgentConfig::default()
+        };
+        server.apply_config(&config).unwrap();
+
+        let listing = list_names(&server, dir.path()).await;
+        let mut paths: Vec<&str> = listing["files"].as_array().unwrap().iter().map(|f| f["path"].as_str().unwrap()).collect();
+        paths.sort();
+        assert_eq!(paths, ["docs", "docs/brief.txt"]);
+
+        let bad = AgentConfig { list_filters: FilterPatterns { include: vec!["[".to_string()], exclude: Vec::new() }, ..config };
+        assert!(server.apply_config(&bad).is_err());
+        assert_eq!(server.config().list_filters.exclude, ["node_modules", "*.tmp"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unreadable_subdirectories_are_skipped_and_noted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::write(dir.path().join("open.txt"), "open").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads through permissions, so there is nothing to test there
+        if std::fs::read_dir(&locked).is_ok() {
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        server.add_allowed_path(dir.path().to_path_buf());
+        let listing = list_names(&server, dir.path()).await;
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(listing["unreadable"], serde_json::json!(["locked"]));
+        assert!(listing["files"].as_array().unwrap().iter().any(|f| f["path"] == "open.txt"));
+    }
+
+    fn call(tool: &str, parameters: serde_json::Value) -> ToolCall {
+        ToolCall { tool: tool.to_string(), parameters }
+    }
+
+    // root/sandbox is the allowed directory; root/secret.txt sits next to it
+    fn sandbox() -> (tempfile::TempDir, PathBuf, MCPServer) {
+        let root = tempfile::tempdir().unwrap();
+        let sandbox = root.path().join("sandbox");
+        std::fs::create_dir(&sandbox).unwrap();
+        std::fs::write(sandbox.join("notes.txt"), "inside").unwrap();
+        std::fs::write(root.path().join("secret.txt"), "outside").unwrap();
+        let mut server = MCPServer::new(true, read_write());
+        server.add_allowed_path(sandbox.clone());
+        (root, sandbox, server)
+    }
+
+    #[tokio::test]
+    async fn sandbox_reads_inside_and_refuses_traversal() {
+        let (_root, sandbox, server) = sandbox();
+
+        let inside = server.execute_tool(call("read_file", serde_json::json!({ "path": sandbox.join("notes.txt") }))).await.unwrap();
+        assert_eq!(inside.result["content"], "inside");
+
+        let escape = sandbox.join("..").join("secret.txt");
+        let read = server.execute_tool(call("read_file", serde_json::json!({ "path": escape }))).await.unwrap();
+        assert!(!read.success);
+        let write = server.execute_tool(call("write_file", serde_json::json!({ "path": escape, "content": "x" }))).await.unwrap();
+        assert!(!write.success);
+        let list = server.execute_tool(call("list_directory", serde_json::json!({ "path": sandbox.join("..") }))).await.unwrap();
+        assert!(!list.success);
+        assert_eq!(std::fs::read_to_string(sandbox.join("../secret.txt")).unwrap(), "outside");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sandbox_refuses_symlinks_that_point_outside() {
+        let (root, sandbox, server) = sandbox();
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), sandbox.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(root.path(), sandbox.join("up")).unwrap();
+
+        for path in [sandbox.join("link.txt"), sandbox.join("up/secret.txt")] {
+            let read = server.execute_tool(call("read_file", serde_json::json!({ "path": path }))).await.unwrap();
+            assert!(!read.success, "{} was readable", path.display());
+        }
+        let write = server.execute_tool(call("write_file", serde_json::json!({ "path": sandbox.join("up/new.txt"), "content": "x" }))).await.unwrap();
+        assert!(!write.success);
+        assert!(!root.path().join("new.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_path_swapped_after_the_check_is_not_opened() {
+        let (root, sandbox, server) = sandbox();
+        let target = sandbox.join("notes.txt");
+        let resolved = server.resolve_path(target.to_str().unwrap(), false).unwrap();
+
+        // Between the check and the open, the file becomes a link to the outside
+        std::fs::remove_file(&target).unwrap();
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), &target).unwrap();
+
+        let opened = server.open_resolved(&resolved, fs::OpenOptions::new().write(true)).await;
+        assert!(opened.is_err());
+        assert_eq!(std::fs::read_to_string(root.path().join("secret.txt")).unwrap(), "outside");
+    }
+
+    #[tokio::test]
+    async fn extract_text_reads_a_committed_file() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let mut server = MCPServer::new(true, ToolPolicy::read_only());
+        server.add_allowed_path(fixtures.clone());
+        let path = fixtures.join("engagement_letter.txt");
+
+        let result = server.execute_tool(call("extract_text", serde_json::json!({ "path": path }))).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.result["format"], "txt");
+        assert_eq!(result.result["truncated"], false);
+        assert!(result.result["text"].as_str().unwrap().contains("represent the Client in the lease dispute"));
+
+        let mismatched = server.execute_tool(call("extract_text", serde_json::json!({ "path": path, "format": "pdf" }))).await.unwrap();
+        assert!(!mismatched.success);
+    }
+
+    async fn run_python(server: &MCPServer, code: &str) -> ToolResult {
+        server.execute_tool(ToolCall {
+            tool: "run_python".to_string(),
+            parameters: serde_json::json!({ "code": code }),
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn run_python_blocks_imports_not_substrings() {
+        let server = MCPServer::new(true, read_write());
+
+        assert!(run_python(&server, "position = 1").await.success);
+        assert!(run_python(&server, "print('import os')  # os.system is mentioned here").await.success);
+
+        for code in ["import os", "from subprocess import run", "x = __builtins__[\"ev\" + \"al\"]", "eval('1')"] {
+            let result = run_python(&server, code).await;
+            assert!(!result.success, "{} should be blocked", code);
+            assert!(result.error.unwrap().starts_with("Forbidden operation"));
+        }
+    }
+
+    #[tokio::test]
+    async fn run_python_denylist_comes_from_the_agent_config() {
+        let mut server = MCPServer::new(true, read_write());
+        assert!(run_python(&server, "import numpy").await.success);
+
+        let mut config = AgentConfig { policy: read_write(), ..AgentConfig::default() };
+        config.python_denylist.modules.push("numpy".to_string());
+        server.apply_config(&config).unwrap();
+
+        assert!(!run_python(&server, "import numpy.linalg").await.success);
+        assert_eq!(server.config().python_denylist.modules, config.python_denylist.modules);
+    }
+
+    #[test]
+    fn agent_reply_skips_braces_that_are_not_a_call() {
+        let reply = r#"Plan: {check the file} then {"note": 1} {"tool": "read_file", "parameters": {"path": "a.txt"}}"#;
+        assert!(matches!(parse_agent_reply(reply), AgentReply::ToolCall(call) if call.tool == "read_file"));
+
+        let reply = r#"Using {"tool"} syntax: {"final_answer": "done"}"#;
+        assert!(matches!(parse_agent_reply(reply), AgentReply::FinalAnswer(answer) if answer == "done"));
+
+        assert!(matches!(parse_agent_reply(" No JSON here "), AgentReply::FinalAnswer(answer) if answer == "No JSON here"));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Skipped unless the user overrides the exclude list
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    ".git", ".svn", ".hg", "node_modules", "target", "__pycache__", ".venv", "venv",
+    ".DS_Store", "Thumbs.db", "*.tmp", "*.temp", "*.swp", "*.part", "*.crdownload", "~$*",
+];
+
+// .gitignore-style patterns: without a `/` a pattern matches a file or directory name at any depth,
+// with one it is matched against the path relative to the root. Empty `include` means everything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterPatterns {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default = "default_excludes")]
+    pub exclude: Vec<String>,
+}
+
+fn default_excludes() -> Vec<String> {
+    DEFAULT_EXCLUDES.iter().map(|p| p.to_string()).collect()
+}
+
+impl Default for FilterPatterns {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: default_excludes(),
+        }
+    }
+}
+
+impl FilterPatterns {
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::data_dir::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+
+    // Per-call overrides replace the configured list for that side only
+    pub fn with_overrides(&self, include: Option<Vec<String>>, exclude: Option<Vec<String>>) -> Self {
+        Self {
+            include: include.unwrap_or_else(|| self.include.clone()),
+            exclude: exclude.unwrap_or_else(|| self.exclude.clone()),
+        }
+    }
+}
+
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub fn new(patterns: &FilterPatterns) -> Result<Self> {
+        let include = if patterns.include.is_empty() {
+            None
+        } else {
+            Some(build_set(&patterns.include)?)
+        };
+
+        Ok(Self {
+            include,
+            exclude: build_set(&patterns.exclude)?,
+        })
+    }
+
+    // `relative` is the path below the walk root
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude.is_match(relative)
+    }
+
+    pub fn allows_file(&self, relative: &Path) -> bool {
+        if self.is_excluded(relative) {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        }
+    }
+
+    // Directories are only pruned by excludes; includes apply to the files inside them
+    pub fn allows_dir(&self, relative: &Path) -> bool {
+        !self.is_excluded(relative)
+    }
+
+    // Checks an absolute path against the filter relative to `root`; paths outside root are rejected
+    pub fn allows_path(&self, root: &Path, path: &Path) -> bool {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        // A file inside an excluded directory is excluded too
+        let mut ancestor = PathBuf::new();
+        let components: Vec<_> = relative.components().collect();
+        for component in &components[..components.len().saturating_sub(1)] {
+            ancestor.push(component);
+            if !self.allows_dir(&ancestor) {
+                return false;
+            }
+        }
+
+        self.allows_file(relative)
+    }
+}
+
+fn build_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.trim().trim_end_matches('/');
+        if pattern.is_empty() || pattern.starts_with('#') {
+            continue;
+        }
+        match pattern.strip_prefix('/') {
+            Some(anchored) => builder.add(Glob::new(anchored)?),
+            None if pattern.contains('/') => builder.add(Glob::new(pattern)?),
+            None => {
+                builder.add(Glob::new(pattern)?);
+                builder.add(Glob::new(&format!("**/{}", pattern))?)
+            }
+        };
+    }
+    Ok(builder.build()?)
+}
+
+// Files under `root` that pass the filter, pruning excluded directories without descending.
+// Returns the files and whether `max_files` cut the walk short.
+pub fn walk_files(root: &Path, filter: &PathFilter, max_files: usize) -> Result<(Vec<PathBuf>, bool)> {
+    let mut files = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+
+            // Symlinked directories are not followed, so link cycles can't trap the walk
+            if entry.file_type()?.is_dir() {
+                if filter.allows_dir(relative) {
+                    stack.push(path);
+                }
+            } else if path.is_file() && filter.allows_file(relative) {
+                if files.len() >= max_files {
+                    return Ok((files, true));
+                }
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok((files, false))
+}
@@ -0,0 +1,369 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Append-only record of every redaction that preceded sending text to the model or the index.
+// Entries are hash-chained, so editing or removing one breaks verification of everything after it.
+// Raw input is never stored: only per-type counts and a salted hash.
+
+const AUDIT_LOG_FILE: &str = "pii_audit.jsonl";
+const AUDIT_SALT_FILE: &str = "pii_audit.salt";
+
+// `previous_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    SendMessage,
+    AddToKnowledgeBase,
+}
+
+impl AuditOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditOperation::SendMessage => "send_message",
+            AuditOperation::AddToKnowledgeBase => "add_to_knowledge_base",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub timestamp: i64,
+    pub operation: AuditOperation,
+    // Document id or chat request id
+    pub subject_id: String,
+    pub redaction_counts: BTreeMap<String, usize>,
+    // Salted so short inputs (a bare SSN) can't be recovered by hashing guesses
+    pub input_hash: String,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let counts = self.redaction_counts
+            .iter()
+            .map(|(pii_type, count)| format!("{}={}", pii_type, count))
+            .collect::<Vec<_>>()
+            .join(",");
+        let body = format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            self.sequence,
+            self.timestamp,
+            self.operation.as_str(),
+            self.subject_id,
+            counts,
+            self.input_hash,
+            self.previous_hash,
+        );
+        format!("{:x}", Sha256::digest(body.as_bytes()))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub operation: Option<AuditOperation>,
+    pub subject_id: Option<String>,
+    // Unix seconds, inclusive
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    // Most recent matches only
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub entries: usize,
+    // Hash of the last entry. Recording it elsewhere also makes truncation of the tail detectable.
+    pub head_hash: Option<String>,
+    pub first_invalid_sequence: Option<u64>,
+    pub error: Option<String>,
+}
+
+pub struct PiiAuditLog {
+    path: PathBuf,
+    salt: String,
+    last_hash: String,
+    next_sequence: u64,
+}
+
+impl PiiAuditLog {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(AUDIT_LOG_FILE);
+        let salt = load_or_create_salt(&dir.join(AUDIT_SALT_FILE))?;
+
+        // A write interrupted by a crash leaves a line without its newline; the next entry
+        // would be appended onto it, so the fragment is dropped before anything is added
+        if truncate_torn_tail(&path)? {
+            eprintln!("Dropped an incomplete final entry from {}", path.display());
+        }
+
+        // Continue from the last readable entry
+        let last = read_lines(&path)?
+            .iter()
+            .rev()
+            .find_map(|line| serde_json::from_str::<AuditEntry>(line).ok());
+
+        Ok(Self {
+            path,
+            salt,
+            last_hash: last.as_ref().map(|e| e.hash.clone()).unwrap_or_else(|| GENESIS_HASH.to_string()),
+            next_sequence: last.map(|e| e.sequence + 1).unwrap_or(0),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        operation: AuditOperation,
+        subject_id: &str,
+        input: &str,
+        redaction_counts: &BTreeMap<String, usize>,
+    ) -> Result<AuditEntry> {
+        let mut entry = AuditEntry {
+            sequence: self.next_sequence,
+            timestamp: chrono::Utc::now().timestamp(),
+            operation,
+            subject_id: subject_id.to_string(),
+            redaction_counts: redaction_counts.clone(),
+            input_hash: self.input_hash(input),
+            previous_hash: self.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.sync_data()) {
+            drop(file);
+            truncate_torn_tail(&self.path)?;
+            return Err(e.into());
+        }
+
+        self.last_hash = entry.hash.clone();
+        self.next_sequence += 1;
+        Ok(entry)
+    }
+
+    // Unreadable lines are skipped here; verify() is what reports them
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for line in read_lines(&self.path)? {
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                continue;
+            };
+            let matches = query.operation.is_none_or(|op| entry.operation == op)
+                && query.subject_id.as_deref().is_none_or(|id| entry.subject_id == id)
+                && query.since.is_none_or(|since| entry.timestamp >= since)
+                && query.until.is_none_or(|until| entry.timestamp <= until);
+            if matches {
+                entries.push(entry);
+            }
+        }
+
+        if let Some(limit) = query.limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+
+    // Recomputes every hash and link from the start of the log
+    pub fn verify(&self) -> Result<ChainVerification> {
+        let mut previous_hash = GENESIS_HASH.to_string();
+        let mut entries = 0;
+
+        for (index, line) in read_lines(&self.path)?.iter().enumerate() {
+            let expected_sequence = index as u64;
+            let failure = match serde_json::from_str::<AuditEntry>(line) {
+                Err(e) => Some(format!("Entry {} is unreadable: {}", expected_sequence, e)),
+                Ok(entry) if entry.sequence != expected_sequence => Some(format!(
+                    "Entry {} has sequence {}; entries were removed or reordered",
+                    expected_sequence, entry.sequence
+                )),
+                Ok(entry) if entry.previous_hash != previous_hash => Some(format!(
+                    "Entry {} does not link to the previous entry",
+                    expected_sequence
+                )),
+                Ok(entry) if entry.compute_hash() != entry.hash => Some(format!(
+                    "Entry {} was modified after it was written",
+                    expected_sequence
+                )),
+                Ok(entry) => {
+                    previous_hash = entry.hash;
+                    entries += 1;
+                    None
+                }
+            };
+
+            if let Some(error) = failure {
+                return Ok(ChainVerification {
+                    valid: false,
+                    entries,
+                    head_hash: None,
+                    first_invalid_sequence: Some(expected_sequence),
+                    error: Some(error),
+                });
+            }
+        }
+
+        Ok(ChainVerification {
+            valid: true,
+            entries,
+            head_hash: if entries > 0 { Some(previous_hash) } else { None },
+            first_invalid_sequence: None,
+            error: None,
+        })
+    }
+
+    // Cuts the log back to its last valid entry so recording can continue on an intact chain.
+    // The whole damaged log is first copied aside, so nothing is lost and the break stays provable.
+    pub fn repair(&mut self) -> Result<ChainVerification> {
+        let verification = self.verify()?;
+        let Some(invalid) = verification.first_invalid_sequence else {
+            return Ok(verification);
+        };
+
+        let backup = self.path.with_extension(format!("jsonl.damaged-{}", chrono::Utc::now().timestamp()));
+        std::fs::copy(&self.path, &backup)?;
+
+        let kept: String = read_lines(&self.path)?
+            .into_iter()
+            .take(invalid as usize)
+            .map(|line| line + "\n")
+            .collect();
+        let file = OpenOptions::new().write(true).truncate(true).open(&self.path)?;
+        (&file).write_all(kept.as_bytes())?;
+        file.sync_data()?;
+
+        let repaired = self.verify()?;
+        self.last_hash = repaired.head_hash.clone().unwrap_or_else(|| GENESIS_HASH.to_string());
+        self.next_sequence = repaired.entries as u64;
+        Ok(repaired)
+    }
+
+    fn input_hash(&self, input: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(input.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+// Drops a final line that was never terminated. Returns whether anything was cut.
+fn truncate_torn_tail(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let bytes = std::fs::read(path)?;
+    if bytes.is_empty() || bytes.ends_with(b"\n") {
+        return Ok(false);
+    }
+
+    let keep = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |pos| pos + 1);
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(keep as u64)?;
+    file.sync_data()?;
+    Ok(true)
+}
+
+// Per-install, so input hashes can't be compared across machines
+fn load_or_create_salt(path: &Path) -> Result<String> {
+    if path.exists() {
+        let salt = std::fs::read_to_string(path)?.trim().to_string();
+        if salt.is_empty() {
+            return Err(anyhow!("Audit salt file {} is empty", path.display()));
+        }
+        return Ok(salt);
+    }
+
+    let salt = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    std::fs::write(path, &salt)?;
+    Ok(salt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts() -> BTreeMap<String, usize> {
+        BTreeMap::from([("SSN".to_string(), 1)])
+    }
+
+    #[test]
+    fn test_torn_tail_is_dropped_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PiiAuditLog::open(dir.path()).unwrap();
+        log.record(AuditOperation::SendMessage, "a", "input", &counts()).unwrap();
+        drop(log);
+
+        let path = dir.path().join(AUDIT_LOG_FILE);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"sequence\":1,\"timest").unwrap();
+        drop(file);
+
+        let mut log = PiiAuditLog::open(dir.path()).unwrap();
+        log.record(AuditOperation::SendMessage, "b", "input", &counts()).unwrap();
+
+        let verification = log.verify().unwrap();
+        assert!(verification.valid, "{:?}", verification.error);
+        assert_eq!(verification.entries, 2);
+    }
+
+    #[test]
+    fn test_query_skips_unreadable_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PiiAuditLog::open(dir.path()).unwrap();
+        log.record(AuditOperation::SendMessage, "a", "input", &counts()).unwrap();
+        let path = dir.path().join(AUDIT_LOG_FILE);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"not json\n").unwrap();
+        drop(file);
+
+        let entries = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(!log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn test_repair_keeps_valid_prefix_and_backs_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PiiAuditLog::open(dir.path()).unwrap();
+        for id in ["a", "b", "c"] {
+            log.record(AuditOperation::AddToKnowledgeBase, id, "input", &counts()).unwrap();
+        }
+        let path = dir.path().join(AUDIT_LOG_FILE);
+        let tampered = std::fs::read_to_string(&path).unwrap().replacen("\"b\"", "\"x\"", 1);
+        std::fs::write(&path, tampered).unwrap();
+
+        let repaired = log.repair().unwrap();
+        assert!(repaired.valid);
+        assert_eq!(repaired.entries, 1);
+        log.record(AuditOperation::SendMessage, "d", "input", &counts()).unwrap();
+        assert_eq!(log.verify().unwrap().entries, 2);
+
+        let backups = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().contains("damaged"))
+            .count();
+        assert_eq!(backups, 1);
+    }
+}
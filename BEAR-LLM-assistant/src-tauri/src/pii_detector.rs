@@ -1,7 +1,14 @@
 use regex::Regex;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
-use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::citations;
+use crate::text_normalizer;
 
 lazy_static! {
     static ref SSN_REGEX: Regex = Regex::new(r"\b\d{3}-\d{2}-\d{4}\b|\b\d{9}\b").unwrap();
@@ -15,94 +22,1244 @@ lazy_static! {
     static ref BANK_ACCOUNT_REGEX: Regex = Regex::new(r"\b\d{8,17}\b").unwrap();
     static ref ADDRESS_REGEX: Regex = Regex::new(r"\b\d+\s+[\w\s]+(?:Street|St|Avenue|Ave|Road|Rd|Boulevard|Blvd|Lane|Ln|Drive|Dr|Court|Ct|Circle|Cir|Plaza|Pl|Way|Parkway|Pkwy)\b").unwrap();
     static ref CASE_NUMBER_REGEX: Regex = Regex::new(r"\b(?:Case|Docket|Matter)\s*(?:No\.?|Number|#)?\s*:?\s*[A-Z0-9\-]+\b").unwrap();
+    // Paper format groups of four ("DE89 3704 0044 0532 0130 00") or one unbroken run
+    static ref IBAN_REGEX: Regex = Regex::new(r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,3})?\b").unwrap();
+    // Only labelled codes: an unlabelled 8-letter capitalized word ("PROPERTY") has the same shape
+    static ref SWIFT_REGEX: Regex = Regex::new(
+        r"\b(?:SWIFT|BIC)(?:\s*/\s*(?:SWIFT|BIC))?(?:\s+[Cc]ode)?\s*[:#]?\s*([A-Z]{4}[A-Z]{2}[A-Z0-9]{2}(?:[A-Z0-9]{3})?)\b"
+    ).unwrap();
+    // UK National Insurance number: two prefix letters (D, F, I, Q, U, V never used), six digits, suffix A-D
+    static ref NINO_REGEX: Regex = Regex::new(
+        r"\b[A-CEGHJ-PR-TW-Z][A-CEGHJ-NPR-TW-Z] ?\d{2} ?\d{2} ?\d{2} ?[A-D]\b"
+    ).unwrap();
+    static ref BSN_REGEX: Regex = Regex::new(r"\b\d{9}\b").unwrap();
+    // +44 20 7946 0958, 0044 20..., +33 1 23 45 67 89, +31 (0)6 12345678
+    static ref INTERNATIONAL_PHONE_REGEX: Regex = Regex::new(
+        r"(?:\+|\b00)[1-9]\d{0,2}[\s.-]?(?:\(0\)[\s.-]?)?\d{1,4}(?:[\s.-]?\d{2,4}){2,4}\b"
+    ).unwrap();
+    // 020 7946 0958, 07700 900123, (01632) 960001
+    static ref UK_PHONE_REGEX: Regex = Regex::new(
+        r"(?:\b|\()0\d{2,4}\)?[\s-]?\d{3,4}[\s-]?\d{3,4}\b"
+    ).unwrap();
+    // National trunk-prefix forms: 06 12345678, 01 23 45 67 89, 030 1234567, 0612345678. Groups
+    // must be separated unless the number is one 10-digit run, so "0123456" and "04711 2023" don't
+    // qualify; is_plausible_trunk_phone then checks the total length.
+    static ref EU_PHONE_REGEX: Regex = Regex::new(
+        r"\b(?:0\d{9}|0\d{1,3}(?:[\s./-]\d{2,8}){1,4})\b"
+    ).unwrap();
+    static ref DMY_DATE_REGEX: Regex = Regex::new(
+        r"\b(?:0[1-9]|[12]\d|3[01])[/\-.](?:0[1-9]|1[0-2])[/\-.](?:19|20)\d{2}\b"
+    ).unwrap();
     static ref EIN_REGEX: Regex = Regex::new(r"\b\d{2}-\d{7}\b").unwrap();
     static ref MEDICAL_RECORD_REGEX: Regex = Regex::new(r"\b(?:MRN|Medical Record Number)\s*:?\s*[A-Z0-9]+\b").unwrap();
+    // "52.3702, 4.8952"; at least three decimals so prices and version pairs don't qualify
+    static ref DECIMAL_COORDINATE_REGEX: Regex = Regex::new(
+        r"(?:^|[^\w.+\-])(?P<coord>(?P<lat>[-+]?\d{1,2}\.\d{3,})\s*,\s*(?P<lon>[-+]?\d{1,3}\.\d{3,}))\b"
+    ).unwrap();
+    // 52°22'12"N 4°53'42"E, longitude optional; NFKC turns ″ into two primes
+    static ref DMS_COORDINATE_REGEX: Regex = Regex::new(
+        r#"(?P<latd>\d{1,2})°\s*(?P<latm>\d{1,2})['′]\s*(?:(?P<lats>\d{1,2}(?:\.\d+)?)(?:"|″|′′|'')\s*)?[NS]\b(?:[,\s]*(?P<lond>\d{1,3})°\s*(?P<lonm>\d{1,2})['′]\s*(?:(?P<lons>\d{1,2}(?:\.\d+)?)(?:"|″|′′|'')\s*)?[EW]\b)?"#
+    ).unwrap();
+    // Numbered tokens only; unnumbered ones can't be mapped back to a single value
+    static ref RESTORE_TOKEN_REGEX: Regex = Regex::new(r"\[[A-Z0-9_]+_REDACTED_\d+\]").unwrap();
+    // Any two capitalized words, optionally with a middle initial
+    static ref NAME_PAIR_REGEX: Regex = Regex::new(
+        &format!(r"\b{word}\s+(?:\p{{Lu}}\.?\s+)?{word}\b", word = NAME_WORD)
+    ).unwrap();
+    // Candidate full names for the gazetteer: given name, optional initial, optional surname particles, surname
+    static ref GAZETTEER_CANDIDATE_REGEX: Regex = Regex::new(&format!(
+        r"\b{word}(?:\s+\p{{Lu}}\.?)?(?:\s+(?:van|von|de|der|den|da|di|du|le|la|ter|ten))*\s+{word}\b",
+        word = NAME_WORD
+    )).unwrap();
+}
+
+// One capitalized word in any cased script: "José", "Łukasz", "Дмитрий", "Jean-Pierre",
+// "O'Brien". The capital must be followed by lowercase, so all-caps terms ("AGREEMENT", "LLC")
+// never count as names.
+const NAME_WORD: &str = r"\p{Lu}(?:['’]\p{Lu})?[\p{Ll}\p{M}]+(?:-\p{Lu}?[\p{Ll}\p{M}]+)*";
+
+// Streaming redaction reads this much at a time and redacts roughly one window per pass
+const STREAM_READ_BYTES: usize = 64 * 1024;
+const STREAM_WINDOW_BYTES: usize = 1024 * 1024;
+// Longest match a stream is guaranteed to catch whole: the last this many bytes of a window are
+// carried into the next one instead of being redacted, so only longer matches could be split
+const STREAM_OVERLAP_BYTES: usize = 4096;
+
+const NAME_TITLES: &[&str] = &[
+    "Mr.", "Mrs.", "Ms.", "Mr", "Mrs", "Ms", "Miss", "Dr.", "Dr", "Prof.", "Professor",
+    "Judge", "Justice", "Attorney", "Counsel", "Esq.",
+];
+
+const BUNDLED_FIRST_NAMES: &str = include_str!("../data/names/first_names.txt");
+const BUNDLED_LAST_NAMES: &str = include_str!("../data/names/last_names.txt");
+
+struct PatternRule<'a> {
+    regex: &'a Regex,
+    // Profile type name used by redaction
+    pii_type: String,
+    // Display name reported by detect_pii
+    label: String,
+    // Base confidence for a bare regex hit: specific shapes (email, labelled MRN) score high,
+    // bare digit runs that also match order numbers and timestamps score low
+    confidence: f32,
+    // Capture group holding the value when the pattern also matches a label ("SWIFT: ...")
+    group: usize,
+    // Checksum the value must pass; matches that fail are not PII of this type
+    validate: Option<fn(&str) -> bool>,
+    // Set for country-specific formats
+    locale: Option<Locale>,
+}
+
+impl<'a> PatternRule<'a> {
+    fn matches<'t>(&self, text: &'t str) -> Vec<regex::Match<'t>> {
+        self.regex
+            .captures_iter(text)
+            .filter_map(|caps| caps.get(self.group))
+            .filter_map(|m| self.validated(text, m))
+            .collect()
+    }
+
+    // A value that fails its check may have swallowed the next word ("BE68 5390 0754 7034 EUR"),
+    // so the shorter matches ending at each earlier space are tried before it is dropped
+    fn validated<'t>(&self, text: &'t str, m: regex::Match<'t>) -> Option<regex::Match<'t>> {
+        let validate = match self.validate {
+            Some(validate) => validate,
+            None => return Some(m),
+        };
+        if validate(m.as_str()) {
+            return Some(m);
+        }
+
+        m.as_str().rmatch_indices(' ').find_map(|(i, _)| {
+            let caps = self.regex.captures_at(&text[..m.start() + i], m.start())?;
+            caps.get(self.group)
+                .filter(|shorter| shorter.start() == m.start() && validate(shorter.as_str()))
+        })
+    }
+
+    fn checksummed(&self) -> bool {
+        self.validate.is_some()
+    }
+}
+
+// Precedence order: when spans overlap, the rule listed first wins and later matches touching
+// it are dropped, so a long digit run is never redacted twice or inside another token.
+//   1. Labelled identifiers (MRN, case number), whose digits would otherwise match bare patterns
+//   2. Email
+//   3. Fixed-shape numbers: IBAN (mod-97 checked), SWIFT/BIC, the locale's national ID
+//      (US SSN, UK NINO, Dutch BSN), credit card (Luhn-checked), EIN (US)
+//   4. Dates of birth (MM/DD for US, DD/MM elsewhere), phone numbers, IP addresses
+//   5. Passport and driver licence (US) numbers
+//   6. Street addresses
+//   7. Bank account, the catch-all for any 8-17 digit run
+// User-registered custom patterns go before all of these (see PIIDetector::rules).
+fn pattern_rules(locale: Locale) -> Vec<PatternRule<'static>> {
+    let rule = |regex: &'static Regex, pii_type: &str, label: &str, confidence| PatternRule {
+        regex,
+        pii_type: pii_type.to_string(),
+        label: label.to_string(),
+        confidence,
+        group: 0,
+        validate: None,
+        locale: None,
+    };
+    let local = |rule: PatternRule<'static>| PatternRule { locale: Some(locale), ..rule };
+
+    let mut rules = vec![
+        rule(&MEDICAL_RECORD_REGEX, "MEDICAL_RECORD", "Medical Record", 0.85),
+        rule(&CASE_NUMBER_REGEX, "CASE_NUMBER", "Case Number", 0.8),
+        rule(&EMAIL_REGEX, "EMAIL", "Email", 0.95),
+        PatternRule { validate: Some(is_valid_iban), ..rule(&IBAN_REGEX, "IBAN", "IBAN", 0.97) },
+        PatternRule { group: 1, ..rule(&SWIFT_REGEX, "SWIFT", "SWIFT", 0.85) },
+    ];
+
+    match locale {
+        Locale::Us => rules.push(local(rule(&SSN_REGEX, "SSN", "SSN", 0.6))),
+        Locale::Uk => rules.push(local(PatternRule {
+            validate: Some(is_valid_nino),
+            ..rule(&NINO_REGEX, "NATIONAL_ID", "National Insurance Number", 0.9)
+        })),
+        Locale::Eu => rules.push(local(PatternRule {
+            validate: Some(is_valid_bsn),
+            ..rule(&BSN_REGEX, "NATIONAL_ID", "BSN", 0.9)
+        })),
+    }
+
+    // Order numbers and tracking IDs share the 16-digit shape; real card numbers pass Luhn
+    rules.push(PatternRule { validate: Some(is_valid_luhn), ..rule(&CREDIT_CARD_REGEX, "CREDIT_CARD", "Credit Card", 0.97) });
+
+    match locale {
+        Locale::Us => {
+            rules.push(local(rule(&EIN_REGEX, "EIN", "EIN", 0.5)));
+            rules.push(local(rule(&DATE_OF_BIRTH_REGEX, "DOB", "Date of Birth", 0.6)));
+            rules.push(local(rule(&PHONE_REGEX, "PHONE", "Phone", 0.6)));
+        }
+        Locale::Uk => {
+            rules.push(local(rule(&DMY_DATE_REGEX, "DOB", "Date of Birth", 0.6)));
+            rules.push(rule(&INTERNATIONAL_PHONE_REGEX, "PHONE", "Phone", 0.7));
+            rules.push(local(rule(&UK_PHONE_REGEX, "PHONE", "Phone", 0.6)));
+        }
+        Locale::Eu => {
+            rules.push(local(rule(&DMY_DATE_REGEX, "DOB", "Date of Birth", 0.6)));
+            rules.push(rule(&INTERNATIONAL_PHONE_REGEX, "PHONE", "Phone", 0.7));
+            rules.push(local(PatternRule {
+                validate: Some(is_plausible_trunk_phone),
+                ..rule(&EU_PHONE_REGEX, "PHONE", "Phone", 0.5)
+            }));
+        }
+    }
+
+    rules.push(rule(&IP_REGEX, "IP_ADDRESS", "IP Address", 0.7));
+    rules.push(rule(&PASSPORT_REGEX, "PASSPORT", "Passport", 0.4));
+    if locale == Locale::Us {
+        rules.push(local(rule(&DRIVER_LICENSE_REGEX, "DRIVER_LICENSE", "Driver License", 0.35)));
+    }
+    rules.push(rule(&ADDRESS_REGEX, "ADDRESS", "Address", 0.6));
+    rules.push(rule(&BANK_ACCOUNT_REGEX, "BANK_ACCOUNT", "Bank Account", 0.3));
+    rules
+}
+
+// 10/8, 172.16/12, 192.168/16, 127/8 and 169.254/16; anything that doesn't parse as an address
+// (e.g. "300.1.2.3") is not internal
+fn is_internal_ipv4(value: &str) -> bool {
+    value
+        .parse::<Ipv4Addr>()
+        .is_ok_and(|ip| ip.is_private() || ip.is_loopback() || ip.is_link_local())
+}
+
+// Byte spans of plausible lat/long coordinates; pairs outside lat -90..90 / lon -180..180 are ignored
+fn find_coordinates(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+
+    for caps in DECIMAL_COORDINATE_REGEX.captures_iter(text) {
+        let lat: f64 = caps["lat"].parse().unwrap_or(f64::MAX);
+        let lon: f64 = caps["lon"].parse().unwrap_or(f64::MAX);
+        if lat.abs() <= 90.0 && lon.abs() <= 180.0 {
+            let coord = caps.name("coord").unwrap();
+            spans.push((coord.start(), coord.end()));
+        }
+    }
+
+    let valid_dms = |degrees: Option<regex::Match>, minutes: Option<regex::Match>, seconds: Option<regex::Match>, max: f64| {
+        let value = |m: Option<regex::Match>| m.map_or(0.0, |m| m.as_str().parse::<f64>().unwrap_or(f64::MAX));
+        value(degrees) <= max && value(minutes) < 60.0 && value(seconds) < 60.0
+    };
+    for caps in DMS_COORDINATE_REGEX.captures_iter(text) {
+        let lat_ok = valid_dms(caps.name("latd"), caps.name("latm"), caps.name("lats"), 90.0);
+        let lon_ok = caps.name("lond").is_none()
+            || valid_dms(caps.name("lond"), caps.name("lonm"), caps.name("lons"), 180.0);
+        if lat_ok && lon_ok {
+            let whole = caps.get(0).unwrap();
+            spans.push((whole.start(), whole.end()));
+        }
+    }
+
+    spans
+}
+
+// Which country's phone, date and national ID formats the built-in patterns look for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    #[default]
+    Us,
+    Uk,
+    // Continental Europe: international and trunk-prefix phones, DD/MM dates, Dutch BSN
+    Eu,
+}
+
+// How person names are found outside the title-prefix rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameDetection {
+    // Any two capitalized words; catches most names but also "Annual Report"
+    #[default]
+    Pattern,
+    // Only given name + surname pairs found in the name dictionaries
+    Gazetteer,
+}
+
+// First- and last-name dictionaries: the bundled census lists plus user additions, lowercased
+#[derive(Debug, Clone)]
+pub struct Gazetteer {
+    first_names: HashSet<String>,
+    last_names: HashSet<String>,
+}
+
+impl Gazetteer {
+    pub fn new(extra_first: &BTreeSet<String>, extra_last: &BTreeSet<String>) -> Self {
+        let parse = |list: &str, extra: &BTreeSet<String>| -> HashSet<String> {
+            list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .chain(extra.iter().map(String::as_str))
+                .map(str::to_lowercase)
+                .collect()
+        };
+
+        Self {
+            first_names: parse(BUNDLED_FIRST_NAMES, extra_first),
+            last_names: parse(BUNDLED_LAST_NAMES, extra_last),
+        }
+    }
+
+    // "John Smith", "Mary J. Williams", "Jan de Jong"
+    fn is_full_name(&self, candidate: &str) -> bool {
+        let words: Vec<&str> = candidate.split_whitespace().collect();
+        let (first, rest) = match words.split_first() {
+            Some(split) => split,
+            None => return false,
+        };
+        if !self.first_names.contains(&first.to_lowercase()) {
+            return false;
+        }
+
+        // Skip a middle initial; the remainder (particles included) is the surname
+        let surname_words: Vec<&str> = rest.iter()
+            .copied()
+            .skip_while(|w| w.trim_end_matches('.').chars().count() == 1)
+            .collect();
+        let surname = surname_words.join(" ").to_lowercase();
+        let last_word = surname_words.last().map(|w| w.to_lowercase()).unwrap_or_default();
+
+        self.last_names.contains(&surname) || self.last_names.contains(&last_word)
+    }
+}
+
+const DEFAULT_ALLOWED_TERMS: &[&str] = &[
+    "United States", "New York", "Los Angeles", "Supreme Court",
+    "District Court", "Circuit Court", "Court of Appeals",
+    "Federal Government", "State Government", "Local Government",
+    // Capitalized terms of art the two-word name pattern would otherwise take for people
+    "Force Majeure", "Due Process", "Good Faith", "Bona Fide", "Prima Facie",
+    "Habeas Corpus", "Attorney General", "Common Law", "Equal Protection",
+];
+
+// Terms that must never be redacted (firm name, published case names, public officials)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Allowlist {
+    #[serde(default)]
+    exact: BTreeSet<String>,
+    // Stored lowercased
+    #[serde(default)]
+    case_insensitive: BTreeSet<String>,
+    // Default terms the user removed, lowercased, so merging in new defaults leaves them out
+    #[serde(default)]
+    removed_defaults: BTreeSet<String>,
+}
+
+impl Default for Allowlist {
+    fn default() -> Self {
+        let mut allowlist = Self {
+            exact: BTreeSet::new(),
+            case_insensitive: BTreeSet::new(),
+            removed_defaults: BTreeSet::new(),
+        };
+        allowlist.merge_defaults();
+        allowlist
+    }
+}
+
+impl Allowlist {
+    pub fn add(&mut self, term: &str, case_sensitive: bool) {
+        let term = term.trim();
+        if term.is_empty() {
+            return;
+        }
+
+        self.removed_defaults.remove(&term.to_lowercase());
+        if case_sensitive {
+            self.exact.insert(term.to_string());
+        } else {
+            self.case_insensitive.insert(term.to_lowercase());
+        }
+    }
+
+    pub fn remove(&mut self, term: &str) -> bool {
+        let term = term.trim();
+        let removed_exact = self.exact.remove(term);
+        let removed_ci = self.case_insensitive.remove(&term.to_lowercase());
+        if removed_ci && DEFAULT_ALLOWED_TERMS.iter().any(|t| t.eq_ignore_ascii_case(term)) {
+            self.removed_defaults.insert(term.to_lowercase());
+        }
+        removed_exact || removed_ci
+    }
+
+    // Adds every default term the user has not removed
+    fn merge_defaults(&mut self) {
+        for term in DEFAULT_ALLOWED_TERMS {
+            if !self.removed_defaults.contains(&term.to_lowercase()) {
+                self.add(term, false);
+            }
+        }
+    }
+
+    pub fn is_allowed(&self, text: &str) -> bool {
+        let text = text.trim();
+        self.exact.contains(text) || self.case_insensitive.contains(&text.to_lowercase())
+    }
+
+    pub fn terms(&self) -> Vec<AllowedTerm> {
+        self.exact
+            .iter()
+            .map(|t| AllowedTerm { term: t.clone(), case_sensitive: true })
+            .chain(self.case_insensitive.iter().map(|t| AllowedTerm { term: t.clone(), case_sensitive: false }))
+            .collect()
+    }
+
+    // Plain-text list: one term per line, `#` comments, `=` prefix for case-sensitive terms
+    pub fn import_text(&mut self, contents: &str) -> usize {
+        let mut added = 0;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('=') {
+                Some(term) => self.add(term, true),
+                None => self.add(line, false),
+            }
+            added += 1;
+        }
+        added
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllowedTerm {
+    pub term: String,
+    pub case_sensitive: bool,
+}
+
+pub const PROFILE_STORAGE: &str = "storage";
+pub const PROFILE_LLM: &str = "llm";
+pub const PROFILE_EXPORT: &str = "export";
+
+// Every type a profile can toggle; NAME and ORG are the heuristic detectors
+pub const ALL_PII_TYPES: &[&str] = &[
+    "SSN", "EMAIL", "PHONE", "CREDIT_CARD", "IP_ADDRESS", "DOB", "PASSPORT",
+    "DRIVER_LICENSE", "BANK_ACCOUNT", "IBAN", "SWIFT", "NATIONAL_ID", "ADDRESS", "CASE_NUMBER", "EIN",
+    "MEDICAL_RECORD", "LOCATION", "NAME", "ORG",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionStyle {
+    Token,   // [SSN_REDACTED_3]
+    Generic, // [REDACTED]
+    Mask,    // ***********
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionProfile {
+    pub enabled_types: BTreeSet<String>,
+    pub style: RedactionStyle,
+}
+
+impl RedactionProfile {
+    pub fn all(style: RedactionStyle) -> Self {
+        Self::with_types(ALL_PII_TYPES, style)
+    }
+
+    pub fn with_types(types: &[&str], style: RedactionStyle) -> Self {
+        Self {
+            enabled_types: types.iter().map(|t| t.to_string()).collect(),
+            style,
+        }
+    }
+
+    pub fn enables(&self, pii_type: &str) -> bool {
+        self.enabled_types.contains(pii_type)
+    }
+}
+
+// storage: light redaction of hard identifiers so internal search still finds names;
+// llm: everything, since this text leaves the document store; export: everything, unlabeled
+fn default_profiles() -> BTreeMap<String, RedactionProfile> {
+    BTreeMap::from([
+        (PROFILE_STORAGE.to_string(), RedactionProfile::with_types(
+            &["SSN", "NATIONAL_ID", "CREDIT_CARD", "BANK_ACCOUNT", "IBAN", "PASSPORT", "DRIVER_LICENSE", "MEDICAL_RECORD", "EIN"],
+            RedactionStyle::Token,
+        )),
+        (PROFILE_LLM.to_string(), RedactionProfile::all(RedactionStyle::Token)),
+        (PROFILE_EXPORT.to_string(), RedactionProfile::all(RedactionStyle::Generic)),
+    ])
+}
+
+// Persisted, user-editable redaction settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PiiConfig {
+    #[serde(default)]
+    pub allowlist: Allowlist,
+    #[serde(default = "default_profiles")]
+    pub profiles: BTreeMap<String, RedactionProfile>,
+    #[serde(default)]
+    pub name_detection: NameDetection,
+    // Local names missing from the bundled lists
+    #[serde(default)]
+    pub extra_first_names: BTreeSet<String>,
+    #[serde(default)]
+    pub extra_last_names: BTreeSet<String>,
+    // Types without an entry are labelled "<TYPE> 1", "<TYPE> 2"
+    #[serde(default = "default_pseudonym_labels")]
+    pub pseudonym_labels: BTreeMap<String, PseudonymLabel>,
+    // `CUSTOM_<NAME>` -> regex source
+    #[serde(default)]
+    pub custom_patterns: BTreeMap<String, String>,
+    #[serde(default)]
+    pub locale: Locale,
+    // Private (RFC 1918), loopback and link-local addresses identify machines on someone's own
+    // network, not people, so they are left alone unless this is set
+    #[serde(default)]
+    pub redact_private_ips: bool,
+}
+
+impl Default for PiiConfig {
+    fn default() -> Self {
+        Self {
+            allowlist: Allowlist::default(),
+            profiles: default_profiles(),
+            name_detection: NameDetection::default(),
+            extra_first_names: BTreeSet::new(),
+            extra_last_names: BTreeSet::new(),
+            pseudonym_labels: default_pseudonym_labels(),
+            custom_patterns: BTreeMap::new(),
+            locale: Locale::default(),
+            redact_private_ips: false,
+        }
+    }
+}
+
+impl PiiConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut config: Self = crate::data_dir::load_json(path)?;
+
+        // Built-in profiles must always exist, even in configs saved before they were added
+        for (name, profile) in default_profiles() {
+            config.profiles.entry(name).or_insert(profile);
+        }
+        // A saved allowlist replaces the default one, so terms of art added to the defaults since
+        // it was saved are merged in, except those the user removed
+        config.allowlist.merge_defaults();
+
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::data_dir::save_json(path, self)
+    }
+}
+
+// Redacted text plus how many replacements were made per PII type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Redaction {
+    pub text: String,
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl Redaction {
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+// What a redaction removed, for showing the user before text goes to the model
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PiiReport {
+    pub counts: BTreeMap<String, usize>,
+    pub total: usize,
+}
+
+impl From<&Redaction> for PiiReport {
+    fn from(redaction: &Redaction) -> Self {
+        Self {
+            counts: redaction.counts.clone(),
+            total: redaction.total(),
+        }
+    }
+}
+
+// How a redaction pass labels its replacements, beyond the profile's style
+enum Tracking {
+    Off,
+    // Every replacement is a numbered token, so a map can tell them apart
+    Reversible,
+    Pseudonyms(Pseudonymizer),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PseudonymNumbering {
+    // A, B, ... Z, AA, AB
+    Letters,
+    // 1, 2, 3
+    Numbers,
+}
+
+// "Person" + Letters gives "Person A", "Person B"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PseudonymLabel {
+    pub prefix: String,
+    pub numbering: PseudonymNumbering,
+}
+
+impl PseudonymLabel {
+    fn label(&self, index: usize) -> String {
+        let suffix = match self.numbering {
+            PseudonymNumbering::Numbers => (index + 1).to_string(),
+            PseudonymNumbering::Letters => {
+                let mut letters = Vec::new();
+                let mut n = index + 1;
+                while n > 0 {
+                    n -= 1;
+                    letters.push((b'A' + (n % 26) as u8) as char);
+                    n /= 26;
+                }
+                letters.iter().rev().collect()
+            }
+        };
+        format!("{} {}", self.prefix, suffix)
+    }
 }
 
-pub struct PIIDetector {
-    custom_patterns: HashMap<String, Regex>,
-    replacement_map: HashMap<String, String>,
-    entity_counter: std::sync::atomic::AtomicUsize,
-}
+fn default_pseudonym_labels() -> BTreeMap<String, PseudonymLabel> {
+    BTreeMap::from([
+        ("NAME".to_string(), PseudonymLabel { prefix: "Person".to_string(), numbering: PseudonymNumbering::Letters }),
+        ("ORG".to_string(), PseudonymLabel { prefix: "Org".to_string(), numbering: PseudonymNumbering::Numbers }),
+    ])
+}
+
+// One label per distinct value and type for the duration of a single call
+struct Pseudonymizer {
+    schemes: BTreeMap<String, PseudonymLabel>,
+    labels: HashMap<(String, String), String>,
+    next_index: HashMap<String, usize>,
+}
+
+impl Pseudonymizer {
+    fn new(schemes: BTreeMap<String, PseudonymLabel>) -> Self {
+        Self {
+            schemes,
+            labels: HashMap::new(),
+            next_index: HashMap::new(),
+        }
+    }
+
+    fn label_for(&mut self, pii_type: &str, original: &str) -> String {
+        // Case, spacing and a title ("Mr. John Smith" vs "John Smith") don't make a different person
+        let mut words: Vec<&str> = original.split_whitespace().collect();
+        if pii_type == "NAME" && words.len() > 1 && NAME_TITLES.contains(&words[0]) {
+            words.remove(0);
+        }
+        let key = (pii_type.to_string(), words.join(" ").to_lowercase());
+        if let Some(label) = self.labels.get(&key) {
+            return label.clone();
+        }
+
+        let index = self.next_index.entry(pii_type.to_string()).or_insert(0);
+        let label = match self.schemes.get(pii_type) {
+            Some(scheme) => scheme.label(*index),
+            None => PseudonymLabel {
+                prefix: pii_type.to_string(),
+                numbering: PseudonymNumbering::Numbers,
+            }.label(*index),
+        };
+        *index += 1;
+
+        self.labels.insert(key, label.clone());
+        label
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PiiMapEntry {
+    pub pii_type: String,
+    pub original: String,
+    // Byte span in the text that was redacted
+    pub start: usize,
+    pub end: usize,
+}
+
+// Maps each `[TYPE_REDACTED_n]` token in a reversibly redacted text back to what it replaced
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PiiMap {
+    pub entries: BTreeMap<String, PiiMapEntry>,
+}
+
+impl PiiMap {
+    // `replacements` are the original-text spans of a reversible redaction; tokens a later pass
+    // swallowed (e.g. into an organization) are no longer among them
+    fn locate(original: &str, replacements: &[Replacement]) -> Self {
+        let entries = replacements
+            .iter()
+            .map(|r| (r.text.clone(), PiiMapEntry {
+                pii_type: r.pii_type.clone(),
+                original: original[r.start..r.end].to_string(),
+                start: r.start,
+                end: r.end,
+            }))
+            .collect();
+
+        Self { entries }
+    }
+
+    // Every occurrence is restored, so tokens the model repeats or reorders all come back
+    pub fn restore(&self, text: &str) -> String {
+        RESTORE_TOKEN_REGEX.replace_all(text, |caps: &regex::Captures| {
+            let token = caps.get(0).unwrap().as_str();
+            match self.entries.get(token) {
+                Some(entry) => entry.original.clone(),
+                None => token.to_string(),
+            }
+        }).to_string()
+    }
+
+    fn longest_token(&self) -> usize {
+        self.entries.keys().map(String::len).max().unwrap_or(0)
+    }
+}
+
+// Restores text that arrives in pieces (streamed tokens, chunks), holding back a trailing
+// partial token until the rest of it arrives
+pub struct PiiRestorer {
+    map: PiiMap,
+    pending: String,
+}
+
+impl PiiRestorer {
+    pub fn new(map: PiiMap) -> Self {
+        Self { map, pending: String::new() }
+    }
+
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let split = match self.pending.rfind('[') {
+            Some(open) if !self.pending[open..].contains(']')
+                && self.pending.len() - open < self.map.longest_token() => open,
+            _ => self.pending.len(),
+        };
+        let ready: String = self.pending.drain(..split).collect();
+        self.map.restore(&ready)
+    }
+
+    // Whatever is still held back, e.g. a `[` that never became a token
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.map.restore(&rest)
+    }
+}
+
+// A byte span of the text it was found in and what replaces it
+#[derive(Debug, Clone)]
+struct Replacement {
+    start: usize,
+    end: usize,
+    pii_type: String,
+    text: String,
+}
+
+impl Replacement {
+    fn new(start: usize, end: usize, pii_type: &str, text: String) -> Self {
+        Self { start, end, pii_type: pii_type.to_string(), text }
+    }
+}
+
+fn count_types(replacements: &[Replacement]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for r in replacements {
+        *counts.entry(r.pii_type.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Replacements made so far, as spans of the normalized text. Name and organization passes scan
+// the text produced by the earlier passes; their spans are mapped back here, so at the end every
+// replacement can be carried over to the original text.
+struct Edits<'a> {
+    source: &'a str,
+    // Sorted and non-overlapping
+    edits: Vec<Replacement>,
+    current: String,
+}
+
+impl<'a> Edits<'a> {
+    fn new(source: &'a str) -> Self {
+        Self { source, edits: Vec::new(), current: source.to_string() }
+    }
+
+    fn current(&self) -> &str {
+        &self.current
+    }
+
+    // `replacements` are sorted, non-overlapping spans of the current text. One covering an
+    // earlier replacement (an organization name around a redacted name) supersedes it.
+    fn apply(&mut self, replacements: Vec<Replacement>) {
+        if replacements.is_empty() {
+            return;
+        }
+        let mapped: Vec<Replacement> = replacements
+            .into_iter()
+            .map(|r| Replacement { start: self.source_pos(r.start, false), end: self.source_pos(r.end, true), ..r })
+            .collect();
+        for r in mapped {
+            self.edits.retain(|e| e.end <= r.start || e.start >= r.end);
+            self.edits.push(r);
+        }
+        self.edits.sort_by_key(|e| e.start);
+        self.current = splice(self.source, &self.edits);
+    }
+
+    // Where a position of the current text was in the source; one inside a replacement widens to
+    // the whole span that replacement stands for
+    fn source_pos(&self, pos: usize, is_end: bool) -> usize {
+        let mut shift: isize = 0;
+        for edit in &self.edits {
+            let current_start = (edit.start as isize + shift) as usize;
+            if pos <= current_start {
+                break;
+            }
+            if pos < current_start + edit.text.len() {
+                return if is_end { edit.end } else { edit.start };
+            }
+            shift += edit.text.len() as isize - (edit.end - edit.start) as isize;
+        }
+        (pos as isize - shift) as usize
+    }
+
+    fn into_original(self, original: &str, normalized: &text_normalizer::NormalizedText) -> (String, Vec<Replacement>) {
+        let mut mapped: Vec<Replacement> = Vec::with_capacity(self.edits.len());
+        for edit in self.edits {
+            let (start, end) = normalized.original_range(edit.start, edit.end);
+            match mapped.last_mut() {
+                // Both ends of a character NFKC expanded ("ﬁ" -> "fi") map to all of it
+                Some(last) if start < last.end => last.end = last.end.max(end),
+                _ => mapped.push(Replacement { start, end, ..edit }),
+            }
+        }
+        (splice(original, &mapped), mapped)
+    }
+}
+
+fn splice(source: &str, replacements: &[Replacement]) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut pos = 0;
+    for r in replacements {
+        out.push_str(&source[pos..r.start]);
+        out.push_str(&r.text);
+        pos = r.end;
+    }
+    out.push_str(&source[pos..]);
+    out
+}
+
+pub struct PIIDetector {
+    custom_patterns: HashMap<String, Regex>,
+    entity_counter: std::sync::atomic::AtomicUsize,
+    config: PiiConfig,
+    gazetteer: Gazetteer,
+}
+
+// A snapshot, so long jobs can redact without holding the shared detector's lock
+impl Clone for PIIDetector {
+    fn clone(&self) -> Self {
+        Self {
+            custom_patterns: self.custom_patterns.clone(),
+            entity_counter: std::sync::atomic::AtomicUsize::new(
+                self.entity_counter.load(std::sync::atomic::Ordering::SeqCst),
+            ),
+            config: self.config.clone(),
+            gazetteer: self.gazetteer.clone(),
+        }
+    }
+}
+
+impl Default for PIIDetector {
+    fn default() -> Self {
+        Self::with_config(PiiConfig::default())
+    }
+}
+
+impl PIIDetector {
+    pub fn with_config(config: PiiConfig) -> Self {
+        let mut custom_patterns = HashMap::new();
+        for (pii_type, pattern) in &config.custom_patterns {
+            match compile_custom_pattern(pattern) {
+                Ok(regex) => {
+                    custom_patterns.insert(pii_type.clone(), regex);
+                }
+                Err(e) => eprintln!("Skipping invalid custom PII pattern {}: {}", pii_type, e),
+            }
+        }
+
+        Self {
+            custom_patterns,
+            entity_counter: std::sync::atomic::AtomicUsize::new(0),
+            gazetteer: Gazetteer::new(&config.extra_first_names, &config.extra_last_names),
+            config,
+        }
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.config.locale = locale;
+    }
+
+    pub fn set_name_detection(&mut self, mode: NameDetection) {
+        self.config.name_detection = mode;
+    }
+
+    pub fn set_redact_private_ips(&mut self, redact: bool) {
+        self.config.redact_private_ips = redact;
+    }
+
+    // Pattern matches that are PII-shaped but configured as not worth redacting
+    fn is_exempt(&self, pii_type: &str, value: &str) -> bool {
+        pii_type == "IP_ADDRESS" && !self.config.redact_private_ips && is_internal_ipv4(value)
+    }
+
+    // Returns how many names were new
+    pub fn add_gazetteer_names(&mut self, first_names: &[String], last_names: &[String]) -> usize {
+        let mut added = 0;
+        for (names, target) in [
+            (first_names, &mut self.config.extra_first_names),
+            (last_names, &mut self.config.extra_last_names),
+        ] {
+            for name in names {
+                let name = name.trim();
+                if !name.is_empty() && target.insert(name.to_string()) {
+                    added += 1;
+                }
+            }
+        }
+
+        self.gazetteer = Gazetteer::new(&self.config.extra_first_names, &self.config.extra_last_names);
+        added
+    }
+
+    pub fn config(&self) -> &PiiConfig {
+        &self.config
+    }
+
+    // Case-insensitive; checked by every detector, names and organizations included
+    pub fn add_allowed_term(&mut self, term: String) {
+        self.config.allowlist.add(&term, false);
+    }
+
+    pub fn allowlist_mut(&mut self) -> &mut Allowlist {
+        &mut self.config.allowlist
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&RedactionProfile> {
+        self.config.profiles
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown redaction profile: {}", name))
+    }
+
+    pub fn set_profile(&mut self, name: String, profile: RedactionProfile) -> Result<()> {
+        if let Some(unknown) = profile.enabled_types.iter().find(|t| !ALL_PII_TYPES.contains(&t.as_str())) {
+            return Err(anyhow!("Unknown PII type in profile: {}", unknown));
+        }
+        self.config.profiles.insert(name, profile);
+        Ok(())
+    }
+
+    pub async fn remove_pii(&self, text: &str) -> Result<String> {
+        Ok(self.redact(text, &RedactionProfile::all(RedactionStyle::Token)).await?.text)
+    }
+
+    // Counts come from the same pass that produced the text, so they always match what was removed
+    pub async fn remove_pii_with_report(&self, text: &str) -> Result<(String, PiiReport)> {
+        let redaction = self.redact(text, &RedactionProfile::all(RedactionStyle::Token)).await?;
+        let report = PiiReport::from(&redaction);
+        Ok((redaction.text, report))
+    }
+
+    // Like remove_pii, but every replacement (names included) gets a unique numbered token
+    // and the returned map can put the originals back into text derived from the cleaned one
+    pub async fn remove_pii_reversible(&self, text: &str) -> Result<(String, PiiMap)> {
+        let (redaction, map) = self.redact_reversible(text, &RedactionProfile::all(RedactionStyle::Token)).await?;
+        Ok((redaction.text, map))
+    }
+
+    // The profile picks the types; every replacement is still a numbered token
+    pub async fn redact_reversible_with_profile(&self, text: &str, profile_name: &str) -> Result<(Redaction, PiiMap)> {
+        let profile = self.profile(profile_name)?;
+        self.redact_reversible(text, profile).await
+    }
+
+    async fn redact_reversible(&self, text: &str, profile: &RedactionProfile) -> Result<(Redaction, PiiMap)> {
+        let (redaction, replacements) = self.redact_tracked(text, profile, &mut Tracking::Reversible).await?;
+        Ok((redaction, PiiMap::locate(text, &replacements)))
+    }
+
+    // Replaces each distinct value with a stable label ("Person A", "Org 1") so the model can
+    // still tell that two mentions are the same entity
+    pub async fn remove_pii_pseudonymized(&self, text: &str) -> Result<String> {
+        let mut tracking = Tracking::Pseudonyms(Pseudonymizer::new(self.config.pseudonym_labels.clone()));
+        let (redaction, _) = self.redact_tracked(text, &RedactionProfile::all(RedactionStyle::Token), &mut tracking).await?;
+        Ok(redaction.text)
+    }
+
+    pub fn set_pseudonym_label(&mut self, pii_type: &str, label: PseudonymLabel) -> Result<()> {
+        if !ALL_PII_TYPES.contains(&pii_type) {
+            return Err(anyhow!("Unknown PII type: {}", pii_type));
+        }
+        if label.prefix.trim().is_empty() {
+            return Err(anyhow!("Pseudonym prefix must not be empty"));
+        }
+        self.config.pseudonym_labels.insert(pii_type.to_string(), label);
+        Ok(())
+    }
+
+    pub fn restore_pii(&self, text: &str, map: &PiiMap) -> String {
+        map.restore(text)
+    }
+
+    // Same output as redact_with_profile, written window by window. Memory stays at a few times
+    // STREAM_WINDOW_BYTES (a handful of MB) regardless of input size.
+    pub async fn remove_pii_stream<R, W>(&self, reader: R, writer: W, profile_name: &str) -> Result<PiiReport>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let profile = self.profile(profile_name)?;
+        self.redact_stream(reader, writer, profile).await
+    }
+
+    async fn redact_stream<R, W>(&self, mut reader: R, mut writer: W, profile: &RedactionProfile) -> Result<PiiReport>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        let mut buf = vec![0u8; STREAM_READ_BYTES];
+        let mut undecoded: Vec<u8> = Vec::new();
+        let mut pending = String::new();
+
+        loop {
+            let read = reader.read(&mut buf).await?;
+            let eof = read == 0;
+            undecoded.extend_from_slice(&buf[..read]);
+            decode_utf8(&mut undecoded, &mut pending, eof);
 
-impl PIIDetector {
-    pub fn new() -> Self {
-        Self {
-            custom_patterns: HashMap::new(),
-            replacement_map: HashMap::new(),
-            entity_counter: std::sync::atomic::AtomicUsize::new(0),
+            if !eof && pending.len() < STREAM_WINDOW_BYTES + STREAM_OVERLAP_BYTES {
+                continue;
+            }
+
+            // The whole buffer is redacted once; only what lies before the cut is written, and
+            // the rest is redacted again with the next read
+            let (_, replacements) = self.redact_tracked(&pending, profile, &mut Tracking::Off).await?;
+            let cut = if eof { pending.len() } else { stream_cut(&pending, &replacements) };
+            let kept: Vec<Replacement> = replacements.into_iter().filter(|r| r.end <= cut).collect();
+            writer.write_all(splice(&pending[..cut], &kept).as_bytes()).await?;
+            for r in kept {
+                *counts.entry(r.pii_type).or_insert(0) += 1;
+            }
+            pending.drain(..cut);
+
+            if eof {
+                break;
+            }
         }
+
+        writer.flush().await?;
+        Ok(PiiReport {
+            total: counts.values().sum(),
+            counts,
+        })
     }
 
-    pub async fn remove_pii(&self, text: &str) -> Result<String> {
-        let mut cleaned = text.to_string();
+    pub async fn remove_pii_with_profile(&self, text: &str, profile_name: &str) -> Result<String> {
+        Ok(self.redact_with_profile(text, profile_name).await?.text)
+    }
+
+    pub async fn redact_with_profile(&self, text: &str, profile_name: &str) -> Result<Redaction> {
+        let profile = self.profile(profile_name)?;
+        self.redact(text, profile).await
+    }
+
+    // Detects on the normalized form so fullwidth digits or zero-width splits can't slip past, but
+    // splices the replacements into the original, so everything that isn't PII comes back untouched
+    async fn redact(&self, text: &str, profile: &RedactionProfile) -> Result<Redaction> {
+        Ok(self.redact_tracked(text, profile, &mut Tracking::Off).await?.0)
+    }
+
+    // Reversible tracking makes every replacement a numbered token and records it;
+    // pseudonym tracking replaces with per-value labels instead of the profile's style.
+    // Also returns each replacement as a span of the original text; the counts are taken from
+    // them, so a name a later organization match swallowed counts once, as the organization.
+    async fn redact_tracked(
+        &self,
+        original: &str,
+        profile: &RedactionProfile,
+        tracking: &mut Tracking,
+    ) -> Result<(Redaction, Vec<Replacement>)> {
+        let normalized = text_normalizer::normalize(original);
+        let text = normalized.text.as_str();
         let mut replacements = Vec::new();
 
-        let patterns = vec![
-            (&*SSN_REGEX, "SSN"),
-            (&*EMAIL_REGEX, "EMAIL"),
-            (&*PHONE_REGEX, "PHONE"),
-            (&*CREDIT_CARD_REGEX, "CREDIT_CARD"),
-            (&*IP_REGEX, "IP_ADDRESS"),
-            (&*DATE_OF_BIRTH_REGEX, "DOB"),
-            (&*PASSPORT_REGEX, "PASSPORT"),
-            (&*DRIVER_LICENSE_REGEX, "DRIVER_LICENSE"),
-            (&*BANK_ACCOUNT_REGEX, "BANK_ACCOUNT"),
-            (&*ADDRESS_REGEX, "ADDRESS"),
-            (&*CASE_NUMBER_REGEX, "CASE_NUMBER"),
-            (&*EIN_REGEX, "EIN"),
-            (&*MEDICAL_RECORD_REGEX, "MEDICAL_RECORD"),
-        ];
+        // Citations like "123 F.3d 456" look like IDs to the generic patterns but are not PII. Only
+        // matches lying wholly inside one are dropped: a phone number that merely touches a
+        // citation's pinpoint is still a phone number.
+        let protected = citations::citation_spans(text);
+        let mut taken: Vec<(usize, usize)> = Vec::new();
 
-        for (regex, pii_type) in patterns {
-            for mat in regex.find_iter(text) {
-                let id = self.entity_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                let replacement = format!("[{}_REDACTED_{}]", pii_type, id);
-                replacements.push((mat.start(), mat.end(), replacement));
+        for rule in self.rules() {
+            // Custom patterns are deliberate firm rules, so every profile applies them
+            if !profile.enables(&rule.pii_type) && !self.custom_patterns.contains_key(&rule.pii_type) {
+                continue;
+            }
+            for mat in rule.matches(text) {
+                if self.config.allowlist.is_allowed(mat.as_str()) || within_any(&protected, mat.start(), mat.end()) {
+                    continue;
+                }
+                if self.is_exempt(&rule.pii_type, mat.as_str()) {
+                    continue;
+                }
+                // A more specific pattern already claimed (part of) this span
+                if overlaps_any(&taken, mat.start(), mat.end()) {
+                    continue;
+                }
+                let replacement = self.replacement(&rule.pii_type, mat.as_str(), profile.style, true, tracking);
+                replacements.push(Replacement::new(mat.start(), mat.end(), &rule.pii_type, replacement));
+                taken.push((mat.start(), mat.end()));
+            }
+        }
+
+        if profile.enables("LOCATION") {
+            for (start, end) in find_coordinates(text) {
+                if overlaps_any(&taken, start, end) {
+                    continue;
+                }
+                let replacement = self.replacement("LOCATION", &text[start..end], profile.style, true, tracking);
+                replacements.push(Replacement::new(start, end, "LOCATION", replacement));
             }
         }
 
-        replacements.sort_by_key(|r| r.0);
-        replacements.reverse();
+        replacements.sort_by_key(|r| r.start);
+        let mut edits = Edits::new(text);
+        edits.apply(replacements);
 
-        for (start, end, replacement) in replacements {
-            cleaned.replace_range(start..end, &replacement);
+        if profile.enables("NAME") {
+            self.remove_names(&mut edits, profile.style, tracking);
+        }
+        if profile.enables("ORG") {
+            self.remove_organizations(&mut edits, profile.style, tracking);
         }
 
-        cleaned = self.remove_names(&cleaned).await?;
-        cleaned = self.remove_organizations(&cleaned).await?;
+        let (cleaned, replacements) = edits.into_original(original, &normalized);
+        let counts = count_types(&replacements);
+        Ok((Redaction { text: cleaned, counts }, replacements))
+    }
 
-        Ok(cleaned)
+    fn replacement(
+        &self,
+        pii_type: &str,
+        original: &str,
+        style: RedactionStyle,
+        numbered: bool,
+        tracking: &mut Tracking,
+    ) -> String {
+        match tracking {
+            Tracking::Off => self.placeholder(pii_type, original, style, numbered),
+            Tracking::Reversible => self.placeholder(pii_type, original, RedactionStyle::Token, true),
+            Tracking::Pseudonyms(pseudonyms) => pseudonyms.label_for(pii_type, original),
+        }
     }
 
-    async fn remove_names(&self, text: &str) -> Result<String> {
-        let common_titles = vec![
-            "Mr.", "Mrs.", "Ms.", "Miss", "Dr.", "Prof.", "Professor",
-            "Judge", "Justice", "Attorney", "Counsel", "Esq.",
-        ];
+    fn placeholder(&self, pii_type: &str, original: &str, style: RedactionStyle, numbered: bool) -> String {
+        match style {
+            RedactionStyle::Token if numbered => {
+                let id = self.entity_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                format!("[{}_REDACTED_{}]", pii_type, id)
+            }
+            RedactionStyle::Token => format!("[{}_REDACTED]", pii_type),
+            RedactionStyle::Generic => "[REDACTED]".to_string(),
+            RedactionStyle::Mask => "*".repeat(original.chars().count()),
+        }
+    }
 
-        let mut cleaned = text.to_string();
-        for title in common_titles {
-            let pattern = format!(r"\b{}\s+[A-Z][a-z]+(?:\s+[A-Z][a-z]+)*\b", regex::escape(title));
+    fn remove_names(
+        &self,
+        edits: &mut Edits,
+        style: RedactionStyle,
+        tracking: &mut Tracking,
+    ) {
+        for title in NAME_TITLES {
+            let pattern = format!(r"\b{}\s+{word}(?:\s+{word})*\b", regex::escape(title), word = NAME_WORD);
             if let Ok(regex) = Regex::new(&pattern) {
-                cleaned = regex.replace_all(&cleaned, "[NAME_REDACTED]").to_string();
+                let replacements: Vec<Replacement> = regex
+                    .find_iter(edits.current())
+                    .filter(|mat| {
+                        let name = mat.as_str()[title.len()..].trim();
+                        !self.config.allowlist.is_allowed(mat.as_str()) && !self.config.allowlist.is_allowed(name)
+                    })
+                    .map(|mat| {
+                        let text = self.replacement("NAME", mat.as_str(), style, false, tracking);
+                        Replacement::new(mat.start(), mat.end(), "NAME", text)
+                    })
+                    .collect();
+                edits.apply(replacements);
             }
         }
 
-        let name_pattern = Regex::new(r"\b[A-Z][a-z]+\s+(?:[A-Z]\.?\s+)?[A-Z][a-z]+\b").unwrap();
-        cleaned = name_pattern.replace_all(&cleaned, |caps: &regex::Captures| {
-            let text = caps.get(0).unwrap().as_str();
-            if !self.is_common_phrase(text) {
-                "[NAME_REDACTED]".to_string()
-            } else {
-                text.to_string()
+        let current = edits.current();
+        let replacements: Vec<Replacement> = self.heuristic_name_spans(current)
+            .into_iter()
+            .map(|(start, end, _)| {
+                let text = self.replacement("NAME", &current[start..end], style, false, tracking);
+                Replacement::new(start, end, "NAME", text)
+            })
+            .collect();
+        edits.apply(replacements);
+    }
+
+    // Dictionary or two-capitalized-word names, without the title rule; the flag is set when the
+    // gazetteer knows the name. Candidates are tried from every word rather than only after the
+    // previous match, so a rejected "Dear John" is retried as "John Smith", and accepted pairs that
+    // overlap ("Contact José" + "José Martínez") merge instead of leaving the surname behind.
+    // Candidates touching an allowlisted phrase are dropped, so "The Force" can't cut into
+    // "Force Majeure".
+    fn heuristic_name_spans(&self, text: &str) -> Vec<(usize, usize, bool)> {
+        let regex = match self.config.name_detection {
+            NameDetection::Gazetteer => &*GAZETTEER_CANDIDATE_REGEX,
+            NameDetection::Pattern => &*NAME_PAIR_REGEX,
+        };
+
+        let mut allowed: Vec<(usize, usize)> = Vec::new();
+        let mut accepted: Vec<(usize, usize, bool)> = Vec::new();
+        let mut pos = 0;
+        while let Some(mat) = regex.find_at(text, pos) {
+            let known = self.gazetteer.is_full_name(mat.as_str());
+            if self.config.allowlist.is_allowed(mat.as_str()) {
+                allowed.push((mat.start(), mat.end()));
+            } else if known || self.config.name_detection == NameDetection::Pattern {
+                accepted.push((mat.start(), mat.end(), known));
             }
-        }).to_string();
+            pos = next_word_start(text, mat.start());
+        }
 
-        Ok(cleaned)
+        let mut spans: Vec<(usize, usize, bool)> = Vec::new();
+        for (start, end, known) in accepted {
+            if overlaps_any(&allowed, start, end) {
+                continue;
+            }
+            match spans.last_mut() {
+                Some(last) if last.1 > start => {
+                    last.1 = last.1.max(end);
+                    last.2 |= known;
+                }
+                _ => spans.push((start, end, known)),
+            }
+        }
+        spans
     }
 
-    async fn remove_organizations(&self, text: &str) -> Result<String> {
+    fn remove_organizations(
+        &self,
+        edits: &mut Edits,
+        style: RedactionStyle,
+        tracking: &mut Tracking,
+    ) {
         let org_indicators = vec![
             "Inc.", "LLC", "LLP", "Ltd.", "Corp.", "Corporation",
             "Company", "Co.", "Partnership", "Associates", "Group",
@@ -110,71 +1267,902 @@ impl PIIDetector {
             "Hospital", "Clinic", "Bank", "Credit Union",
         ];
 
-        let mut cleaned = text.to_string();
         for indicator in org_indicators {
             let pattern = format!(r"\b[\w\s]+\s+{}\b", regex::escape(indicator));
             if let Ok(regex) = Regex::new(&pattern) {
-                cleaned = regex.replace_all(&cleaned, "[ORG_REDACTED]").to_string();
+                let replacements: Vec<Replacement> = regex
+                    .find_iter(edits.current())
+                    .filter(|mat| !self.is_allowed_org(mat.as_str()))
+                    .map(|mat| {
+                        let text = self.replacement("ORG", mat.as_str(), style, false, tracking);
+                        Replacement::new(mat.start(), mat.end(), "ORG", text)
+                    })
+                    .collect();
+                edits.apply(replacements);
             }
         }
+    }
 
-        Ok(cleaned)
+    // The org pattern greedily swallows leading words, so check every word suffix
+    // ("We retained Acme Corp." -> "Acme Corp.") against the allowlist
+    fn is_allowed_org(&self, text: &str) -> bool {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        (0..words.len()).any(|i| self.config.allowlist.is_allowed(&words[i..].join(" ")))
     }
 
-    fn is_common_phrase(&self, text: &str) -> bool {
-        let common_phrases = vec![
-            "United States", "New York", "Los Angeles", "Supreme Court",
-            "District Court", "Circuit Court", "Court of Appeals",
-            "Federal Government", "State Government", "Local Government",
-        ];
-        common_phrases.iter().any(|phrase| text.eq_ignore_ascii_case(phrase))
+    // Registered under `CUSTOM_<NAME>`, which is also the token prefix: "matter" -> [CUSTOM_MATTER_REDACTED_1]
+    pub fn add_custom_pattern(&mut self, name: String, pattern: String) -> Result<String> {
+        let pii_type = custom_pattern_type(&name)?;
+        let regex = compile_custom_pattern(&pattern)?;
+        self.custom_patterns.insert(pii_type.clone(), regex);
+        self.config.custom_patterns.insert(pii_type.clone(), pattern);
+        Ok(pii_type)
     }
 
-    pub fn add_custom_pattern(&mut self, name: String, pattern: String) -> Result<()> {
-        let regex = Regex::new(&pattern)?;
-        self.custom_patterns.insert(name, regex);
-        Ok(())
+    pub fn remove_custom_pattern(&mut self, name: &str) -> bool {
+        let pii_type = match custom_pattern_type(name) {
+            Ok(pii_type) => pii_type,
+            Err(_) => return false,
+        };
+        self.config.custom_patterns.remove(&pii_type);
+        self.custom_patterns.remove(&pii_type).is_some()
+    }
+
+    // Custom patterns (sorted by name, so runs are deterministic) followed by the built-ins
+    fn rules(&self) -> Vec<PatternRule<'_>> {
+        let mut custom: Vec<_> = self.custom_patterns.iter().collect();
+        custom.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut rules: Vec<PatternRule<'_>> = custom
+            .into_iter()
+            .map(|(pii_type, regex)| PatternRule {
+                regex,
+                pii_type: pii_type.clone(),
+                label: pii_type.clone(),
+                confidence: 0.9,
+                group: 0,
+                validate: None,
+                locale: None,
+            })
+            .collect();
+        for rule in pattern_rules(self.config.locale) {
+            rules.push(rule);
+        }
+        rules
     }
 
+    // Scans the normalized text but reports spans and text from the original
     pub async fn detect_pii(&self, text: &str) -> Result<Vec<PIIMatch>> {
+        let normalized = text_normalizer::normalize(text);
         let mut matches = Vec::new();
 
-        let patterns = vec![
-            (&*SSN_REGEX, "SSN"),
-            (&*EMAIL_REGEX, "Email"),
-            (&*PHONE_REGEX, "Phone"),
-            (&*CREDIT_CARD_REGEX, "Credit Card"),
-            (&*IP_REGEX, "IP Address"),
-            (&*DATE_OF_BIRTH_REGEX, "Date of Birth"),
-            (&*PASSPORT_REGEX, "Passport"),
-            (&*DRIVER_LICENSE_REGEX, "Driver License"),
-            (&*BANK_ACCOUNT_REGEX, "Bank Account"),
-            (&*ADDRESS_REGEX, "Address"),
-            (&*CASE_NUMBER_REGEX, "Case Number"),
-            (&*EIN_REGEX, "EIN"),
-            (&*MEDICAL_RECORD_REGEX, "Medical Record"),
-        ];
+        let protected = citations::citation_spans(&normalized.text);
+        let mut taken: Vec<(usize, usize)> = Vec::new();
 
-        for (regex, pii_type) in patterns {
-            for mat in regex.find_iter(text) {
+        for rule in self.rules() {
+            let pii_type = rule.label.as_str();
+            for mat in rule.matches(&normalized.text) {
+                if self.config.allowlist.is_allowed(mat.as_str()) || within_any(&protected, mat.start(), mat.end()) {
+                    continue;
+                }
+                if self.is_exempt(&rule.pii_type, mat.as_str()) {
+                    continue;
+                }
+                if overlaps_any(&taken, mat.start(), mat.end()) {
+                    continue;
+                }
+                taken.push((mat.start(), mat.end()));
+                let (source, confidence) = match pii_type {
+                    // Bare 9-digit numbers passing the 11-check are Dutch BSNs
+                    "SSN" if !mat.as_str().contains('-') && is_valid_bsn(mat.as_str()) => (DetectorKind::Checksum, 0.9),
+                    "SSN" if !mat.as_str().contains('-') => (DetectorKind::Regex, 0.4),
+                    _ if rule.checksummed() => (DetectorKind::Checksum, rule.confidence),
+                    _ => (DetectorKind::Regex, rule.confidence),
+                };
+                let (start, end) = normalized.original_range(mat.start(), mat.end());
                 matches.push(PIIMatch {
                     pii_type: pii_type.to_string(),
-                    start: mat.start(),
-                    end: mat.end(),
-                    text: mat.as_str().to_string(),
+                    start,
+                    end,
+                    char_start: 0,
+                    char_end: 0,
+                    text: text[start..end].to_string(),
+                    confidence,
+                    source,
+                    locale: rule.locale,
                 });
             }
         }
 
+        for (start, end, confidence, source) in self.name_candidates(&normalized.text) {
+            if overlaps_any(&taken, start, end) {
+                continue;
+            }
+            taken.push((start, end));
+            let (start, end) = normalized.original_range(start, end);
+            matches.push(PIIMatch {
+                pii_type: "Name".to_string(),
+                start,
+                end,
+                char_start: 0,
+                char_end: 0,
+                text: text[start..end].to_string(),
+                confidence,
+                source,
+                locale: None,
+            });
+        }
+
+        // Range-checked, so more trustworthy than a bare regex
+        for (start, end) in find_coordinates(&normalized.text) {
+            if overlaps_any(&taken, start, end) {
+                continue;
+            }
+            let (start, end) = normalized.original_range(start, end);
+            matches.push(PIIMatch {
+                pii_type: "Location".to_string(),
+                start,
+                end,
+                char_start: 0,
+                char_end: 0,
+                text: text[start..end].to_string(),
+                confidence: 0.75,
+                source: DetectorKind::Regex,
+                locale: None,
+            });
+        }
+
         matches.sort_by_key(|m| m.start);
+
+        // Matches are in start order, so one walk through the text converts every offset
+        let mut units_before = 0;
+        let mut bytes_before = 0;
+        for m in matches.iter_mut() {
+            units_before += text[bytes_before..m.start].encode_utf16().count();
+            bytes_before = m.start;
+            m.char_start = units_before;
+            m.char_end = units_before + m.text.encode_utf16().count();
+        }
+        Ok(matches)
+    }
+
+    // Only matches at or above `min_confidence`; the rest can go to manual review instead
+    pub async fn detect_pii_above(&self, text: &str, min_confidence: f32) -> Result<Vec<PIIMatch>> {
+        let mut matches = self.detect_pii(text).await?;
+        matches.retain(|m| m.confidence >= min_confidence);
         Ok(matches)
     }
+
+    // Name spans in the same order remove_names tries them. A title prefix or a dictionary hit
+    // is decent evidence; two capitalized words alone ("Annual Report") are a weak guess.
+    fn name_candidates(&self, text: &str) -> Vec<(usize, usize, f32, DetectorKind)> {
+        let mut candidates = Vec::new();
+
+        for title in NAME_TITLES {
+            let pattern = format!(r"\b{}\s+{word}(?:\s+{word})*\b", regex::escape(title), word = NAME_WORD);
+            if let Ok(regex) = Regex::new(&pattern) {
+                for mat in regex.find_iter(text) {
+                    let name = mat.as_str()[title.len()..].trim();
+                    if !self.config.allowlist.is_allowed(mat.as_str()) && !self.config.allowlist.is_allowed(name) {
+                        candidates.push((mat.start(), mat.end(), 0.75, DetectorKind::Regex));
+                    }
+                }
+            }
+        }
+
+        for (start, end, known) in self.heuristic_name_spans(text) {
+            if known {
+                candidates.push((start, end, 0.8, DetectorKind::Dictionary));
+            } else {
+                candidates.push((start, end, 0.3, DetectorKind::Regex));
+            }
+        }
+
+        candidates
+    }
+
+    // Aggregate detector behaviour over many documents, for tuning profiles and thresholds
+    pub async fn corpus_stats(&self, texts: &[String]) -> Result<PiiStats> {
+        let mut stats = PiiStats {
+            documents: texts.len(),
+            ..PiiStats::default()
+        };
+        let mut length_sums: BTreeMap<String, usize> = BTreeMap::new();
+        let mut confidence_sums: BTreeMap<String, f32> = BTreeMap::new();
+
+        for text in texts {
+            for m in self.detect_pii(text).await? {
+                stats.total_matches += 1;
+                *length_sums.entry(m.pii_type.clone()).or_insert(0) += m.text.chars().count();
+                *confidence_sums.entry(m.pii_type.clone()).or_insert(0.0) += m.confidence;
+
+                let entry = stats.by_type.entry(m.pii_type).or_default();
+                entry.count += 1;
+                *entry.by_source.entry(m.source).or_insert(0) += 1;
+            }
+        }
+
+        for (pii_type, entry) in stats.by_type.iter_mut() {
+            let count = entry.count.max(1) as f32;
+            entry.average_length = length_sums.get(pii_type).copied().unwrap_or(0) as f32 / count;
+            entry.average_confidence = confidence_sums.get(pii_type).copied().unwrap_or(0.0) / count;
+        }
+
+        Ok(stats)
+    }
 }
 
-#[derive(Debug, Clone)]
+// Which kind of evidence produced a match; drives the confidence estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectorKind {
+    // Pattern shape only
+    Regex,
+    // Pattern plus a passing check digit (Luhn, BSN 11-check)
+    Checksum,
+    // Found in the first/last name dictionaries
+    Dictionary,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PIIMatch {
     pub pii_type: String,
+    // Byte offsets into the scanned text, for slicing it in Rust
     pub start: usize,
     pub end: usize,
+    // The same span in UTF-16 code units, i.e. JavaScript string indices, for highlighting in the
+    // UI; an emoji counts as two
+    pub char_start: usize,
+    pub char_end: usize,
     pub text: String,
-}
\ No newline at end of file
+    // 0.0 - 1.0
+    pub confidence: f32,
+    pub source: DetectorKind,
+    // Locale whose format matched; None for formats that are the same everywhere
+    pub locale: Option<Locale>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PiiTypeStats {
+    pub count: usize,
+    // In characters
+    pub average_length: f32,
+    pub average_confidence: f32,
+    pub by_source: BTreeMap<DetectorKind, usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PiiStats {
+    pub documents: usize,
+    pub total_matches: usize,
+    pub by_type: BTreeMap<String, PiiTypeStats>,
+}
+
+fn custom_pattern_type(name: &str) -> Result<String> {
+    let name: String = name
+        .trim()
+        .trim_start_matches("CUSTOM_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    if name.trim_matches('_').is_empty() {
+        return Err(anyhow!("Custom pattern name must contain letters or digits"));
+    }
+    Ok(format!("CUSTOM_{}", name))
+}
+
+// A pattern that matches "" (e.g. `a*`) would report a zero-width match at every position
+fn compile_custom_pattern(pattern: &str) -> Result<Regex> {
+    let regex = Regex::new(pattern)?;
+    if regex.is_match("") {
+        return Err(anyhow!("Custom pattern must not match empty text: {}", pattern));
+    }
+    Ok(regex)
+}
+
+// End of the next stream window: before the trailing overlap, just after whitespace, and moved
+// earlier whenever a replacement would straddle it (the match then goes to the next window)
+fn stream_cut(text: &str, replacements: &[Replacement]) -> usize {
+    let mut cut = whitespace_boundary(text, text.len().saturating_sub(STREAM_OVERLAP_BYTES));
+    loop {
+        match replacements.iter().filter(|r| r.start < cut && r.end > cut).map(|r| r.start).min() {
+            Some(start) => cut = whitespace_boundary(text, start),
+            None => return cut,
+        }
+    }
+}
+
+// Last position at or before `pos` that directly follows whitespace, or the char boundary at `pos`
+fn whitespace_boundary(text: &str, pos: usize) -> usize {
+    let mut pos = pos.min(text.len());
+    while !text.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    match text[..pos].char_indices().rev().find(|(_, c)| c.is_whitespace()) {
+        Some((i, c)) => i + c.len_utf8(),
+        None => pos,
+    }
+}
+
+// Start of the word after the one at `pos`, or the end of the text
+fn next_word_start(text: &str, pos: usize) -> usize {
+    let rest = &text[pos..];
+    let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let next = rest[word_end..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(rest.len(), |i| word_end + i);
+    pos + next
+}
+
+// Moves the valid UTF-8 prefix of `bytes` into `text`, keeping an incomplete trailing sequence
+// for the next read; invalid bytes become U+FFFD
+fn decode_utf8(bytes: &mut Vec<u8>, text: &mut String, eof: bool) {
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                bytes.clear();
+                return;
+            }
+            Err(e) => {
+                let valid_up_to = e.valid_up_to();
+                text.push_str(std::str::from_utf8(&bytes[..valid_up_to]).unwrap_or_default());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push('\u{FFFD}');
+                        bytes.drain(..valid_up_to + len);
+                    }
+                    None if eof => {
+                        text.push('\u{FFFD}');
+                        bytes.clear();
+                        return;
+                    }
+                    None => {
+                        bytes.drain(..valid_up_to);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn overlaps_any(spans: &[(usize, usize)], start: usize, end: usize) -> bool {
+    spans.iter().any(|&(s, e)| start < e && s < end)
+}
+
+fn within_any(spans: &[(usize, usize)], start: usize, end: usize) -> bool {
+    spans.iter().any(|&(s, e)| s <= start && end <= e)
+}
+
+// Luhn check over the digits only, so "4111 1111-1111 1111" is accepted
+pub fn is_valid_luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() < 12 || number.chars().any(|c| !c.is_ascii_digit() && c != ' ' && c != '-') {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+// IBAN lengths per country (ISO 13616 registry)
+const IBAN_LENGTHS: &[(&str, usize)] = &[
+    ("AD", 24), ("AE", 23), ("AL", 28), ("AT", 20), ("AZ", 28), ("BA", 20), ("BE", 16), ("BG", 22),
+    ("BH", 22), ("BR", 29), ("BY", 28), ("CH", 21), ("CR", 22), ("CY", 28), ("CZ", 24), ("DE", 22),
+    ("DK", 18), ("DO", 28), ("EE", 20), ("EG", 29), ("ES", 24), ("FI", 18), ("FO", 18), ("FR", 27),
+    ("GB", 22), ("GE", 22), ("GI", 23), ("GL", 18), ("GR", 27), ("GT", 28), ("HR", 21), ("HU", 28),
+    ("IE", 22), ("IL", 23), ("IQ", 23), ("IS", 26), ("IT", 27), ("JO", 30), ("KW", 30), ("KZ", 20),
+    ("LB", 28), ("LC", 32), ("LI", 21), ("LT", 20), ("LU", 20), ("LV", 21), ("MC", 27), ("MD", 24),
+    ("ME", 22), ("MK", 19), ("MR", 27), ("MT", 31), ("MU", 30), ("NL", 18), ("NO", 15), ("PK", 24),
+    ("PL", 28), ("PS", 29), ("PT", 25), ("QA", 29), ("RO", 24), ("RS", 22), ("SA", 24), ("SC", 31),
+    ("SE", 24), ("SI", 19), ("SK", 24), ("SM", 27), ("ST", 25), ("SV", 28), ("TL", 23), ("TN", 24),
+    ("TR", 26), ("UA", 29), ("VA", 22), ("VG", 24), ("XK", 20),
+];
+
+// Known country prefix, that country's length, and the ISO 7064 mod-97 check (remainder 1)
+pub fn is_valid_iban(iban: &str) -> bool {
+    let compact: String = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    if compact.len() < 5 || !compact.is_ascii() {
+        return false;
+    }
+
+    let expected_len = IBAN_LENGTHS.iter().find(|(country, _)| *country == &compact[..2]).map(|(_, len)| *len);
+    if expected_len != Some(compact.len()) {
+        return false;
+    }
+
+    // Move the country code and check digits to the end, then read letters as 10..35
+    let mut remainder = 0u32;
+    for c in compact[4..].chars().chain(compact[..4].chars()) {
+        let value = match c.to_digit(36) {
+            Some(value) => value,
+            None => return false,
+        };
+        remainder = if value < 10 {
+            (remainder * 10 + value) % 97
+        } else {
+            (remainder * 100 + value) % 97
+        };
+    }
+    remainder == 1
+}
+
+// NINO prefixes that are never issued
+const INVALID_NINO_PREFIXES: &[&str] = &["BG", "GB", "KN", "NK", "NT", "TN", "ZZ"];
+
+pub fn is_valid_nino(nino: &str) -> bool {
+    let compact: String = nino.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() == 9 && compact.is_ascii() && !INVALID_NINO_PREFIXES.contains(&&compact[..2])
+}
+
+// National numbers dialled with a trunk 0 have 9 (Belgium) to 11 (Germany, Austria) digits
+fn is_plausible_trunk_phone(number: &str) -> bool {
+    (9..=11).contains(&number.chars().filter(char::is_ascii_digit).count())
+}
+
+// Dutch BSN "elfproef": weights 9..2 and -1 on the last digit, sum divisible by 11
+pub fn is_valid_bsn(number: &str) -> bool {
+    let digits: Vec<i32> = number.chars().filter_map(|c| c.to_digit(10).map(|d| d as i32)).collect();
+    if digits.len() != 9 || digits.iter().all(|&d| d == 0) {
+        return false;
+    }
+
+    let sum: i32 = digits[..8]
+        .iter()
+        .zip((2..=9).rev())
+        .map(|(d, weight)| d * weight)
+        .sum::<i32>()
+        - digits[8];
+
+    sum % 11 == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn redacts_accented_hyphenated_and_non_latin_names() {
+        let detector = PIIDetector::default();
+
+        for (text, leaked) in [
+            ("Contact José Martínez today.", &["José", "Martínez"][..]),
+            ("Signed by Łukasz Nowak on behalf of the buyer.", &["Łukasz", "Nowak"][..]),
+            ("Mr Jean-Pierre Dupont attended.", &["Jean-Pierre", "Dupont"][..]),
+            ("Witness: Дмитрий Иванов.", &["Дмитрий", "Иванов"][..]),
+            ("Counsel for Ελένη Παπαδοπούλου objected.", &["Ελένη", "Παπαδοπούλου"][..]),
+        ] {
+            let cleaned = detector.remove_pii(text).await.unwrap();
+            for word in leaked {
+                assert!(!cleaned.contains(word), "{:?} leaked from {:?}: {}", word, text, cleaned);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn all_caps_terms_are_not_names() {
+        let detector = PIIDetector::default();
+        let cleaned = detector.remove_pii("THIS AGREEMENT shall be governed by NEW YORK LAW").await.unwrap();
+        assert_eq!(cleaned, "THIS AGREEMENT shall be governed by NEW YORK LAW");
+    }
+
+    #[tokio::test]
+    async fn gazetteer_retries_candidates_from_the_next_word() {
+        let mut detector = PIIDetector::default();
+        detector.set_name_detection(NameDetection::Gazetteer);
+
+        let cleaned = detector.remove_pii("Dear John Smith, please find the Annual Report attached.").await.unwrap();
+        assert!(!cleaned.contains("Smith"), "{}", cleaned);
+        assert!(cleaned.starts_with("Dear [NAME_REDACTED]"), "{}", cleaned);
+        assert!(cleaned.contains("Annual Report"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn gazetteer_uses_names_added_by_the_user() {
+        let mut detector = PIIDetector::default();
+        detector.set_name_detection(NameDetection::Gazetteer);
+        assert!(detector.remove_pii("Ask Saoirse Nic Giolla about it").await.unwrap().contains("Saoirse"));
+
+        detector.add_gazetteer_names(&["Saoirse".to_string()], &["Giolla".to_string()]);
+        let cleaned = detector.remove_pii("Ask Saoirse Giolla about it").await.unwrap();
+        assert!(!cleaned.contains("Saoirse"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn detected_name_spans_cover_the_whole_name() {
+        let detector = PIIDetector::default();
+        let text = "Contact José Martínez today";
+        let names: Vec<PIIMatch> = detector.detect_pii(text).await.unwrap()
+            .into_iter()
+            .filter(|m| m.pii_type == "Name")
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].text.ends_with("José Martínez"), "{:?}", names[0].text);
+    }
+
+    #[tokio::test]
+    async fn char_offsets_are_javascript_string_indices() {
+        let detector = PIIDetector::default();
+        let text = "😀😀 mail a@b.com";
+        let email = detector.detect_pii(text).await.unwrap()
+            .into_iter()
+            .find(|m| m.pii_type == "Email")
+            .unwrap();
+
+        assert_eq!(&text[email.start..email.end], "a@b.com");
+        assert_eq!((email.char_start, email.char_end), (10, 17));
+
+        let accented = "Café née: a@b.com";
+        let email = detector.detect_pii(accented).await.unwrap().pop().unwrap();
+        assert_eq!((email.char_start, email.char_end), (10, 17));
+    }
+
+    #[tokio::test]
+    async fn uk_locale_redacts_phone_numbers_and_national_insurance_numbers() {
+        let detector = PIIDetector::with_config(PiiConfig { locale: Locale::Uk, ..PiiConfig::default() });
+        let text = "Call 020 7946 0958 or 07700 900123. NI number AB 12 34 56 C.";
+
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        for leaked in ["7946", "900123", "AB 12 34 56 C"] {
+            assert!(!cleaned.contains(leaked), "{} leaked: {}", leaked, cleaned);
+        }
+
+        let matches = detector.detect_pii(text).await.unwrap();
+        let nino = matches.iter().find(|m| m.pii_type == "National Insurance Number").unwrap();
+        assert_eq!(nino.text, "AB 12 34 56 C");
+        assert_eq!(nino.locale, Some(Locale::Uk));
+        assert_eq!(matches.iter().filter(|m| m.pii_type == "Phone").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn national_insurance_numbers_need_an_issued_prefix_and_the_uk_locale() {
+        let uk = PIIDetector::with_config(PiiConfig { locale: Locale::Uk, ..PiiConfig::default() });
+        assert_eq!(uk.remove_pii("Ref GB123456A").await.unwrap(), "Ref GB123456A");
+        assert_eq!(uk.remove_pii("Ref QQ123456C").await.unwrap(), "Ref QQ123456C");
+
+        let us = PIIDetector::default();
+        let matches = us.detect_pii("NI number AB 12 34 56 C").await.unwrap();
+        assert!(matches.iter().all(|m| m.pii_type != "National Insurance Number"));
+    }
+
+    #[tokio::test]
+    async fn eu_trunk_phones_need_a_phone_shape() {
+        let detector = PIIDetector::with_config(PiiConfig { locale: Locale::Eu, ..PiiConfig::default() });
+
+        for phone in ["06 12345678", "01 23 45 67 89", "030 1234567", "02 123 45 67", "0612345678"] {
+            let cleaned = detector.remove_pii(&format!("Tel. {} (office)", phone)).await.unwrap();
+            assert!(!cleaned.contains(phone), "{} not redacted: {}", phone, cleaned);
+        }
+        for text in ["Invoice 0123456 paid", "Order ref 04711 2023 shipped"] {
+            assert_eq!(detector.remove_pii(text).await.unwrap(), text);
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_patterns_are_redacted_and_detected() {
+        let mut detector = PIIDetector::default();
+        let pii_type = detector.add_custom_pattern("matter".to_string(), r"MATTER-\d{4}".to_string()).unwrap();
+        assert_eq!(pii_type, "CUSTOM_MATTER");
+
+        let cleaned = detector.remove_pii("Re: MATTER-2024 closing documents").await.unwrap();
+        assert!(!cleaned.contains("MATTER-2024"), "{}", cleaned);
+        assert!(cleaned.contains("[CUSTOM_MATTER_REDACTED_"), "{}", cleaned);
+
+        let matches = detector.detect_pii("Re: MATTER-2024").await.unwrap();
+        assert_eq!(matches[0].pii_type, "CUSTOM_MATTER");
+        assert_eq!(matches[0].text, "MATTER-2024");
+
+        assert!(detector.remove_custom_pattern("matter"));
+        assert!(detector.remove_pii("MATTER-2024").await.unwrap().contains("MATTER-2024"));
+    }
+
+    #[test]
+    fn custom_patterns_matching_empty_text_are_rejected() {
+        let mut detector = PIIDetector::default();
+        assert!(detector.add_custom_pattern("greedy".to_string(), "a*".to_string()).is_err());
+        assert!(detector.add_custom_pattern("broken".to_string(), "(".to_string()).is_err());
+        assert!(detector.config().custom_patterns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ibans_are_redacted_only_when_the_checksum_passes() {
+        let detector = PIIDetector::default();
+
+        for iban in ["DE89 3704 0044 0532 0130 00", "FR14 2004 1010 0505 0001 3M02 606", "DE89370400440532013000"] {
+            let cleaned = detector.remove_pii(&format!("Pay to {} by Friday", iban)).await.unwrap();
+            assert!(cleaned.contains("[IBAN_REDACTED_"), "{} not redacted: {}", iban, cleaned);
+            assert!(!cleaned.contains(&iban[5..9]), "{}", cleaned);
+        }
+
+        let invalid = "Pay to DE89 3704 0044 0532 0130 01 by Friday";
+        let matches = detector.detect_pii(invalid).await.unwrap();
+        assert!(matches.iter().all(|m| m.pii_type != "IBAN"), "{:?}", matches);
+    }
+
+    #[tokio::test]
+    async fn iban_match_does_not_absorb_a_following_word() {
+        let detector = PIIDetector::default();
+        let text = "Account BE68 5390 0754 7034 EUR";
+
+        let matches = detector.detect_pii(text).await.unwrap();
+        let iban = matches.iter().find(|m| m.pii_type == "IBAN").unwrap();
+        assert_eq!(iban.text, "BE68 5390 0754 7034");
+
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(cleaned.starts_with("Account [IBAN_REDACTED_") && cleaned.ends_with("] EUR"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn swift_codes_need_a_label() {
+        let detector = PIIDetector::default();
+        let cleaned = detector.remove_pii("SWIFT: DEUTDEFF500. The PROPERTY passes").await.unwrap();
+        assert!(!cleaned.contains("DEUTDEFF500"), "{}", cleaned);
+        assert!(cleaned.contains("PROPERTY"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn internal_ip_addresses_are_kept_unless_configured() {
+        let mut detector = PIIDetector::default();
+        let text = "Requests from 8.8.8.8 were proxied via 10.0.0.5 and 127.0.0.1";
+
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(!cleaned.contains("8.8.8.8"), "{}", cleaned);
+        assert!(cleaned.contains("10.0.0.5") && cleaned.contains("127.0.0.1"), "{}", cleaned);
+        let ips: Vec<String> = detector.detect_pii(text).await.unwrap()
+            .into_iter()
+            .filter(|m| m.pii_type == "IP Address")
+            .map(|m| m.text)
+            .collect();
+        assert_eq!(ips, ["8.8.8.8"]);
+
+        detector.set_redact_private_ips(true);
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(!cleaned.contains("10.0.0.5") && !cleaned.contains("127.0.0.1"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn ssn_split_by_a_zero_width_space_is_still_redacted() {
+        let detector = PIIDetector::default();
+        let text = "SSN 123-45\u{200B}-6789 on file";
+
+        let matches = detector.detect_pii(text).await.unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].text, "123-45\u{200B}-6789");
+
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(!cleaned.contains("6789"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn redaction_keeps_the_original_text_around_replacements() {
+        let detector = PIIDetector::default();
+        let text = "The ﬁnal “notice”  went to ｊｏｈｎ@ｅｘａｍｐｌｅ.ｃｏｍ,\u{00A0}see Annex Ⅱ.";
+
+        let cleaned = detector.redact_with_profile(text, PROFILE_EXPORT).await.unwrap().text;
+        assert_eq!(cleaned, "The ﬁnal “notice”  went to [REDACTED],\u{00A0}see Annex Ⅱ.");
+    }
+
+    #[tokio::test]
+    async fn names_found_after_earlier_replacements_map_back_to_the_original() {
+        let detector = PIIDetector::default();
+        let text = "SSN 123-45-6789 belongs to Mr.\u{00A0}John   Smith of Acme Corporation.";
+
+        let (cleaned, map) = detector.remove_pii_reversible(text).await.unwrap();
+        assert!(!cleaned.contains("Smith") && !cleaned.contains("6789"), "{}", cleaned);
+        assert!(cleaned.starts_with("SSN [SSN_REDACTED_"), "{}", cleaned);
+        for entry in map.entries.values() {
+            assert_eq!(&text[entry.start..entry.end], entry.original);
+        }
+        assert_eq!(map.restore(&cleaned), text);
+    }
+
+    #[tokio::test]
+    async fn restorer_puts_back_tokens_split_across_chunks() {
+        let detector = PIIDetector::default();
+        let text = "Mr. John Smith (SSN 123-45-6789) wrote to jane@example.com; see [1].";
+        let (cleaned, map) = detector.remove_pii_reversible(text).await.unwrap();
+        assert_eq!(map.entries.len(), 3, "{}", cleaned);
+
+        // Every chunk size splits some token somewhere
+        for size in 1..12 {
+            let mut restorer = PiiRestorer::new(map.clone());
+            let chars: Vec<char> = cleaned.chars().collect();
+            let mut streamed = String::new();
+            for chunk in chars.chunks(size) {
+                streamed.push_str(&restorer.push(&chunk.iter().collect::<String>()));
+            }
+            streamed.push_str(&restorer.finish());
+            assert_eq!(streamed, text, "chunk size {}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn restorer_releases_brackets_that_are_not_tokens() {
+        let detector = PIIDetector::default();
+        let (_, map) = detector.remove_pii_reversible("Call Mr. John Smith").await.unwrap();
+        let mut restorer = PiiRestorer::new(map);
+
+        assert_eq!(restorer.push("see [1"), "see ");
+        assert_eq!(restorer.push("] and ["), "[1] and ");
+        assert_eq!(restorer.finish(), "[");
+    }
+
+    #[tokio::test]
+    async fn citations_only_shield_matches_inside_them() {
+        let detector = PIIDetector::default();
+
+        let text = "See 550 F. Supp. 2d 1 and 410 U.S. 113.";
+        assert_eq!(detector.remove_pii(text).await.unwrap(), text);
+
+        // The pinpoint "555" is part of the citation, the phone number around it is not
+        let text = "Smith v. Jones, 410 U.S. 113, 555-123-4567 is the clerk's line";
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(cleaned.contains("410 U.S. 113"), "{}", cleaned);
+        assert!(!cleaned.contains("4567"), "{}", cleaned);
+        let matches = detector.detect_pii(text).await.unwrap();
+        assert!(matches.iter().any(|m| m.pii_type == "Phone" && m.text == "555-123-4567"), "{:?}", matches);
+    }
+
+    #[tokio::test]
+    async fn allowlisted_phrases_survive_name_redaction() {
+        let mut detector = PIIDetector::default();
+        let text = "The Force Majeure clause covers Acme Widgets shipments.";
+        assert!(!detector.remove_pii(text).await.unwrap().contains("Acme Widgets"));
+
+        detector.add_allowed_term("acme widgets".to_string());
+        let cleaned = detector.remove_pii(text).await.unwrap();
+        assert!(cleaned.contains("Force Majeure") && cleaned.contains("Acme Widgets"), "{}", cleaned);
+    }
+
+    #[test]
+    fn saved_allowlists_gain_new_default_terms_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pii_config.json");
+        std::fs::write(&path, r#"{"allowlist": {"exact": ["ACME"], "case_insensitive": ["supreme court"]}}"#).unwrap();
+
+        let config = PiiConfig::load(&path).unwrap();
+        assert!(config.allowlist.is_allowed("ACME"));
+        assert!(config.allowlist.is_allowed("Force Majeure"));
+        assert!(config.allowlist.is_allowed("habeas corpus"));
+        assert!(config.profiles.contains_key(PROFILE_LLM));
+    }
+
+    #[test]
+    fn removed_default_terms_stay_removed_after_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pii_config.json");
+        let mut config = PiiConfig::default();
+        assert!(config.allowlist.remove("Good Faith"));
+        config.save(&path).unwrap();
+
+        let mut config = PiiConfig::load(&path).unwrap();
+        assert!(!config.allowlist.is_allowed("good faith"));
+        assert!(config.allowlist.is_allowed("Bona Fide"));
+
+        // Adding it back undoes the removal
+        config.allowlist.add("Good Faith", false);
+        config.save(&path).unwrap();
+        assert!(PiiConfig::load(&path).unwrap().allowlist.is_allowed("Good Faith"));
+    }
+
+    #[test]
+    fn luhn_ignores_separators_and_rejects_bad_check_digits() {
+        assert!(is_valid_luhn("4111111111111111"));
+        assert!(is_valid_luhn("4111 1111-1111 1111"));
+        assert!(is_valid_luhn("5500 0000 0000 0004"));
+        assert!(!is_valid_luhn("4111 1111 1111 1112"));
+        assert!(!is_valid_luhn("1234"));
+        assert!(!is_valid_luhn("4111.1111.1111.1111"));
+    }
+
+    #[tokio::test]
+    async fn only_luhn_valid_numbers_are_redacted_as_cards() {
+        let detector = PIIDetector::default();
+        let cleaned = detector
+            .remove_pii("Card 4111 1111 1111 1111, order 1234 5678 9012 3456")
+            .await
+            .unwrap();
+        assert!(cleaned.contains("[CREDIT_CARD_REDACTED_"), "{}", cleaned);
+        assert!(!cleaned.contains("4111"), "{}", cleaned);
+        assert!(cleaned.contains("1234 5678 9012 3456"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn the_same_entity_gets_the_same_pseudonym() {
+        let detector = PIIDetector::default();
+        let text = "Mr. John Smith signed. He said John Smith paid; SSN 123-45-6789 and again 123-45-6789. \
+                    Mary Jones witnessed.";
+
+        let cleaned = detector.remove_pii_pseudonymized(text).await.unwrap();
+        assert_eq!(cleaned.matches("Person A").count(), 2, "{}", cleaned);
+        assert!(cleaned.contains("Person B"), "{}", cleaned);
+        assert_eq!(cleaned.matches("SSN 1").count(), 2, "{}", cleaned);
+        assert!(!cleaned.contains("Smith") && !cleaned.contains("6789"), "{}", cleaned);
+    }
+
+    #[tokio::test]
+    async fn pseudonym_labels_are_configurable_per_type() {
+        let mut detector = PIIDetector::default();
+        detector
+            .set_pseudonym_label("NAME", PseudonymLabel { prefix: "Party".to_string(), numbering: PseudonymNumbering::Numbers })
+            .unwrap();
+        assert!(detector
+            .set_pseudonym_label("NAME", PseudonymLabel { prefix: " ".to_string(), numbering: PseudonymNumbering::Letters })
+            .is_err());
+
+        let cleaned = detector.remove_pii_pseudonymized("Mr. John Smith met Dr. Mary Jones.").await.unwrap();
+        assert_eq!(cleaned, "Party 1 met Party 2.");
+    }
+
+    #[tokio::test]
+    async fn overlapping_patterns_never_nest_tokens() {
+        let detector = PIIDetector::default();
+        let text = "SSN 123-45-6789, reference 987654321, card 4111-1111-1111-1111, account 12345678901234";
+
+        let redaction = detector.redact_with_profile(text, PROFILE_LLM).await.unwrap();
+        assert_eq!(redaction.text.matches('[').count(), 4, "{}", redaction.text);
+        assert_eq!(RESTORE_TOKEN_REGEX.find_iter(&redaction.text).count(), 4, "{}", redaction.text);
+        assert_eq!(redaction.counts.get("SSN"), Some(&2), "{:?}", redaction.counts);
+        assert_eq!(redaction.counts.get("CREDIT_CARD"), Some(&1), "{:?}", redaction.counts);
+        assert_eq!(redaction.counts.get("BANK_ACCOUNT"), Some(&1), "{:?}", redaction.counts);
+        assert_eq!(redaction.total(), 4);
+
+        let matches = detector.detect_pii(text).await.unwrap();
+        for pair in matches.windows(2) {
+            assert!(pair[0].end <= pair[1].start, "{:?} overlaps {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[tokio::test]
+    async fn decimal_and_dms_coordinates_are_locations() {
+        let detector = PIIDetector::default();
+
+        for coordinate in ["52.3702, 4.8952", "-33.8688,151.2093", "52°22'12\"N 4°53'42\"E", "40°26′46″N 79°58′56″W"] {
+            let cleaned = detector.remove_pii(&format!("Meet at {} tonight", coordinate)).await.unwrap();
+            assert!(cleaned.starts_with("Meet at [LOCATION_REDACTED_"), "{} not redacted: {}", coordinate, cleaned);
+            assert!(cleaned.ends_with("] tonight"), "{}", cleaned);
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_and_short_number_pairs_are_not_coordinates() {
+        let detector = PIIDetector::default();
+        for text in ["Readings 95.1234, 4.5678 today", "Prices 12.50, 13.75 each", "Angle 91°10'05\"N here"] {
+            let matches = detector.detect_pii(text).await.unwrap();
+            assert!(matches.iter().all(|m| m.pii_type != "Location"), "{}: {:?}", text, matches);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_redacts_every_email_across_window_boundaries() {
+        let detector = PIIDetector::default();
+        let mut input = String::new();
+        let mut emails = 0;
+        while input.len() < 3 * STREAM_WINDOW_BYTES {
+            input.push_str(&format!("reply to user{}@example.com before noon\n", emails));
+            emails += 1;
+        }
+
+        let mut output = Vec::new();
+        let report = detector.remove_pii_stream(input.as_bytes(), &mut output, PROFILE_EXPORT).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("@example.com"));
+        assert_eq!(report.counts.get("EMAIL"), Some(&emails));
+        assert_eq!(output.lines().count(), emails);
+    }
+}
@@ -1,48 +1,486 @@
 use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Serialize, Deserialize};
 use serde_json::Value as JsonValue;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use uuid::Uuid;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::embeddings::EmbeddingModel;
+use crate::vector_store::VectorStore;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub id: String,
     pub content: String,
+    // Chunk-specific fields only; document-level metadata lives in `RAGEngine::doc_metadata`
     pub metadata: JsonValue,
-    pub embeddings: Vec<f32>,
+    // Shared so a search can scan the vectors without holding the engine's lock
+    pub embeddings: Arc<[f32]>,
     pub timestamp: i64,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub collection: Option<String>,
+    // Missing for chunks indexed before v3; re-index the document to fill it in
+    #[serde(default)]
+    pub source_location: Option<SourceLocation>,
 }
 
+// A page break or heading at a byte offset into the indexed text
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchResult {
-    pub document_id: String,
-    pub content: String,
-    pub score: f32,
-    pub metadata: JsonValue,
+pub struct StructureMarker {
+    pub offset: usize,
+    pub page: Option<u32>,
+    pub heading: Option<String>,
+}
+
+// Where in the source document a chunk came from, for citing search results
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub page_start: Option<u32>,
+    pub page_end: Option<u32>,
+    pub section: Option<String>,
+}
+
+lazy_static! {
+    // Emitted by the file processor at the top of each PDF page
+    static ref PAGE_MARKER_REGEX: Regex = Regex::new(r"(?m)^\[Page (\d+)\][ \t]*$").unwrap();
+    // A blank line between paragraphs
+    static ref PARAGRAPH_BREAK_REGEX: Regex = Regex::new(r"\n[ \t]*\n").unwrap();
+    // Markdown headings, spreadsheet sheet headers and numbered articles/sections
+    static ref HEADING_REGEX: Regex = Regex::new(
+        r"(?m)^(?:#{1,6}[ \t]+(?P<markdown>[^\n]+?)|Sheet: (?P<sheet>[^\n]+?)|(?P<numbered>(?:ARTICLE|Article|SECTION|Section)[ \t]+[0-9IVXLC]+(?:\.\d+)*\b[^\n]{0,80}?))[ \t]*$"
+    ).unwrap();
+}
+
+// Removes the "[Page N]" lines the file processor puts at the top of each PDF page, returning
+// the text without them and page markers at the matching offsets into it. The lines are plain
+// text, so they survive normalization and PII redaction; stripping them afterwards keeps them
+// out of embeddings and exports.
+pub fn strip_page_markers(text: &str) -> (String, Vec<StructureMarker>) {
+    let mut stripped = String::with_capacity(text.len());
+    let mut markers = Vec::new();
+    let mut last = 0;
+
+    for caps in PAGE_MARKER_REGEX.captures_iter(text) {
+        let Some(line) = caps.get(0) else { continue };
+        stripped.push_str(&text[last..line.start()]);
+        markers.push(StructureMarker {
+            offset: stripped.len(),
+            page: caps[1].parse().ok(),
+            heading: None,
+        });
+        last = line.end();
+        if text[last..].starts_with('\n') {
+            last += 1;
+        }
+    }
+    stripped.push_str(&text[last..]);
+
+    (stripped, markers)
+}
+
+// Page markers split out of the text as above, plus the headings found in what remains
+pub fn split_structure(text: &str) -> (String, Vec<StructureMarker>) {
+    let (stripped, mut markers) = strip_page_markers(text);
+
+    for caps in HEADING_REGEX.captures_iter(&stripped) {
+        let heading = ["markdown", "sheet", "numbered"]
+            .iter()
+            .find_map(|name| caps.name(name))
+            .map(|m| m.as_str().trim().to_string());
+        markers.push(StructureMarker {
+            offset: caps.get(0).map_or(0, |m| m.start()),
+            page: None,
+            heading,
+        });
+    }
+
+    markers.sort_by_key(|m| m.offset);
+    (stripped, markers)
+}
+
+// `markers` must be sorted by offset. The section is the heading in effect where the chunk
+// starts, or failing that the first heading inside it.
+fn locate_chunk(markers: &[StructureMarker], start: usize, end: usize) -> Option<SourceLocation> {
+    let before = markers.iter().take_while(|m| m.offset <= start);
+    let within = markers.iter().filter(|m| m.offset > start && m.offset < end);
+
+    let page_start = before.clone().filter_map(|m| m.page).last()
+        .or_else(|| within.clone().find_map(|m| m.page));
+    let page_end = within.clone().filter_map(|m| m.page).next_back().or(page_start);
+    let section = before.filter_map(|m| m.heading.clone()).last()
+        .or_else(|| within.clone().find_map(|m| m.heading.clone()));
+
+    if page_start.is_none() && section.is_none() {
+        return None;
+    }
+    Some(SourceLocation { page_start, page_end, section })
+}
+
+// Chunks and embeddings computed ahead of insertion, see `prepare_document`
+pub struct PreparedDocument {
+    doc_id: String,
+    metadata: JsonValue,
+    chunks: Vec<Document>,
+}
+
+// New vectors from `prepare_reindex`, keyed by chunk id, waiting for `commit_reindex`
+pub struct PreparedReindex {
+    embedder: Arc<dyn EmbeddingModel>,
+    embeddings: HashMap<String, Vec<f32>>,
+}
+
+impl PreparedDocument {
+    // The id commit_document will store it under
+    pub fn doc_id(&self) -> &str {
+        &self.doc_id
+    }
+}
+
+// Whether chunk_size/chunk_overlap count whitespace words or embedding-model tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkUnit {
+    Words,
+    Tokens,
+}
+
+// Where chunk boundaries fall. FixedWords cuts windows of exactly `size` units; Sentences and
+// Paragraphs pack whole segments up to `size` units and repeat the trailing segments that fit in
+// `overlap` units at the start of the next chunk. A segment longer than `size` is cut into windows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    #[default]
+    FixedWords,
+    Sentences,
+    Paragraphs,
+}
+
+// Chunking used for one document; recorded in its metadata so re-indexing is reproducible
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkParams {
+    pub size: usize,
+    pub overlap: usize,
+    pub unit: ChunkUnit,
+    // Absent in metadata written before strategies existed, which was all fixed windows
+    #[serde(default)]
+    pub strategy: ChunkStrategy,
+}
+
+impl Default for ChunkParams {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            overlap: 50,
+            unit: ChunkUnit::Tokens,
+            strategy: ChunkStrategy::default(),
+        }
+    }
+}
+
+impl ChunkParams {
+    // overlap >= size would make the chunk stride zero (or negative)
+    pub fn validate(&self) -> Result<()> {
+        if self.size == 0 {
+            return Err(anyhow!("Chunk size must be greater than zero"));
+        }
+        if self.overlap >= self.size {
+            return Err(anyhow!(
+                "Chunk overlap ({}) must be smaller than chunk size ({})",
+                self.overlap,
+                self.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Per-call overrides; unset fields fall back to the engine defaults
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ChunkOptions {
+    pub size: Option<usize>,
+    pub overlap: Option<usize>,
+    pub unit: Option<ChunkUnit>,
+    pub strategy: Option<ChunkStrategy>,
+}
+
+struct TextChunk {
+    text: String,
+    start: usize,
+    end: usize,
+    unit: ChunkUnit,
+    // Span of the source text the chunk covers, for locating it against structure markers
+    byte_start: usize,
+    byte_end: usize,
+}
+
+impl TextChunk {
+    fn position_metadata(&self, index: usize) -> JsonValue {
+        match self.unit {
+            ChunkUnit::Words => serde_json::json!({
+                "chunk_index": index,
+                "start_word": self.start,
+                "end_word": self.end,
+            }),
+            ChunkUnit::Tokens => serde_json::json!({
+                "chunk_index": index,
+                "start_token": self.start,
+                "end_token": self.end,
+            }),
+        }
+    }
+}
+
+// Room for the [CLS]/[SEP] style tokens the embedder adds around each chunk
+const SPECIAL_TOKEN_ALLOWANCE: usize = 2;
+
+const INDEX_DB_FILE: &str = "index.sqlite3";
+// Pre-SQLite index, imported once and then renamed to documents.json.migrated
+const LEGACY_INDEX_FILE: &str = "documents.json";
+
+// On-disk layout of documents.json; older indexes are a bare chunk map
+#[derive(Deserialize)]
+struct IndexFile {
+    version: u32,
+    documents: HashMap<String, Document>,
+    doc_metadata: HashMap<String, JsonValue>,
+    #[serde(default)]
+    embedding_model: Option<String>,
+    #[serde(default)]
+    embedding_dim: Option<usize>,
+    #[serde(default)]
+    collection_models: HashMap<String, String>,
+}
+
+// v3: chunks carry `source_location`; v2 chunks load without one
+const INDEX_VERSION: u32 = 3;
+
+// Streaming search: how often (in chunks scanned) to check for cancellation, and the minimum
+// gap between two partial result updates
+const PARTIAL_RESULTS_CHECK_EVERY: usize = 512;
+const PARTIAL_RESULTS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// A query embedding and the chunk vectors it is compared against, detached from the engine.
+// The vectors are shared with the index rather than copied.
+pub struct SearchScan {
+    embedding: Vec<f32>,
+    candidates: Vec<(String, Arc<[f32]>)>,
+}
+
+impl SearchScan {
+    // Chunks the query is compared against
+    pub fn chunk_count(&self) -> usize {
+        self.candidates.len()
+    }
+
+    // The best `limit` chunk ids and scores, best first. While scanning, `on_partial` gets the
+    // number scanned and the running top-k, at most every PARTIAL_RESULTS_INTERVAL and only when
+    // it changed.
+    pub fn rank(
+        &self,
+        limit: usize,
+        cancel: Option<&CancellationToken>,
+        mut on_partial: impl FnMut(usize, Vec<(String, f32)>),
+    ) -> Result<Vec<(String, f32)>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        // Min-heap of at most `limit` ids and scores; the root is the weakest hit kept so far
+        let mut top: BinaryHeap<Reverse<RankedHit>> = BinaryHeap::with_capacity(limit + 1);
+        let mut changed = false;
+        let mut last_partial = std::time::Instant::now();
+
+        for (scanned, (id, embeddings)) in self.candidates.iter().enumerate() {
+            if scanned % PARTIAL_RESULTS_CHECK_EVERY == 0 && scanned > 0 {
+                if cancel.is_some_and(|c| c.is_cancelled()) {
+                    return Err(anyhow!("Search cancelled"));
+                }
+                if changed && last_partial.elapsed() >= PARTIAL_RESULTS_INTERVAL {
+                    on_partial(scanned, owned_hits(ranked(&top)));
+                    changed = false;
+                    last_partial = std::time::Instant::now();
+                }
+            }
+
+            let hit = RankedHit {
+                score: cosine_similarity(&self.embedding, embeddings),
+                order: scanned,
+                id,
+            };
+            if top.len() < limit {
+                top.push(Reverse(hit));
+                changed = true;
+            } else if let Some(mut weakest) = top.peek_mut() {
+                if hit > weakest.0 {
+                    *weakest = Reverse(hit);
+                    changed = true;
+                }
+            }
+        }
+
+        Ok(owned_hits(ranked(&top)))
+    }
+}
+
+// A sub-search that still failed after retrying; reported alongside the partial results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubSearchError {
+    pub source: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiSearchResult {
+    pub results: Vec<JsonValue>,
+    pub errors: Vec<SubSearchError>,
+}
+
+// Okapi BM25 term-frequency saturation and length normalization
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+// Inverted index over chunk content for the keyword half of hybrid search. Postings hold
+// term frequencies per chunk id; built from `keyword_tokens` on load and kept current on
+// commit and removal.
+#[derive(Default)]
+struct KeywordIndex {
+    postings: HashMap<String, HashMap<String, u32>>,
+    // Token count and distinct terms of each chunk, for length normalization and removal
+    chunks: HashMap<String, (usize, Vec<String>)>,
+    total_length: usize,
+}
+
+impl KeywordIndex {
+    fn build(documents: &HashMap<String, Document>) -> Self {
+        let mut index = Self::default();
+        for doc in documents.values() {
+            index.insert(&doc.id, &doc.content);
+        }
+        index
+    }
+
+    fn insert(&mut self, id: &str, content: &str) {
+        self.remove(id);
+        let tokens = keyword_tokens(content);
+        for token in &tokens {
+            *self.postings.entry(token.clone()).or_default().entry(id.to_string()).or_insert(0) += 1;
+        }
+        self.total_length += tokens.len();
+
+        let length = tokens.len();
+        let mut terms = tokens;
+        terms.sort();
+        terms.dedup();
+        self.chunks.insert(id.to_string(), (length, terms));
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some((length, terms)) = self.chunks.remove(id) else {
+            return;
+        };
+        self.total_length -= length;
+        for term in terms {
+            if let Some(chunks) = self.postings.get_mut(&term) {
+                chunks.remove(id);
+                if chunks.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    // BM25 score of every chunk containing at least one query term
+    fn scores(&self, query: &str) -> HashMap<&str, f32> {
+        let mut scores = HashMap::new();
+        let chunk_count = self.chunks.len() as f32;
+        if chunk_count == 0.0 {
+            return scores;
+        }
+        let average_length = (self.total_length as f32 / chunk_count).max(1.0);
+
+        let mut terms = keyword_tokens(query);
+        terms.sort();
+        terms.dedup();
+        for term in &terms {
+            let Some(chunks) = self.postings.get(term) else {
+                continue;
+            };
+            // Rare terms (a statute number) weigh far more than common ones
+            let df = chunks.len() as f32;
+            let idf = (1.0 + (chunk_count - df + 0.5) / (df + 0.5)).ln();
+            for (id, frequency) in chunks {
+                let length = self.chunks.get(id).map_or(0, |(length, _)| *length) as f32;
+                let tf = *frequency as f32;
+                let score = idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length));
+                *scores.entry(id.as_str()).or_insert(0.0) += score;
+            }
+        }
+        scores
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub document_count: usize,
+    pub chunk_count: usize,
+    pub total_size_bytes: u64,
+    // Index database and its journal files as stored on disk
+    pub index_size_bytes: u64,
+    // Unix seconds of the most recent write to those files
+    pub last_modified: Option<i64>,
+    pub embedding_model: String,
+    pub embedding_dim: usize,
 }
 
 pub struct RAGEngine {
     documents: HashMap<String, Document>,
+    doc_metadata: HashMap<String, JsonValue>,
     index_path: PathBuf,
-    embedding_dim: usize,
-    chunk_size: usize,
-    chunk_overlap: usize,
+    // Opened by `initialize`
+    store: Option<VectorStore>,
+    // Shared so embedding can run on the blocking pool while the engine stays borrowed
+    embedder: Arc<dyn EmbeddingModel>,
+    // Additional models by id, for collections assigned something other than the default
+    extra_embedders: HashMap<String, Arc<dyn EmbeddingModel>>,
+    collection_models: HashMap<String, String>,
+    // Set when the on-disk index was built with a different embedding model; blocks use and saving
+    index_mismatch: Option<String>,
+    chunk_defaults: ChunkParams,
+    keyword_index: KeywordIndex,
+    embed_queue: EmbedQueue,
 }
 
 impl RAGEngine {
-    pub fn new() -> Self {
-        let index_path = dirs::data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("./"))
-            .join("legal-ai-assistant")
-            .join("rag_index");
+    // Starts on the character hash; the app swaps in `embeddings::default_embedder` with
+    // set_embedder once that model has loaded
+    pub fn new(data_dir: &Path) -> Self {
+        Self::with_embedder(data_dir, Box::new(crate::embeddings::CharHashEmbedder::default()))
+    }
+
+    pub fn with_embedder(data_dir: &Path, embedder: Box<dyn EmbeddingModel>) -> Self {
+        let index_path = data_dir.join("rag_index");
 
         Self {
             documents: HashMap::new(),
+            doc_metadata: HashMap::new(),
             index_path,
-            embedding_dim: 384,
-            chunk_size: 512,
-            chunk_overlap: 50,
+            store: None,
+            embedder: Arc::from(embedder),
+            extra_embedders: HashMap::new(),
+            collection_models: HashMap::new(),
+            index_mismatch: None,
+            chunk_defaults: ChunkParams::default(),
+            keyword_index: KeywordIndex::default(),
+            embed_queue: EmbedQueue::default(),
         }
     }
 
@@ -52,161 +490,1702 @@ impl RAGEngine {
         Ok(())
     }
 
-    pub async fn add_document(&mut self, content: &str, metadata: JsonValue) -> Result<String> {
+    // Chunks, embeds and inserts in one step. Ingestion that should not block readers while
+    // embedding can split this into prepare_document and commit_document instead.
+    pub async fn add_document(
+        &mut self,
+        content: &str,
+        metadata: JsonValue,
+        chunking: Option<ChunkOptions>,
+        markers: Option<Vec<StructureMarker>>,
+    ) -> Result<String> {
+        let prepared = self
+            .prepare_document(content, metadata, chunking, markers, &CancellationToken::new(), |_, _| {})
+            .await?;
+        self.commit_document(prepared).await
+    }
+
+    // Chunks and embeds without touching the index, so callers only need a read lock
+    // and can abort midway. `on_progress` receives (chunks embedded, total chunks).
+    // A `collection` key in `metadata` routes the document to that collection's embedding model.
+    // `markers` are byte offsets into `content`; without them, page markers are split out of the
+    // text (see `split_structure`) and headings are detected in it.
+    pub async fn prepare_document(
+        &self,
+        content: &str,
+        mut metadata: JsonValue,
+        chunking: Option<ChunkOptions>,
+        markers: Option<Vec<StructureMarker>>,
+        cancel: &CancellationToken,
+        mut on_progress: impl FnMut(usize, usize),
+    ) -> Result<PreparedDocument> {
+        let params = self.resolve_chunk_params(chunking)?;
+        let collection = metadata["collection"].as_str().map(str::to_string);
+        let embedder = self.embedder_for(collection.as_deref())?;
+
+        if metadata.is_null() {
+            metadata = serde_json::json!({});
+        }
+        if let Some(fields) = metadata.as_object_mut() {
+            fields.insert("chunking".to_string(), serde_json::to_value(params)?);
+        }
+
+        let (content, mut markers) = match markers {
+            Some(markers) => (Cow::Borrowed(content), markers),
+            None => {
+                let (stripped, markers) = split_structure(content);
+                (Cow::Owned(stripped), markers)
+            }
+        };
+        markers.sort_by_key(|m| m.offset);
+
         let doc_id = Uuid::new_v4().to_string();
-        let chunks = self.chunk_text(content);
-
-        for (i, chunk) in chunks.iter().enumerate() {
-            let chunk_id = format!("{}_{}", doc_id, i);
-            let embeddings = self.generate_embeddings(chunk).await?;
-
-            let document = Document {
-                id: chunk_id.clone(),
-                content: chunk.clone(),
-                metadata: metadata.clone(),
-                embeddings,
-                timestamp: chrono::Utc::now().timestamp(),
-            };
+        let chunks = self.chunk_text(embedder.as_ref(), &content, &params);
+        let total = chunks.len();
+        let mut documents = Vec::with_capacity(total);
+        // Chunking is done; embedding starts
+        on_progress(0, total);
+
+        // One forward pass per batch instead of per chunk, shared with other documents being
+        // prepared at the same time; progress and cancellation per batch
+        let batch_size = embedder.max_batch_size().max(1);
+        let mut chunks = chunks.into_iter().enumerate().peekable();
+
+        while chunks.peek().is_some() {
+            if cancel.is_cancelled() {
+                return Err(anyhow!("Document processing cancelled"));
+            }
+
+            let batch: Vec<(usize, TextChunk)> = chunks.by_ref().take(batch_size).collect();
+            let texts: Vec<String> = batch.iter().map(|(_, chunk)| chunk.text.clone()).collect();
+            let embeddings = self.embed_queue.embed(embedder.clone(), texts).await?;
+
+            for ((i, chunk), embeddings) in batch.into_iter().zip(embeddings) {
+                let source_location = locate_chunk(&markers, chunk.byte_start, chunk.byte_end);
+                let metadata = chunk.position_metadata(i);
+                documents.push(Document {
+                    id: format!("{}_{}", doc_id, i),
+                    content: chunk.text,
+                    metadata,
+                    embeddings: embeddings.into(),
+                    timestamp: chrono::Utc::now().timestamp(),
+                    parent_id: Some(doc_id.clone()),
+                    collection: collection.clone(),
+                    source_location,
+                });
+            }
+
+            on_progress(documents.len(), total);
+        }
+
+        Ok(PreparedDocument {
+            doc_id,
+            metadata,
+            chunks: documents,
+        })
+    }
+
+    // Writes only this document's rows; the in-memory index is updated once they are stored
+    pub async fn commit_document(&mut self, prepared: PreparedDocument) -> Result<String> {
+        self.ensure_index_usable()?;
+        let doc_id = prepared.doc_id;
 
-            self.documents.insert(chunk_id, document);
+        self.store()?.insert_document(&doc_id, &prepared.metadata, &prepared.chunks)?;
+
+        for document in prepared.chunks {
+            self.keyword_index.insert(&document.id, &document.content);
+            self.documents.insert(document.id.clone(), document);
         }
+        self.doc_metadata.insert(doc_id.clone(), prepared.metadata);
 
-        self.save_index().await?;
         Ok(doc_id)
     }
 
+    pub async fn remove_document(&mut self, doc_id: &str) -> Result<usize> {
+        self.ensure_index_usable()?;
+        self.store()?.remove_document(doc_id)?;
+        Ok(self.remove_from_memory(doc_id))
+    }
+
+    // Removes every document ingested from `source` (its "source" metadata), so a re-ingested
+    // file replaces its earlier version. Returns how many documents were removed.
+    pub async fn remove_documents_from_source(&mut self, source: &str) -> Result<usize> {
+        let doc_ids: Vec<String> = self.doc_metadata
+            .iter()
+            .filter(|(_, metadata)| metadata["source"].as_str() == Some(source))
+            .map(|(doc_id, _)| doc_id.clone())
+            .collect();
+        for doc_id in &doc_ids {
+            self.remove_document(doc_id).await?;
+        }
+        Ok(doc_ids.len())
+    }
+
+    fn remove_from_memory(&mut self, doc_id: &str) -> usize {
+        let before = self.documents.len();
+        let keyword_index = &mut self.keyword_index;
+        self.documents.retain(|id, doc| {
+            let keep = doc.parent_id.as_deref() != Some(doc_id);
+            if !keep {
+                keyword_index.remove(id);
+            }
+            keep
+        });
+        self.doc_metadata.remove(doc_id);
+        before - self.documents.len()
+    }
+
+    // Searches every chunk embedded with the default model
     pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<JsonValue>> {
-        let query_embedding = self.generate_embeddings(query).await?;
-        let mut results: Vec<(String, f32, Document)> = Vec::new();
+        self.search_collection(query, None, limit).await
+    }
 
-        for (id, doc) in &self.documents {
-            let score = self.cosine_similarity(&query_embedding, &doc.embeddings);
-            results.push((id.clone(), score, doc.clone()));
+    pub async fn search_collection(&self, query: &str, collection: Option<&str>, limit: usize) -> Result<Vec<JsonValue>> {
+        let embedder = self.embedder_for(collection)?;
+        let query_embedding = embed_with(embedder.as_ref(), &[query.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding model returned no vector"))?;
+        self.search_embedding_in(&query_embedding, collection, limit)
+    }
+
+    // Blends cosine similarity with BM25 keyword relevance, so exact terms the embedding can't
+    // represent (statute and case numbers) still rank. `alpha` is the vector share: 1.0 is
+    // `search`, 0.0 keyword-only.
+    pub async fn search_hybrid(&self, query: &str, limit: usize, alpha: f32) -> Result<Vec<JsonValue>> {
+        self.search_hybrid_collection(query, None, limit, alpha).await
+    }
+
+    pub async fn search_hybrid_collection(
+        &self,
+        query: &str,
+        collection: Option<&str>,
+        limit: usize,
+        alpha: f32,
+    ) -> Result<Vec<JsonValue>> {
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(anyhow!("alpha must be between 0 and 1, got {}", alpha));
         }
+        self.ensure_index_usable()?;
+        let embedder = self.embedder_for(collection)?;
+        let query_embedding = embed_with(embedder.as_ref(), &[query.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding model returned no vector"))?;
 
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-        results.truncate(limit);
+        let model_id = embedder.model_id();
+        let in_scope = |doc: &Document| match collection {
+            Some(collection) => doc.collection.as_deref() == Some(collection),
+            None => self.collection_model_id(doc.collection.as_deref()) == model_id,
+        };
 
-        let search_results: Vec<JsonValue> = results
-            .into_iter()
-            .map(|(id, score, doc)| {
-                serde_json::json!({
-                    "id": id,
-                    "content": doc.content,
-                    "score": score,
-                    "metadata": doc.metadata,
-                })
+        // BM25 is unbounded, so it is scaled by the best in-scope keyword score
+        let keyword_scores = self.keyword_index.scores(query);
+        let best_keyword = keyword_scores
+            .iter()
+            .filter(|(id, _)| self.documents.get(**id).is_some_and(&in_scope))
+            .map(|(_, score)| *score)
+            .fold(0.0f32, f32::max);
+
+        let mut top: BinaryHeap<Reverse<RankedHit>> = BinaryHeap::with_capacity(limit + 1);
+        for (order, (id, doc)) in self.documents.iter().enumerate() {
+            if !in_scope(doc) || limit == 0 {
+                continue;
+            }
+            let vector = cosine_similarity(&query_embedding, &doc.embeddings).max(0.0);
+            let keyword = match keyword_scores.get(id.as_str()) {
+                Some(score) if best_keyword > 0.0 => score / best_keyword,
+                _ => 0.0,
+            };
+
+            let hit = RankedHit { score: alpha * vector + (1.0 - alpha) * keyword, order, id };
+            if top.len() < limit {
+                top.push(Reverse(hit));
+            } else if let Some(mut weakest) = top.peek_mut() {
+                if hit > weakest.0 {
+                    *weakest = Reverse(hit);
+                }
+            }
+        }
+
+        Ok(self.result_json(&ranked(&top)))
+    }
+
+    // Same ranking as `search`, for callers with a precomputed or stored embedding
+    pub fn search_by_embedding(&self, embedding: &[f32], limit: usize) -> Result<Vec<JsonValue>> {
+        self.search_embedding_in(embedding, None, limit)
+    }
+
+    // Only chunks embedded by the same model as the query are compared; vectors from
+    // different models live in unrelated spaces even when their dimensions happen to match
+    pub fn search_embedding_in(&self, embedding: &[f32], collection: Option<&str>, limit: usize) -> Result<Vec<JsonValue>> {
+        let scan = self.scan_for(embedding.to_vec(), collection)?;
+        Ok(self.result_json(&scan.rank(limit, None, |_, _| {})?))
+    }
+
+    // Embeds the query and takes the chunks `search_collection` would compare, so the scan itself
+    // can run on a blocking thread without the engine's lock. Rank it with `SearchScan::rank` and
+    // turn the hits into results with `result_json`; the final set matches `search_collection`.
+    pub fn prepare_search(&self, query: &str, collection: Option<&str>) -> Result<SearchScan> {
+        let embedder = self.embedder_for(collection)?;
+        let query_embedding = embed_with(embedder.as_ref(), &[query.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding model returned no vector"))?;
+        self.scan_for(query_embedding, collection)
+    }
+
+    fn scan_for(&self, embedding: Vec<f32>, collection: Option<&str>) -> Result<SearchScan> {
+        self.ensure_index_usable()?;
+        let embedder = self.embedder_for(collection)?;
+        if embedding.len() != embedder.dimension() {
+            return Err(anyhow!(
+                "Embedding dimension mismatch: expected {}, got {}",
+                embedder.dimension(),
+                embedding.len()
+            ));
+        }
+
+        let model_id = embedder.model_id();
+        let candidates = self.documents
+            .iter()
+            .filter(|(_, doc)| match collection {
+                Some(collection) => doc.collection.as_deref() == Some(collection),
+                None => self.collection_model_id(doc.collection.as_deref()) == model_id,
             })
+            .map(|(id, doc)| (id.clone(), doc.embeddings.clone()))
             .collect();
 
-        Ok(search_results)
+        Ok(SearchScan { embedding, candidates })
+    }
+
+    // Chunks removed since the hits were ranked are left out
+    pub fn result_json<S: AsRef<str>>(&self, hits: &[(S, f32)]) -> Vec<JsonValue> {
+        hits.iter()
+            .filter_map(|(id, score)| {
+                let doc = self.documents.get(id.as_ref())?;
+                Some(serde_json::json!({
+                    "id": id.as_ref(),
+                    "score": score,
+                    "metadata": self.merged_metadata(doc),
+                    "content": doc.content,
+                    "location": doc.source_location,
+                }))
+            })
+            .collect()
+    }
+
+    pub fn chunk_embedding(&self, chunk_id: &str) -> Option<&[f32]> {
+        self.documents.get(chunk_id).map(|doc| &doc.embeddings[..])
     }
 
-    pub async fn agentic_search(&self, query: &str, context: &str) -> Result<Vec<JsonValue>> {
-        let enhanced_query = format!("{} Context: {}", query, context);
-        let mut results = self.search(&enhanced_query, 10).await?;
+    // Searches `query` in each collection (None is the default index) and merges the hits, keeping
+    // the best score per chunk. A collection whose model is unavailable becomes an entry in
+    // `errors`; only fails when every sub-search does.
+    pub async fn multi_search(&self, query: &str, collections: &[Option<String>], limit: usize) -> Result<MultiSearchResult> {
+        let mut merged: HashMap<String, JsonValue> = HashMap::new();
+        let mut errors = Vec::new();
 
-        results = self.rerank_results(results, query).await?;
+        for collection in collections {
+            let source = collection.clone().unwrap_or_else(|| "default".to_string());
+            match self.search_collection(query, collection.as_deref(), limit).await {
+                Ok(results) => {
+                    for mut result in results {
+                        let id = result["id"].as_str().unwrap_or_default().to_string();
+                        result["source"] = JsonValue::String(source.clone());
+                        let better = merged
+                            .get(&id)
+                            .map(|existing| result["score"].as_f64() > existing["score"].as_f64())
+                            .unwrap_or(true);
+                        if better {
+                            merged.insert(id, result);
+                        }
+                    }
+                }
+                Err(e) => errors.push(SubSearchError {
+                    source: source.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        if !collections.is_empty() && errors.len() == collections.len() {
+            let details: Vec<String> = errors.iter().map(|e| format!("{}: {}", e.source, e.error)).collect();
+            return Err(anyhow!("All searches failed: {}", details.join("; ")));
+        }
+
+        let mut results: Vec<JsonValue> = merged.into_values().collect();
+        results.sort_by(|a, b| {
+            let score_a = a["score"].as_f64().unwrap_or(0.0);
+            let score_b = b["score"].as_f64().unwrap_or(0.0);
+            score_b.partial_cmp(&score_a).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        results.truncate(limit);
+
+        Ok(MultiSearchResult { results, errors })
+    }
 
-        let reasoning = self.generate_reasoning(&results, query).await?;
-        results.push(serde_json::json!({
-            "type": "reasoning",
-            "content": reasoning,
-        }));
+    // Replaces the default model. Call before `initialize`, which checks the index against it.
+    pub fn set_embedder(&mut self, embedder: Box<dyn EmbeddingModel>) {
+        self.embedder = Arc::from(embedder);
+    }
 
-        Ok(results)
+    // Makes a model available for `assign_collection_model`; the default model needs no registration
+    pub fn register_embedder(&mut self, embedder: Box<dyn EmbeddingModel>) {
+        self.extra_embedders.insert(embedder.model_id().to_string(), Arc::from(embedder));
     }
 
-    async fn generate_embeddings(&self, text: &str) -> Result<Vec<f32>> {
-        let mut embeddings = vec![0.0; self.embedding_dim];
-        for (i, char) in text.chars().enumerate().take(self.embedding_dim) {
-            embeddings[i % self.embedding_dim] += (char as u32) as f32 / 1000.0;
+    // A collection's model is fixed once it holds documents, since its vectors would no longer compare
+    pub async fn assign_collection_model(&mut self, collection: &str, model_id: &str) -> Result<()> {
+        if model_id != self.embedder.model_id() && !self.extra_embedders.contains_key(model_id) {
+            return Err(anyhow!("Embedding model {} is not loaded", model_id));
         }
 
-        let norm: f32 = embeddings.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if norm > 0.0 {
-            for val in &mut embeddings {
-                *val /= norm;
-            }
+        let current = self.collection_model_id(Some(collection));
+        let has_documents = self.documents.values().any(|doc| doc.collection.as_deref() == Some(collection));
+        if has_documents && current != model_id {
+            return Err(anyhow!(
+                "Collection {} already contains documents embedded with {}; clear or re-index it before switching models",
+                collection,
+                current
+            ));
         }
 
-        Ok(embeddings)
+        self.ensure_index_usable()?;
+        self.store()?.set_collection_model(collection, model_id)?;
+        self.collection_models.insert(collection.to_string(), model_id.to_string());
+        Ok(())
     }
 
-    fn cosine_similarity(&self, a: &[f32], b: &[f32]) -> f32 {
-        let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    pub fn collection_models(&self) -> &HashMap<String, String> {
+        &self.collection_models
+    }
 
-        if norm_a * norm_b > 0.0 {
-            dot_product / (norm_a * norm_b)
-        } else {
-            0.0
+    fn collection_model_id(&self, collection: Option<&str>) -> &str {
+        collection
+            .and_then(|c| self.collection_models.get(c))
+            .map(String::as_str)
+            .unwrap_or_else(|| self.embedder.model_id())
+    }
+
+    fn embedder_for(&self, collection: Option<&str>) -> Result<&Arc<dyn EmbeddingModel>> {
+        let model_id = self.collection_model_id(collection);
+        if model_id == self.embedder.model_id() {
+            return Ok(&self.embedder);
         }
+        self.extra_embedders
+            .get(model_id)
+            .ok_or_else(|| anyhow!(
+                "Embedding model {} for collection {} is not loaded",
+                model_id,
+                collection.unwrap_or("default")
+            ))
     }
 
-    fn chunk_text(&self, text: &str) -> Vec<String> {
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let mut chunks = Vec::new();
+    // Document-level metadata overlaid with the chunk's own fields
+    fn merged_metadata(&self, doc: &Document) -> JsonValue {
+        let parent = doc.parent_id
+            .as_ref()
+            .and_then(|id| self.doc_metadata.get(id));
 
-        for i in (0..words.len()).step_by(self.chunk_size - self.chunk_overlap) {
-            let end = std::cmp::min(i + self.chunk_size, words.len());
-            let chunk = words[i..end].join(" ");
-            chunks.push(chunk);
+        match (parent, &doc.metadata) {
+            (Some(JsonValue::Object(parent)), JsonValue::Object(chunk)) => {
+                let mut merged = parent.clone();
+                for (key, value) in chunk {
+                    merged.insert(key.clone(), value.clone());
+                }
+                JsonValue::Object(merged)
+            }
+            (Some(parent), JsonValue::Null) => parent.clone(),
+            _ => doc.metadata.clone(),
         }
+    }
+
+    pub fn chunk_defaults(&self) -> ChunkParams {
+        self.chunk_defaults
+    }
+
+    pub fn set_chunk_defaults(&mut self, params: ChunkParams) -> Result<()> {
+        params.validate()?;
+        self.chunk_defaults = params;
+        Ok(())
+    }
+
+    fn resolve_chunk_params(&self, options: Option<ChunkOptions>) -> Result<ChunkParams> {
+        let options = options.unwrap_or_default();
+        let params = ChunkParams {
+            size: options.size.unwrap_or(self.chunk_defaults.size),
+            overlap: options.overlap.unwrap_or(self.chunk_defaults.overlap),
+            unit: options.unit.unwrap_or(self.chunk_defaults.unit),
+            strategy: options.strategy.unwrap_or(self.chunk_defaults.strategy),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    // Token chunking needs a tokenizer from the embedder; without one we fall back to words
+    fn chunk_text(&self, embedder: &dyn EmbeddingModel, text: &str, params: &ChunkParams) -> Vec<TextChunk> {
+        let token_offsets = match params.unit {
+            ChunkUnit::Tokens => embedder.token_offsets(text),
+            ChunkUnit::Words => None,
+        };
+
+        let mut chunks = match (params.strategy, token_offsets) {
+            (ChunkStrategy::FixedWords, Some(offsets)) => chunk_by_tokens(embedder, text, &offsets, params),
+            (ChunkStrategy::FixedWords, None) => chunk_by_words(text, params),
+            (ChunkStrategy::Sentences, offsets) => chunk_by_segments(embedder, text, &sentence_spans(text), offsets, params),
+            (ChunkStrategy::Paragraphs, offsets) => chunk_by_segments(embedder, text, &paragraph_spans(text), offsets, params),
+        };
 
         if chunks.is_empty() {
-            chunks.push(text.to_string());
+            chunks.push(TextChunk {
+                text: text.to_string(),
+                start: 0,
+                end: 0,
+                unit: ChunkUnit::Words,
+                byte_start: 0,
+                byte_end: text.len(),
+            });
         }
 
         chunks
     }
 
-    async fn rerank_results(&self, results: Vec<JsonValue>, query: &str) -> Result<Vec<JsonValue>> {
-        let mut reranked = results;
-        reranked.sort_by(|a, b| {
-            let score_a = a["score"].as_f64().unwrap_or(0.0);
-            let score_b = b["score"].as_f64().unwrap_or(0.0);
-            score_b.partial_cmp(&score_a).unwrap()
-        });
-
-        Ok(reranked)
+    fn store(&self) -> Result<&VectorStore> {
+        self.store.as_ref().ok_or_else(|| anyhow!("Knowledge base index is not initialized"))
     }
 
-    async fn generate_reasoning(&self, results: &[JsonValue], query: &str) -> Result<String> {
-        let reasoning = format!(
-            "Based on the query '{}', I found {} relevant documents. \
-             The top results suggest that the answer relates to the following key points...",
-            query,
-            results.len()
-        );
+    async fn load_index(&mut self) -> Result<()> {
+        let store = VectorStore::open(&self.index_path.join(INDEX_DB_FILE))?;
+        let legacy_file = self.index_path.join(LEGACY_INDEX_FILE);
+        let migrate = store.is_empty()? && legacy_file.exists();
+        self.store = Some(store);
+
+        if migrate {
+            self.migrate_json_index(&legacy_file).await?;
+        } else {
+            let index = self.store()?.load()?;
+            if let Some(version) = index.version.filter(|v| *v > INDEX_VERSION) {
+                return Err(anyhow!("Index format v{} is newer than supported v{}", version, INDEX_VERSION));
+            }
+            // Loaded even on a mismatch: the stored content is what `reindex` re-embeds
+            let compat = self.check_embedding_compat(index.embedding_model.as_deref(), index.embedding_dim, &index.documents);
+            self.documents = index.documents;
+            self.doc_metadata = index.doc_metadata;
+            self.collection_models = index.collection_models;
+            self.keyword_index = KeywordIndex::build(&self.documents);
+            compat?;
+        }
 
-        Ok(reasoning)
+        self.store()?.set_index_info(INDEX_VERSION, self.embedder.model_id(), self.embedder.dimension())
     }
 
-    async fn save_index(&self) -> Result<()> {
-        let index_file = self.index_path.join("documents.json");
-        let json = serde_json::to_string(&self.documents)?;
-        tokio::fs::write(index_file, json).await?;
+    // One-time import of a documents.json index. The file is renamed rather than deleted so a
+    // failed import can be retried by hand; if the embedding model no longer matches, it is left
+    // in place and imported once the mismatch is resolved.
+    async fn migrate_json_index(&mut self, legacy_file: &Path) -> Result<()> {
+        let json = tokio::fs::read_to_string(legacy_file).await?;
+        let raw: JsonValue = serde_json::from_str(&json)?;
+
+        if raw.get("version").is_some() {
+            let index: IndexFile = serde_json::from_value(raw)?;
+            if index.version > INDEX_VERSION {
+                return Err(anyhow!("Index format v{} is newer than supported v{}", index.version, INDEX_VERSION));
+            }
+            let compat = self.check_embedding_compat(index.embedding_model.as_deref(), index.embedding_dim, &index.documents);
+            self.documents = index.documents;
+            self.doc_metadata = index.doc_metadata;
+            self.collection_models = index.collection_models;
+            self.keyword_index = KeywordIndex::build(&self.documents);
+            compat?;
+        } else {
+            let documents: HashMap<String, Document> = serde_json::from_value(raw)?;
+            let compat = self.check_embedding_compat(None, None, &documents);
+            self.documents = documents;
+            self.migrate_chunk_metadata();
+            self.keyword_index = KeywordIndex::build(&self.documents);
+            compat?;
+        }
+
+        self.store()?.import(&self.documents, &self.doc_metadata, &self.collection_models)?;
+        tokio::fs::rename(legacy_file, legacy_file.with_extension("json.migrated")).await?;
         Ok(())
     }
 
-    async fn load_index(&mut self) -> Result<()> {
-        let index_file = self.index_path.join("documents.json");
-        if index_file.exists() {
-            let json = tokio::fs::read_to_string(index_file).await?;
-            self.documents = serde_json::from_str(&json)?;
+    // Indexes saved before the model was recorded are checked by the stored vector length only
+    fn check_embedding_compat(
+        &mut self,
+        model: Option<&str>,
+        dimension: Option<usize>,
+        documents: &HashMap<String, Document>,
+    ) -> Result<()> {
+        let stored_dim = dimension.or_else(|| documents.values().next().map(|doc| doc.embeddings.len()));
+        let current_model = self.embedder.model_id();
+        let current_dim = self.embedder.dimension();
+
+        let model_changed = model.map(|m| m != current_model).unwrap_or(false);
+        let dim_changed = stored_dim.map(|d| d != current_dim).unwrap_or(false);
+
+        if model_changed || dim_changed {
+            let message = format!(
+                "The knowledge base was built with embedding model {} ({} dimensions) but the current model is {} ({} dimensions). \
+                 Re-index your documents to use it.",
+                model.unwrap_or("unknown"),
+                stored_dim.map(|d| d.to_string()).unwrap_or_else(|| "?".to_string()),
+                current_model,
+                current_dim
+            );
+            self.index_mismatch = Some(message.clone());
+            return Err(anyhow!(message));
         }
+
         Ok(())
     }
 
+    // First half of a re-index, which only needs a read lock: embeds the stored content of every
+    // chunk with its model, on the blocking pool. That is `embedder` for the default model and the
+    // registered model for a collection that has its own; chunks of a collection whose model isn't
+    // loaded keep their vectors. Nothing changes until `commit_reindex`.
+    pub async fn prepare_reindex(&self, embedder: Box<dyn EmbeddingModel>) -> Result<PreparedReindex> {
+        let embedder: Arc<dyn EmbeddingModel> = Arc::from(embedder);
+        let groups = self.reindex_groups(&embedder, |_| true);
+        let embeddings = tokio::task::spawn_blocking(move || embed_groups(groups)).await??;
+        Ok(PreparedReindex { embedder, embeddings })
+    }
+
+    // Switches the default model and swaps in the prepared vectors; chunks added while they were
+    // computed are embedded here. This resolves an embedding mismatch without re-reading the
+    // source files, and a failure leaves the index as it was. Returns the chunks re-embedded.
+    pub async fn commit_reindex(&mut self, prepared: PreparedReindex) -> Result<usize> {
+        let PreparedReindex { embedder, mut embeddings } = prepared;
+        let late = self.reindex_groups(&embedder, |id| !embeddings.contains_key(id));
+        embeddings.extend(embed_groups(late)?);
+
+        let mut documents = self.documents.clone();
+        let mut reembedded = 0;
+        for (id, doc) in documents.iter_mut() {
+            if let Some(embedding) = embeddings.remove(id) {
+                doc.embeddings = embedding.into();
+                reembedded += 1;
+            }
+        }
+
+        let store = self.store()?;
+        store.import(&documents, &self.doc_metadata, &self.collection_models)?;
+        store.set_index_info(INDEX_VERSION, embedder.model_id(), embedder.dimension())?;
+
+        // A documents.json whose import was held back by the mismatch is now in the database
+        let legacy_file = self.index_path.join(LEGACY_INDEX_FILE);
+        if legacy_file.exists() {
+            tokio::fs::rename(&legacy_file, legacy_file.with_extension("json.migrated")).await?;
+        }
+
+        self.documents = documents;
+        self.embedder = embedder;
+        self.index_mismatch = None;
+        Ok(reembedded)
+    }
+
+    // Chunks accepted by `include`, grouped by the model that re-embeds them
+    fn reindex_groups(&self, default: &Arc<dyn EmbeddingModel>, include: impl Fn(&str) -> bool) -> Vec<EmbedGroup> {
+        let mut groups: HashMap<String, EmbedGroup> = HashMap::new();
+        for (id, doc) in self.documents.iter().filter(|(id, _)| include(id)) {
+            let model = match doc.collection.as_ref().and_then(|c| self.collection_models.get(c)) {
+                Some(model_id) if model_id != default.model_id() => match self.extra_embedders.get(model_id) {
+                    Some(model) => model.clone(),
+                    None => continue,
+                },
+                _ => default.clone(),
+            };
+            let group = groups
+                .entry(model.model_id().to_string())
+                .or_insert_with(|| EmbedGroup { model, ids: Vec::new(), texts: Vec::new() });
+            group.ids.push(id.clone());
+            group.texts.push(doc.content.clone());
+        }
+        groups.into_values().collect()
+    }
+
+    pub fn index_error(&self) -> Option<&str> {
+        self.index_mismatch.as_deref()
+    }
+
+    fn ensure_index_usable(&self) -> Result<()> {
+        match &self.index_mismatch {
+            Some(message) => Err(anyhow!(message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // Pre-v2 indexes copied the full document metadata into every chunk; hoist it once per document
+    fn migrate_chunk_metadata(&mut self) {
+        for doc in self.documents.values_mut() {
+            if doc.parent_id.is_some() {
+                continue;
+            }
+
+            let (parent, chunk_index) = match doc.id.rsplit_once('_') {
+                Some((parent, index)) => (parent.to_string(), index.parse::<usize>().ok()),
+                None => (doc.id.clone(), None),
+            };
+
+            let metadata = std::mem::replace(&mut doc.metadata, serde_json::json!({ "chunk_index": chunk_index }));
+            self.doc_metadata.entry(parent.clone()).or_insert(metadata);
+            doc.parent_id = Some(parent);
+        }
+    }
+
+    // Also the way out of an embedding-model mismatch: the old index is discarded
     pub async fn clear_index(&mut self) -> Result<()> {
+        let store = self.store()?;
+        store.clear()?;
+        store.set_index_info(INDEX_VERSION, self.embedder.model_id(), self.embedder.dimension())?;
+
+        // A documents.json still waiting for migration would otherwise be imported on next start.
+        // It may be the only copy of an older index, so it is set aside rather than deleted.
+        let legacy_file = self.index_path.join(LEGACY_INDEX_FILE);
+        if legacy_file.exists() {
+            tokio::fs::rename(&legacy_file, legacy_file.with_extension("json.cleared")).await?;
+        }
+
+        self.index_mismatch = None;
         self.documents.clear();
-        self.save_index().await?;
+        self.doc_metadata.clear();
+        self.keyword_index = KeywordIndex::default();
         Ok(())
     }
 
-    pub fn get_document_count(&self) -> usize {
-        self.documents.len()
+    // Sources of documents none of whose chunks has a page or section location: indexed before
+    // chunks carried one (index v2), or without any pages or headings to find
+    pub fn sources_missing_locations(&self) -> Vec<String> {
+        let located: HashSet<&str> = self.documents
+            .values()
+            .filter(|doc| doc.source_location.is_some())
+            .filter_map(|doc| doc.parent_id.as_deref())
+            .collect();
+        let mut sources: Vec<String> = self.doc_metadata
+            .iter()
+            .filter(|(id, _)| !located.contains(id.as_str()))
+            .filter_map(|(_, metadata)| metadata["source"].as_str().map(str::to_string))
+            .collect();
+        sources.sort();
+        sources.dedup();
+        sources
     }
-}
\ No newline at end of file
+
+    pub fn contains_content_hash(&self, hash: &str) -> bool {
+        self.doc_metadata
+            .values()
+            .any(|metadata| metadata["content_hash"].as_str() == Some(hash))
+    }
+
+    // Documents whose ingest-time citation list contains `identifier` (see citations::Citation)
+    pub fn documents_citing(&self, identifier: &str) -> Vec<(String, JsonValue)> {
+        let mut found: Vec<(String, JsonValue)> = self.doc_metadata
+            .iter()
+            .filter(|(_, metadata)| {
+                metadata["citations"]
+                    .as_array()
+                    .map(|citations| citations.iter().any(|c| c["identifier"].as_str() == Some(identifier)))
+                    .unwrap_or(false)
+            })
+            .map(|(id, metadata)| (id.clone(), metadata.clone()))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    // Walks ids and content lengths only; embeddings are never touched
+    pub fn get_stats(&self) -> IndexStats {
+        let parents: HashSet<&str> = self.documents
+            .keys()
+            .map(|id| parent_doc_id(id))
+            .collect();
+
+        // Not the rest of rag_index/, such as a documents.json.migrated backup
+        let files: Vec<std::fs::Metadata> = ["", "-wal", "-shm"]
+            .iter()
+            .filter_map(|suffix| std::fs::metadata(self.index_path.join(format!("{}{}", INDEX_DB_FILE, suffix))).ok())
+            .collect();
+        let last_modified = files
+            .iter()
+            .filter_map(|m| m.modified().ok())
+            .max()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs() as i64);
+
+        IndexStats {
+            document_count: parents.len(),
+            chunk_count: self.documents.len(),
+            total_size_bytes: self.documents.values().map(|d| d.content.len() as u64).sum(),
+            index_size_bytes: files.iter().map(|m| m.len()).sum(),
+            last_modified,
+            embedding_model: self.embedder.model_id().to_string(),
+            embedding_dim: self.embedder.dimension(),
+        }
+    }
+}
+
+// Greater means better: higher score, then earlier in scan order, which matches a stable
+// descending sort of every score
+struct RankedHit<'a> {
+    score: f32,
+    order: usize,
+    id: &'a String,
+}
+
+impl Ord for RankedHit<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for RankedHit<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedHit<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedHit<'_> {}
+
+// Best first; only the winners are materialized by `result_json`
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    // zip() would silently compare a prefix of the longer vector
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a * norm_b > 0.0 {
+        dot_product / (norm_a * norm_b)
+    } else {
+        0.0
+    }
+}
+
+fn owned_hits(hits: Vec<(&String, f32)>) -> Vec<(String, f32)> {
+    hits.into_iter().map(|(id, score)| (id.clone(), score)).collect()
+}
+
+fn ranked<'a>(top: &BinaryHeap<Reverse<RankedHit<'a>>>) -> Vec<(&'a String, f32)> {
+    let mut hits: Vec<&RankedHit<'a>> = top.iter().map(|Reverse(hit)| hit).collect();
+    hits.sort_by(|a, b| b.cmp(a));
+    hits.into_iter().map(|hit| (hit.id, hit.score)).collect()
+}
+
+// Windows of `size` words advancing by `size - overlap`; the last one ends at the final word,
+// so every word lands in some chunk and no chunk is a suffix of the one before it
+fn chunk_by_words(text: &str, params: &ChunkParams) -> Vec<TextChunk> {
+    let spans = word_spans(text);
+    let mut chunks = Vec::new();
+    // ChunkParams::validate rejects these, but a zero stride would panic in step_by
+    let size = params.size.max(1);
+    let overlap = params.overlap.min(size - 1);
+
+    for i in (0..spans.len()).step_by(size - overlap) {
+        let end = std::cmp::min(i + size, spans.len());
+        chunks.push(TextChunk {
+            text: spans[i..end].iter().map(|&(s, e)| &text[s..e]).collect::<Vec<_>>().join(" "),
+            start: i,
+            end,
+            unit: ChunkUnit::Words,
+            byte_start: spans[i].0,
+            byte_end: spans[end - 1].1,
+        });
+        if end == spans.len() {
+            break;
+        }
+    }
+
+    chunks
+}
+
+// Byte spans of the same words `split_whitespace` yields
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, ch) in text.char_indices() {
+        match (ch.is_whitespace(), start) {
+            (true, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+// Slices the original text at token boundaries so no chunk exceeds the embedder's max sequence length
+fn chunk_by_tokens(embedder: &dyn EmbeddingModel, text: &str, offsets: &[(usize, usize)], params: &ChunkParams) -> Vec<TextChunk> {
+    let size = token_chunk_size(embedder, params.size);
+    let overlap = params.overlap.min(size - 1);
+    let mut chunks = Vec::new();
+
+    for i in (0..offsets.len()).step_by(size - overlap) {
+        let end = std::cmp::min(i + size, offsets.len());
+        let (start_byte, end_byte) = (offsets[i].0, offsets[end - 1].1);
+        chunks.push(TextChunk {
+            text: text.get(start_byte..end_byte).unwrap_or_default().trim().to_string(),
+            start: i,
+            end,
+            unit: ChunkUnit::Tokens,
+            byte_start: start_byte,
+            byte_end: end_byte,
+        });
+        if end == offsets.len() {
+            break;
+        }
+    }
+
+    chunks
+}
+
+fn token_chunk_size(embedder: &dyn EmbeddingModel, size: usize) -> usize {
+    match embedder.max_sequence_length() {
+        Some(max) => size.min(max.saturating_sub(SPECIAL_TOKEN_ALLOWANCE)),
+        None => size,
+    }
+    .max(1)
+}
+
+// Packs whole segments (byte spans of sentences or paragraphs) into chunks of at most `size`
+// units, measured in tokens when `token_offsets` is given and words otherwise
+fn chunk_by_segments(
+    embedder: &dyn EmbeddingModel,
+    text: &str,
+    segments: &[(usize, usize)],
+    token_offsets: Option<Vec<(usize, usize)>>,
+    params: &ChunkParams,
+) -> Vec<TextChunk> {
+    let (units, unit, size) = match token_offsets {
+        Some(offsets) => (offsets, ChunkUnit::Tokens, token_chunk_size(embedder, params.size)),
+        None => (word_spans(text), ChunkUnit::Words, params.size.max(1)),
+    };
+    let overlap = params.overlap.min(size - 1);
+
+    // Each segment as the half-open range of units starting inside it
+    let ranges: Vec<(usize, usize)> = segments
+        .iter()
+        .map(|&(start, end)| {
+            (units.partition_point(|u| u.0 < start), units.partition_point(|u| u.0 < end))
+        })
+        .filter(|(first, last)| first < last)
+        .collect();
+
+    let make_chunk = |first: usize, last: usize| {
+        let (byte_start, byte_end) = (units[first].0, units[last - 1].1);
+        TextChunk {
+            text: text.get(byte_start..byte_end).unwrap_or_default().trim().to_string(),
+            start: first,
+            end: last,
+            unit,
+            byte_start,
+            byte_end,
+        }
+    };
+
+    let mut chunks = Vec::new();
+    let mut i = 0;
+    while i < ranges.len() {
+        let first = ranges[i].0;
+        let mut j = i;
+        while j < ranges.len() && ranges[j].1 - first <= size {
+            j += 1;
+        }
+
+        if j == i {
+            // One segment longer than a chunk: fixed windows over it
+            let (start, end) = ranges[i];
+            let mut window = start;
+            loop {
+                let window_end = (window + size).min(end);
+                chunks.push(make_chunk(window, window_end));
+                if window_end == end {
+                    break;
+                }
+                window += size - overlap;
+            }
+            i += 1;
+            continue;
+        }
+
+        chunks.push(make_chunk(first, ranges[j - 1].1));
+        if j == ranges.len() {
+            break;
+        }
+
+        // Carry over the trailing segments that fit in `overlap`, but always move forward
+        let mut next = j;
+        while next > i + 1 && ranges[j - 1].1 - ranges[next - 1].0 <= overlap {
+            next -= 1;
+        }
+        i = next;
+    }
+
+    chunks
+}
+
+// Words that end in a period without ending the sentence ("Acme Inc. agrees", "Smith v. Jones").
+// Dotted forms such as "U.S." and "e.g." are recognised by their inner period instead.
+const ABBREVIATIONS: &[&str] = &[
+    "inc", "corp", "co", "ltd", "llc", "llp", "plc", "bros", "no", "nos", "mr", "mrs", "ms", "dr",
+    "prof", "jr", "sr", "st", "v", "vs", "sec", "secs", "art", "para", "paras", "cl", "ch", "p", "pp",
+    "fig", "ex", "cf", "al", "approx", "dept", "est", "gov", "jan", "feb", "mar", "apr", "jun", "jul",
+    "aug", "sep", "sept", "oct", "nov", "dec",
+];
+
+fn is_abbreviation(word: &str) -> bool {
+    let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let lower = word.to_lowercase();
+    // Dotted letters ("U.S.", "e.g."), but not numbers like "5.2."
+    (lower.contains('.') && lower.chars().all(|c| c.is_alphabetic() || c == '.'))
+        || (word.chars().count() == 1 && word.chars().all(char::is_alphabetic))
+        || ABBREVIATIONS.contains(&lower.as_str())
+}
+
+// Byte spans of sentences, trimmed. A sentence ends at . ! or ? (plus closing quotes and
+// brackets) before whitespace and a capital, digit or opening quote, unless the period closes an
+// abbreviation; a blank line always ends one, so headings don't run into the next sentence.
+fn sentence_spans(text: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut boundaries = Vec::new();
+    let mut k = 0;
+
+    while k < chars.len() {
+        let (pos, c) = chars[k];
+        if matches!(c, '.' | '!' | '?') {
+            let mut end = k + 1;
+            while end < chars.len() && matches!(chars[end].1, '"' | '\'' | ')' | ']' | '\u{201D}' | '\u{2019}') {
+                end += 1;
+            }
+            let next = chars[end..].iter().find(|(_, c)| !c.is_whitespace());
+            let spaced = end == chars.len() || chars[end].1.is_whitespace();
+            let starts_sentence = next.is_none_or(|(_, c)| {
+                c.is_uppercase() || c.is_ascii_digit() || matches!(c, '"' | '\'' | '(' | '[' | '\u{201C}' | '\u{2018}')
+            });
+            let word = text[..pos].rsplit(char::is_whitespace).next().unwrap_or("");
+            if spaced && starts_sentence && (c != '.' || !is_abbreviation(word)) {
+                let byte_end = chars.get(end).map_or(text.len(), |(p, _)| *p);
+                boundaries.push(byte_end);
+            }
+            k = end;
+        } else if c == '\n' {
+            let blank_line = chars[k + 1..]
+                .iter()
+                .take_while(|(_, c)| c.is_whitespace())
+                .any(|(_, c)| *c == '\n');
+            if blank_line {
+                boundaries.push(pos);
+            }
+            k += 1;
+        } else {
+            k += 1;
+        }
+    }
+    boundaries.push(text.len());
+
+    trimmed_spans(text, &boundaries)
+}
+
+fn paragraph_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut boundaries: Vec<usize> = PARAGRAPH_BREAK_REGEX.find_iter(text).map(|m| m.start()).collect();
+    boundaries.push(text.len());
+    trimmed_spans(text, &boundaries)
+}
+
+// Splits `text` at the given ascending byte offsets, dropping surrounding whitespace and empty pieces
+fn trimmed_spans(text: &str, boundaries: &[usize]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for &boundary in boundaries {
+        let piece = &text[start..boundary];
+        let leading = piece.len() - piece.trim_start().len();
+        let trimmed = piece.trim();
+        if !trimmed.is_empty() {
+            spans.push((start + leading, start + leading + trimmed.len()));
+        }
+        start = boundary;
+    }
+    spans
+}
+
+// Coalesces embedding requests from concurrent ingestions, so small documents share forward
+// passes instead of each running a mostly empty batch. Whoever takes the runner embeds everything
+// queued so far; requests arriving meanwhile go in the next round.
+#[derive(Default)]
+struct EmbedQueue {
+    pending: std::sync::Mutex<Vec<QueuedTexts>>,
+    runner: tokio::sync::Mutex<()>,
+}
+
+struct QueuedTexts {
+    model: Arc<dyn EmbeddingModel>,
+    texts: Vec<String>,
+    reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<Vec<f32>>, String>>,
+}
+
+impl EmbedQueue {
+    async fn embed(&self, model: Arc<dyn EmbeddingModel>, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let (reply, embedded) = tokio::sync::oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(QueuedTexts { model, texts, reply });
+
+        {
+            let _runner = self.runner.lock().await;
+            let queued = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+            if !queued.is_empty() {
+                // A forward pass can take seconds; keep it off the async workers
+                tokio::task::spawn_blocking(move || embed_queued(queued)).await?;
+            }
+        }
+
+        embedded
+            .await
+            .map_err(|_| anyhow!("Embedding stopped unexpectedly"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+// One embed_with call per model over all queued texts, handed back to each request in order
+fn embed_queued(queued: Vec<QueuedTexts>) {
+    let mut by_model: Vec<Vec<QueuedTexts>> = Vec::new();
+    for item in queued {
+        match by_model.iter_mut().find(|group| group[0].model.model_id() == item.model.model_id()) {
+            Some(group) => group.push(item),
+            None => by_model.push(vec![item]),
+        }
+    }
+
+    for group in by_model {
+        let texts: Vec<String> = group.iter().flat_map(|item| item.texts.iter().cloned()).collect();
+        match embed_with(group[0].model.as_ref(), &texts) {
+            Ok(embeddings) => {
+                let mut embeddings = embeddings.into_iter();
+                for item in group {
+                    let own = embeddings.by_ref().take(item.texts.len()).collect();
+                    // The requester may have been cancelled meanwhile
+                    let _ = item.reply.send(Ok(own));
+                }
+            }
+            Err(e) => {
+                for item in group {
+                    let _ = item.reply.send(Err(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+// Normalizes and embeds in groups of at most `max_batch_size`, checking the backend's output shape
+struct EmbedGroup {
+    model: Arc<dyn EmbeddingModel>,
+    ids: Vec<String>,
+    texts: Vec<String>,
+}
+
+fn embed_groups(groups: Vec<EmbedGroup>) -> Result<HashMap<String, Vec<f32>>> {
+    let mut embeddings = HashMap::new();
+    for group in groups {
+        let vectors = embed_with(group.model.as_ref(), &group.texts)?;
+        embeddings.extend(group.ids.into_iter().zip(vectors));
+    }
+    Ok(embeddings)
+}
+
+fn embed_with(embedder: &dyn EmbeddingModel, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let dimension = embedder.dimension();
+    let mut all = Vec::with_capacity(texts.len());
+
+    for batch in texts.chunks(embedder.max_batch_size().max(1)) {
+        let normalized: Vec<String> = batch
+            .iter()
+            .map(|text| crate::text_normalizer::normalize_text(text))
+            .collect();
+        let embeddings = embedder.embed(&normalized)?;
+
+        if embeddings.len() != batch.len() {
+            return Err(anyhow!(
+                "Embedding model {} returned {} vectors for {} texts",
+                embedder.model_id(),
+                embeddings.len(),
+                batch.len()
+            ));
+        }
+        if let Some(bad) = embeddings.iter().find(|e| e.len() != dimension) {
+            return Err(anyhow!(
+                "Embedding model {} returned {} dimensions, expected {}",
+                embedder.model_id(),
+                bad.len(),
+                dimension
+            ));
+        }
+
+        all.extend(embeddings);
+    }
+
+    Ok(all)
+}
+
+// Lowercased alphanumeric runs for keyword scoring, used for both chunks and queries. Runs joined
+// by `.`, `-` or `/` stay one token, so "12-345", "1983.1" and "U.S.C" match only as a whole.
+fn keyword_tokens(text: &str) -> Vec<String> {
+    let normalized = crate::text_normalizer::normalize_text(text).to_lowercase();
+    let chars: Vec<char> = normalized.chars().collect();
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        // Dots, hyphens and slashes inside a token keep "u.s.c", "12-345" and "and/or" whole
+        let joins_token = matches!(c, '.' | '-' | '/')
+            && !current.is_empty()
+            && chars.get(i + 1).is_some_and(|next| next.is_alphanumeric());
+        if c.is_alphanumeric() || joins_token {
+            current.push(*c);
+        } else if !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+// Used to recognise re-ingestion of an already indexed file
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+// Chunk ids are `{doc_id}_{chunk_index}`
+fn parent_doc_id(chunk_id: &str) -> &str {
+    chunk_id.rsplit_once('_').map(|(parent, _)| parent).unwrap_or(chunk_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::CharHashEmbedder;
+
+    async fn engine(dir: &Path) -> RAGEngine {
+        let mut rag = RAGEngine::new(dir);
+        rag.initialize().await.unwrap();
+        rag
+    }
+
+    #[tokio::test]
+    async fn test_reingested_source_replaces_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        let lease = serde_json::json!({ "source": "/watched/lease.txt" });
+        rag.add_document("The rent is 900 dollars per month.", lease.clone(), None, None).await.unwrap();
+        rag.add_document("Unrelated memo about parking.", serde_json::json!({ "source": "/watched/memo.txt" }), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(rag.remove_documents_from_source("/watched/lease.txt").await.unwrap(), 1);
+        rag.add_document("The rent is 950 dollars per month.", lease, None, None).await.unwrap();
+
+        assert_eq!(rag.doc_metadata.len(), 2);
+        let contents: Vec<String> = rag.search("rent per month", 10)
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|r| r["content"].as_str().map(str::to_string))
+            .collect();
+        assert!(contents.iter().any(|c| c.contains("950")));
+        assert!(!contents.iter().any(|c| c.contains("900")));
+    }
+
+    // Two-dimensional vectors decided by the first letter, so rankings are known in advance
+    struct FirstLetterModel;
+
+    impl EmbeddingModel for FirstLetterModel {
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            Ok(texts.iter().map(|text| if text.starts_with('A') { vec![1.0, 0.0] } else { vec![0.0, 1.0] }).collect())
+        }
+
+        fn dimension(&self) -> usize {
+            2
+        }
+
+        fn model_id(&self) -> &str {
+            "test-first-letter"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_embedder_decides_the_ranking() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(FirstLetterModel));
+        rag.initialize().await.unwrap();
+        rag.add_document("Bills of lading for the cargo.", serde_json::json!({}), None, None).await.unwrap();
+        rag.add_document("A lease for the warehouse.", serde_json::json!({}), None, None).await.unwrap();
+
+        let results = rag.search("Another question", 2).await.unwrap();
+        assert_eq!(results[0]["content"], "A lease for the warehouse.");
+        let results = rag.search("Something else", 2).await.unwrap();
+        assert_eq!(results[0]["content"], "Bills of lading for the cargo.");
+    }
+
+    // CharHashEmbedder under another model id, standing in for a collection's own model
+    struct CollectionModel(CharHashEmbedder);
+
+    impl EmbeddingModel for CollectionModel {
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.0.embed(texts)
+        }
+
+        fn dimension(&self) -> usize {
+            self.0.dimension()
+        }
+
+        fn model_id(&self) -> &str {
+            "contracts-model"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_search_keeps_results_when_one_collection_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        rag.add_document("Notice periods for terminating the lease.", serde_json::json!({ "collection": "leases" }), None, None)
+            .await
+            .unwrap();
+        // Assigned a model that is no longer installed
+        rag.collection_models.insert("contracts".to_string(), "contracts-model".to_string());
+
+        let collections = [Some("leases".to_string()), Some("contracts".to_string())];
+        let found = rag.multi_search("lease notice", &collections, 5).await.unwrap();
+        assert_eq!(found.results.len(), 1);
+        assert_eq!(found.results[0]["source"], "leases");
+        assert_eq!(found.errors.len(), 1);
+        assert_eq!(found.errors[0].source, "contracts");
+        assert!(found.errors[0].error.contains("not loaded"));
+
+        assert!(rag.multi_search("lease notice", &collections[1..], 5).await.is_err());
+    }
+
+    // CharHashEmbedder vectors, with a fixed cost per forward pass like a real model
+    struct TimedModel {
+        inner: CharHashEmbedder,
+        batch_size: usize,
+        pass_cost: std::time::Duration,
+        passes: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl TimedModel {
+        fn new(batch_size: usize, pass_cost_ms: u64) -> (Self, Arc<std::sync::atomic::AtomicUsize>) {
+            let passes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let model = Self {
+                inner: CharHashEmbedder::default(),
+                batch_size,
+                pass_cost: std::time::Duration::from_millis(pass_cost_ms),
+                passes: passes.clone(),
+            };
+            (model, passes)
+        }
+    }
+
+    impl EmbeddingModel for TimedModel {
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.passes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::thread::sleep(self.pass_cost);
+            self.inner.embed(texts)
+        }
+
+        fn dimension(&self) -> usize {
+            self.inner.dimension()
+        }
+
+        fn max_batch_size(&self) -> usize {
+            self.batch_size
+        }
+
+        fn model_id(&self) -> &str {
+            self.inner.model_id()
+        }
+    }
+
+    // Benchmark-style: a 200-page document embedded one chunk per pass, then in batches of 32
+    #[tokio::test]
+    async fn test_batched_embedding_speeds_up_a_long_document() {
+        let content: String = (1..=200)
+            .map(|page| format!("Page {}. {}\n\n", page, "The tenant shall pay the rent when due. ".repeat(40)))
+            .collect();
+
+        let mut runs = Vec::new();
+        for batch_size in [1, 32] {
+            let dir = tempfile::tempdir().unwrap();
+            let (model, passes) = TimedModel::new(batch_size, 5);
+            let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(model));
+            rag.initialize().await.unwrap();
+
+            let started = std::time::Instant::now();
+            rag.add_document(&content, serde_json::json!({}), None, None).await.unwrap();
+            runs.push((passes.load(std::sync::atomic::Ordering::SeqCst), started.elapsed(), rag.documents.len()));
+        }
+
+        let ((unbatched_passes, unbatched_time, chunks), (batched_passes, batched_time, _)) = (runs[0], runs[1]);
+        println!(
+            "200 pages, {} chunks: {} passes in {:?} unbatched, {} passes in {:?} batched",
+            chunks, unbatched_passes, unbatched_time, batched_passes, batched_time
+        );
+        assert_eq!(unbatched_passes, chunks);
+        assert_eq!(batched_passes, chunks.div_ceil(32));
+        assert!(batched_time < unbatched_time);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_documents_share_forward_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (model, passes) = TimedModel::new(32, 20);
+        let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(model));
+        rag.initialize().await.unwrap();
+
+        let texts: Vec<String> = (0..8).map(|i| format!("Memo {} about the parking lease.", i)).collect();
+        let cancel = CancellationToken::new();
+        let (rag, texts_ref, cancel) = (&rag, &texts, &cancel);
+        let prepare = move |i: usize| rag.prepare_document(&texts_ref[i], serde_json::json!({}), None, None, cancel, |_, _| {});
+        let prepared = tokio::join!(
+            prepare(0), prepare(1), prepare(2), prepare(3),
+            prepare(4), prepare(5), prepare(6), prepare(7),
+        );
+        let prepared = [prepared.0, prepared.1, prepared.2, prepared.3, prepared.4, prepared.5, prepared.6, prepared.7];
+
+        // The first document runs alone; the other seven queue behind it and share one pass
+        assert_eq!(passes.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let expected = embed_with(&CharHashEmbedder::default(), &texts).unwrap();
+        for (prepared, expected) in prepared.into_iter().zip(expected) {
+            let chunks = prepared.unwrap().chunks;
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(&chunks[0].embeddings[..], &expected[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clear_sets_aside_a_pending_legacy_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        rag.add_document("Notice period is thirty days.", serde_json::json!({}), None, None).await.unwrap();
+        let legacy_file = rag.index_path.join(LEGACY_INDEX_FILE);
+        std::fs::write(&legacy_file, "{}").unwrap();
+
+        rag.clear_index().await.unwrap();
+
+        assert_eq!(rag.documents.len(), 0);
+        assert!(!legacy_file.exists());
+        assert_eq!(std::fs::read_to_string(legacy_file.with_extension("json.cleared")).unwrap(), "{}");
+        // Not imported again on the next start
+        assert_eq!(engine(dir.path()).await.documents.len(), 0);
+    }
+
+    // Benchmark-style: adding to a large index writes only the new rows, so it costs about the
+    // same as adding to an empty one
+    #[tokio::test]
+    async fn test_insert_time_does_not_grow_with_the_corpus() {
+        async fn median_insert(rag: &mut RAGEngine) -> std::time::Duration {
+            let mut times = Vec::new();
+            for i in 0..7 {
+                let started = std::time::Instant::now();
+                rag.add_document(&format!("Amendment {} extends the lease term.", i), serde_json::json!({}), None, None)
+                    .await
+                    .unwrap();
+                times.push(started.elapsed());
+            }
+            times.sort();
+            times[times.len() / 2]
+        }
+
+        let empty_dir = tempfile::tempdir().unwrap();
+        let empty = median_insert(&mut engine(empty_dir.path()).await).await;
+
+        let large_dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(large_dir.path()).await;
+        let texts: Vec<String> = (0..20_000).map(|i| format!("Clause {} of the master agreement", i)).collect();
+        let vectors = embed_with(rag.embedder_for(None).unwrap().as_ref(), &texts).unwrap();
+        for (i, (content, vector)) in texts.into_iter().zip(vectors).enumerate() {
+            let id = format!("master_{}", i);
+            rag.documents.insert(id.clone(), Document {
+                id,
+                content,
+                metadata: serde_json::json!({}),
+                embeddings: vector.into(),
+                timestamp: 0,
+                parent_id: None,
+                collection: None,
+                source_location: None,
+            });
+        }
+        rag.store().unwrap().import(&rag.documents, &rag.doc_metadata, &rag.collection_models).unwrap();
+        let large = median_insert(&mut rag).await;
+
+        println!("median insert: {:?} into an empty index, {:?} into 20000 chunks", empty, large);
+        assert!(large < empty * 5 + std::time::Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_detached_scan_matches_search_and_can_be_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        let texts: Vec<String> = (0..2000)
+            .map(|i| format!("Clause {} of lease {} covers the monthly rent", i % 37, i))
+            .collect();
+        let vectors = embed_with(rag.embedder_for(None).unwrap().as_ref(), &texts).unwrap();
+        for (i, (content, vector)) in texts.into_iter().zip(vectors).enumerate() {
+            let id = format!("lease_{}", i);
+            rag.documents.insert(id.clone(), Document {
+                id,
+                content,
+                metadata: serde_json::json!({}),
+                embeddings: vector.into(),
+                timestamp: 0,
+                parent_id: None,
+                collection: None,
+                source_location: None,
+            });
+        }
+
+        let expected = rag.search("rent under clause 12", 5).await.unwrap();
+        let scan = rag.prepare_search("rent under clause 12", None).unwrap();
+        assert_eq!(scan.chunk_count(), 2000);
+        let hits = scan.rank(5, None, |_, partial| assert!(partial.len() <= 5)).unwrap();
+        assert_eq!(rag.result_json(&hits), expected);
+
+        // A chunk removed while the scan ran is dropped from the results
+        rag.documents.remove(&hits[0].0);
+        assert_eq!(rag.result_json(&hits).len(), 4);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(scan.rank(5, Some(&cancel), |_, _| {}).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_top_k_matches_sorting_every_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        for i in 0..60 {
+            let content = format!("Invoice {} for {} hours of contract review", i, i * 7 % 23);
+            rag.add_document(&content, serde_json::json!({}), None, None).await.unwrap();
+        }
+
+        // What search did before the heap: score and clone every chunk, sort, truncate
+        let query = "contract review hours";
+        let query_embedding = embed_with(rag.embedder_for(None).unwrap().as_ref(), &[query.to_string()]).unwrap().remove(0);
+        let mut everything: Vec<(String, f32)> = rag.documents
+            .values()
+            .map(|doc| (doc.id.clone(), cosine_similarity(&query_embedding, &doc.embeddings)))
+            .collect();
+        everything.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        everything.truncate(5);
+
+        let results = rag.search(query, 5).await.unwrap();
+        assert_eq!(results.len(), 5);
+        let found: Vec<(String, f32)> = results
+            .iter()
+            .map(|r| (r["id"].as_str().unwrap().to_string(), r["score"].as_f64().unwrap() as f32))
+            .collect();
+        assert_eq!(found, everything);
+    }
+
+    #[tokio::test]
+    async fn test_index_with_other_dimension_is_reembedded() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(CharHashEmbedder::new(384)));
+            rag.register_embedder(Box::new(CollectionModel(CharHashEmbedder::new(384))));
+            rag.initialize().await.unwrap();
+            rag.assign_collection_model("contracts", "contracts-model").await.unwrap();
+            rag.add_document("Notice period is thirty days.", serde_json::json!({}), None, None).await.unwrap();
+            rag.add_document("Supply agreement terms.", serde_json::json!({ "collection": "contracts" }), None, None)
+                .await
+                .unwrap();
+        }
+
+        let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(CharHashEmbedder::new(128)));
+        rag.register_embedder(Box::new(CollectionModel(CharHashEmbedder::new(64))));
+        assert!(rag.initialize().await.is_err());
+        assert!(rag.index_error().unwrap().contains("384"));
+        assert!(rag.search("notice period", 5).await.is_err());
+
+        let prepared = rag.prepare_reindex(Box::new(CharHashEmbedder::new(128))).await.unwrap();
+        assert_eq!(rag.commit_reindex(prepared).await.unwrap(), 2);
+        assert!(rag.index_error().is_none());
+        for doc in rag.documents.values() {
+            let expected = if doc.collection.as_deref() == Some("contracts") { 64 } else { 128 };
+            assert_eq!(doc.embeddings.len(), expected);
+        }
+        assert!(!rag.search("notice period", 5).await.unwrap().is_empty());
+
+        drop(rag);
+        let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(CharHashEmbedder::new(128)));
+        rag.initialize().await.unwrap();
+        assert_eq!(rag.documents.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_embedder_set_before_initialize_is_the_one_checked_against_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut rag = RAGEngine::with_embedder(dir.path(), Box::new(CharHashEmbedder::new(128)));
+            rag.initialize().await.unwrap();
+            rag.add_document("Notice period is thirty days.", serde_json::json!({}), None, None).await.unwrap();
+        }
+
+        // As at startup: the engine exists before the model has loaded
+        let mut rag = RAGEngine::new(dir.path());
+        rag.set_embedder(Box::new(CharHashEmbedder::new(128)));
+        rag.initialize().await.unwrap();
+        assert!(rag.index_error().is_none());
+        assert!(!rag.search("notice period", 5).await.unwrap().is_empty());
+    }
+
+    fn chunk_texts(strategy: ChunkStrategy, text: &str) -> Vec<String> {
+        let rag = RAGEngine::new(Path::new("unused"));
+        let params = ChunkParams { size: 12, overlap: 0, unit: ChunkUnit::Words, strategy };
+        rag.chunk_text(&CharHashEmbedder::default(), text, &params)
+            .into_iter()
+            .map(|chunk| chunk.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_sentence_boundaries_skip_abbreviations_but_not_section_numbers() {
+        let text = "Acme Inc. sold goods in the U.S. last year. Payment is due under Section 5.2. The Buyer shall pay.";
+        let sentences: Vec<&str> = sentence_spans(text).into_iter().map(|(start, end)| &text[start..end]).collect();
+        assert_eq!(sentences, [
+            "Acme Inc. sold goods in the U.S. last year.",
+            "Payment is due under Section 5.2.",
+            "The Buyer shall pay.",
+        ]);
+    }
+
+    #[test]
+    fn test_chunk_boundaries_by_strategy() {
+        let text = "The lease starts in May. Rent is due monthly on the first day.\n\n\
+                    The tenant keeps the premises clean. Repairs are the landlord's duty.";
+
+        let fixed = chunk_texts(ChunkStrategy::FixedWords, text);
+        assert_eq!(fixed[0], "The lease starts in May. Rent is due monthly on the first");
+        assert!(fixed.iter().any(|chunk| chunk.starts_with("day.")));
+
+        let sentences = chunk_texts(ChunkStrategy::Sentences, text);
+        // Whole sentences packed up to 12 words
+        assert_eq!(sentences, [
+            "The lease starts in May.",
+            "Rent is due monthly on the first day.",
+            "The tenant keeps the premises clean. Repairs are the landlord's duty.",
+        ]);
+
+        // The 13-word first paragraph is cut into windows; the second fits whole
+        let paragraphs = chunk_texts(ChunkStrategy::Paragraphs, text);
+        assert_eq!(paragraphs.last().unwrap(), "The tenant keeps the premises clean. Repairs are the landlord's duty.");
+        assert!(paragraphs.iter().all(|chunk| !(chunk.contains("May") && chunk.contains("tenant"))));
+        // Paragraphs that fit together are packed like sentences
+        assert_eq!(chunk_texts(ChunkStrategy::Paragraphs, "Short one.\n\nShort two."), ["Short one.\n\nShort two."]);
+    }
+
+    #[test]
+    fn test_word_windows_cover_a_count_that_is_not_a_multiple_of_the_stride() {
+        let text = (0..47).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ");
+        let params = ChunkParams { size: 10, overlap: 3, unit: ChunkUnit::Words, strategy: ChunkStrategy::FixedWords };
+        let chunks = chunk_by_words(&text, &params);
+
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks.last().unwrap().end, 47);
+        for pair in chunks.windows(2) {
+            // Consecutive windows overlap, so no word falls between them
+            assert!(pair[1].start < pair[0].end);
+            assert!(pair[1].end > pair[0].end);
+        }
+        let covered: HashSet<&str> = chunks.iter().flat_map(|chunk| chunk.text.split(' ')).collect();
+        assert_eq!(covered.len(), 47);
+
+        assert!(ChunkParams { overlap: 10, ..params }.validate().is_err());
+        assert!(ChunkParams { size: 0, overlap: 0, ..params }.validate().is_err());
+        // Even unvalidated params don't panic
+        assert_eq!(chunk_by_words(&text, &ChunkParams { overlap: 12, ..params }).last().unwrap().end, 47);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_search_ranks_an_exact_statute_number_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        for text in [
+            "Civil rights claims against state officials were dismissed.",
+            "Civil rights claims against state officials were settled.",
+            "Civil rights claims against state officials were appealed.",
+            "Section 1983 provides the remedy.",
+        ] {
+            rag.add_document(text, serde_json::json!({}), None, None).await.unwrap();
+        }
+
+        // Every chunk but the statute's shares most query words, so its vector score is mediocre
+        let query = "civil rights claims against state officials under section 1983";
+        let first = |results: Vec<JsonValue>| results[0]["content"].as_str().unwrap().to_string();
+        assert!(!first(rag.search_hybrid(query, 4, 1.0).await.unwrap()).contains("1983"));
+        assert!(first(rag.search_hybrid(query, 4, 0.3).await.unwrap()).contains("1983"));
+        assert!(rag.search_hybrid(query, 4, 1.5).await.is_err());
+    }
+
+    #[test]
+    fn test_page_markers_are_split_out_of_the_text() {
+        let (text, markers) = split_structure("[Page 1]\nRecitals.\n\n[Page 2]\n## Payment\nRent is due monthly.");
+        assert_eq!(text, "Recitals.\n\n## Payment\nRent is due monthly.");
+        let found: Vec<(usize, Option<u32>, Option<&str>)> = markers
+            .iter()
+            .map(|m| (m.offset, m.page, m.heading.as_deref()))
+            .collect();
+        assert_eq!(found, [(0, Some(1), None), (11, Some(2), None), (11, None, Some("Payment"))]);
+        // Only whole marker lines are removed
+        assert_eq!(strip_page_markers("See [Page 4] of the exhibit.").0, "See [Page 4] of the exhibit.");
+    }
+
+    #[tokio::test]
+    async fn test_indexed_chunks_keep_pages_as_locations_not_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        let pdf_text = "[Page 1]\nThe parties agree as follows.\n\n[Page 2]\nThe tenant pays rent monthly.";
+        rag.add_document(pdf_text, serde_json::json!({ "source": "/docs/lease.pdf" }), None, None).await.unwrap();
+        rag.add_document("An old memo.", serde_json::json!({ "source": "/docs/memo.txt" }), None, None).await.unwrap();
+
+        let results = rag.search("tenant rent", 2).await.unwrap();
+        let lease = results.iter().find(|r| r["content"].as_str().unwrap().contains("tenant")).unwrap();
+        assert!(!lease["content"].as_str().unwrap().contains("[Page"));
+        assert_eq!(lease["location"]["page_start"], 1);
+        assert_eq!(lease["location"]["page_end"], 2);
+        assert_eq!(rag.sources_missing_locations(), ["/docs/memo.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_documents_chunks_and_database_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rag = engine(dir.path()).await;
+        let words = |n: usize| vec!["clause"; n].join(" ");
+        let chunking = ChunkOptions { size: Some(10), overlap: Some(0), unit: Some(ChunkUnit::Words), strategy: None };
+        rag.add_document(&words(25), serde_json::json!({}), Some(chunking), None).await.unwrap();
+        rag.add_document(&words(5), serde_json::json!({}), Some(chunking), None).await.unwrap();
+
+        let before = rag.get_stats();
+        assert_eq!(before.document_count, 2);
+        assert_eq!(before.chunk_count, 4);
+        assert!(before.index_size_bytes > 0);
+
+        std::fs::write(dir.path().join("rag_index").join("documents.json.migrated"), vec![b' '; 1 << 20]).unwrap();
+        assert!(rag.get_stats().index_size_bytes < 1 << 20);
+    }
+}
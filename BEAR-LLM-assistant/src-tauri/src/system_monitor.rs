@@ -1,31 +1,58 @@
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::process::Command;
-use sysinfo::{CpuExt, System, SystemExt, ComponentExt, DiskExt, NetworkExt, PidExt, ProcessExt};
-use nvml_wrapper::Nvml;
+use sysinfo::{CpuExt, System, SystemExt};
+
+use crate::gpu_adapter::{self, GpuAdapter};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SystemSpecs {
+    // The selected device; `gpus` lists every device including this one
     pub gpu: GpuInfo,
+    pub gpus: Vec<GpuInfo>,
     pub cpu: CpuInfo,
     pub memory: MemoryInfo,
     pub os: String,
     pub capability_score: u32, // 0-100
+    pub capability_breakdown: CapabilityBreakdown,
+}
+
+// Where the capability score comes from, so users can see what to upgrade
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CapabilityBreakdown {
+    pub gpu_points: u32, // 0-50
+    pub cpu_points: u32, // 0-25
+    pub ram_points: u32, // 0-25
+    pub total: u32,
+    pub gpu_reason: String,
+    pub cpu_reason: String,
+    pub ram_reason: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GpuInfo {
     pub available: bool,
+    pub index: u32,
     pub name: String,
     pub vram_total_mb: u64,
     pub vram_used_mb: u64,
     pub vram_free_mb: u64,
     pub temperature: f32,
     pub utilization: u32,
+    #[serde(default)]
+    pub power_watts: u32,
     pub cuda_available: bool,
     pub compute_capability: String,
     pub driver_version: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GpuDevice {
+    pub index: u32,
+    pub name: String,
+    pub vram_total_mb: u64,
+    pub selected: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CpuInfo {
     pub brand: String,
@@ -43,6 +70,18 @@ pub struct MemoryInfo {
     pub usage_percent: f32,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct MemoryUsage {
+    pub ram_used_mb: u64,
+    pub vram_used_mb: u64,
+}
+
+impl MemoryUsage {
+    pub fn total_mb(&self) -> u64 {
+        self.ram_used_mb + self.vram_used_mb
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ModelCompatibility {
     pub model_name: String,
@@ -65,7 +104,8 @@ pub enum CompatibilityLevel {
 
 pub struct SystemMonitor {
     system: System,
-    nvml: Option<Nvml>,
+    adapters: Vec<Box<dyn GpuAdapter>>,
+    gpu_index: u32,
 }
 
 impl SystemMonitor {
@@ -73,122 +113,94 @@ impl SystemMonitor {
         let mut system = System::new_all();
         system.refresh_all();
 
-        // Try to initialize NVIDIA Management Library
-        let nvml = Nvml::init().ok();
+        let adapters = gpu_adapter::detect();
+        let gpu_index = gpu_adapter::largest_vram_index(&gpu_adapter::all_gpus(&adapters)).unwrap_or(0);
+
+        SystemMonitor { system, adapters, gpu_index }
+    }
+
+    // Every GPU any adapter reports; NVIDIA devices first, at their CUDA ordinals
+    pub fn gpus(&self) -> Vec<GpuInfo> {
+        gpu_adapter::all_gpus(&self.adapters)
+    }
+
+    pub fn gpu_index(&self) -> u32 {
+        self.gpu_index
+    }
 
-        SystemMonitor { system, nvml }
+    // None re-selects the largest-VRAM device
+    pub fn set_gpu_index(&mut self, index: Option<u32>) -> Result<u32> {
+        self.gpu_index = match index {
+            Some(index) => {
+                let count = self.gpus().len() as u32;
+                if index >= count {
+                    return Err(anyhow!("GPU index {} out of range ({} devices found)", index, count));
+                }
+                index
+            }
+            None => gpu_adapter::largest_vram_index(&self.gpus()).unwrap_or(0),
+        };
+        Ok(self.gpu_index)
+    }
+
+    pub fn list_gpus(&self) -> Vec<GpuDevice> {
+        self.gpus()
+            .into_iter()
+            .map(|gpu| GpuDevice {
+                index: gpu.index,
+                name: gpu.name,
+                vram_total_mb: gpu.vram_total_mb,
+                selected: gpu.index == self.gpu_index,
+            })
+            .collect()
     }
 
     pub fn get_system_specs(&mut self) -> SystemSpecs {
         self.system.refresh_all();
 
-        let gpu = self.get_gpu_info();
+        let gpus = self.gpus();
+        let gpu = self.select_primary(&gpus);
         let cpu = self.get_cpu_info();
         let memory = self.get_memory_info();
         let os = self.get_os_info();
 
-        let capability_score = self.calculate_capability_score(&gpu, &cpu, &memory);
+        let capability_breakdown = calculate_capability_breakdown(&gpus, &cpu, &memory);
 
         SystemSpecs {
             gpu,
+            gpus,
             cpu,
             memory,
             os,
-            capability_score,
+            capability_score: capability_breakdown.total,
+            capability_breakdown,
         }
     }
 
-    fn get_gpu_info(&self) -> GpuInfo {
-        if let Some(ref nvml) = self.nvml {
-            // NVIDIA GPU detected
-            if let Ok(device_count) = nvml.device_count() {
-                if device_count > 0 {
-                    if let Ok(device) = nvml.device_by_index(0) {
-                        let name = device.name().unwrap_or_else(|_| "Unknown GPU".to_string());
-
-                        let mem_info = device.memory_info().unwrap_or_else(|_| {
-                            nvml_wrapper::structs::device::MemoryInfo {
-                                total: 0,
-                                free: 0,
-                                used: 0,
-                            }
-                        });
-
-                        let temperature = device
-                            .temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu)
-                            .unwrap_or(0) as f32;
-
-                        let utilization = device
-                            .utilization_rates()
-                            .map(|u| u.gpu)
-                            .unwrap_or(0);
-
-                        let compute_cap = device
-                            .cuda_compute_capability()
-                            .map(|cc| format!("{}.{}", cc.major, cc.minor))
-                            .unwrap_or_else(|_| "Unknown".to_string());
-
-                        let driver_version = nvml
-                            .sys_driver_version()
-                            .unwrap_or_else(|_| "Unknown".to_string());
-
-                        return GpuInfo {
-                            available: true,
-                            name,
-                            vram_total_mb: mem_info.total / 1_048_576,
-                            vram_used_mb: mem_info.used / 1_048_576,
-                            vram_free_mb: mem_info.free / 1_048_576,
-                            temperature,
-                            utilization,
-                            cuda_available: true,
-                            compute_capability: compute_cap,
-                            driver_version,
-                        };
-                    }
-                }
-            }
-        }
-
-        // Check for AMD GPU using rocm-smi or Windows WMI
-        #[cfg(target_os = "windows")]
-        {
-            if let Ok(output) = Command::new("wmic")
-                .args(&["path", "win32_VideoController", "get", "name,AdapterRAM"])
-                .output()
-            {
-                let output_str = String::from_utf8_lossy(&output.stdout);
-                if output_str.contains("AMD") || output_str.contains("Radeon") {
-                    // Parse AMD GPU info from WMI
-                    // This is simplified - in production you'd parse properly
-                    return GpuInfo {
-                        available: true,
-                        name: "AMD GPU Detected".to_string(),
-                        vram_total_mb: 8192, // Would parse from WMI
-                        vram_used_mb: 0,
-                        vram_free_mb: 8192,
-                        temperature: 0.0,
-                        utilization: 0,
-                        cuda_available: false,
-                        compute_capability: "ROCm".to_string(),
-                        driver_version: "Unknown".to_string(),
-                    };
-                }
-            }
-        }
+    // The device selected with set_gpu_index
+    pub fn primary_gpu(&self) -> GpuInfo {
+        self.select_primary(&self.gpus())
+    }
 
-        // No GPU detected or CPU only
-        GpuInfo {
-            available: false,
-            name: "No GPU detected".to_string(),
-            vram_total_mb: 0,
-            vram_used_mb: 0,
-            vram_free_mb: 0,
-            temperature: 0.0,
-            utilization: 0,
-            cuda_available: false,
-            compute_capability: "N/A".to_string(),
-            driver_version: "N/A".to_string(),
-        }
+    fn select_primary(&self, gpus: &[GpuInfo]) -> GpuInfo {
+        gpus.iter()
+            .find(|gpu| gpu.index == self.gpu_index)
+            .cloned()
+            .unwrap_or_else(|| GpuInfo {
+                // No GPU detected or CPU only
+                available: false,
+                index: 0,
+                name: "No GPU detected".to_string(),
+                vram_total_mb: 0,
+                vram_used_mb: 0,
+                vram_free_mb: 0,
+                temperature: 0.0,
+                utilization: 0,
+                power_watts: 0,
+                cuda_available: false,
+                compute_capability: "N/A".to_string(),
+                driver_version: "N/A".to_string(),
+            })
     }
 
     fn get_cpu_info(&mut self) -> CpuInfo {
@@ -200,12 +212,7 @@ impl SystemMonitor {
         let usage_percent = self.system.global_cpu_info().cpu_usage();
 
         // Get CPU temperature
-        let temperature = self.system
-            .components()
-            .iter()
-            .find(|c| c.label().contains("CPU") || c.label().contains("Core"))
-            .map(|c| c.temperature())
-            .unwrap_or(0.0);
+        let temperature = crate::thermal::cpu_temperature(&self.system).unwrap_or(0.0);
 
         CpuInfo {
             brand,
@@ -236,60 +243,20 @@ impl SystemMonitor {
         format!(
             "{} {}",
             self.system.name().unwrap_or_else(|| "Unknown".to_string()),
-            self.system.os_version().unwrap_or_else(|| "".to_string())
+            self.system.os_version().unwrap_or_default()
         )
     }
 
-    fn calculate_capability_score(&self, gpu: &GpuInfo, cpu: &CpuInfo, memory: &MemoryInfo) -> u32 {
-        let mut score = 0u32;
-
-        // GPU scoring (0-50 points)
-        if gpu.available {
-            if gpu.cuda_available {
-                score += 10; // CUDA available
-            }
-
-            // VRAM scoring
-            score += match gpu.vram_total_mb {
-                v if v >= 24576 => 40, // 24GB+ - Excellent
-                v if v >= 16384 => 35, // 16GB+ - Very good
-                v if v >= 12288 => 30, // 12GB+ - Good
-                v if v >= 8192 => 25,  // 8GB+ - Decent
-                v if v >= 6144 => 20,  // 6GB+ - Minimum for most models
-                v if v >= 4096 => 15,  // 4GB+ - Limited
-                _ => 5,                 // Less than 4GB
-            };
-        }
+    // Cheap snapshot of resident RAM/VRAM, used to measure load/unload footprints
+    pub fn memory_usage(&mut self) -> MemoryUsage {
+        self.system.refresh_memory();
 
-        // CPU scoring (0-25 points)
-        score += match cpu.core_count {
-            c if c >= 16 => 15,
-            c if c >= 12 => 12,
-            c if c >= 8 => 10,
-            c if c >= 6 => 8,
-            c if c >= 4 => 5,
-            _ => 2,
-        };
+        let vram_used_mb = self.primary_gpu().vram_used_mb;
 
-        // CPU frequency bonus
-        if cpu.frequency_mhz >= 4000 {
-            score += 10;
-        } else if cpu.frequency_mhz >= 3500 {
-            score += 7;
-        } else if cpu.frequency_mhz >= 3000 {
-            score += 5;
+        MemoryUsage {
+            ram_used_mb: self.system.used_memory() / 1024,
+            vram_used_mb,
         }
-
-        // RAM scoring (0-25 points)
-        score += match memory.total_mb {
-            m if m >= 65536 => 25,  // 64GB+ - Excellent
-            m if m >= 32768 => 20,  // 32GB+ - Very good
-            m if m >= 16384 => 15,  // 16GB+ - Good
-            m if m >= 8192 => 10,   // 8GB+ - Minimum
-            _ => 5,                  // Less than 8GB
-        };
-
-        score.min(100) // Cap at 100
     }
 
     pub fn check_model_compatibility(&mut self, model_params: &ModelParams) -> ModelCompatibility {
@@ -365,29 +332,17 @@ impl SystemMonitor {
     pub fn monitor_resources_realtime(&mut self) -> ResourceSnapshot {
         self.system.refresh_all();
 
-        let gpu_snapshot = if let Some(ref nvml) = self.nvml {
-            if let Ok(device) = nvml.device_by_index(0) {
-                GpuSnapshot {
-                    vram_used_percent: {
-                        let mem = device.memory_info().unwrap();
-                        (mem.used as f32 / mem.total as f32) * 100.0
-                    },
-                    utilization: device.utilization_rates().map(|u| u.gpu).unwrap_or(0),
-                    temperature: device
-                        .temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu)
-                        .unwrap_or(0) as f32,
-                    power_watts: device.power_usage().unwrap_or(0) / 1000,
-                }
-            } else {
-                GpuSnapshot::default()
-            }
-        } else {
-            GpuSnapshot::default()
-        };
+        let gpus: Vec<GpuSnapshot> = self.gpus().iter().map(GpuSnapshot::from_gpu).collect();
+        let gpu_snapshot = gpus
+            .iter()
+            .find(|gpu| gpu.index == self.gpu_index)
+            .cloned()
+            .unwrap_or_default();
 
         ResourceSnapshot {
             timestamp: std::time::SystemTime::now(),
             gpu: gpu_snapshot,
+            gpus,
             cpu_usage: self.system.global_cpu_info().cpu_usage(),
             ram_usage_percent: (self.system.used_memory() as f32 / self.system.total_memory() as f32) * 100.0,
         }
@@ -397,9 +352,16 @@ impl SystemMonitor {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelParams {
     pub name: String,
-    pub param_count: u64,        // in millions (7B = 7000)
+    pub param_count: u64,        // raw count (7B = 7_000_000_000)
     pub quantization: Quantization,
     pub context_length: u32,
+    // Architecture details for an exact KV cache size; without them it is estimated from the
+    // parameter count. kv_embedding_dim is KV heads times head size, smaller than the hidden
+    // size on grouped-query models.
+    #[serde(default)]
+    pub layer_count: Option<u32>,
+    #[serde(default)]
+    pub kv_embedding_dim: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -407,45 +369,152 @@ pub enum Quantization {
     F32,    // Full precision
     F16,    // Half precision
     Q8_0,   // 8-bit quantization
-    Q5_K_M, // 5-bit quantization
-    Q4_K_M, // 4-bit quantization
+    #[serde(rename = "Q5_K_M")]
+    Q5KM,   // 5-bit quantization
+    #[serde(rename = "Q4_K_M")]
+    Q4KM,   // 4-bit quantization
     Q4_0,   // 4-bit quantization (older)
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct GpuSnapshot {
+    pub index: u32,
     pub vram_used_percent: f32,
     pub utilization: u32,
     pub temperature: f32,
     pub power_watts: u32,
 }
 
+impl GpuSnapshot {
+    fn from_gpu(gpu: &GpuInfo) -> Self {
+        Self {
+            index: gpu.index,
+            vram_used_percent: if gpu.vram_total_mb > 0 {
+                (gpu.vram_used_mb as f32 / gpu.vram_total_mb as f32) * 100.0
+            } else {
+                0.0
+            },
+            utilization: gpu.utilization,
+            temperature: gpu.temperature,
+            power_watts: gpu.power_watts,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResourceSnapshot {
     pub timestamp: std::time::SystemTime,
+    // The selected device, also present in `gpus`
     pub gpu: GpuSnapshot,
+    pub gpus: Vec<GpuSnapshot>,
     pub cpu_usage: f32,
     pub ram_usage_percent: f32,
 }
 
+// VRAM is summed across devices: GGUF layers can be split over several cards
+pub fn calculate_capability_breakdown(gpus: &[GpuInfo], cpu: &CpuInfo, memory: &MemoryInfo) -> CapabilityBreakdown {
+    let gpus: Vec<&GpuInfo> = gpus.iter().filter(|gpu| gpu.available).collect();
+    let cuda_available = gpus.iter().any(|gpu| gpu.cuda_available);
+    let vram_total_mb: u64 = gpus.iter().map(|gpu| gpu.vram_total_mb).sum();
+
+    // GPU scoring (0-50 points)
+    let (gpu_points, gpu_reason) = if !gpus.is_empty() {
+        let cuda_points = if cuda_available { 10 } else { 0 };
+
+        let (vram_points, vram_label) = match vram_total_mb {
+            v if v >= 24576 => (40, "24GB+ VRAM - excellent"),
+            v if v >= 16384 => (35, "16GB+ VRAM - very good"),
+            v if v >= 12288 => (30, "12GB+ VRAM - good"),
+            v if v >= 8192 => (25, "8GB+ VRAM - decent"),
+            v if v >= 6144 => (20, "6GB+ VRAM - minimum for most models"),
+            v if v >= 4096 => (15, "4GB+ VRAM - limited"),
+            _ => (5, "under 4GB VRAM - very limited"),
+        };
+
+        let cuda_label = if cuda_available { "CUDA available (+10)" } else { "no CUDA (+0)" };
+        let names = gpus.iter().map(|gpu| gpu.name.as_str()).collect::<Vec<_>>().join(" + ");
+        (
+            cuda_points + vram_points,
+            format!("{}: {}, {} (+{})", names, cuda_label, vram_label, vram_points),
+        )
+    } else {
+        (0, "No GPU detected - inference runs on CPU only".to_string())
+    };
+
+    // CPU scoring (0-25 points): cores plus a frequency bonus
+    let core_points = match cpu.core_count {
+        c if c >= 16 => 15,
+        c if c >= 12 => 12,
+        c if c >= 8 => 10,
+        c if c >= 6 => 8,
+        c if c >= 4 => 5,
+        _ => 2,
+    };
+    let frequency_points = match cpu.frequency_mhz {
+        f if f >= 4000 => 10,
+        f if f >= 3500 => 7,
+        f if f >= 3000 => 5,
+        _ => 0,
+    };
+    let cpu_reason = format!(
+        "{} cores (+{}), {} MHz (+{})",
+        cpu.core_count, core_points, cpu.frequency_mhz, frequency_points
+    );
+
+    // RAM scoring (0-25 points)
+    let (ram_points, ram_label) = match memory.total_mb {
+        m if m >= 65536 => (25, "64GB+ - excellent"),
+        m if m >= 32768 => (20, "32GB+ - very good"),
+        m if m >= 16384 => (15, "16GB+ - good"),
+        m if m >= 8192 => (10, "8GB+ - minimum"),
+        _ => (5, "under 8GB - below minimum"),
+    };
+
+    let cpu_points = core_points + frequency_points;
+
+    CapabilityBreakdown {
+        gpu_points,
+        cpu_points,
+        ram_points,
+        total: (gpu_points + cpu_points + ram_points).min(100),
+        gpu_reason,
+        cpu_reason,
+        ram_reason: format!("{} MB RAM: {}", memory.total_mb, ram_label),
+    }
+}
+
 fn calculate_vram_requirement(model: &ModelParams) -> u64 {
     // Calculate VRAM requirement in MB based on model size and quantization
-    let base_size = model.param_count * match model.quantization {
-        Quantization::F32 => 4,     // 4 bytes per parameter
-        Quantization::F16 => 2,     // 2 bytes per parameter
-        Quantization::Q8_0 => 1,    // ~1 byte per parameter
-        Quantization::Q5_K_M => 5/8, // ~0.625 bytes per parameter
-        Quantization::Q4_K_M => 1/2, // ~0.5 bytes per parameter
-        Quantization::Q4_0 => 1/2,  // ~0.5 bytes per parameter
+    let bytes_per_param = match model.quantization {
+        Quantization::F32 => 4.0,
+        Quantization::F16 => 2.0,
+        Quantization::Q8_0 => 1.0,
+        Quantization::Q5KM => 0.625,
+        Quantization::Q4KM => 0.5,
+        Quantization::Q4_0 => 0.5,
     };
+    let base_size_mb = model.param_count as f64 * bytes_per_param / 1_048_576.0;
 
     // Add overhead for context, activations, etc (roughly 20%)
-    let with_overhead = (base_size as f64 * 1.2) as u64;
+    let with_overhead = (base_size_mb * 1.2) as u64;
 
-    // Add context buffer (depends on context length)
-    let context_buffer = (model.context_length as u64 * 1024) / 1_048_576; // Rough estimate
+    with_overhead + kv_cache_mb(model)
+}
 
-    with_overhead + context_buffer
+// Bytes per KV cache element; llama.cpp-style runtimes keep the cache in f16
+const KV_CACHE_BYTES_PER_ELEMENT: u64 = 2;
+// Per token and billion parameters when the architecture is unknown. Matches full multi-head
+// attention (Llama 2 7B: 512 KiB/token); grouped-query models need a quarter of this or less.
+const KV_CACHE_BYTES_PER_TOKEN_PER_BILLION: f64 = 72.0 * 1024.0;
+
+// Keys and values for every layer and every token of the context window
+fn kv_cache_mb(model: &ModelParams) -> u64 {
+    let context = model.context_length as u64;
+    let bytes = match (model.layer_count, model.kv_embedding_dim) {
+        (Some(layers), Some(kv_dim)) => 2 * layers as u64 * kv_dim as u64 * context * KV_CACHE_BYTES_PER_ELEMENT,
+        _ => (model.param_count as f64 / 1e9 * KV_CACHE_BYTES_PER_TOKEN_PER_BILLION * context as f64) as u64,
+    };
+    bytes / 1_048_576
 }
 
 fn calculate_ram_requirement(model: &ModelParams) -> u64 {
@@ -455,9 +524,10 @@ fn calculate_ram_requirement(model: &ModelParams) -> u64 {
 
 fn estimate_inference_speed(specs: &SystemSpecs, model: &ModelParams) -> f32 {
     // Rough estimation of tokens per second based on hardware
+    let params_millions = model.param_count / 1_000_000;
     if !specs.gpu.available {
         // CPU only - very rough estimates
-        match model.param_count {
+        match params_millions {
             p if p <= 3_000 => 5.0,   // 3B or less
             p if p <= 7_000 => 2.0,   // 7B
             p if p <= 13_000 => 0.5,  // 13B
@@ -473,7 +543,7 @@ fn estimate_inference_speed(specs: &SystemSpecs, model: &ModelParams) -> f32 {
             _ => 1.0,
         };
 
-        match model.param_count {
+        match params_millions {
             p if p <= 3_000 => 50.0 * gpu_factor,
             p if p <= 7_000 => 30.0 * gpu_factor,
             p if p <= 13_000 => 15.0 * gpu_factor,
@@ -481,4 +551,146 @@ fn estimate_inference_speed(specs: &SystemSpecs, model: &ModelParams) -> f32 {
             _ => 3.0 * gpu_factor,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(index: u32, name: &str, vram_total_mb: u64, cuda_available: bool) -> GpuInfo {
+        GpuInfo {
+            available: true,
+            index,
+            name: name.to_string(),
+            vram_total_mb,
+            vram_used_mb: 0,
+            vram_free_mb: vram_total_mb,
+            temperature: 0.0,
+            utilization: 0,
+            power_watts: 0,
+            cuda_available,
+            compute_capability: "N/A".to_string(),
+            driver_version: "Unknown".to_string(),
+        }
+    }
+
+    fn cpu(core_count: usize, frequency_mhz: u64) -> CpuInfo {
+        CpuInfo { brand: "Test CPU".to_string(), core_count, frequency_mhz, usage_percent: 0.0, temperature: 0.0 }
+    }
+
+    fn memory(total_mb: u64) -> MemoryInfo {
+        MemoryInfo { total_mb, used_mb: 0, available_mb: total_mb, usage_percent: 0.0 }
+    }
+
+    #[test]
+    fn test_capability_breakdown_for_a_workstation() {
+        let breakdown = calculate_capability_breakdown(
+            &[gpu(0, "NVIDIA GeForce RTX 4090", 24564, true)],
+            &cpu(16, 4500),
+            &memory(65536),
+        );
+        // 24564 MB is just under 24 GiB, so the card lands in the 16GB+ band
+        assert_eq!(breakdown, CapabilityBreakdown {
+            gpu_points: 45,
+            cpu_points: 25,
+            ram_points: 25,
+            total: 95,
+            gpu_reason: "NVIDIA GeForce RTX 4090: CUDA available (+10), 16GB+ VRAM - very good (+35)".to_string(),
+            cpu_reason: "16 cores (+15), 4500 MHz (+10)".to_string(),
+            ram_reason: "65536 MB RAM: 64GB+ - excellent".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_capability_breakdown_for_a_cpu_only_laptop() {
+        let breakdown = calculate_capability_breakdown(&[], &cpu(4, 2400), &memory(8000));
+        assert_eq!((breakdown.gpu_points, breakdown.cpu_points, breakdown.ram_points), (0, 5, 5));
+        assert_eq!(breakdown.total, 10);
+        assert_eq!(breakdown.gpu_reason, "No GPU detected - inference runs on CPU only");
+        assert_eq!(breakdown.cpu_reason, "4 cores (+5), 2400 MHz (+0)");
+        assert_eq!(breakdown.ram_reason, "8000 MB RAM: under 8GB - below minimum");
+    }
+
+    // Stands in for NVML on a two-card workstation
+    struct TwoCardAdapter;
+
+    impl GpuAdapter for TwoCardAdapter {
+        fn gpus(&self) -> Vec<GpuInfo> {
+            let mut display = gpu(0, "NVIDIA T400", 2048, true);
+            display.vram_used_mb = 1024;
+            display.vram_free_mb = 1024;
+            let mut compute = gpu(1, "NVIDIA RTX A6000", 49140, true);
+            compute.utilization = 80;
+            vec![display, compute]
+        }
+    }
+
+    #[test]
+    fn test_every_gpu_is_reported_and_the_largest_is_primary() {
+        let adapters: Vec<Box<dyn GpuAdapter>> = vec![Box::new(TwoCardAdapter)];
+        let gpu_index = gpu_adapter::largest_vram_index(&gpu_adapter::all_gpus(&adapters)).unwrap();
+        let mut monitor = SystemMonitor { system: System::new(), adapters, gpu_index };
+        assert_eq!(monitor.gpu_index(), 1);
+        assert_eq!(monitor.primary_gpu().name, "NVIDIA RTX A6000");
+
+        let snapshot = monitor.monitor_resources_realtime();
+        assert_eq!(snapshot.gpus.len(), 2);
+        assert_eq!(snapshot.gpus[0].vram_used_percent, 50.0);
+        assert_eq!(snapshot.gpu.index, 1);
+        assert_eq!(snapshot.gpu.utilization, 80);
+
+        assert_eq!(monitor.set_gpu_index(Some(0)).unwrap(), 0);
+        assert_eq!(monitor.primary_gpu().name, "NVIDIA T400");
+        assert!(monitor.set_gpu_index(Some(2)).is_err());
+        assert_eq!(monitor.set_gpu_index(None).unwrap(), 1);
+
+        // VRAM counts across both cards
+        let breakdown = calculate_capability_breakdown(&monitor.gpus(), &cpu(8, 3000), &memory(32768));
+        assert!(breakdown.gpu_reason.starts_with("NVIDIA T400 + NVIDIA RTX A6000:"));
+        assert_eq!(breakdown.gpu_points, 50);
+    }
+
+    fn model(param_count: u64, quantization: Quantization, context_length: u32) -> ModelParams {
+        ModelParams {
+            name: "test".to_string(),
+            param_count,
+            quantization,
+            context_length,
+            layer_count: None,
+            kv_embedding_dim: None,
+        }
+    }
+
+    #[test]
+    fn test_quantized_7b_needs_gigabytes_not_nothing() {
+        // Mistral 7B: 32 layers, 8 KV heads of 128, so the 2k cache adds a known 256 MB
+        let mut q4 = model(7_000_000_000, Quantization::Q4KM, 2048);
+        q4.layer_count = Some(32);
+        q4.kv_embedding_dim = Some(1024);
+        let q4_mb = calculate_vram_requirement(&q4);
+        assert!((4000..=5000).contains(&q4_mb), "7B Q4_K_M estimated at {} MB", q4_mb);
+
+        let q5 = ModelParams { quantization: Quantization::Q5KM, ..q4 };
+        let q5_mb = calculate_vram_requirement(&q5);
+        assert!(q5_mb > q4_mb && q5_mb < 6000, "7B Q5_K_M estimated at {} MB", q5_mb);
+    }
+
+    #[test]
+    fn test_long_context_raises_the_vram_estimate() {
+        // Architecture known: the cache grows by exactly 2 * layers * kv_dim * 2 bytes per token
+        let with_architecture = |context_length| ModelParams {
+            layer_count: Some(32),
+            kv_embedding_dim: Some(1024),
+            ..model(7_000_000_000, Quantization::Q4KM, context_length)
+        };
+        let (short, long) = (with_architecture(4096), with_architecture(32768));
+        assert_eq!(kv_cache_mb(&short), 512);
+        assert_eq!(kv_cache_mb(&long), 4096);
+        assert_eq!(calculate_vram_requirement(&long) - calculate_vram_requirement(&short), 3584);
+
+        // Architecture unknown: the per-billion heuristic still scales with the window
+        let short = model(7_000_000_000, Quantization::Q4KM, 4096);
+        let long = model(7_000_000_000, Quantization::Q4KM, 32768);
+        let (short_mb, long_mb) = (calculate_vram_requirement(&short), calculate_vram_requirement(&long));
+        assert!(long_mb >= short_mb * 3, "4k: {} MB, 32k: {} MB", short_mb, long_mb);
+    }
+}
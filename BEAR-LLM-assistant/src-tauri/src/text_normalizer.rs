@@ -0,0 +1,96 @@
+// Canonicalizes text before embedding and PII scanning: NFKC (fullwidth digits, ligatures,
+// NBSP), zero-width/bidi/control characters removed, whitespace runs collapsed.
+// Keeps a byte map back to the original so detections can still be shown in place.
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub struct NormalizedText {
+    pub text: String,
+    // For every byte of `text`, the original byte range it was produced from
+    origins: Vec<(usize, usize)>,
+}
+
+impl NormalizedText {
+    // Maps a byte range in the normalized text back to the original text
+    pub fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        if start >= end || start >= self.origins.len() {
+            let pos = self.origins.last().map(|o| o.1).unwrap_or(0);
+            return (pos, pos);
+        }
+        let end = end.min(self.origins.len());
+        (self.origins[start].0, self.origins[end - 1].1)
+    }
+}
+
+pub fn normalize_text(text: &str) -> String {
+    normalize(text).text
+}
+
+pub fn normalize(text: &str) -> NormalizedText {
+    let mut out = String::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len());
+
+    // Pending whitespace run: (original start, newline count)
+    let mut pending_ws: Option<(usize, usize)> = None;
+
+    for (start, end) in clusters(text) {
+        for ch in text[start..end].nfkc() {
+            if is_invisible(ch) || (ch.is_control() && !ch.is_whitespace()) {
+                continue;
+            }
+
+            if ch.is_whitespace() {
+                let run = pending_ws.get_or_insert((start, 0));
+                if ch == '\n' {
+                    run.1 += 1;
+                }
+                continue;
+            }
+
+            if let Some((ws_start, newlines)) = pending_ws.take() {
+                // Leading whitespace is dropped; paragraph breaks survive as at most one blank line
+                if !out.is_empty() {
+                    let sep = match newlines {
+                        0 => " ",
+                        1 => "\n",
+                        _ => "\n\n",
+                    };
+                    out.push_str(sep);
+                    origins.extend(std::iter::repeat_n((ws_start, start), sep.len()));
+                }
+            }
+
+            out.push(ch);
+            origins.extend(std::iter::repeat_n((start, end), ch.len_utf8()));
+        }
+    }
+
+    NormalizedText { text: out, origins }
+}
+
+// A base character plus any combining marks that follow it, so NFKC can still compose them
+fn clusters(text: &str) -> Vec<(usize, usize)> {
+    let mut clusters: Vec<(usize, usize)> = Vec::new();
+    for (i, ch) in text.char_indices() {
+        let end = i + ch.len_utf8();
+        match clusters.last_mut() {
+            Some(last) if is_combining_mark(ch) => last.1 = end,
+            _ => clusters.push((i, end)),
+        }
+    }
+    clusters
+}
+
+// Zero-width, soft hyphen, BOM and bidi controls: invisible, but enough to split a regex match
+fn is_invisible(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{00AD}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{FEFF}'
+    )
+}
@@ -0,0 +1,290 @@
+use sysinfo::{ComponentExt, System, SystemExt};
+
+// CPU temperature in °C. sysinfo's component labels only name the CPU on some platforms, so
+// Linux reads the kernel's thermal files and macOS asks the SMC first; the component scan is
+// the fallback everywhere.
+pub fn cpu_temperature(system: &System) -> Option<f32> {
+    #[cfg(target_os = "linux")]
+    if let Some(temperature) = linux::cpu_temperature() {
+        return Some(temperature);
+    }
+
+    #[cfg(target_os = "macos")]
+    if let Some(temperature) = smc::cpu_temperature() {
+        return Some(temperature);
+    }
+
+    system
+        .components()
+        .iter()
+        .find(|c| c.label().contains("CPU") || c.label().contains("Core"))
+        .map(|c| c.temperature())
+}
+
+// Sensors report millidegrees; anything outside this range is a disconnected or bogus sensor
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn plausible(celsius: f32) -> Option<f32> {
+    (celsius > 0.0 && celsius < 150.0).then_some(celsius)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::path::Path;
+
+    // hwmon drivers lm-sensors reads for the CPU package (Intel, AMD, Zen out-of-tree)
+    const CPU_HWMON_NAMES: &[&str] = &["coretemp", "k10temp", "zenpower", "cpu_thermal"];
+    // Thermal zone types that describe the CPU, best first; acpitz is the motherboard's guess
+    const CPU_ZONE_TYPES: &[&str] = &["x86_pkg_temp", "cpu-thermal", "cpu_thermal", "soc_thermal", "soc-thermal", "acpitz"];
+
+    fn read(path: &Path) -> Option<String> {
+        std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+    }
+
+    // Contents of a `temp` or `tempN_input` file: integer millidegrees Celsius
+    pub fn parse_millidegrees(contents: &str) -> Option<f32> {
+        let millidegrees: i64 = contents.trim().parse().ok()?;
+        super::plausible(millidegrees as f32 / 1000.0)
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<std::path::PathBuf> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        entries.sort();
+        entries
+    }
+
+    pub fn cpu_temperature() -> Option<f32> {
+        cpu_temperature_in(Path::new("/sys/class/hwmon"), Path::new("/sys/class/thermal"))
+    }
+
+    pub fn cpu_temperature_in(hwmon_root: &Path, thermal_root: &Path) -> Option<f32> {
+        // hwmon first: it is the CPU's own sensor where thermal zones are often ACPI estimates
+        for hwmon in dir_entries(hwmon_root) {
+            let is_cpu = read(&hwmon.join("name")).is_some_and(|name| CPU_HWMON_NAMES.contains(&name.as_str()));
+            if !is_cpu {
+                continue;
+            }
+            // temp1 is the package (coretemp) or Tctl (k10temp)
+            if let Some(temperature) = read(&hwmon.join("temp1_input")).and_then(|t| parse_millidegrees(&t)) {
+                return Some(temperature);
+            }
+        }
+
+        let zones: Vec<(String, f32)> = dir_entries(thermal_root)
+            .into_iter()
+            .filter(|zone| zone.file_name().is_some_and(|name| name.to_string_lossy().starts_with("thermal_zone")))
+            .filter_map(|zone| {
+                let zone_type = read(&zone.join("type"))?;
+                let temperature = parse_millidegrees(&read(&zone.join("temp"))?)?;
+                Some((zone_type, temperature))
+            })
+            .collect();
+        CPU_ZONE_TYPES.iter().find_map(|wanted| {
+            zones
+                .iter()
+                .filter(|(zone_type, _)| zone_type == wanted)
+                .map(|(_, temperature)| *temperature)
+                .reduce(f32::max)
+        })
+    }
+}
+
+// Reads CPU die sensors through the AppleSMC IOKit service, the same interface the menu bar
+// temperature tools use. Apple Silicon reports floats; Intel Macs use sp78 fixed point.
+#[cfg(target_os = "macos")]
+mod smc {
+    use std::ffi::{c_char, c_void};
+
+    type KernReturn = i32;
+    type IoObject = u32;
+    type MachPort = u32;
+
+    const KERN_SUCCESS: KernReturn = 0;
+    const KERNEL_INDEX_SMC: u32 = 2;
+    const SMC_CMD_READ_BYTES: u8 = 5;
+    const SMC_CMD_READ_KEYINFO: u8 = 9;
+
+    // Intel package/die keys, then the M1/M2/M3 performance-core keys
+    const CPU_KEYS: &[&str] = &[
+        "TC0P", "TC0D", "TC0E", "TC0F",
+        "Tp09", "Tp0T", "Tp01", "Tp05", "Tp0D", "Tp0H", "Tp0L", "Tp0P", "Tp0X", "Tp0b",
+        "Tp1h", "Tp1t", "Tp1p", "Tp1l", "Tp0f", "Tp0j",
+    ];
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct KeyDataVersion {
+        major: u8,
+        minor: u8,
+        build: u8,
+        reserved: u8,
+        release: u16,
+    }
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct KeyDataLimits {
+        version: u16,
+        length: u16,
+        cpu_limit: u32,
+        gpu_limit: u32,
+        mem_limit: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct KeyInfo {
+        data_size: u32,
+        data_type: u32,
+        data_attributes: u8,
+    }
+
+    // Mirrors the driver's SMCKeyData_t (80 bytes)
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct KeyData {
+        key: u32,
+        version: KeyDataVersion,
+        limits: KeyDataLimits,
+        key_info: KeyInfo,
+        result: u8,
+        status: u8,
+        data8: u8,
+        data32: u32,
+        bytes: [u8; 32],
+    }
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        fn IOServiceMatching(name: *const c_char) -> *mut c_void;
+        fn IOServiceGetMatchingService(main_port: MachPort, matching: *mut c_void) -> IoObject;
+        fn IOServiceOpen(service: IoObject, owning_task: MachPort, kind: u32, connect: *mut IoObject) -> KernReturn;
+        fn IOServiceClose(connect: IoObject) -> KernReturn;
+        fn IOObjectRelease(object: IoObject) -> KernReturn;
+        fn IOConnectCallStructMethod(
+            connection: IoObject,
+            selector: u32,
+            input: *const c_void,
+            input_size: usize,
+            output: *mut c_void,
+            output_size: *mut usize,
+        ) -> KernReturn;
+    }
+
+    extern "C" {
+        static mach_task_self_: MachPort;
+    }
+
+    fn four_char_code(code: &str) -> u32 {
+        code.bytes().fold(0, |value, byte| (value << 8) | byte as u32)
+    }
+
+    struct Connection(IoObject);
+
+    impl Connection {
+        fn open() -> Option<Self> {
+            unsafe {
+                let service = IOServiceGetMatchingService(0, IOServiceMatching(c"AppleSMC".as_ptr()));
+                if service == 0 {
+                    return None;
+                }
+                let mut connection = 0;
+                let result = IOServiceOpen(service, mach_task_self_, 0, &mut connection);
+                IOObjectRelease(service);
+                (result == KERN_SUCCESS).then_some(Connection(connection))
+            }
+        }
+
+        fn call(&self, input: &KeyData) -> Option<KeyData> {
+            let mut output = KeyData::default();
+            let mut output_size = std::mem::size_of::<KeyData>();
+            let result = unsafe {
+                IOConnectCallStructMethod(
+                    self.0,
+                    KERNEL_INDEX_SMC,
+                    input as *const KeyData as *const c_void,
+                    std::mem::size_of::<KeyData>(),
+                    &mut output as *mut KeyData as *mut c_void,
+                    &mut output_size,
+                )
+            };
+            (result == KERN_SUCCESS && output.result == 0).then_some(output)
+        }
+
+        fn read_celsius(&self, key: &str) -> Option<f32> {
+            let key = four_char_code(key);
+            let info = self.call(&KeyData { key, data8: SMC_CMD_READ_KEYINFO, ..Default::default() })?.key_info;
+            let value = self.call(&KeyData { key, key_info: info, data8: SMC_CMD_READ_BYTES, ..Default::default() })?;
+
+            let celsius = if info.data_type == four_char_code("flt ") && info.data_size == 4 {
+                f32::from_le_bytes([value.bytes[0], value.bytes[1], value.bytes[2], value.bytes[3]])
+            } else if info.data_type == four_char_code("sp78") && info.data_size == 2 {
+                i16::from_be_bytes([value.bytes[0], value.bytes[1]]) as f32 / 256.0
+            } else {
+                return None;
+            };
+            super::plausible(celsius)
+        }
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe {
+                IOServiceClose(self.0);
+            }
+        }
+    }
+
+    // Hottest readable CPU sensor; which keys exist depends on the chip
+    pub fn cpu_temperature() -> Option<f32> {
+        let connection = Connection::open()?;
+        CPU_KEYS.iter().filter_map(|key| connection.read_celsius(key)).reduce(f32::max)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::linux::{cpu_temperature_in, parse_millidegrees};
+    use std::path::Path;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_thermal_zone_temp_values_parse_as_millidegrees() {
+        assert_eq!(parse_millidegrees("45000\n"), Some(45.0));
+        assert_eq!(parse_millidegrees("62500"), Some(62.5));
+        // Unconnected sensors report 0 or absurd values
+        assert_eq!(parse_millidegrees("0"), None);
+        assert_eq!(parse_millidegrees("-273000"), None);
+        assert_eq!(parse_millidegrees("255000"), None);
+        assert_eq!(parse_millidegrees("N/A"), None);
+    }
+
+    #[test]
+    fn test_cpu_zone_is_preferred_over_acpi_and_hwmon_over_zones() {
+        let dir = tempfile::tempdir().unwrap();
+        let (hwmon, thermal) = (dir.path().join("hwmon"), dir.path().join("thermal"));
+        write(&thermal.join("thermal_zone0/type"), "acpitz\n");
+        write(&thermal.join("thermal_zone0/temp"), "40000\n");
+        write(&thermal.join("thermal_zone1/type"), "x86_pkg_temp\n");
+        write(&thermal.join("thermal_zone1/temp"), "71000\n");
+        write(&thermal.join("thermal_zone2/type"), "iwlwifi_1\n");
+        write(&thermal.join("thermal_zone2/temp"), "90000\n");
+        write(&thermal.join("cooling_device0/type"), "Processor\n");
+        assert_eq!(cpu_temperature_in(&hwmon, &thermal), Some(71.0));
+
+        write(&hwmon.join("hwmon0/name"), "nvme\n");
+        write(&hwmon.join("hwmon0/temp1_input"), "38000\n");
+        write(&hwmon.join("hwmon1/name"), "k10temp\n");
+        write(&hwmon.join("hwmon1/temp1_input"), "68250\n");
+        assert_eq!(cpu_temperature_in(&hwmon, &thermal), Some(68.25));
+
+        std::fs::remove_dir_all(&thermal).unwrap();
+        std::fs::remove_dir_all(hwmon.join("hwmon1")).unwrap();
+        assert_eq!(cpu_temperature_in(&hwmon, &thermal), None);
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{Result, anyhow};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::rag_engine::{Document, SourceLocation};
+
+// SQLite backing for the RAG index. Every write touches only the rows it changes, so adding a
+// document costs the same regardless of how many are already indexed. The engine keeps its
+// in-memory copy for searching and loads it from here at startup.
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS documents (
+        id TEXT PRIMARY KEY,
+        metadata TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS chunks (
+        id TEXT PRIMARY KEY,
+        parent_id TEXT,
+        collection TEXT,
+        content TEXT NOT NULL,
+        metadata TEXT NOT NULL,
+        embedding BLOB NOT NULL,
+        timestamp INTEGER NOT NULL,
+        source_location TEXT
+    );
+    CREATE INDEX IF NOT EXISTS chunks_parent_id ON chunks(parent_id);
+    CREATE TABLE IF NOT EXISTS collection_models (
+        collection TEXT PRIMARY KEY,
+        model_id TEXT NOT NULL
+    );
+";
+
+// Everything needed to rebuild the engine's in-memory index
+pub struct StoredIndex {
+    pub version: Option<u32>,
+    pub embedding_model: Option<String>,
+    pub embedding_dim: Option<usize>,
+    pub documents: HashMap<String, Document>,
+    pub doc_metadata: HashMap<String, JsonValue>,
+    pub collection_models: HashMap<String, String>,
+}
+
+pub struct VectorStore {
+    // rusqlite connections are Send but not Sync; the engine itself is shared behind a RwLock
+    conn: Mutex<Connection>,
+}
+
+impl VectorStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow!("Cannot open index database {}: {}", path.display(), e))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock().map_err(|_| anyhow!("Index database lock poisoned"))
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        let conn = self.conn()?;
+        let chunks: i64 = conn.query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get(0))?;
+        let models: i64 = conn.query_row("SELECT COUNT(*) FROM collection_models", [], |row| row.get(0))?;
+        Ok(chunks == 0 && models == 0)
+    }
+
+    pub fn load(&self) -> Result<StoredIndex> {
+        let conn = self.conn()?;
+
+        let meta = |key: &str| -> Result<Option<String>> {
+            Ok(conn
+                .query_row("SELECT value FROM meta WHERE key = ?1", params![key], |row| row.get(0))
+                .optional()?)
+        };
+        let version = meta("version")?.and_then(|v| v.parse().ok());
+        let embedding_model = meta("embedding_model")?;
+        let embedding_dim = meta("embedding_dim")?.and_then(|v| v.parse().ok());
+
+        let mut doc_metadata = HashMap::new();
+        let mut statement = conn.prepare("SELECT id, metadata FROM documents")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (id, metadata) = row?;
+            doc_metadata.insert(id, serde_json::from_str(&metadata)?);
+        }
+
+        let mut documents = HashMap::new();
+        let mut statement = conn.prepare(
+            "SELECT id, parent_id, collection, content, metadata, embedding, timestamp, source_location FROM chunks",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Vec<u8>>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, Option<String>>(7)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, parent_id, collection, content, metadata, embedding, timestamp, source_location) = row?;
+            let source_location: Option<SourceLocation> = match source_location {
+                Some(json) => serde_json::from_str(&json)?,
+                None => None,
+            };
+            documents.insert(id.clone(), Document {
+                id,
+                content,
+                metadata: serde_json::from_str(&metadata)?,
+                embeddings: decode_embedding(&embedding)?.into(),
+                timestamp,
+                parent_id,
+                collection,
+                source_location,
+            });
+        }
+
+        let mut collection_models = HashMap::new();
+        let mut statement = conn.prepare("SELECT collection, model_id FROM collection_models")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (collection, model_id) = row?;
+            collection_models.insert(collection, model_id);
+        }
+
+        Ok(StoredIndex {
+            version,
+            embedding_model,
+            embedding_dim,
+            documents,
+            doc_metadata,
+            collection_models,
+        })
+    }
+
+    // One transaction per document, so a crash never leaves half its chunks behind
+    pub fn insert_document<'a>(
+        &self,
+        doc_id: &str,
+        metadata: &JsonValue,
+        chunks: impl IntoIterator<Item = &'a Document>,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO documents (id, metadata) VALUES (?1, ?2)",
+            params![doc_id, serde_json::to_string(metadata)?],
+        )?;
+        insert_chunks(&tx, chunks)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Bulk load used by the documents.json migration; a single transaction for the whole index
+    pub fn import(
+        &self,
+        documents: &HashMap<String, Document>,
+        doc_metadata: &HashMap<String, JsonValue>,
+        collection_models: &HashMap<String, String>,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare("INSERT OR REPLACE INTO documents (id, metadata) VALUES (?1, ?2)")?;
+            for (id, metadata) in doc_metadata {
+                statement.execute(params![id, serde_json::to_string(metadata)?])?;
+            }
+        }
+        insert_chunks(&tx, documents.values())?;
+        for (collection, model_id) in collection_models {
+            tx.execute(
+                "INSERT OR REPLACE INTO collection_models (collection, model_id) VALUES (?1, ?2)",
+                params![collection, model_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_document(&self, doc_id: &str) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let removed = tx.execute("DELETE FROM chunks WHERE parent_id = ?1", params![doc_id])?;
+        tx.execute("DELETE FROM documents WHERE id = ?1", params![doc_id])?;
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    pub fn set_collection_model(&self, collection: &str, model_id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO collection_models (collection, model_id) VALUES (?1, ?2)",
+            params![collection, model_id],
+        )?;
+        Ok(())
+    }
+
+    // Records which index format and embedding model the stored vectors belong to
+    pub fn set_index_info(&self, version: u32, embedding_model: &str, embedding_dim: usize) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        for (key, value) in [
+            ("version", version.to_string()),
+            ("embedding_model", embedding_model.to_string()),
+            ("embedding_dim", embedding_dim.to_string()),
+        ] {
+            tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)", params![key, value])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Collection model assignments are kept; they describe settings, not indexed content
+    pub fn clear(&self) -> Result<()> {
+        self.conn()?.execute_batch("DELETE FROM chunks; DELETE FROM documents;")?;
+        Ok(())
+    }
+}
+
+fn insert_chunks<'a>(tx: &rusqlite::Transaction, chunks: impl IntoIterator<Item = &'a Document>) -> Result<()> {
+    let mut statement = tx.prepare(
+        "INSERT OR REPLACE INTO chunks (id, parent_id, collection, content, metadata, embedding, timestamp, source_location)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for chunk in chunks {
+        let source_location = match &chunk.source_location {
+            Some(location) => Some(serde_json::to_string(location)?),
+            None => None,
+        };
+        statement.execute(params![
+            chunk.id,
+            chunk.parent_id,
+            chunk.collection,
+            chunk.content,
+            serde_json::to_string(&chunk.metadata)?,
+            encode_embedding(&chunk.embeddings),
+            chunk.timestamp,
+            source_location,
+        ])?;
+    }
+    Ok(())
+}
+
+// Little-endian f32s; a quarter the size of the JSON text and no float formatting on save
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Result<Vec<f32>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(anyhow!("Corrupt embedding of {} bytes in index database", bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
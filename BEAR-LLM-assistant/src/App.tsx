@@ -73,14 +73,16 @@ function App() {
     });
 
     try {
-      const response = await invoke<string>('send_message', {
+      const response = await invoke<{ text: string; truncated: boolean }>('send_message', {
         message: userMessage,
         modelName: selectedModel,
       });
 
       addMessage({
         role: 'assistant',
-        content: response,
+        content: response.truncated
+          ? `${response.text}\n\n[Response truncated at the token limit]`
+          : response.text,
         timestamp: Date.now(),
       });
     } catch (error: any) {
@@ -11,6 +11,7 @@ interface Document {
   filename: string;
   content: string;
   pii_removed: boolean;
+  pii_report?: { counts: Record<string, number>; total: number };
   metadata: any;
 }
 